@@ -0,0 +1,205 @@
+//! `actix-web` integration, enabled by the `actix` feature. The `ScannedBytes` extractor scans
+//! request payloads with `INSTREAM` before the handler runs, rejecting infected uploads.
+//!
+//! The extractor uses the `ClamClient` registered as `web::Data<ClamClient>`, so a single client
+//! is shared by every worker. Rejected requests receive the response built by
+//! `ScannedBytesConfig`, by default `422 Unprocessable Entity` for infected payloads, or
+//! `503 Service Unavailable` if the payload could not be scanned. The payload size is limited by
+//! `web::PayloadConfig` exactly as for `Bytes`.
+//!
+//! *Example*
+//!
+//! ```rust,no_run
+//! extern crate clam_client;
+//!
+//! use actix_web::{web, App, HttpServer};
+//! use clam_client::actix::ScannedBytes;
+//! use clam_client::client::ClamClient;
+//!
+//! async fn upload(body: ScannedBytes) -> String {
+//!     format!("Received {} clean bytes", body.len())
+//! }
+//!
+//! #[actix_web::main]
+//! async fn main() -> std::io::Result<()> {
+//!     let client = web::Data::new(ClamClient::new("127.0.0.1", 3310).unwrap());
+//!
+//!     HttpServer::new(move || {
+//!         App::new()
+//!             .app_data(client.clone())
+//!             .route("/upload", web::post().to(upload))
+//!     })
+//!     .bind(("127.0.0.1", 8080))?
+//!     .run()
+//!     .await
+//! }
+//! ```
+
+use crate::client::ClamClient;
+pub use crate::extract::{ScanRejection, ScannedBytes};
+use crate::response::ClamScanResult;
+use actix_web::dev::Payload;
+use actix_web::error::{ErrorInternalServerError, InternalError};
+use actix_web::http::StatusCode;
+use actix_web::web::{Bytes, Data};
+use actix_web::{FromRequest, HttpRequest, HttpResponse};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// `ScannedBytesConfig` customises the response to rejected payloads, it is registered as app
+/// data alongside the `ClamClient`.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use actix_web::{App, HttpResponse};
+/// use clam_client::actix::{ScanRejection, ScannedBytesConfig};
+///
+/// fn main() {
+///     let app = App::new().app_data(ScannedBytesConfig::default().rejection(|r| match r {
+///         ScanRejection::Infected(_) => HttpResponse::Forbidden().body("Upload rejected"),
+///         ScanRejection::Failed(_) => HttpResponse::ServiceUnavailable().finish(),
+///     }));
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ScannedBytesConfig {
+    rejection: Arc<dyn Fn(&ScanRejection) -> HttpResponse + Send + Sync>,
+}
+
+impl ScannedBytesConfig {
+    /// Sets the function building the response to a rejected payload.
+    pub fn rejection<F>(mut self, rejection: F) -> Self
+    where
+        F: Fn(&ScanRejection) -> HttpResponse + Send + Sync + 'static,
+    {
+        self.rejection = Arc::new(rejection);
+        self
+    }
+}
+
+impl Default for ScannedBytesConfig {
+    fn default() -> Self {
+        ScannedBytesConfig {
+            rejection: Arc::new(|rejection| {
+                let status = match rejection {
+                    ScanRejection::Infected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                    ScanRejection::Failed(_) => StatusCode::SERVICE_UNAVAILABLE,
+                };
+                HttpResponse::build(status).body(rejection.response_body())
+            }),
+        }
+    }
+}
+
+impl FromRequest for ScannedBytes {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<ScannedBytes, actix_web::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let bytes = Bytes::from_request(req, payload);
+        let client = req.app_data::<Data<ClamClient>>().cloned();
+        let config = req
+            .app_data::<ScannedBytesConfig>()
+            .cloned()
+            .unwrap_or_default();
+
+        Box::pin(async move {
+            let bytes = bytes.await?;
+            let client = client.ok_or_else(|| {
+                ErrorInternalServerError("ClamClient is not registered as app data")
+            })?;
+
+            let rejection = match client.scan_stream_async(&bytes[..]).await {
+                Ok(ClamScanResult::Ok) => return Ok(ScannedBytes(bytes)),
+                Ok(ClamScanResult::Found(_, signature)) => ScanRejection::Infected(signature),
+                Ok(ClamScanResult::Error(e)) => ScanRejection::Failed(e.to_string()),
+                Err(e) => ScanRejection::Failed(e.to_string()),
+            };
+
+            let response = (config.rejection)(&rejection);
+            Err(InternalError::from_response(rejection, response).into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::actix::{ScanRejection, ScannedBytes, ScannedBytesConfig};
+    use crate::client::ClamClient;
+    use crate::testing::fake_clamd;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpResponse};
+
+    async fn upload(body: ScannedBytes) -> String {
+        format!("{} bytes", body.len())
+    }
+
+    fn client() -> web::Data<ClamClient> {
+        let port = fake_clamd(|cmd| {
+            if cmd.ends_with(b"bad") {
+                b"stream: Sig-One FOUND".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        web::Data::new(ClamClient::new("127.0.0.1", port).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_scanned_bytes() {
+        let app = test::init_service(
+            App::new()
+                .app_data(client())
+                .route("/", web::post().to(upload)),
+        )
+        .await;
+
+        let req = test::TestRequest::post().set_payload("good").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(test::read_body(resp).await, "4 bytes");
+
+        let req = test::TestRequest::post().set_payload("bad").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_web::test]
+    async fn test_scanned_bytes_custom_rejection() {
+        let config = ScannedBytesConfig::default().rejection(|r| match r {
+            ScanRejection::Infected(_) => HttpResponse::Forbidden().finish(),
+            ScanRejection::Failed(_) => HttpResponse::BadGateway().finish(),
+        });
+        let app = test::init_service(
+            App::new()
+                .app_data(client())
+                .app_data(config)
+                .route("/", web::post().to(upload)),
+        )
+        .await;
+
+        let req = test::TestRequest::post().set_payload("bad").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn test_scan_failure_is_not_disclosed() {
+        let client = web::Data::new(ClamClient::new("127.0.0.1", 1).unwrap());
+        let app = test::init_service(
+            App::new()
+                .app_data(client)
+                .route("/", web::post().to(upload)),
+        )
+        .await;
+
+        let req = test::TestRequest::post().set_payload("good").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(test::read_body(resp).await, "Body could not be scanned");
+    }
+}
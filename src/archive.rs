@@ -0,0 +1,187 @@
+//! Client side extraction of zip archives, enabled by the `zip` feature. Each entry is extracted
+//! locally and streamed to ClamD individually, so that results can be attributed to the entry
+//! that caused them. Only the outer archive is extracted, an archive nested within it is streamed
+//! as a single entry and so remains subject to ClamD's own archive scanning limits.
+
+use crate::client::{ClamClient, ClamResult};
+use crate::error::ClamError;
+use crate::response::ClamScanResult;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom};
+use zip::ZipArchive;
+
+/// The signature which begins each record of a zip central directory.
+const CENTRAL_HEADER_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x01, 0x02];
+
+/// The length of the fixed length part of a central directory record.
+const CENTRAL_HEADER_LEN: usize = 46;
+
+impl ClamClient {
+    /// Extracts each file in the zip `archive` and scans it with `INSTREAM`, returning the result
+    /// for every entry keyed by its name within the archive. Directories are skipped, and each
+    /// entry is subject to `ClamClientBuilder::stream_max_length`.
+    ///
+    /// Returns `ClamError::ArchiveError` if the archive cannot be read, or the first error from
+    /// scanning an entry. An archive which repeats an entry name is also rejected with
+    /// `ClamError::ArchiveError`, since only the last entry of each name can be extracted and the
+    /// others would otherwise go unscanned.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::response::ClamScanResult;
+    /// use std::fs::File;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     if let Ok(archive) = File::open("upload.zip") {
+    ///         if let Ok(results) = client.scan_zip(archive) {
+    ///             for (name, result) in results {
+    ///                 if let ClamScanResult::Found(_, virus) = result {
+    ///                     println!("Found virus: '{}' in {}", virus, name)
+    ///                 }
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn scan_zip<R: Read + Seek>(
+        &self,
+        archive: R,
+    ) -> ClamResult<BTreeMap<String, ClamScanResult>> {
+        let archive =
+            ZipArchive::new(archive).map_err(|e| ClamError::ArchiveError(e.to_string()))?;
+        let (start, extractable) = (archive.central_directory_start(), archive.len());
+        let mut reader = archive.into_inner();
+        let recorded = central_directory_len(&mut reader, start)
+            .map_err(|e| ClamError::ArchiveError(e.to_string()))?;
+        if recorded > extractable {
+            return Err(ClamError::ArchiveError(format!(
+                "{} of {} entries repeat the name of another entry",
+                recorded - extractable,
+                recorded
+            )));
+        }
+
+        let mut archive =
+            ZipArchive::new(reader).map_err(|e| ClamError::ArchiveError(e.to_string()))?;
+        let mut results = BTreeMap::new();
+
+        for index in 0..archive.len() {
+            let entry = archive
+                .by_index(index)
+                .map_err(|e| ClamError::ArchiveError(e.to_string()))?;
+            if entry.is_dir() {
+                continue;
+            }
+
+            let name = entry.name().to_owned();
+            results.insert(name, self.scan_stream(entry)?);
+        }
+
+        Ok(results)
+    }
+}
+
+/// Counts the records in the zip central directory beginning at `start`. Unlike
+/// `ZipArchive::len` this includes entries whose name is repeated by a later entry, which
+/// `ZipArchive` does not expose.
+fn central_directory_len<R: Read + Seek>(reader: &mut R, start: u64) -> io::Result<usize> {
+    reader.seek(SeekFrom::Start(start))?;
+
+    let mut records = 0;
+    let mut header = [0u8; CENTRAL_HEADER_LEN];
+    loop {
+        if reader.read_exact(&mut header[..4]).is_err() || header[..4] != CENTRAL_HEADER_SIGNATURE {
+            return Ok(records);
+        }
+        reader.read_exact(&mut header[4..])?;
+
+        // The name, extra field and comment follow the fixed length part of the record
+        let field_len = |at: usize| i64::from(u16::from_le_bytes([header[at], header[at + 1]]));
+        reader.seek(SeekFrom::Current(
+            field_len(28) + field_len(30) + field_len(32),
+        ))?;
+        records += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::response::ClamScanResult;
+    use crate::testing::fake_clamd;
+    use std::io::{Cursor, Write};
+    use zip::write::{SimpleFileOptions, ZipWriter};
+
+    #[test]
+    fn test_scan_zip() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .add_directory("docs/", SimpleFileOptions::default())
+            .unwrap();
+        writer
+            .start_file("docs/clean.txt", SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"clean").unwrap();
+        writer
+            .start_file("bad.exe", SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"bad").unwrap();
+        let archive = writer.finish().unwrap();
+
+        let port = fake_clamd(|cmd| {
+            if cmd.ends_with(b"bad") {
+                b"stream: Sig-One FOUND".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+        let results = client.scan_zip(archive).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results["docs/clean.txt"], ClamScanResult::Ok);
+        assert_eq!(
+            results["bad.exe"],
+            ClamScanResult::Found("stream".to_owned(), "Sig-One".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_scan_zip_duplicate_names() {
+        // ZipWriter refuses repeated names, so the second entry is renamed in the written bytes
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in [("report.pdf", &b"clean"[..]), ("report.pdX", &b"bad"[..])] {
+            writer
+                .start_file(name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        let mut archive = writer.finish().unwrap().into_inner();
+        for at in 0..archive.len() - 9 {
+            if &archive[at..at + 10] == b"report.pdX" {
+                archive[at + 9] = b'f';
+            }
+        }
+
+        let client = ClamClient::new("127.0.0.1", 1).unwrap();
+        assert!(matches!(
+            client.scan_zip(Cursor::new(archive)),
+            Err(crate::error::ClamError::ArchiveError(_))
+        ));
+    }
+
+    #[test]
+    fn test_scan_zip_invalid() {
+        let client = ClamClient::new("127.0.0.1", 1).unwrap();
+        assert!(matches!(
+            client.scan_zip(Cursor::new(b"not a zip".to_vec())),
+            Err(crate::error::ClamError::ArchiveError(_))
+        ));
+    }
+}
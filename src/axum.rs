@@ -0,0 +1,295 @@
+//! `axum` integration, enabled by the `axum` feature. Request bodies are streamed to ClamD with
+//! `INSTREAM` as they arrive, and are buffered so the handler receives them only once ClamD has
+//! reported them clean.
+//!
+//! Bodies can be scanned per handler with the `ScannedBytes` extractor, or for every route with
+//! `ClamScanLayer`. `ScannedBytes` uses the `Arc<ClamClient>` held in the router's state, extract
+//! `Result<ScannedBytes, ScanRejection>` to customise the response to rejected bodies.
+//!
+//! The body is limited only by `ClamClientBuilder::stream_max_length`, which should be set when
+//! accepting bodies from untrusted clients.
+//!
+//! *Example*
+//!
+//! ```rust
+//! extern crate clam_client;
+//!
+//! use axum::routing::post;
+//! use axum::Router;
+//! use clam_client::axum::ScannedBytes;
+//! use clam_client::client::ClamClient;
+//! use std::sync::Arc;
+//!
+//! async fn upload(body: ScannedBytes) -> String {
+//!     format!("Received {} clean bytes", body.len())
+//! }
+//!
+//! fn main() {
+//!     let client = Arc::new(ClamClient::new("127.0.0.1", 3310).unwrap());
+//!     let app: Router = Router::new()
+//!         .route("/upload", post(upload))
+//!         .with_state(client);
+//! }
+//! ```
+
+use crate::client::{ClamClient, Tokio};
+pub use crate::extract::{ScanRejection, ScannedBytes};
+use crate::response::ClamScanResult;
+use ::axum::body::{Body, Bytes, HttpBody};
+use ::axum::extract::{FromRef, FromRequest, Request};
+use ::axum::http::StatusCode;
+use ::axum::response::{IntoResponse, Response};
+use bytes::BytesMut;
+use http_body_util::BodyExt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+impl IntoResponse for ScanRejection {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ScanRejection::Infected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ScanRejection::Failed(_) => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status, self.response_body()).into_response()
+    }
+}
+
+impl<S> FromRequest<S> for ScannedBytes
+where
+    S: Send + Sync,
+    Arc<ClamClient>: FromRef<S>,
+{
+    type Rejection = ScanRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let client = Arc::<ClamClient>::from_ref(state);
+        scan(&client, req.into_body()).await.map(ScannedBytes)
+    }
+}
+
+/// `ClamScanLayer` scans the body of every request before passing it on to the inner service,
+/// responding with the rejection response instead if the body is infected or cannot be scanned.
+/// Requests without a body are passed straight through.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use axum::http::StatusCode;
+/// use axum::response::IntoResponse;
+/// use axum::routing::post;
+/// use axum::Router;
+/// use clam_client::axum::{ClamScanLayer, ScanRejection};
+/// use clam_client::client::ClamClient;
+/// use std::sync::Arc;
+///
+/// async fn upload(body: String) -> String {
+///     format!("Received {} clean bytes", body.len())
+/// }
+///
+/// fn main() {
+///     let client = Arc::new(ClamClient::new("127.0.0.1", 3310).unwrap());
+///     let app: Router = Router::new()
+///         .route("/upload", post(upload))
+///         .layer(ClamScanLayer::new(client).rejection(|rejection| match rejection {
+///             ScanRejection::Infected(_) => StatusCode::FORBIDDEN.into_response(),
+///             failed => failed.into_response(),
+///         }));
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ClamScanLayer {
+    client: Arc<ClamClient>,
+    rejection: Arc<dyn Fn(ScanRejection) -> Response + Send + Sync>,
+}
+
+impl ClamScanLayer {
+    /// Creates a layer scanning request bodies with `client`.
+    pub fn new(client: Arc<ClamClient>) -> ClamScanLayer {
+        ClamScanLayer {
+            client,
+            rejection: Arc::new(IntoResponse::into_response),
+        }
+    }
+
+    /// Sets the function building the response to a rejected body, by default the
+    /// `ScanRejection` itself.
+    pub fn rejection<F>(mut self, rejection: F) -> Self
+    where
+        F: Fn(ScanRejection) -> Response + Send + Sync + 'static,
+    {
+        self.rejection = Arc::new(rejection);
+        self
+    }
+}
+
+impl<S> Layer<S> for ClamScanLayer {
+    type Service = ClamScan<S>;
+
+    fn layer(&self, inner: S) -> ClamScan<S> {
+        ClamScan {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// `ClamScan` is the service produced by `ClamScanLayer`.
+#[derive(Clone)]
+pub struct ClamScan<S> {
+    inner: S,
+    layer: ClamScanLayer,
+}
+
+impl<S> Service<Request> for ClamScan<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        // The service polled ready is the one which must be called, leave a clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+
+        Box::pin(async move {
+            if req.body().is_end_stream() {
+                return inner.call(req).await;
+            }
+
+            let (parts, body) = req.into_parts();
+            match scan(&layer.client, body).await {
+                Ok(bytes) => {
+                    inner
+                        .call(Request::from_parts(parts, Body::from(bytes)))
+                        .await
+                }
+                Err(rejection) => Ok((layer.rejection)(rejection)),
+            }
+        })
+    }
+}
+
+/// Streams `body` to ClamD whilst buffering it, returning the buffered body if it is clean.
+async fn scan(client: &ClamClient, mut body: Body) -> Result<Bytes, ScanRejection> {
+    let mut instream = client.begin_instream_on::<Tokio>().await?;
+    let mut buffer = BytesMut::new();
+
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|e| ScanRejection::Failed(e.to_string()))?;
+
+        if let Ok(data) = frame.into_data() {
+            client.write_chunk_async(&mut instream, &data).await?;
+            buffer.extend_from_slice(&data);
+        }
+    }
+
+    match client.finish_instream_async(instream).await? {
+        ClamScanResult::Ok => Ok(buffer.freeze()),
+        ClamScanResult::Found(_, signature) => Err(ScanRejection::Infected(signature)),
+        ClamScanResult::Error(e) => Err(ScanRejection::Failed(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::axum::{ClamScanLayer, ScanRejection, ScannedBytes};
+    use crate::client::ClamClient;
+    use crate::testing::fake_clamd;
+    use ::axum::body::{to_bytes, Body};
+    use ::axum::http::{Request, StatusCode};
+    use ::axum::response::IntoResponse;
+    use ::axum::routing::post;
+    use ::axum::Router;
+    use std::sync::Arc;
+    use tower_service::Service;
+
+    fn client() -> Arc<ClamClient> {
+        let port = fake_clamd(|cmd| {
+            if cmd.ends_with(b"bad") {
+                b"stream: Sig-One FOUND".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        Arc::new(ClamClient::new("127.0.0.1", port).unwrap())
+    }
+
+    async fn post_body(app: &mut Router, body: &'static str) -> (StatusCode, String) {
+        let req = Request::post("/").body(Body::from(body)).unwrap();
+        let resp = app.call(req).await.unwrap();
+        let status = resp.status();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_scanned_bytes() {
+        async fn upload(body: ScannedBytes) -> String {
+            format!("{} bytes", body.len())
+        }
+
+        let mut app = Router::new().route("/", post(upload)).with_state(client());
+
+        assert_eq!(
+            post_body(&mut app, "good").await,
+            (StatusCode::OK, "4 bytes".to_owned())
+        );
+        assert_eq!(
+            post_body(&mut app, "bad").await.0,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_layer() {
+        async fn upload(body: String) -> String {
+            body
+        }
+
+        let layer = ClamScanLayer::new(client()).rejection(|rejection| match rejection {
+            ScanRejection::Infected(_) => StatusCode::FORBIDDEN.into_response(),
+            failed => failed.into_response(),
+        });
+        let mut app = Router::new().route("/", post(upload)).layer(layer);
+
+        assert_eq!(
+            post_body(&mut app, "good").await,
+            (StatusCode::OK, "good".to_owned())
+        );
+        assert_eq!(post_body(&mut app, "bad").await.0, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_scan_failure_is_not_disclosed() {
+        async fn upload(body: String) -> String {
+            body
+        }
+
+        let client = Arc::new(ClamClient::new("127.0.0.1", 1).unwrap());
+        let mut app = Router::new()
+            .route("/", post(upload))
+            .layer(ClamScanLayer::new(client));
+
+        assert_eq!(
+            post_body(&mut app, "good").await,
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Body could not be scanned".to_owned()
+            )
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! `clam-client`, a command line interface to ClamD built on the library, enabled by the `cli`
+//! feature. Exit codes follow `clamdscan`: 0 if clean, 1 if a virus was found and 2 on error.
+
+extern crate clam_client;
+
+use clam_client::client::ClamClient;
+use clam_client::response::ClamScanResult;
+use clap::{Parser, Subcommand};
+use std::error::Error;
+use std::io;
+use std::process::ExitCode;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(
+    name = "clam-client",
+    version,
+    about = "Talk to a ClamD instance over TCP"
+)]
+struct Cli {
+    /// The host ClamD is listening on
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+
+    /// The port ClamD is listening on
+    #[arg(long, default_value_t = 3310)]
+    port: u16,
+
+    /// Connect, read and write timeout in seconds, 0 for none
+    #[arg(long)]
+    timeout: Option<u64>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check that ClamD is responding
+    Ping,
+    /// Print the version of ClamD and its signature database
+    Version,
+    /// Print ClamD's statistics
+    Stats,
+    /// Scan a path on the ClamD host, reporting every virus found
+    Scan {
+        /// The path to scan, as seen by ClamD
+        path: String,
+    },
+    /// Stream a local file to ClamD with INSTREAM, use `-` for standard input
+    Stream {
+        /// The file to stream
+        file: String,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(cli) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("clam-client: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<ExitCode, Box<dyn Error>> {
+    let mut builder = ClamClient::builder(&cli.host, cli.port);
+    if let Some(secs) = cli.timeout {
+        let timeout = Duration::from_secs(secs);
+        builder = builder
+            .connect_timeout(timeout)
+            .read_timeout(timeout)
+            .write_timeout(timeout);
+    }
+    let client = builder.build()?;
+
+    match cli.command {
+        Command::Ping => {
+            if client.ping() {
+                println!("PONG");
+                Ok(ExitCode::SUCCESS)
+            } else {
+                eprintln!("clam-client: no PONG from {}:{}", cli.host, cli.port);
+                Ok(ExitCode::from(2))
+            }
+        }
+        Command::Version => {
+            println!("{}", client.version()?);
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Stats => {
+            println!("{}", client.stats()?);
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Scan { path } => Ok(report(&client.scan_path(&path, true)?, &path)),
+        Command::Stream { file } => {
+            let result = if file == "-" {
+                match client.scan_stream(io::stdin().lock())? {
+                    ClamScanResult::Found(_, virus) => ClamScanResult::Found(file.clone(), virus),
+                    result => result,
+                }
+            } else {
+                client
+                    .scan_file(&file)
+                    .map_err(|e| format!("{}: {}", file, e))?
+            };
+            Ok(report(&[result], &file))
+        }
+    }
+}
+
+/// Prints each result in `clamdscan`'s format and returns the matching exit code, errors taking
+/// precedence over viruses found.
+fn report(results: &[ClamScanResult], path: &str) -> ExitCode {
+    let mut code = 0;
+
+    for result in results {
+        match result {
+            ClamScanResult::Ok => println!("{}: OK", path),
+            ClamScanResult::Found(..) => {
+                println!("{}", result);
+                code = code.max(1);
+            }
+            ClamScanResult::Error(err) => {
+                match err.path() {
+                    Some(_) => println!("{}", result),
+                    None => println!("{}: {}", path, result),
+                }
+                code = 2;
+            }
+        }
+    }
+
+    ExitCode::from(code)
+}
@@ -0,0 +1,296 @@
+//! `ScanCache` lets `ClamClient` remember the verdicts of payloads it has recently scanned, keyed
+//! by their SHA-256, so that an identical payload - such as an attachment forwarded repeatedly -
+//! is answered without another round trip to ClamD. Requires the `hash` feature.
+//!
+//! Cached verdicts are discarded once ClamD's signature database changes, since a payload which
+//! was clean may be detected by the new signatures.
+
+use crate::response::ClamScanResult;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// `ScanCache` configures the scan result cache, it is supplied to
+/// `ClamClientBuilder::scan_cache`.
+///
+/// At most `capacity` verdicts are held, the oldest being evicted first, and each is held for
+/// no longer than its time to live. ClamD's database version is checked with `VERSION` at most
+/// once per version check interval, and every cached verdict is discarded when it changes, or
+/// when the client issues `RELOAD`. Whilst the version cannot be checked scans bypass the cache,
+/// rather than failing. Only `OK` and `FOUND` verdicts are cached, never errors.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::cache::ScanCache;
+/// use clam_client::client::ClamClient;
+/// use std::time::Duration;
+///
+/// fn main() {
+///     let client = ClamClient::builder("127.0.0.1", 3310)
+///         .scan_cache(ScanCache::new(10_000).ttl(Duration::from_secs(3600)))
+///         .build()
+///         .unwrap();
+///
+///     // Only the first of these is sent to ClamD
+///     for _ in 0..3 {
+///         println!("{:?}", client.scan_bytes(b"a frequently forwarded attachment"));
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanCache {
+    capacity: usize,
+    ttl: Duration,
+    version_check_interval: Duration,
+}
+
+impl ScanCache {
+    /// Creates a new `ScanCache` holding at most `capacity` verdicts, each for up to 10 minutes,
+    /// and checking the database version at most once a minute.
+    pub fn new(capacity: usize) -> ScanCache {
+        ScanCache {
+            capacity: capacity.max(1),
+            ttl: Duration::from_secs(600),
+            version_check_interval: Duration::from_secs(60),
+        }
+    }
+
+    /// Sets how long each verdict is cached for.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets how often ClamD's database version is checked, which bounds how long verdicts from
+    /// an outdated database may still be returned after ClamD reloads without this client
+    /// issuing `RELOAD`.
+    pub fn version_check_interval(mut self, interval: Duration) -> Self {
+        self.version_check_interval = interval;
+        self
+    }
+}
+
+/// The SHA-256 of a payload, which cached verdicts are keyed by.
+pub(crate) type CacheKey = [u8; 32];
+
+/// Returns the SHA-256 of `data`, the key it is cached under.
+pub(crate) fn sha256(data: &[u8]) -> CacheKey {
+    Sha256::digest(data).into()
+}
+
+/// The verdicts held by a client, see `ScanCache`.
+pub(crate) struct Cache {
+    config: ScanCache,
+    state: Mutex<CacheState>,
+}
+
+/// The mutable state of a cache.
+#[derive(Default)]
+struct CacheState {
+    verdicts: HashMap<CacheKey, (ClamScanResult, Instant)>,
+    order: VecDeque<CacheKey>,
+    database: Option<u64>,
+    checked_at: Option<Instant>,
+    /// Bumped whenever every verdict is discarded, so that a verdict from a scan which was in
+    /// flight at the time is not cached afterwards
+    generation: u64,
+}
+
+impl Cache {
+    pub(crate) fn new(config: ScanCache) -> Cache {
+        Cache {
+            config,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Returns true if ClamD's database version has not been checked within the version check
+    /// interval, in which case it should be checked before the cache is used.
+    pub(crate) fn needs_version_check(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .checked_at
+            .is_none_or(|checked_at| checked_at.elapsed() >= self.config.version_check_interval)
+    }
+
+    /// Records the version of ClamD's database, discarding every verdict if it has changed.
+    pub(crate) fn version_checked(&self, database: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.database != Some(database) {
+            state.discard();
+            state.database = Some(database);
+        }
+        state.checked_at = Some(Instant::now());
+    }
+
+    /// Returns the cached verdict for `key`, if one was cached within its time to live.
+    pub(crate) fn get(&self, key: &CacheKey) -> Option<ClamScanResult> {
+        let state = self.state.lock().unwrap();
+        match state.verdicts.get(key) {
+            Some((result, cached_at)) if cached_at.elapsed() < self.config.ttl => {
+                Some(result.clone())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the current generation of the cache, which is taken before scanning a payload and
+    /// passed to `insert` with its verdict.
+    pub(crate) fn generation(&self) -> u64 {
+        self.state.lock().unwrap().generation
+    }
+
+    /// Caches `result` as the verdict for `key`, evicting the oldest verdicts if the cache is
+    /// full. Errors are not cached, nor are verdicts from a scan begun in an earlier `generation`,
+    /// before the cache was last cleared.
+    pub(crate) fn insert(&self, key: CacheKey, result: &ClamScanResult, generation: u64) {
+        if let ClamScanResult::Error(_) = result {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            return;
+        }
+        if state.verdicts.contains_key(&key) {
+            state.order.retain(|k| k != &key);
+        }
+        while state.verdicts.len() >= self.config.capacity {
+            match state.order.pop_front() {
+                Some(oldest) => state.verdicts.remove(&oldest),
+                None => break,
+            };
+        }
+
+        state.verdicts.insert(key, (result.clone(), Instant::now()));
+        state.order.push_back(key);
+    }
+
+    /// Discards every cached verdict, as when ClamD reloads its database.
+    pub(crate) fn clear(&self) {
+        self.state.lock().unwrap().discard();
+    }
+}
+
+impl CacheState {
+    /// Discards every verdict and begins a new generation.
+    fn discard(&mut self) {
+        self.verdicts.clear();
+        self.order.clear();
+        self.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::{sha256, Cache, ScanCache};
+    use crate::client::ClamClient;
+    use crate::response::{ClamScanError, ClamScanResult};
+    use crate::testing::fake_clamd;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_cache_eviction() {
+        let cache = Cache::new(ScanCache::new(2));
+        let found = ClamScanResult::Found("stream".to_owned(), "Sig-One".to_owned());
+
+        cache.insert(sha256(b"a"), &ClamScanResult::Ok, cache.generation());
+        cache.insert(sha256(b"b"), &found, cache.generation());
+        cache.insert(sha256(b"a"), &ClamScanResult::Ok, cache.generation());
+        cache.insert(sha256(b"c"), &ClamScanResult::Ok, cache.generation());
+        cache.insert(
+            sha256(b"d"),
+            &ClamScanResult::Error(ClamScanError::Other("failed".to_owned())),
+            cache.generation(),
+        );
+
+        // `b` was the oldest once `a` was refreshed, and errors are never cached
+        assert_eq!(cache.get(&sha256(b"a")), Some(ClamScanResult::Ok));
+        assert_eq!(cache.get(&sha256(b"b")), None);
+        assert_eq!(cache.get(&sha256(b"c")), Some(ClamScanResult::Ok));
+        assert_eq!(cache.get(&sha256(b"d")), None);
+
+        // A change of database discards everything, whilst re-checking the same one does not
+        cache.version_checked(1);
+        cache.insert(sha256(b"a"), &found, cache.generation());
+        cache.version_checked(1);
+        assert_eq!(cache.get(&sha256(b"a")), Some(found));
+        cache.version_checked(2);
+        assert_eq!(cache.get(&sha256(b"a")), None);
+
+        // A verdict from a scan begun before the cache was cleared is not cached
+        let generation = cache.generation();
+        cache.clear();
+        cache.insert(sha256(b"a"), &ClamScanResult::Ok, generation);
+        assert_eq!(cache.get(&sha256(b"a")), None);
+
+        let cache = Cache::new(ScanCache::new(2).ttl(Duration::ZERO));
+        cache.insert(sha256(b"a"), &ClamScanResult::Ok, cache.generation());
+        assert_eq!(cache.get(&sha256(b"a")), None);
+    }
+
+    #[test]
+    fn test_client_scan_cache() {
+        static STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+        let port = fake_clamd(|cmd| {
+            match cmd {
+                b"zVERSION" => return b"ClamAV 0.100.0/24802/Wed Aug  1 08:43:37 2018".to_vec(),
+                b"zRELOAD" => return b"RELOADING".to_vec(),
+                _ => {}
+            }
+            STREAMS.fetch_add(1, Ordering::SeqCst);
+            if cmd.ends_with(b"bad") {
+                b"stream: Sig-One FOUND".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        let client = ClamClient::builder("127.0.0.1", port)
+            .scan_cache(ScanCache::new(16))
+            .build()
+            .unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(client.scan_bytes(b"good").unwrap(), ClamScanResult::Ok);
+            assert!(matches!(
+                client.scan_bytes(b"bad").unwrap(),
+                ClamScanResult::Found(..)
+            ));
+        }
+        assert_eq!(STREAMS.load(Ordering::SeqCst), 2);
+
+        // `RELOAD` discards the cached verdicts
+        client.reload().unwrap();
+        client.scan_bytes(b"good").unwrap();
+        assert_eq!(STREAMS.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_client_scan_cache_version_check_fails() {
+        static STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+        let port = fake_clamd(|cmd| {
+            if cmd == b"zVERSION" {
+                return b"UNKNOWN COMMAND".to_vec();
+            }
+            STREAMS.fetch_add(1, Ordering::SeqCst);
+            b"stream: OK".to_vec()
+        });
+        let client = ClamClient::builder("127.0.0.1", port)
+            .scan_cache(ScanCache::new(16))
+            .build()
+            .unwrap();
+
+        // Scans still succeed, but bypass the cache whilst the version cannot be checked
+        for _ in 0..2 {
+            assert_eq!(client.scan_bytes(b"good").unwrap(), ClamScanResult::Ok);
+        }
+        assert_eq!(STREAMS.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,277 @@
+//! `CircuitBreaker` stops `ClamClient` from repeatedly waiting on a ClamD instance which is down,
+//! once enough consecutive commands have failed, further commands fail immediately until a
+//! cool-down has passed.
+
+use crate::client::ClamResult;
+use crate::error::ClamError;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// `CircuitBreaker` configures the circuit breaker, it is supplied to
+/// `ClamClientBuilder::circuit_breaker`.
+///
+/// The circuit opens after `failure_threshold` consecutive commands fail to reach ClamD, whilst
+/// open every command fails with `ClamError::CircuitOpen` without connecting. Once the cool-down
+/// has passed the circuit is half-open, and a single command is let through as a probe, closing
+/// the circuit if it succeeds or opening it again if it fails.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::circuit::CircuitBreaker;
+/// use clam_client::client::ClamClient;
+/// use std::time::Duration;
+///
+/// fn main() {
+///     let client = ClamClient::builder("127.0.0.1", 3310)
+///         .circuit_breaker(CircuitBreaker::new(5).cool_down(Duration::from_secs(10)))
+///         .build()
+///         .unwrap();
+///
+///     println!("{:?}", client.version());
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cool_down: Duration,
+}
+
+impl CircuitBreaker {
+    /// Creates a new `CircuitBreaker` which opens after `failure_threshold` consecutive failures,
+    /// with a cool-down of 30 seconds.
+    pub fn new(failure_threshold: u32) -> CircuitBreaker {
+        CircuitBreaker {
+            failure_threshold: failure_threshold.max(1),
+            cool_down: Duration::from_secs(30),
+        }
+    }
+
+    /// Sets how long the circuit stays open before a probe is let through.
+    pub fn cool_down(mut self, cool_down: Duration) -> Self {
+        self.cool_down = cool_down;
+        self
+    }
+}
+
+impl Default for CircuitBreaker {
+    /// A circuit breaker opening after 5 consecutive failures.
+    fn default() -> Self {
+        CircuitBreaker::new(5)
+    }
+}
+
+/// `CircuitState` is the current state of a client's circuit breaker.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Commands are issued as normal.
+    Closed,
+    /// Commands fail immediately, ClamD is considered unavailable.
+    Open,
+    /// The cool-down has passed, the next command is issued as a probe.
+    HalfOpen,
+}
+
+/// The mutable state of a circuit breaker.
+struct CircuitInner {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool,
+}
+
+/// `Circuit` is the circuit breaker owned by a `ClamClient`.
+pub(crate) struct Circuit {
+    config: CircuitBreaker,
+    inner: Mutex<CircuitInner>,
+}
+
+impl Circuit {
+    pub(crate) fn new(config: CircuitBreaker) -> Circuit {
+        Circuit {
+            config,
+            inner: Mutex::new(CircuitInner {
+                consecutive_failures: 0,
+                opened_at: None,
+                probing: false,
+            }),
+        }
+    }
+
+    /// Returns the current state of the circuit.
+    pub(crate) fn state(&self) -> CircuitState {
+        let inner = self.inner.lock().unwrap();
+
+        match inner.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.config.cool_down => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Runs `operation` if the circuit allows it, recording whether it reached ClamD.
+    pub(crate) fn call<T, F>(&self, operation: F) -> ClamResult<T>
+    where
+        F: FnOnce() -> ClamResult<T>,
+    {
+        let _probe = self.admit()?;
+        let result = operation();
+        self.record(&result);
+        result
+    }
+
+    /// Fails with `ClamError::CircuitOpen` unless the circuit allows a command to be issued, for
+    /// commands such as asynchronous scans which cannot be run by `call`. A half-open circuit
+    /// admits a single probe, which lasts until the returned `Probe` is dropped.
+    pub(crate) fn admit(&self) -> ClamResult<Option<Probe<'_>>> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.opened_at {
+            Some(opened_at) if inner.probing || opened_at.elapsed() < self.config.cool_down => {
+                Err(ClamError::CircuitOpen)
+            }
+            Some(_) => {
+                inner.probing = true;
+                Ok(Some(Probe(&self.inner)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Records whether a command reached ClamD, for commands such as a `ScanResults` which go on
+    /// reading from ClamD after the call that connected to it has returned.
+    pub(crate) fn record<T>(&self, result: &ClamResult<T>) {
+        let mut inner = self.inner.lock().unwrap();
+        match result {
+            Err(e) if e.is_connection_error() => {
+                inner.consecutive_failures += 1;
+                if inner.opened_at.is_some()
+                    || inner.consecutive_failures >= self.config.failure_threshold
+                {
+                    inner.opened_at = Some(Instant::now());
+                }
+            }
+            _ => {
+                inner.consecutive_failures = 0;
+                inner.opened_at = None;
+            }
+        }
+    }
+}
+
+/// `Probe` clears the half-open circuit's `probing` flag when the probe ends, including when the
+/// probing command panics, which would otherwise leave the circuit open for good.
+pub(crate) struct Probe<'a>(&'a Mutex<CircuitInner>);
+
+impl Drop for Probe<'_> {
+    fn drop(&mut self) {
+        let mut inner = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        inner.probing = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::circuit::{Circuit, CircuitBreaker, CircuitState};
+    use crate::client::{ClamClient, ClamResult};
+    use crate::error::ClamError;
+    use std::io;
+    use std::panic;
+    use std::thread;
+    use std::time::Duration;
+
+    fn refused() -> ClamResult<()> {
+        Err(ClamError::ConnectionError(io::Error::from(
+            io::ErrorKind::ConnectionRefused,
+        )))
+    }
+
+    #[test]
+    fn test_circuit_opens_and_recovers() {
+        let circuit = Circuit::new(CircuitBreaker::new(2).cool_down(Duration::from_millis(50)));
+
+        assert!(circuit.call(refused).is_err());
+        assert_eq!(circuit.state(), CircuitState::Closed);
+        assert!(circuit.call(refused).is_err());
+        assert_eq!(circuit.state(), CircuitState::Open);
+
+        let mut called = false;
+        let result = circuit.call(|| {
+            called = true;
+            Ok(())
+        });
+        assert!(matches!(result, Err(ClamError::CircuitOpen)));
+        assert!(!called);
+
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(circuit.state(), CircuitState::HalfOpen);
+        assert!(circuit.call(|| Ok(())).is_ok());
+        assert_eq!(circuit.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_circuit_failed_probe_reopens() {
+        let circuit = Circuit::new(CircuitBreaker::new(1).cool_down(Duration::from_millis(50)));
+
+        assert!(circuit.call(refused).is_err());
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(circuit.state(), CircuitState::HalfOpen);
+        assert!(circuit.call(refused).is_err());
+        assert_eq!(circuit.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn test_circuit_panicked_probe_allows_another() {
+        let circuit = Circuit::new(CircuitBreaker::new(1).cool_down(Duration::from_millis(50)));
+
+        assert!(circuit.call(refused).is_err());
+        thread::sleep(Duration::from_millis(60));
+        let panicked = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            circuit.call::<(), _>(|| panic!("probe panicked"))
+        }));
+        assert!(panicked.is_err());
+
+        assert_eq!(circuit.state(), CircuitState::HalfOpen);
+        assert!(circuit.call(|| Ok(())).is_ok());
+        assert_eq!(circuit.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_circuit_ignores_invalid_data() {
+        let circuit = Circuit::new(CircuitBreaker::new(1));
+
+        let result: ClamResult<()> = circuit.call(|| Err(ClamError::InvalidData("x".to_owned())));
+        assert!(result.is_err());
+        assert_eq!(circuit.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_client_circuit_breaker() {
+        let client = ClamClient::builder("127.0.0.1", 1)
+            .circuit_breaker(CircuitBreaker::new(1))
+            .build()
+            .unwrap();
+
+        assert!(matches!(
+            client.scan_bytes(b"data"),
+            Err(ClamError::ConnectionError(_))
+        ));
+        assert_eq!(client.circuit_state(), Some(CircuitState::Open));
+        assert!(matches!(
+            client.scan_bytes(b"data"),
+            Err(ClamError::CircuitOpen)
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_circuit_state_serde() {
+        let json = serde_json::to_string(&CircuitState::HalfOpen).unwrap();
+        assert_eq!(json, "\"HalfOpen\"");
+        assert_eq!(
+            serde_json::from_str::<CircuitState>(&json).unwrap(),
+            CircuitState::HalfOpen
+        );
+    }
+}
@@ -1,27 +1,686 @@
 //! `ClamClient` provides the bridge between the Rust code and the ClamD socket, and implements
 //! most Clam commands in a Rust idiomatic interface.
 
-use crate::error::ClamError;
-use crate::response::{ClamScanResult, ClamStats, ClamVersion};
+#[cfg(feature = "hash")]
+use crate::cache::{self, Cache, ScanCache};
+use crate::circuit::{Circuit, CircuitBreaker, CircuitState};
+use crate::command::{ClamCommand, ClamResponse, Command};
+use crate::conf::{self, ClamdConf};
+use crate::diagnostics::{Check, DiagnosticsReport, Failure, LikelyCause};
+use crate::error::{ClamError, Phase};
+use crate::hash::{ContentHasher, HashingReader};
+use crate::health::{EndpointStatus, HealthMonitor};
+use crate::instrument;
+use crate::latency::{ClientMetrics, LatencyRecorder};
+use crate::observer::{ClamObserver, Observers};
+#[cfg(feature = "hash")]
+use crate::policy::{HashPolicy, PolicyVerdict};
+use crate::protocol::{self, ReplyLength};
+use crate::proxy::Proxy;
+use crate::redact::{Redact, Redaction};
+use crate::report::{ScanOutcome, ScanReport};
+use crate::response::{
+    ClamScanResult, ClamStats, ClamVersion, DatabaseInfo, ReloadStatus, ScanLine, SelfTestResult,
+    ServerInfo, EICAR,
+};
+use crate::retry::RetryPolicy;
+use crate::session::{ClamSession, SessionOptions, DEFAULT_IDLE_TIMEOUT};
+#[cfg(feature = "hash")]
+use crate::singleflight::InFlight;
+use crate::stream::{ClamScanWriter, ScanningReader, StreamTarget};
+#[cfg(feature = "tls")]
+use crate::tls::{TlsConfig, TlsConnector};
+use crate::transport::{self, Connection, SocketOptions};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
 use std::io::{BufRead, BufReader, Read, Write};
-use std::net::IpAddr;
-use std::net::SocketAddr;
-use std::net::TcpStream;
-use std::time::Duration;
+use std::net::{IpAddr, TcpStream};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::ops::ControlFlow;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "hyper")]
+mod async_body;
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+mod async_instream;
+#[cfg(feature = "async-io")]
+mod async_io;
+#[cfg(feature = "tokio")]
+mod async_tokio;
+
+#[cfg(any(feature = "hyper", feature = "axum"))]
+pub(crate) use async_tokio::Tokio;
+
+/// How long `ClamClient::server_info` is cached for, unless set with
+/// `ClamClientBuilder::server_info_ttl`.
+const DEFAULT_SERVER_INFO_TTL: Duration = Duration::from_secs(60);
+
+/// The maximum number of scans `ClamClient::scan_paths` pipelines on its session at once.
+const PIPELINE_DEPTH: usize = 32;
 
 /// `ClamResult` is a simple wrapper used for all operations, this makes it simple to handle
 /// from the callers side.
 pub type ClamResult<T> = Result<T, ClamError>;
 
 /// `ClamClient` is the crux of the crate, it retains information about what socket to connect
-/// to, thus that it can reconnect, and what timeout (if any) to use when connecting.
+/// to, thus that it can reconnect, and what timeouts (if any) to use when connecting, reading
+/// and writing.
+///
+/// *Note:* `timeout` only applies when connecting, use `ClamClientBuilder::read_timeout` and
+/// `ClamClientBuilder::write_timeout` to bound operations on an established connection.
+///
+/// `ClamClient` is `Send + Sync`, and every method takes `&self`, so a single client can be
+/// shared between threads, e.g. between a web server's workers, behind an `Arc`. Each command
+/// opens its own connection, or is serialised on the persistent connection if one is enabled,
+/// so concurrent commands do not interfere with each other.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
 ///
-/// *Note:* Future versions may move `timeout` to be use in command operations as well as
-/// when connecting. However since the latter is so variable, this may require a different - or even
-/// per call - timeout value.
+/// use clam_client::client::ClamClient;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// fn main() {
+///     let client = Arc::new(ClamClient::new("127.0.0.1", 3310).unwrap());
+///
+///     let workers: Vec<_> = (0..4)
+///         .map(|_| {
+///             let client = Arc::clone(&client);
+///             thread::spawn(move || client.ping())
+///         })
+///         .collect();
+///
+///     for worker in workers {
+///         println!("{:?}", worker.join().unwrap());
+///     }
+/// }
+/// ```
 pub struct ClamClient {
     socket: SocketAddr,
+    fallbacks: Vec<SocketAddr>,
+    addresses: HashMap<SocketAddr, Vec<SocketAddr>>,
+    timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    stream_max_length: Option<u64>,
+    lenient_stats: bool,
+    strict_parsing: bool,
+    redaction: Redaction,
+    delimiter: CommandDelimiter,
+    retry_policy: Option<RetryPolicy>,
+    circuit: Option<Circuit>,
+    proxy: Option<Proxy>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConnector>,
+    #[cfg(unix)]
+    unix_socket: Option<PathBuf>,
+    socket_options: SocketOptions,
+    nodelay: bool,
+    tcp_keepalive: Option<Duration>,
+    observers: Observers,
+    latency: Option<LatencyRecorder>,
+    persistent: Option<Mutex<Option<(ClamSession, Instant)>>>,
+    /// Whether the client may open new connections; false for clients created from an existing
+    /// stream, since they cannot reconnect.
+    reconnect: bool,
+    server_info_ttl: Duration,
+    server_info: Mutex<Option<(ServerInfo, Instant)>>,
+    health: Option<HealthMonitor>,
+    #[cfg(feature = "hash")]
+    cache: Option<Cache>,
+    #[cfg(feature = "hash")]
+    in_flight: Option<InFlight>,
+}
+
+/// `CommandDelimiter` selects which of ClamD's two command formats is used, see
+/// `ClamClientBuilder::command_delimiter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandDelimiter {
+    /// Commands are prefixed with `z` and terminated with NUL, as are ClamD's replies.
+    Null,
+    /// Commands are prefixed with `n` and terminated with a newline, as are ClamD's replies.
+    Newline,
+}
+
+impl CommandDelimiter {
+    /// Frames `command`, such as `SCAN /some/file`, for sending to ClamD.
+    pub(crate) fn command(self, command: &str) -> Vec<u8> {
+        match self {
+            CommandDelimiter::Null => format!("z{}\0", command).into_bytes(),
+            CommandDelimiter::Newline => format!("n{}\n", command).into_bytes(),
+        }
+    }
+
+    /// Returns the byte terminating each reply from ClamD.
+    pub(crate) fn terminator(self) -> u8 {
+        match self {
+            CommandDelimiter::Null => b'\0',
+            CommandDelimiter::Newline => b'\n',
+        }
+    }
+
+    /// Converts a reply into the NUL terminated form expected by the parsers in
+    /// `clam_client::response`. If `multi_line` is set every line is a separate reply, otherwise
+    /// only the final newline terminates the reply, as for `STATS`.
+    pub(crate) fn normalise(self, reply: String, multi_line: bool) -> String {
+        match self {
+            CommandDelimiter::Null => reply,
+            CommandDelimiter::Newline if multi_line => reply.replace('\n', "\0"),
+            CommandDelimiter::Newline => match reply.strip_suffix('\n') {
+                Some(reply) => format!("{}\0", reply),
+                None => reply,
+            },
+        }
+    }
+}
+
+/// `ClamClientBuilder` provides finer grained control over how a `ClamClient` is configured than
+/// `ClamClient::new` and `ClamClient::new_with_timeout`, it is obtained via `ClamClient::builder`.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use std::time::Duration;
+///
+/// fn main() {
+///     let client = ClamClient::builder("127.0.0.1", 3310)
+///         .connect_timeout(Duration::from_secs(5))
+///         .read_timeout(Duration::from_secs(60))
+///         .write_timeout(Duration::from_secs(30))
+///         .build()
+///         .unwrap();
+///
+///     println!("{:?}", client.ping());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ClamClientBuilder {
+    ip: String,
+    port: u16,
+    fallbacks: Vec<(String, u16)>,
     timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    stream_max_length: Option<u64>,
+    lenient_stats: bool,
+    strict_parsing: bool,
+    redaction: Redaction,
+    delimiter: CommandDelimiter,
+    retry_policy: Option<RetryPolicy>,
+    circuit_breaker: Option<CircuitBreaker>,
+    proxy: Option<Proxy>,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsConfig>,
+    #[cfg(unix)]
+    unix_socket: Option<PathBuf>,
+    local_address: Option<IpAddr>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    nodelay: bool,
+    tcp_keepalive: Option<Duration>,
+    observers: Observers,
+    collect_latency: bool,
+    persistent: bool,
+    health_check_interval: Option<Duration>,
+    server_info_ttl: Duration,
+    #[cfg(feature = "hash")]
+    scan_cache: Option<ScanCache>,
+    #[cfg(feature = "hash")]
+    coalesce_scans: bool,
+}
+
+impl ClamClientBuilder {
+    /// Adds a fallback ClamD endpoint, used when connecting to or issuing a command on the
+    /// preceding endpoints fails. Endpoints are always tried in the order they were given,
+    /// starting with the address supplied to `ClamClient::builder`.
+    ///
+    /// *Note*: An `INSTREAM` only falls back whilst connecting, since the stream cannot be
+    /// replayed once it has started being sent.
+    pub fn fallback(mut self, ip: &str, port: u16) -> Self {
+        self.fallbacks.push((ip.to_owned(), port));
+        self
+    }
+
+    /// Sets the maximum amount of time to wait when establishing a connection to ClamD. A zero
+    /// `timeout` waits indefinitely, as if none had been set.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout).filter(|t| !t.is_zero());
+        self
+    }
+
+    /// Sets the maximum amount of time to block whilst waiting for ClamD to respond on an
+    /// established connection, for example during a long running `SCAN`. A zero `timeout`
+    /// blocks indefinitely, as if none had been set.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout).filter(|t| !t.is_zero());
+        self
+    }
+
+    /// Sets the maximum amount of time to block whilst writing to an established connection,
+    /// for example when ClamD stops accepting data part way through an `INSTREAM`. A zero
+    /// `timeout` blocks indefinitely, as if none had been set.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout).filter(|t| !t.is_zero());
+        self
+    }
+
+    /// Sets the maximum number of bytes that may be sent in a single `INSTREAM`, this should match
+    /// the `StreamMaxLength` configured on ClamD. ClamD does not expose this value, so it cannot
+    /// be probed, but when set, streams which exceed it fail fast with `ClamError::StreamTooLarge`
+    /// rather than ClamD forcibly closing the connection.
+    pub fn stream_max_length(mut self, bytes: u64) -> Self {
+        self.stream_max_length = Some(bytes);
+        self
+    }
+
+    /// When enabled, `stats` parses the `STATS` response with `ClamStats::parse_lenient` rather
+    /// than `ClamStats::parse`, so that fields added or reordered by newer versions of ClamD do
+    /// not cause the whole response to be rejected.
+    pub fn lenient_stats(mut self, lenient: bool) -> Self {
+        self.lenient_stats = lenient;
+        self
+    }
+
+    /// When enabled, any reply ClamD is not known to send, such as `UNKNOWN COMMAND`, fails with
+    /// `ClamError::ProtocolError`, rather than being returned as a `ClamScanResult::Error` as
+    /// though ClamD had reported an error scanning. See `ClamScanResult::parse_strict`.
+    pub fn strict_parsing(mut self, strict: bool) -> Self {
+        self.strict_parsing = strict;
+        self
+    }
+
+    /// Sets how file paths and fragments of ClamD's replies are embedded in errors, including
+    /// those reported as `ClamScanResult::Error`, see `Redaction`. Defaults to `Redaction::Off`.
+    pub fn redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = redaction;
+        self
+    }
+
+    /// Sets the format commands are issued in, by default commands are `z` prefixed and NUL
+    /// terminated. `CommandDelimiter::Newline` may be needed for proxies or older daemons which
+    /// handle the newline terminated form better.
+    ///
+    /// *Note*: Sessions, including the persistent connection, always use the NUL terminated form
+    /// since multi-line replies cannot be delimited within a session.
+    pub fn command_delimiter(mut self, delimiter: CommandDelimiter) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Sets the `RetryPolicy` used to retry commands which fail with transient errors. Complete
+    /// commands are retried, except for `INSTREAM` where only establishing the connection is
+    /// retried, since the stream cannot be replayed once it has been read. Disabled by default.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Wraps commands in a `CircuitBreaker`, so that once ClamD has repeatedly failed to respond
+    /// further commands fail immediately with `ClamError::CircuitOpen` rather than waiting on
+    /// the connect timeout. Failures are counted after any retries. Disabled by default.
+    pub fn circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(breaker);
+        self
+    }
+
+    /// Tunnels connections to ClamD through a SOCKS5 or HTTP `CONNECT` proxy. The connect timeout
+    /// applies to connecting to the proxy itself.
+    ///
+    /// *Note*: `ClamClient::scan_stream_async` does not support proxies, and will return
+    /// `ClamError::ProxyError` when a proxy is configured.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Connects to ClamD over TLS using the given `TlsConfig`, for use when ClamD is exposed
+    /// behind a TLS terminating proxy such as stunnel. Requires the `tls` feature.
+    ///
+    /// *Note*: `ClamClient::scan_stream_async` does not support TLS, and will return
+    /// `ClamError::TlsError` when TLS is configured.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, config: TlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Connects to ClamD over the Unix domain socket at `path`, as configured by `LocalSocket` in
+    /// `clamd.conf`, rather than over TCP. The address given to `ClamClient::builder`, and any
+    /// fallbacks, proxy or TLS configuration, are then unused.
+    ///
+    /// On Linux a path beginning with `@`, such as `@clamd`, names a socket in the abstract
+    /// namespace rather than on the filesystem, e.g. where ClamD is exposed through `socat`.
+    ///
+    /// *Note*: The asynchronous scans do not support Unix sockets, and will return
+    /// `ClamError::ConnectionError` when one is configured.
+    #[cfg(unix)]
+    pub fn unix_socket<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.unix_socket = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Binds TCP connections to ClamD, or to the proxy, to the given local address, for hosts with
+    /// several interfaces where ClamD only accepts connections from one of their networks. Only
+    /// addresses of the same family as `address` can then be connected to.
+    pub fn local_address(mut self, address: IpAddr) -> Self {
+        self.local_address = Some(address);
+        self
+    }
+
+    /// Sets the size of the send buffer, `SO_SNDBUF`, of TCP connections to ClamD. A larger buffer
+    /// than the platform's default improves the throughput of `INSTREAM` across high latency
+    /// links, where the amount of data in flight is otherwise limited by the buffer. The platform
+    /// may round or cap the size, Linux for example doubles it and caps it at `wmem_max`.
+    pub fn send_buffer_size(mut self, bytes: usize) -> Self {
+        self.send_buffer_size = Some(bytes);
+        self
+    }
+
+    /// Sets the size of the receive buffer, `SO_RCVBUF`, of TCP connections to ClamD, see
+    /// `ClamClientBuilder::send_buffer_size`. It is set before connecting, so that a large enough
+    /// TCP window scale is negotiated.
+    pub fn recv_buffer_size(mut self, bytes: usize) -> Self {
+        self.recv_buffer_size = Some(bytes);
+        self
+    }
+
+    /// Disables Nagle's algorithm on TCP connections to ClamD, so that small writes such as the
+    /// length prefix of each `INSTREAM` chunk are sent immediately rather than being delayed
+    /// waiting for an acknowledgement. Disabled by default.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Enables TCP keepalive on connections to ClamD, sending probes once a connection has been
+    /// idle for `interval` and then every `interval`, where the platform allows it to be set.
+    /// This stops firewalls dropping the idle connections held by `ClamPool` and
+    /// `ClamClientBuilder::persistent_connection`. Disabled by default.
+    pub fn tcp_keepalive(mut self, interval: Duration) -> Self {
+        self.tcp_keepalive = Some(interval);
+        self
+    }
+
+    /// Registers a `ClamObserver` to be notified of connections, commands and results on the
+    /// client, and on the sessions and pools created from it. Several observers may be
+    /// registered, and are notified in the order they were registered.
+    pub fn observer<O: ClamObserver + 'static>(mut self, observer: O) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    /// When enabled, the client records the number and latency of each `PING`, `SCAN`,
+    /// `CONTSCAN`, `ALLMATCHSCAN`, `MULTISCAN`, `INSTREAM` and `STATS` it issues, which can be
+    /// retrieved with `ClamClient::client_metrics`. Disabled by default.
+    pub fn collect_latency(mut self, collect: bool) -> Self {
+        self.collect_latency = collect;
+        self
+    }
+
+    /// When enabled, the client holds a single `IDSESSION` connection open and reuses it for
+    /// `PING`, `VERSION`, `SCAN`, `INSTREAM` and `STATS` rather than reconnecting for every
+    /// command. If the connection dies it is transparently re-established.
+    ///
+    /// Commands issued through the persistent connection are serialised, so this is best suited
+    /// to low volume but latency sensitive callers, busier callers should use `ClamPool`.
+    pub fn persistent_connection(mut self, persistent: bool) -> Self {
+        self.persistent = persistent;
+        self
+    }
+
+    /// Caches the verdicts of `scan_bytes`, keyed by the SHA-256 of the payload, so that an
+    /// identical payload scanned again is answered without a round trip to ClamD until the
+    /// signature database changes. Disabled by default, see `ScanCache`. Requires the `hash`
+    /// feature.
+    #[cfg(feature = "hash")]
+    pub fn scan_cache(mut self, cache: ScanCache) -> Self {
+        self.scan_cache = Some(cache);
+        self
+    }
+
+    /// When enabled, concurrent `scan_bytes` calls with identical payloads, identified by their
+    /// SHA-256, are coalesced into a single `INSTREAM` whose verdict is shared with every caller,
+    /// so that bursts of the same payload do not multiply the load on ClamD. Disabled by default.
+    /// Requires the `hash` feature.
+    #[cfg(feature = "hash")]
+    pub fn coalesce_scans(mut self, coalesce: bool) -> Self {
+        self.coalesce_scans = coalesce;
+        self
+    }
+
+    /// Starts a background thread which issues `PING` to every endpoint each `interval`, the
+    /// results of which are available through `ClamClient::is_healthy` and `ClamClient::health`.
+    /// The thread exits when the client is dropped.
+    pub fn health_check_interval(mut self, interval: Duration) -> Self {
+        self.health_check_interval = Some(interval);
+        self
+    }
+
+    /// Sets how long the `ServerInfo` returned by `ClamClient::server_info` is cached before
+    /// ClamD is asked for it again. Defaults to 60 seconds.
+    pub fn server_info_ttl(mut self, ttl: Duration) -> Self {
+        self.server_info_ttl = ttl;
+        self
+    }
+
+    /// Consumes the builder and creates the configured `ClamClient`, this will fail if any of the
+    /// supplied hosts cannot be resolved, or if the TLS configuration is incomplete. Hostnames are
+    /// resolved once, when the client is built. Where a hostname resolves to several addresses
+    /// they are raced when connecting, as described by RFC 8305, and the first to accept the
+    /// connection is used.
+    pub fn build(self) -> ClamResult<ClamClient> {
+        let mut addresses = HashMap::new();
+        let mut resolve_endpoint = |host: &str, port: u16| -> ClamResult<SocketAddr> {
+            let resolved = resolve(host, port)?;
+            let endpoint = resolved[0];
+            if resolved.len() > 1 {
+                addresses.insert(endpoint, resolved);
+            }
+            Ok(endpoint)
+        };
+
+        let socket = resolve_endpoint(&self.ip, self.port)?;
+        let mut fallbacks = Vec::new();
+        for (ip, port) in &self.fallbacks {
+            fallbacks.push(resolve_endpoint(ip, *port)?);
+        }
+
+        let health = match self.health_check_interval {
+            Some(interval) => {
+                let probe = ClamClientBuilder {
+                    observers: Observers::default(),
+                    collect_latency: false,
+                    persistent: false,
+                    retry_policy: None,
+                    circuit_breaker: None,
+                    health_check_interval: None,
+                    #[cfg(feature = "hash")]
+                    scan_cache: None,
+                    #[cfg(feature = "hash")]
+                    coalesce_scans: false,
+                    ..self.clone()
+                }
+                .build()?;
+                Some(HealthMonitor::start(probe, interval))
+            }
+            None => None,
+        };
+
+        Ok(ClamClient {
+            socket,
+            fallbacks,
+            addresses,
+            timeout: self.timeout,
+            read_timeout: self.read_timeout,
+            write_timeout: self.write_timeout,
+            stream_max_length: self.stream_max_length,
+            lenient_stats: self.lenient_stats,
+            strict_parsing: self.strict_parsing,
+            redaction: self.redaction,
+            delimiter: self.delimiter,
+            retry_policy: self.retry_policy,
+            circuit: self.circuit_breaker.map(Circuit::new),
+            proxy: self.proxy,
+            #[cfg(feature = "tls")]
+            tls: match self.tls {
+                Some(config) => Some(config.connector()?),
+                None => None,
+            },
+            #[cfg(unix)]
+            unix_socket: self.unix_socket,
+            socket_options: SocketOptions {
+                local_address: self.local_address,
+                send_buffer_size: self.send_buffer_size,
+                recv_buffer_size: self.recv_buffer_size,
+            },
+            nodelay: self.nodelay,
+            tcp_keepalive: self.tcp_keepalive,
+            observers: self.observers,
+            latency: if self.collect_latency {
+                Some(LatencyRecorder::default())
+            } else {
+                None
+            },
+            persistent: if self.persistent {
+                Some(Mutex::new(None))
+            } else {
+                None
+            },
+            reconnect: true,
+            server_info_ttl: self.server_info_ttl,
+            server_info: Mutex::new(None),
+            health,
+            #[cfg(feature = "hash")]
+            cache: self.scan_cache.map(Cache::new),
+            #[cfg(feature = "hash")]
+            in_flight: if self.coalesce_scans {
+                Some(InFlight::default())
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// `ScanResults` yields the results of a `SCAN` or `CONTSCAN` one at a time as ClamD reports
+/// them, it is obtained via `ClamClient::scan_path_iter`. Iteration ends when ClamD closes the
+/// connection, or after the first error reading from it.
+///
+/// The scan is instrumented and reported to the client's observers as each result arrives, and a
+/// failure reading from ClamD counts against the client's circuit breaker.
+pub struct ScanResults<'a> {
+    client: &'a ClamClient,
+    reader: BufReader<Connection>,
+    endpoint: String,
+    /// The instrumented command, until ClamD has reported every result or failed
+    command: Option<instrument::Command>,
+    /// The most significant result so far, which classifies the scan once it finishes
+    significant: Option<ClamScanResult>,
+}
+
+impl ScanResults<'_> {
+    /// Reads the next result from ClamD, or `None` once it has closed the connection.
+    fn read_result(&mut self, command: &'static str) -> Option<ClamResult<ClamScanResult>> {
+        let terminator = self.client.delimiter.terminator();
+        loop {
+            let mut line = Vec::new();
+            match self.reader.read_until(terminator, &mut line) {
+                Ok(0) => return None,
+                Ok(_) => {
+                    if line.last() == Some(&terminator) {
+                        line.pop();
+                    }
+                    let line = String::from_utf8_lossy(&line);
+                    if line == COMMAND_READ_TIMED_OUT_REPLY {
+                        return Some(Err(ClamError::CommandReadTimedOut));
+                    }
+                    match parse_results(&line, self.client.strict_parsing)
+                        .redact(self.client.redaction)
+                    {
+                        Ok(mut results) => {
+                            if let Some(result) = results.pop() {
+                                return Some(Ok(result));
+                            }
+                        }
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                Err(e) => {
+                    let e = ClamError::CommandError(e).in_phase(Phase::Receiving, &self.endpoint);
+                    let e = Err(e.for_command(command));
+                    if let Some(circuit) = &self.client.circuit {
+                        circuit.record(&e);
+                    }
+                    return Some(e);
+                }
+            }
+        }
+    }
+
+    /// Records the scan's duration and classification once it has finished.
+    fn finish(&mut self, command: instrument::Command) {
+        let significant = self.significant.as_slice();
+        self.client
+            .finish_command(command, &Ok(()), |_| instrument::classify_scan(significant));
+    }
+}
+
+impl Iterator for ScanResults<'_> {
+    type Item = ClamResult<ClamScanResult>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let command = self.command.take()?;
+        let name = command.name();
+        let next = command.in_scope(|| self.read_result(name));
+        let observers = self.client.observers();
+
+        match next {
+            Some(Ok(result)) => {
+                if matches!(result, ClamScanResult::Found(..))
+                    || (matches!(result, ClamScanResult::Error(..)) && self.significant.is_none())
+                {
+                    self.significant = Some(result.clone());
+                }
+                let result = Ok(result);
+                observers.finished(name, &result, std::slice::from_ref);
+                self.command = Some(command);
+                Some(result)
+            }
+            Some(Err(e)) => {
+                let result = Err(e);
+                self.client.finish_command(command, &result, |_| "");
+                observers.finished(name, &result, std::slice::from_ref);
+                Some(result)
+            }
+            None => {
+                self.finish(command);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for ScanResults<'_> {
+    /// Records a scan abandoned before ClamD reported every result, such as one aborted by
+    /// `ClamClient::scan_path_streaming`, classified by the results seen so far.
+    fn drop(&mut self) {
+        if let Some(command) = self.command.take() {
+            self.finish(command);
+        }
+    }
 }
 
 impl ClamClient {
@@ -30,7 +689,7 @@ impl ClamClient {
     ///
     /// *Arguments*
     ///
-    /// - `ip`: The IP address to connect to
+    /// - `ip`: The IP address or hostname to connect to
     /// - `port`: The port to connect to
     ///
     /// *Example*
@@ -47,7 +706,7 @@ impl ClamClient {
     /// }
     /// ```
     pub fn new(ip: &str, port: u16) -> ClamResult<ClamClient> {
-        build(ip, port, None)
+        ClamClient::builder(ip, port).build()
     }
 
     /// Creates a new instance of `ClamClient` with a connection timeout (in seconds). Any command
@@ -55,7 +714,7 @@ impl ClamClient {
     ///
     /// *Arguments*
     ///
-    /// - `ip`: The IP address to connect to
+    /// - `ip`: The IP address or hostname to connect to
     /// - `port`: The port to connect to
     /// - `timeout_secs`: The number of seconds to wait before aborting the connection
     ///
@@ -73,38 +732,65 @@ impl ClamClient {
     /// }
     /// ```
     pub fn new_with_timeout(ip: &str, port: u16, timeout_secs: u64) -> ClamResult<ClamClient> {
-        build(ip, port, Some(Duration::from_secs(timeout_secs)))
+        ClamClient::builder(ip, port)
+            .connect_timeout(Duration::from_secs(timeout_secs))
+            .build()
     }
 
-    /// Implements the ClamD `PING` command, returns true if ClamD responds with `PONG`, or false if
-    /// there was an error, or ClamD did not respond with `PONG`.
-    pub fn ping(&self) -> bool {
-        match self.send_command(b"zPING\0") {
-            Ok(resp) => resp == "PONG",
-            Err(_) => false,
+    /// Creates a new `ClamClientBuilder` for the given address, allowing connect, read and write
+    /// timeouts to be configured independently. See `ClamClientBuilder` for an example.
+    ///
+    /// *Arguments*
+    ///
+    /// - `ip`: The IP address or hostname to connect to
+    /// - `port`: The port to connect to
+    pub fn builder(ip: &str, port: u16) -> ClamClientBuilder {
+        ClamClientBuilder {
+            ip: ip.to_owned(),
+            port,
+            fallbacks: Vec::new(),
+            timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            stream_max_length: None,
+            lenient_stats: false,
+            strict_parsing: false,
+            redaction: Redaction::Off,
+            delimiter: CommandDelimiter::Null,
+            retry_policy: None,
+            circuit_breaker: None,
+            proxy: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(unix)]
+            unix_socket: None,
+            local_address: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            nodelay: false,
+            tcp_keepalive: None,
+            observers: Observers::default(),
+            collect_latency: false,
+            persistent: false,
+            health_check_interval: None,
+            server_info_ttl: DEFAULT_SERVER_INFO_TTL,
+            #[cfg(feature = "hash")]
+            scan_cache: None,
+            #[cfg(feature = "hash")]
+            coalesce_scans: false,
         }
     }
 
-    /// Implements the ClamD `VERSION` command, returns a struct of `ClamVersion` if successful,
-    /// or an error if processing the response failed, or if there was an issue talking to ClamD.
-    pub fn version(&self) -> ClamResult<ClamVersion> {
-        let resp = self.send_command(b"zVERSION\0")?;
-        ClamVersion::parse(resp)
-    }
-
-    /// Implements the ClamD `RELOAD` command, returns the state of the request as a `String` from
-    /// ClamD, or a network error if the command failed.
-    pub fn reload(&self) -> ClamResult<String> {
-        self.send_command(b"zRELOAD\0")
-    }
-
-    /// Implements the ClamD `SCAN` and `CONTSCAN` commands, returns a `Vec<ClamScanResult>` if the command
-    /// was successful, or a network error if the command failed.
+    /// Creates a `ClamClient` configured from a `clamd.conf`, so that the socket and stream limit
+    /// are taken from the file ClamD itself is configured with rather than being duplicated.
     ///
-    /// *Arguments:*
+    /// `LocalSocket` is preferred if it is set, as it is by `clamdscan`, otherwise the client
+    /// connects to `TCPSocket` on the first `TCPAddr`, or on `127.0.0.1` if ClamD listens on every
+    /// address. `StreamMaxLength` is applied with `ClamClientBuilder::stream_max_length`.
     ///
-    /// - `path`: The path to scan, this is a path that is on the ClamD server, or that it has access to.
-    /// - `continue_on_virus`: If true, instructs ClamD to continue scanning even after it detects a virus.
+    /// *Arguments*
+    ///
+    /// - `path`: The path to the `clamd.conf`, typically `/etc/clamav/clamd.conf`
     ///
     /// *Example*
     ///
@@ -112,54 +798,34 @@ impl ClamClient {
     /// extern crate clam_client;
     ///
     /// use clam_client::client::ClamClient;
-    /// use clam_client::response::ClamScanResult;
     ///
     /// fn main() {
-    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
-    ///
-    ///     if let Ok(scan_results) = client.scan_path("/tmp/", true){
-    ///         for result in scan_results.iter() {
-    ///             match result {
-    ///                 ClamScanResult::Found(location, virus) => {
-    ///                     println!("Found virus: '{}' in {}", virus, location)
-    ///                 },
-    ///                 _ => {},
-    ///             }
-    ///         }
+    ///     if let Ok(client) = ClamClient::from_clamd_conf("/etc/clamav/clamd.conf") {
+    ///         println!("{:?}", client.version());
     ///     }
     /// }
     /// ```
-    pub fn scan_path(
-        &self,
-        path: &str,
-        continue_on_virus: bool,
-    ) -> ClamResult<Vec<ClamScanResult>> {
-        let result = if continue_on_virus {
-            self.send_command(&format!("zCONTSCAN {}\0", path).into_bytes())?
-        } else {
-            self.send_command(&format!("zSCAN {}\0", path).into_bytes())?
-        };
-
-        Ok(ClamScanResult::parse(result))
-    }
+    pub fn from_clamd_conf<P: AsRef<Path>>(path: P) -> ClamResult<ClamClient> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ClamError::ConfigError(format!("{}: {}", path.display(), e)))?;
 
-    /// Implements the ClamD `MULTISCAN` command which allows the ClamD instance to perform
-    /// multi-threaded scanning. Returns a `Vec<ClamScanResult>` if the command was successful,
-    /// or a network error if the command failed.
-    pub fn multiscan_path(&self, path: &str) -> ClamResult<Vec<ClamScanResult>> {
-        let result = self.send_command(&format!("zSCAN {}\0", path).into_bytes())?;
-        Ok(ClamScanResult::parse(result))
+        ClamdConf::parse(&contents)?.builder()?.build()
     }
 
-    /// Implements the ClamD `INSTREAM` command, which allows the caller to stream a file to the ClamD
-    /// instance. Returns a `ClamScanResult` if the command was successful.
+    /// Creates a `ClamClient` configured from environment variables, so that it can be configured
+    /// per deployment without code changes. Every variable is optional:
     ///
-    /// *Arguments*:
+    /// - `CLAMD_HOST`: The IP address or hostname to connect to, by default `127.0.0.1`
+    /// - `CLAMD_PORT`: The port to connect to, by default `3310`
+    /// - `CLAMD_SOCKET`: A Unix socket to connect to instead, see `ClamClientBuilder::unix_socket`
+    /// - `CLAMD_TIMEOUT_SECS`: The connect timeout in seconds, 0 for none
+    /// - `CLAMD_READ_TIMEOUT_SECS`: The read timeout in seconds, 0 for none
+    /// - `CLAMD_WRITE_TIMEOUT_SECS`: The write timeout in seconds, 0 for none
+    /// - `CLAMD_STREAM_MAX_LENGTH`: The maximum `INSTREAM` length, in bytes or with a `K`, `M` or
+    ///   `G` suffix as in `clamd.conf`
     ///
-    /// - `stream`: The object to be scanned, this must implement `Read`, it will be read into a buffer
-    /// of 4096 bytes and then written to the ClamD instance. This object must not exceed the ClamD
-    /// max stream size, else the socket will be forcibly closed - in which case an error will be returned
-    /// from this function.
+    /// Returns `ClamError::ConfigError` if any variable is set to an invalid value.
     ///
     /// *Example*
     ///
@@ -167,159 +833,3058 @@ impl ClamClient {
     /// extern crate clam_client;
     ///
     /// use clam_client::client::ClamClient;
-    /// use clam_client::response::ClamScanResult;
-    /// use std::fs::File;
     ///
     /// fn main() {
-    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
-    ///     let file = File::open("/etc/hosts").unwrap();
-    ///
-    ///     match client.scan_stream(file) {
-    ///         Ok(result) => match result {
-    ///             ClamScanResult::Ok => println!("File /etc/hostname is OK!"),
-    ///             ClamScanResult::Found(location, virus) => {
-    ///                 println!("Found virus: '{}' in {}", virus, location)
-    ///             },
-    ///             ClamScanResult::Error(err) => println!("Received error from ClamAV: {}", err),
-    ///         },
-    ///         Err(e) => println!("A network error occurred whilst talking to ClamAV:\n{}", e),
+    ///     if let Ok(client) = ClamClient::from_env() {
+    ///         println!("{:?}", client.version());
     ///     }
     /// }
     /// ```
-    pub fn scan_stream<T: Read>(&self, stream: T) -> ClamResult<ClamScanResult> {
-        let mut reader = BufReader::new(stream);
-        let mut connection = self.connect()?;
+    pub fn from_env() -> ClamResult<ClamClient> {
+        conf::env_builder(|name| std::env::var(name).ok())?.build()
+    }
 
-        self.connection_write(&connection, b"zINSTREAM\0")?;
+    /// Creates a `ClamClient` which talks to ClamD over `stream`, a connection the caller has
+    /// already established, such as through a bastion host or with a custom dialer. `IDSESSION`
+    /// is issued on the connection, as for `ClamClientBuilder::persistent_connection`, so that
+    /// `PING`, `VERSION`, `STATS`, `SCAN` and `INSTREAM` may all be issued over it.
+    ///
+    /// The client never opens a connection of its own, so commands which cannot be issued within
+    /// a session, such as `CONTSCAN` and the asynchronous scans, fail with
+    /// `ClamError::ConnectionError`, as does every command once ClamD has closed the connection,
+    /// e.g. after it has been idle for ClamD's `IdleTimeout`.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use std::net::TcpStream;
+    ///
+    /// fn main() {
+    ///     if let Ok(stream) = TcpStream::connect("127.0.0.1:3310") {
+    ///         let client = ClamClient::from_stream(stream).unwrap();
+    ///         println!("{:?}", client.scan_bytes(b"some data"));
+    ///     }
+    /// }
+    /// ```
+    pub fn from_stream(stream: TcpStream) -> ClamResult<ClamClient> {
+        let peer = stream.peer_addr()?;
+        let client = ClamClient::builder(&peer.ip().to_string(), peer.port())
+            .persistent_connection(true)
+            .build()?;
+        client.adopt(Connection::Tcp(stream), Some(peer))
+    }
 
-        loop {
-            let bytes_read = {
-                let buf = reader.fill_buf()?;
-                assert!(buf.len() < u32::MAX as usize);
-                if buf.is_empty() {
-                    break;
-                }
-                // Make sure to pad `buf.len()` to 4 bytes regardless of the architecture
-                self.connection_write(&connection, &(buf.len() as u32).to_be_bytes())?;
-                self.connection_write(&connection, buf)?;
-                buf.len()
-            };
-            reader.consume(bytes_read);
+    /// Creates a `ClamClient` which talks to ClamD over the Unix socket connection `stream`, as
+    /// `from_stream` does for a TCP connection. A connection inherited as a file descriptor, such
+    /// as from systemd, can be converted with `UnixStream::from(OwnedFd)`.
+    #[cfg(unix)]
+    pub fn from_unix_stream(stream: UnixStream) -> ClamResult<ClamClient> {
+        let mut builder = ClamClient::builder("127.0.0.1", 3310).persistent_connection(true);
+        if let Some(path) = stream.peer_addr()?.as_pathname() {
+            builder = builder.unix_socket(path);
         }
+        builder.build()?.adopt(Connection::Unix(stream), None)
+    }
 
-        self.connection_write(&connection, &[0, 0, 0, 0])?;
+    /// Opens the persistent session on the supplied `connection`, after which the client never
+    /// opens another connection.
+    fn adopt(mut self, connection: Connection, endpoint: Option<SocketAddr>) -> ClamResult<Self> {
+        let session = ClamSession::open(connection, endpoint, self.session_options())?;
+        self.persistent = Some(Mutex::new(Some((session, Instant::now()))));
+        self.reconnect = false;
+        Ok(self)
+    }
 
-        let mut result = String::new();
-        match connection.read_to_string(&mut result) {
-            Ok(_) => {
-                let scan_result = ClamScanResult::parse(&result);
+    /// Returns the address of the primary ClamD endpoint this client connects to.
+    pub fn endpoint(&self) -> SocketAddr {
+        self.socket
+    }
 
-                if let Some(singular) = scan_result.first() {
-                    Ok(singular.clone())
-                } else {
-                    Err(ClamError::InvalidData(result))
-                }
+    /// Implements the ClamD `PING` command, returns true if ClamD responds with `PONG`, or false if
+    /// there was an error, or ClamD did not respond with `PONG`.
+    pub fn ping(&self) -> bool {
+        self.timed("PING", || {
+            if self.persistent.is_some() {
+                return self.with_session(true, |s| s.ping());
             }
-            Err(e) => Err(ClamError::ConnectionError(e)),
-        }
-    }
 
-    /// Implements the ClamD `STATS` command, and returns a struct of `ClamStats`.
-    pub fn stats(&self) -> ClamResult<ClamStats> {
-        let resp: String = self.send_command(b"zSTATS\0")?;
-        ClamStats::parse(&resp)
+            let resp = self.send_command("PING")?;
+            Ok(resp.trim_end_matches('\0') == "PONG")
+        })
+        .unwrap_or(false)
     }
 
-    /// Implements the ClamD `SHUTDOWN` command, and returns the status message - if any -
-    /// from ClamD.
+    /// Streams the EICAR test file to ClamD with `INSTREAM`, proving that detection works end to
+    /// end rather than just that ClamD is answering, as `ping` does. Returns
+    /// `SelfTestResult::Failed` if ClamD did not report the file as infected, for example because
+    /// its signature database failed to load, or an error if there was an issue talking to ClamD.
     ///
-    /// *Note*: Since this shuts down the ClamD instance, it will ensure all future calls to
-    /// this or any other `ClamClient` return errors, as such, thus function consumes the calling client.
-    pub fn shutdown(self) -> ClamResult<String> {
-        self.send_command(b"zSHUTDOWN\0")
-    }
-
-    /// Simple reusable wrapper function to send a basic command to the ClamD instance and obtain
-    /// a `ClamResult` that can propagate up the error chain. This is responsible for creating,
-    /// writing to, and managing the connection in all 'one-shot' operations.
+    /// *Example*
     ///
-    /// *Arguments*:
+    /// ```rust
+    /// extern crate clam_client;
     ///
-    /// - `command`: The command to issue in byte form.
-    fn send_command(&self, command: &[u8]) -> ClamResult<String> {
-        let mut connection = self.connect()?;
-
-        match connection.write_all(command) {
-            Ok(_) => {
-                let mut result = String::new();
-                match connection.read_to_string(&mut result) {
-                    Ok(_) => Ok(result),
-                    Err(e) => Err(ClamError::CommandError(e)),
-                }
-            }
-            Err(e) => Err(ClamError::CommandError(e)),
-        }
-    }
-
-    /// Simple reusable wrapper function for writing a byte stream to an established connection,
-    /// returns the length of the data written if successful. This is especially useful for writing
-    /// file streams.
-    ///  
-    /// *Arguments*:
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::response::SelfTestResult;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
     ///
-    /// - `connection`: The established connection to write to.
-    /// - `data`: The byte stream to send.
-    fn connection_write(&self, mut connection: &TcpStream, data: &[u8]) -> ClamResult<usize> {
-        match connection.write(data) {
-            Ok(v) => Ok(v),
-            Err(e) => Err(ClamError::CommandError(e)),
+    ///     if let Ok(SelfTestResult::Failed(result)) = client.self_test() {
+    ///         println!("ClamD did not detect EICAR, it reported: {}", result);
+    ///     }
+    /// }
+    /// ```
+    pub fn self_test(&self) -> ClamResult<SelfTestResult> {
+        match self.scan_bytes(EICAR)? {
+            ClamScanResult::Found(_, signature) => Ok(SelfTestResult::Passed(signature)),
+            result => Ok(SelfTestResult::Failed(result)),
         }
     }
 
-    /// Simple helper function to create a new connection to the ClamD socket.
-    fn connect(&self) -> ClamResult<TcpStream> {
-        let connection = if let Some(t) = self.timeout {
-            TcpStream::connect_timeout(&self.socket, t)
-        } else {
-            TcpStream::connect(&self.socket)
-        };
-
-        match connection {
-            Ok(handle) => Ok(handle),
-            Err(e) => Err(ClamError::ConnectionError(e)),
+    /// Runs a battery of checks against ClamD - connecting, `PING`, `VERSION`, `STATS` and
+    /// streaming the EICAR test file - and reports what failed along with its likely cause, such
+    /// as a wrong port or ClamD still loading its database. Each command is issued once, without
+    /// retrying, so that the report reflects what ClamD is doing right now.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     for failure in client.diagnose().failures() {
+    ///         println!("{} failed: {:?}", failure.check, failure.cause);
+    ///     }
+    /// }
+    /// ```
+    pub fn diagnose(&self) -> DiagnosticsReport {
+        let mut report = DiagnosticsReport::default();
+
+        report.run(Check::Connect, || match self.connect_endpoint()? {
+            (_, Some(endpoint)) => Ok(format!("connected to {}", endpoint)),
+            (_, None) => Ok("connected to the Unix socket".to_owned()),
+        });
+        report.run(Check::Ping, || {
+            let ping = self.delimiter.command("PING");
+            let reply = self.send_command_once(&ping, ReplyLength::Single)?;
+            let reply = self.delimiter.normalise(reply, true);
+            match reply.trim_end_matches('\0') {
+                "PONG" => Ok("PONG".to_owned()),
+                reply => Err(Failure::Diagnosed(
+                    format!("expected PONG, received {:?}", reply),
+                    LikelyCause::NotClamD,
+                )),
+            }
+        });
+        report.run(Check::Version, || {
+            let framed = self.delimiter.command("VERSION");
+            let reply = self.send_command_once(&framed, ReplyLength::Single)?;
+            let version = ClamVersion::parse(self.delimiter.normalise(reply, true));
+            Ok(version.map_err(|e| e.redact(self.redaction))?.version_tag)
+        });
+        report.run(Check::Stats, || {
+            let framed = self.delimiter.command("STATS");
+            let reply = self.send_command_once(&framed, ReplyLength::of("STATS"))?;
+            let stats = ClamStats::parse_lenient(&self.delimiter.normalise(reply, false))
+                .map_err(|e| e.redact(self.redaction))?;
+            Ok(format!(
+                "{}, {} of {} threads live, {} queued",
+                stats.state, stats.threads_live, stats.threads_max, stats.queue
+            ))
+        });
+        report.run(Check::Stream, || {
+            let (result, _) =
+                self.scan_stream_uninstrumented(EICAR, self.stream_max_length, &mut |_| {})?;
+            match result {
+                ClamScanResult::Found(_, signature) => Ok(format!("detected {}", signature)),
+                result => Err(Failure::Diagnosed(
+                    format!("EICAR was reported as {}", result),
+                    LikelyCause::SignaturesNotLoaded,
+                )),
+            }
+        });
+
+        report
+    }
+
+    /// Repeatedly issues `PING`, backing off between attempts, until ClamD responds with `PONG` or
+    /// `timeout` has passed. This is useful at start-up, since ClamD does not accept commands
+    /// until it has finished loading its signature database. Returns a `ConnectionError` of kind
+    /// `TimedOut` if ClamD did not become ready in time.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use std::time::Duration;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     if client.wait_until_ready(Duration::from_millis(500)).is_err() {
+    ///         println!("ClamD is not available");
+    ///     }
+    /// }
+    /// ```
+    pub fn wait_until_ready(&self, timeout: Duration) -> ClamResult<()> {
+        let deadline = Instant::now() + timeout;
+        let backoff = RetryPolicy::default()
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_secs(2));
+        let ping = self.delimiter.command("PING");
+        let mut attempt = 1;
+
+        loop {
+            if let Ok(resp) = self.send_command_once(&ping, ReplyLength::Single) {
+                if resp.trim_end_matches(['\0', '\n']) == "PONG" {
+                    return Ok(());
+                }
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ClamError::ConnectionError(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("ClamD was not ready within {:?}", timeout),
+                )));
+            }
+
+            thread::sleep(backoff.backoff(attempt).min(deadline - now));
+            attempt += 1;
+        }
+    }
+
+    /// Returns true if any endpoint responded to its most recent background health check, see
+    /// `ClamClientBuilder::health_check_interval`. If background health checks are disabled,
+    /// this issues a `PING` instead.
+    pub fn is_healthy(&self) -> bool {
+        match &self.health {
+            Some(monitor) => monitor.statuses().iter().any(|status| status.healthy),
+            None => self.ping(),
+        }
+    }
+
+    /// Returns the result of the most recent background health check of each endpoint, or `None`
+    /// if background health checks are disabled.
+    pub fn health(&self) -> Option<Vec<EndpointStatus>> {
+        self.health.as_ref().map(|monitor| monitor.statuses())
+    }
+
+    /// Returns a snapshot of the number and latency of the commands issued by the client, or
+    /// `None` unless `ClamClientBuilder::collect_latency` is enabled.
+    pub fn client_metrics(&self) -> Option<ClientMetrics> {
+        self.latency.as_ref().map(|latency| latency.snapshot())
+    }
+
+    /// Returns the state of the circuit breaker, or `None` if no circuit breaker is configured.
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.circuit.as_ref().map(|circuit| circuit.state())
+    }
+
+    /// Implements the ClamD `VERSION` command, returns a struct of `ClamVersion` if successful,
+    /// or an error if processing the response failed, or if there was an issue talking to ClamD.
+    pub fn version(&self) -> ClamResult<ClamVersion> {
+        if self.persistent.is_some() {
+            return self.with_session(true, |s| s.version());
+        }
+
+        let resp = self.send_command("VERSION")?;
+        ClamVersion::parse(resp).map_err(|e| e.redact(self.redaction))
+    }
+
+    /// Returns the version and release date of the signature database loaded by ClamD, taken from
+    /// the reply to the `VERSION` command.
+    pub fn database_info(&self) -> ClamResult<DatabaseInfo> {
+        Ok(self.version()?.database_info())
+    }
+
+    /// Returns the version of ClamD, the signature database it has loaded and the commands it
+    /// supports, using the `VERSIONCOMMANDS` command, or `VERSION` if ClamD predates it. The
+    /// result is cached for `ClamClientBuilder::server_info_ttl`, and until `reload` is called,
+    /// so that it can be checked before every scan without a round trip to ClamD.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     if let Ok(info) = client.server_info() {
+    ///         if !info.is_at_least(0, 103, 0) {
+    ///             println!("{} is no longer supported", info.version.version_tag);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn server_info(&self) -> ClamResult<ServerInfo> {
+        let mut cached = self.server_info.lock().unwrap();
+        if let Some((info, fetched)) = &*cached {
+            if fetched.elapsed() < self.server_info_ttl {
+                return Ok(info.clone());
+            }
+        }
+
+        let info = self.fetch_server_info()?;
+        *cached = Some((info.clone(), Instant::now()));
+        Ok(info)
+    }
+
+    /// Asks ClamD for its `ServerInfo`, regardless of whether it is cached, and caches the reply,
+    /// e.g. after ClamD has been upgraded or reloaded by another client.
+    pub fn refresh_server_info(&self) -> ClamResult<ServerInfo> {
+        let mut cached = self.server_info.lock().unwrap();
+        let info = self.fetch_server_info()?;
+        *cached = Some((info.clone(), Instant::now()));
+        Ok(info)
+    }
+
+    /// Implements `server_info`, without the cache.
+    fn fetch_server_info(&self) -> ClamResult<ServerInfo> {
+        // Sessions were introduced alongside `VERSIONCOMMANDS`, so every daemon supports both
+        if self.persistent.is_some() {
+            return self.with_session(true, |s| s.server_info());
+        }
+
+        let resp = self.send_command("VERSIONCOMMANDS")?;
+        if resp.trim_end_matches(['\0', '\n']) == "UNKNOWN COMMAND" {
+            return Ok(ServerInfo::from_version(self.version()?));
+        }
+        ServerInfo::parse(resp).map_err(|e| e.redact(self.redaction))
+    }
+
+    /// Returns true if the signature database loaded by ClamD was published more than `max_age`
+    /// ago, allowing stale definitions to be alerted on.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use std::time::Duration;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     if let Ok(true) = client.is_database_older_than(Duration::from_secs(2 * 24 * 3600)) {
+    ///         println!("ClamAV signatures are more than two days old");
+    ///     }
+    /// }
+    /// ```
+    #[cfg(feature = "chrono")]
+    pub fn is_database_older_than(&self, max_age: Duration) -> ClamResult<bool> {
+        Ok(self.database_info()?.is_older_than(max_age))
+    }
+
+    /// Implements the ClamD `RELOAD` command, returns the `ReloadStatus` reported by ClamD, or an
+    /// error if the reply was not recognised or there was an issue talking to ClamD.
+    pub fn reload(&self) -> ClamResult<ReloadStatus> {
+        let resp = self.send_command("RELOAD")?;
+        *self.server_info.lock().unwrap() = None;
+        #[cfg(feature = "hash")]
+        if let Some(cache) = &self.cache {
+            cache.clear();
+        }
+        ReloadStatus::parse(resp).map_err(|e| e.redact(self.redaction))
+    }
+
+    /// Implements the ClamD `SCAN` and `CONTSCAN` commands, returns a `Vec<ClamScanResult>` if the command
+    /// was successful, or a network error if the command failed.
+    ///
+    /// *Arguments:*
+    ///
+    /// - `path`: The path to scan, this is a path that is on the ClamD server, or that it has access to.
+    /// - `continue_on_virus`: If true, instructs ClamD to continue scanning even after it detects a virus.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::response::ClamScanResult;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     if let Ok(scan_results) = client.scan_path("/tmp/", true){
+    ///         for result in scan_results.iter() {
+    ///             match result {
+    ///                 ClamScanResult::Found(location, virus) => {
+    ///                     println!("Found virus: '{}' in {}", virus, location)
+    ///                 },
+    ///                 _ => {},
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn scan_path(
+        &self,
+        path: &str,
+        continue_on_virus: bool,
+    ) -> ClamResult<Vec<ClamScanResult>> {
+        let command = if continue_on_virus {
+            "CONTSCAN"
+        } else {
+            "SCAN"
+        };
+
+        self.instrumented(
+            command,
+            |r: &Vec<ClamScanResult>| r,
+            || {
+                if self.persistent.is_some() && !continue_on_virus {
+                    return Ok(vec![self.with_session(true, |s| s.scan_path(path))?]);
+                }
+
+                let result = self.send_command(&format!("{} {}", command, path))?;
+                parse_results(&result, self.strict_parsing).redact(self.redaction)
+            },
+        )
+    }
+
+    /// Implements the ClamD `SCAN` and `CONTSCAN` commands as `scan_path` does, returning each
+    /// result along with the exact line ClamD sent for it, see `ScanLine`.
+    pub fn scan_path_lines(
+        &self,
+        path: &str,
+        continue_on_virus: bool,
+    ) -> ClamResult<Vec<ScanLine>> {
+        let command = if continue_on_virus {
+            "CONTSCAN"
+        } else {
+            "SCAN"
+        };
+
+        let (results, lines) = self.instrumented(
+            command,
+            |(results, _): &(Vec<ClamScanResult>, Vec<String>)| results,
+            || {
+                let result = self.send_command(&format!("{} {}", command, path))?;
+                let lines = ClamScanResult::parse_lines(result);
+
+                match lines.iter().find(|line| !line.is_recognised()) {
+                    Some(line) if self.strict_parsing => Err(ClamError::ProtocolError {
+                        reply: line.raw().to_owned(),
+                    }
+                    .redact(self.redaction)),
+                    _ => Ok(lines.into_iter().map(ScanLine::into_parts).unzip()),
+                }
+            },
+        )?;
+
+        Ok(results
+            .into_iter()
+            .zip(lines)
+            .map(|(result, line)| ScanLine::from_parts(result, line))
+            .collect())
+    }
+
+    /// Implements the ClamD `SCAN` and `CONTSCAN` commands as `scan_path` does, but rather than
+    /// buffering the whole response returns a `ScanResults` iterator which parses and yields each
+    /// result as it arrives, keeping memory bounded when scanning large directory trees.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::response::ClamScanResult;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     if let Ok(results) = client.scan_path_iter("/tmp/", true) {
+    ///         for result in results {
+    ///             if let Ok(ClamScanResult::Found(location, virus)) = result {
+    ///                 println!("Found virus: '{}' in {}", virus, location)
+    ///             }
+    ///         }
+    ///     };
+    /// }
+    /// ```
+    pub fn scan_path_iter(
+        &self,
+        path: &str,
+        continue_on_virus: bool,
+    ) -> ClamResult<ScanResults<'_>> {
+        let name = if continue_on_virus {
+            "CONTSCAN"
+        } else {
+            "SCAN"
+        };
+        let framed = self.delimiter.command(&format!("{} {}", name, path));
+        let command = instrument::Command::start(name);
+
+        let started = command.in_scope(|| {
+            self.retrying(|| {
+                let (mut connection, endpoint) = self.connect_endpoint()?;
+                let endpoint = self.describe_endpoint(endpoint);
+                match connection.write_all(&framed) {
+                    Ok(_) => {
+                        self.observers.command_sent(&framed);
+                        Ok((connection, endpoint))
+                    }
+                    Err(e) => Err(ClamError::CommandError(e).in_phase(Phase::Sending, &endpoint)),
+                }
+            })
+            .map_err(|e| e.for_command(name))
+        });
+
+        match started {
+            Ok((connection, endpoint)) => Ok(ScanResults {
+                client: self,
+                reader: BufReader::new(connection),
+                endpoint,
+                command: Some(command),
+                significant: None,
+            }),
+            Err(e) => self.finish_instrumented(command, Err(e), |_| &[]),
+        }
+    }
+
+    /// Implements the ClamD `SCAN` and `CONTSCAN` commands as `scan_path_iter` does, passing each
+    /// result to `on_result` as it arrives. Returning `ControlFlow::Break` from `on_result` aborts
+    /// the scan by closing the connection, after which ClamD abandons the scan as soon as it
+    /// fails to report the next result. Returns `ControlFlow::Break` if the scan was aborted, or
+    /// `ControlFlow::Continue` once ClamD has reported every result.
+    ///
+    /// Like `scan_path_iter`, the scan is instrumented and guarded by the circuit breaker, and an
+    /// aborted scan is recorded along with the results reported before it was aborted.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::response::ClamScanResult;
+    /// use std::ops::ControlFlow;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///     let mut detections = 0;
+    ///
+    ///     // Give up on the tree once it is clearly compromised
+    ///     let scanned = client.scan_path_streaming("/mnt/nfs/", true, |result| {
+    ///         if let ClamScanResult::Found(location, virus) = result {
+    ///             println!("Found virus: '{}' in {}", virus, location);
+    ///             detections += 1;
+    ///         }
+    ///         if detections < 100 {
+    ///             ControlFlow::Continue(())
+    ///         } else {
+    ///             ControlFlow::Break(())
+    ///         }
+    ///     });
+    ///     println!("{:?}", scanned);
+    /// }
+    /// ```
+    pub fn scan_path_streaming<F>(
+        &self,
+        path: &str,
+        continue_on_virus: bool,
+        mut on_result: F,
+    ) -> ClamResult<ControlFlow<()>>
+    where
+        F: FnMut(ClamScanResult) -> ControlFlow<()>,
+    {
+        for result in self.scan_path_iter(path, continue_on_virus)? {
+            if on_result(result?).is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Implements the ClamD `SCAN` and `CONTSCAN` commands as `scan_path` does, summarising the
+    /// results in a `ScanReport` along with how long the scan took.
+    pub fn scan_path_report(&self, path: &str, continue_on_virus: bool) -> ClamResult<ScanReport> {
+        let start = Instant::now();
+        let results = self.scan_path(path, continue_on_virus)?;
+        Ok(ScanReport::new(results, start.elapsed()))
+    }
+
+    /// Implements the ClamD `ALLMATCHSCAN` command, which instructs ClamD to continue scanning a
+    /// file after a match so that every matching signature is reported rather than only the
+    /// first. Returns a `Vec<ClamScanResult>` with an entry per detection if the command was
+    /// successful, or a network error if the command failed.
+    /// `ClamScanResult::group_detections` collects the detections for each file together.
+    ///
+    /// *Arguments:*
+    ///
+    /// - `path`: The path to scan, this is a path that is on the ClamD server, or that it has access to.
+    pub fn scan_path_all_matches(&self, path: &str) -> ClamResult<Vec<ClamScanResult>> {
+        self.instrumented(
+            "ALLMATCHSCAN",
+            |r: &Vec<ClamScanResult>| r,
+            || {
+                let result = self.send_command(&format!("ALLMATCHSCAN {}", path))?;
+                parse_results(&result, self.strict_parsing).redact(self.redaction)
+            },
+        )
+    }
+
+    /// Implements the ClamD `MULTISCAN` command which allows the ClamD instance to perform
+    /// multi-threaded scanning. Returns a `Vec<ClamScanResult>` if the command was successful,
+    /// or a network error if the command failed.
+    ///
+    /// ClamD reports each file as soon as the thread scanning it finishes, so the replies for a
+    /// directory arrive in no particular order. The results are sorted by path to make them
+    /// deterministic, with any results lacking a path, such as `Ok`, last.
+    pub fn multiscan_path(&self, path: &str) -> ClamResult<Vec<ClamScanResult>> {
+        self.instrumented(
+            "MULTISCAN",
+            |r: &Vec<ClamScanResult>| r,
+            || {
+                let result = self.send_command(&format!("MULTISCAN {}", path))?;
+                let mut results =
+                    parse_results(&result, self.strict_parsing).redact(self.redaction)?;
+
+                results.sort_by(|a, b| match (result_path(a), result_path(b)) {
+                    (Some(a), Some(b)) => a.cmp(b),
+                    (a, b) => b.is_some().cmp(&a.is_some()),
+                });
+
+                Ok(results)
+            },
+        )
+    }
+
+    /// Implements the ClamD `INSTREAM` command, which allows the caller to stream a file to the ClamD
+    /// instance. Returns a `ClamScanResult` if the command was successful.
+    ///
+    /// *Arguments*:
+    ///
+    /// - `stream`: The object to be scanned, this must implement `Read`, it will be read into a buffer
+    ///   of 4096 bytes and then written to the ClamD instance. This object must not exceed the ClamD
+    ///   max stream size, else the socket will be forcibly closed - in which case an error will be returned
+    ///   from this function. If `ClamClientBuilder::stream_max_length` is set, `ClamError::StreamTooLarge`
+    ///   is returned as soon as the stream exceeds it.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::response::ClamScanResult;
+    /// use std::fs::File;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///     let file = File::open("/etc/hosts").unwrap();
+    ///
+    ///     match client.scan_stream(file) {
+    ///         Ok(result) => match result {
+    ///             ClamScanResult::Ok => println!("File /etc/hostname is OK!"),
+    ///             ClamScanResult::Found(location, virus) => {
+    ///                 println!("Found virus: '{}' in {}", virus, location)
+    ///             },
+    ///             ClamScanResult::Error(err) => println!("Received error from ClamAV: {}", err),
+    ///         },
+    ///         Err(e) => println!("A network error occurred whilst talking to ClamAV:\n{}", e),
+    ///     }
+    /// }
+    /// ```
+    pub fn scan_stream<T: Read>(&self, stream: T) -> ClamResult<ClamScanResult> {
+        self.instrumented("INSTREAM", std::slice::from_ref, || {
+            self.scan_stream_uninstrumented(stream, self.stream_max_length, &mut |_| {})
+                .map(|(result, _)| result)
+        })
+    }
+
+    /// Implements the ClamD `INSTREAM` command as `scan_stream` does, returning a `ScanOutcome`
+    /// which records how long the scan took, the number of bytes streamed and the endpoint which
+    /// performed it along with the result, so that scan latency and throughput can be tracked
+    /// without timing each call.
+    pub fn scan_stream_outcome<T: Read>(&self, stream: T) -> ClamResult<ScanOutcome> {
+        let start = Instant::now();
+        let mut bytes_streamed = 0;
+
+        let (result, endpoint) = self.instrumented(
+            "INSTREAM",
+            |(result, _)| std::slice::from_ref(result),
+            || {
+                self.scan_stream_uninstrumented(stream, self.stream_max_length, &mut |sent| {
+                    bytes_streamed = sent
+                })
+            },
+        )?;
+
+        Ok(ScanOutcome {
+            result,
+            duration: start.elapsed(),
+            bytes_streamed,
+            endpoint,
+        })
+    }
+
+    /// Implements the ClamD `INSTREAM` command as `scan_stream` does, feeding every byte streamed
+    /// to `hasher` on its way through, and returns its digest alongside the result. The source is
+    /// only read once, however large it is. With the `hash` feature `hash::Sha256` and
+    /// `hash::Md5` may be used as the hasher, see `ContentHasher` for an example of implementing
+    /// another.
+    pub fn scan_stream_hashed<T: Read, H: ContentHasher>(
+        &self,
+        stream: T,
+        mut hasher: H,
+    ) -> ClamResult<(ClamScanResult, H::Output)> {
+        let result = self.scan_stream(HashingReader::new(stream, &mut hasher))?;
+        Ok((result, hasher.finish()))
+    }
+
+    /// Implements the ClamD `INSTREAM` command as `scan_stream` does, calling `progress` after
+    /// each chunk has been written to ClamD with the total number of bytes sent so far and
+    /// `total_hint`, so that the progress of a large scan can be reported.
+    ///
+    /// *Arguments*:
+    ///
+    /// - `stream`: The object to be scanned, see `scan_stream`.
+    /// - `total_hint`: The expected length of the stream if known, such as a file's size or an
+    ///   upload's `Content-Length`. It is passed through to `progress` as is.
+    /// - `progress`: Called with `(bytes_sent, total_hint)` after every chunk.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use std::fs::File;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///     let file = File::open("/etc/hosts").unwrap();
+    ///     let size = file.metadata().ok().map(|m| m.len());
+    ///
+    ///     let result = client.scan_stream_with_progress(file, size, |sent, total| {
+    ///         println!("Sent {} of {:?} bytes", sent, total)
+    ///     });
+    ///     println!("{:?}", result);
+    /// }
+    /// ```
+    pub fn scan_stream_with_progress<T, P>(
+        &self,
+        stream: T,
+        total_hint: Option<u64>,
+        mut progress: P,
+    ) -> ClamResult<ClamScanResult>
+    where
+        T: Read,
+        P: FnMut(u64, Option<u64>),
+    {
+        self.instrumented("INSTREAM", std::slice::from_ref, || {
+            self.scan_stream_uninstrumented(stream, self.stream_max_length, &mut |sent| {
+                progress(sent, total_hint)
+            })
+            .map(|(result, _)| result)
+        })
+    }
+
+    /// Implements the ClamD `INSTREAM` command as `scan_stream` does, but abandons the stream with
+    /// `ClamError::StreamTooLarge` as soon as it exceeds `max_bytes`, rather than leaving ClamD to
+    /// forcibly close the connection once its own `StreamMaxLength` is reached. If
+    /// `ClamClientBuilder::stream_max_length` is also set the lower of the two limits applies.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::error::ClamError;
+    /// use std::fs::File;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///     let file = File::open("/etc/hosts").unwrap();
+    ///
+    ///     match client.scan_stream_with_limit(file, 25 * 1024 * 1024) {
+    ///         Err(ClamError::StreamTooLarge(limit)) => println!("Larger than {} bytes", limit),
+    ///         result => println!("{:?}", result),
+    ///     }
+    /// }
+    /// ```
+    pub fn scan_stream_with_limit<T: Read>(
+        &self,
+        stream: T,
+        max_bytes: u64,
+    ) -> ClamResult<ClamScanResult> {
+        let limit = match self.stream_max_length {
+            Some(configured) => configured.min(max_bytes),
+            None => max_bytes,
+        };
+
+        self.instrumented("INSTREAM", std::slice::from_ref, || {
+            self.scan_stream_uninstrumented(stream, Some(limit), &mut |_| {})
+                .map(|(result, _)| result)
+        })
+    }
+
+    /// Implements `scan_stream`, outside of any tracing span, returning the result along with the
+    /// endpoint which performed the scan.
+    fn scan_stream_uninstrumented<T: Read>(
+        &self,
+        stream: T,
+        max_length: Option<u64>,
+        progress: &mut dyn FnMut(u64),
+    ) -> ClamResult<(ClamScanResult, Option<SocketAddr>)> {
+        if self.persistent.is_some() {
+            let mut stream = Some(stream);
+            return self.with_session(false, |s| {
+                let result = s.stream(stream.take().unwrap(), max_length, &mut *progress)?;
+                Ok((result, s.endpoint()))
+            });
+        }
+
+        let (mut connection, endpoint) = self
+            .retrying(|| self.connect_endpoint())
+            .map_err(|e| e.for_command("INSTREAM"))?;
+        let failed = |e: ClamError, phase| {
+            e.in_phase(phase, &self.describe_endpoint(endpoint))
+                .for_command("INSTREAM")
+                .redact(self.redaction)
+        };
+
+        let command = self.delimiter.command("INSTREAM");
+        connection_write(&mut connection, &command).map_err(|e| failed(e, Phase::Sending))?;
+        self.observers.command_sent(&command);
+        if let Err(e) = write_stream(
+            &mut connection,
+            stream,
+            max_length,
+            &self.observers,
+            progress,
+        ) {
+            return Err(failed(
+                recover_stream_error(&mut connection, e),
+                Phase::Sending,
+            ));
+        }
+
+        let terminator = self.delimiter.terminator();
+        match protocol::read_reply(
+            &mut BufReader::new(connection),
+            terminator,
+            ReplyLength::Single,
+        ) {
+            Ok(reply) => {
+                let result = String::from_utf8_lossy(&reply).into_owned();
+                Ok((
+                    stream_result(self.delimiter.normalise(result, true), self.strict_parsing)
+                        .redact(self.redaction)?,
+                    endpoint,
+                ))
+            }
+            Err(e) => Err(failed(ClamError::ConnectionError(e), Phase::Receiving)),
+        }
+    }
+
+    /// Scans each of `inputs` with `INSTREAM`, using at most `concurrency` connections at once,
+    /// and returns the results in the same order as `inputs`. Since ClamD scans a single stream
+    /// per connection this is the way to scan many streams concurrently, each worker thread is
+    /// scoped to this call. A failure to scan one input does not affect the others.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use std::fs::File;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///     let files = vec![File::open("/etc/hosts").unwrap(), File::open("/etc/hostname").unwrap()];
+    ///
+    ///     for result in client.scan_streams_parallel(files, 4) {
+    ///         println!("{:?}", result);
+    ///     }
+    /// }
+    /// ```
+    pub fn scan_streams_parallel<T: Read + Send>(
+        &self,
+        inputs: Vec<T>,
+        concurrency: usize,
+    ) -> Vec<ClamResult<ClamScanResult>> {
+        let workers = concurrency.clamp(1, inputs.len().max(1));
+        let mut results: Vec<Option<ClamResult<ClamScanResult>>> =
+            inputs.iter().map(|_| None).collect();
+        let pending = Mutex::new(inputs.into_iter().enumerate());
+        let finished = Mutex::new(&mut results);
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let next = pending.lock().unwrap().next();
+                    let (index, input) = match next {
+                        Some(next) => next,
+                        None => return,
+                    };
+
+                    let result = self.scan_stream(input);
+                    finished.lock().unwrap()[index] = Some(result);
+                });
+            }
+        });
+
+        results.into_iter().map(|r| r.unwrap()).collect()
+    }
+
+    /// Implements the ClamD `INSTREAM` command as a `Write` sink, returning a `ClamScanWriter`
+    /// which streams everything written to it to ClamD. The result is collected with
+    /// `ClamScanWriter::finish`. If `ClamClientBuilder::persistent_connection` is enabled the
+    /// persistent session is used, and other commands open a session of their own until the
+    /// writer is finished.
+    pub fn scan_writer(&self) -> ClamResult<ClamScanWriter<'_>> {
+        let command = instrument::Command::start("INSTREAM");
+        match command.in_scope(|| self.start_stream()) {
+            Ok(target) => Ok(ClamScanWriter::new(
+                self,
+                target,
+                command,
+                self.delimiter,
+                self.stream_max_length,
+                self.strict_parsing,
+                self.redaction,
+            )),
+            Err(e) => self.finish_instrumented(command, Err(e), |_| &[]),
+        }
+    }
+
+    /// Issues `INSTREAM` for a `ClamScanWriter`, on the persistent session if one is enabled and
+    /// otherwise on a new connection.
+    fn start_stream(&self) -> ClamResult<StreamTarget> {
+        if self.persistent.is_some() {
+            let mut session = self.take_session()?;
+            let id = session.begin_stream()?;
+            return Ok(StreamTarget::Session(Box::new(session), id));
+        }
+
+        let mut connection = self
+            .retrying(|| self.connect())
+            .map_err(|e| e.for_command("INSTREAM"))?;
+        let command = self.delimiter.command("INSTREAM");
+        connection
+            .write_all(&command)
+            .map_err(|e| ClamError::CommandError(e).for_command("INSTREAM"))?;
+        self.observers.command_sent(&command);
+        Ok(StreamTarget::Connection(connection))
+    }
+
+    /// Implements the ClamD `INSTREAM` command as a pass-through reader, returning a
+    /// `ScanningReader` which streams everything read from `source` to ClamD whilst passing it
+    /// on to the caller unchanged. The verdict is available from `ScanningReader::verdict` once
+    /// `source` reaches EOF.
+    pub fn scanning_reader<R: Read>(&self, source: R) -> ClamResult<ScanningReader<'_, R>> {
+        Ok(ScanningReader::new(source, self.scan_writer()?))
+    }
+
+    /// Implements the ClamD `INSTREAM` command for data that is already held in memory, such as a
+    /// decoded upload, without the caller having to wrap it in a `Cursor`. Returns a
+    /// `ClamScanResult` if the command was successful. If `ClamClientBuilder::scan_cache` is
+    /// configured, a recently scanned identical payload is answered from the cache instead, and
+    /// if `ClamClientBuilder::coalesce_scans` is enabled, identical payloads being scanned at the
+    /// same time share a single scan.
+    ///
+    /// *Arguments*:
+    ///
+    /// - `data`: The bytes to be scanned, subject to the same size limits as `scan_stream`.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     if let Ok(result) = client.scan_bytes(b"some in memory payload") {
+    ///         println!("{:?}", result);
+    ///     }
+    /// }
+    /// ```
+    pub fn scan_bytes(&self, data: &[u8]) -> ClamResult<ClamScanResult> {
+        #[cfg(feature = "hash")]
+        if self.cache.is_some() || self.in_flight.is_some() {
+            return self.scan_bytes_by_hash(data);
+        }
+
+        self.scan_stream(data)
+    }
+
+    /// Implements `scan_bytes` when verdicts are cached or coalesced by the payload's SHA-256.
+    #[cfg(feature = "hash")]
+    fn scan_bytes_by_hash(&self, data: &[u8]) -> ClamResult<ClamScanResult> {
+        let key = cache::sha256(data);
+
+        // If the database version cannot be checked the cache may be stale, so it is bypassed
+        let mut cached = None;
+        if let Some(cache) = &self.cache {
+            let current = !cache.needs_version_check()
+                || match self.version() {
+                    Ok(version) => {
+                        cache.version_checked(version.build_number);
+                        true
+                    }
+                    Err(_) => false,
+                };
+
+            if current {
+                if let Some(result) = cache.get(&key) {
+                    return Ok(result);
+                }
+                cached = Some((cache, cache.generation()));
+            }
+        }
+
+        let result = match &self.in_flight {
+            Some(in_flight) => in_flight.run(key, || self.scan_stream(data))?,
+            None => self.scan_stream(data)?,
+        };
+
+        if let Some((cache, generation)) = cached {
+            cache.insert(key, &result, generation);
+        }
+        Ok(result)
+    }
+
+    /// Implements the ClamD `INSTREAM` command for data held in memory as `scan_bytes` does,
+    /// subject to `policy`. If the SHA-256 of `data` is allowlisted or denylisted the verdict is
+    /// decided without a round trip to ClamD. Requires the `hash` feature.
+    #[cfg(feature = "hash")]
+    pub fn scan_bytes_with_policy(
+        &self,
+        data: &[u8],
+        policy: &HashPolicy,
+    ) -> ClamResult<PolicyVerdict> {
+        let sha256 = cache::sha256(data);
+        match policy.decide(sha256) {
+            Some(verdict) => Ok(verdict),
+            None => Ok(policy.apply(sha256, self.scan_bytes(data)?)),
+        }
+    }
+
+    /// Implements the ClamD `INSTREAM` command as `scan_stream` does, subject to `policy`. The
+    /// stream's SHA-256 is only known once it has been read, so it is always scanned by ClamD,
+    /// and ClamD's verdict is then overridden if the hash is allowlisted or denylisted. Requires
+    /// the `hash` feature.
+    #[cfg(feature = "hash")]
+    pub fn scan_stream_with_policy<T: Read>(
+        &self,
+        stream: T,
+        policy: &HashPolicy,
+    ) -> ClamResult<PolicyVerdict> {
+        let (result, sha256) = self.scan_stream_hashed(stream, crate::hash::Sha256::default())?;
+        let sha256 = <[u8; 32]>::try_from(sha256.as_slice()).expect("SHA-256 is 32 bytes");
+        Ok(policy.apply(sha256, result))
+    }
+
+    /// Implements the ClamD `INSTREAM` command for a file on the local filesystem, this is useful
+    /// when ClamD cannot see the path itself, for example when it runs in a separate container.
+    /// Unlike `scan_stream`, the location of a `ClamScanResult::Found` is the given path rather
+    /// than `stream`. A file which cannot be opened or read fails with `ClamError::LocalIoError`,
+    /// never a connection error.
+    ///
+    /// *Arguments*:
+    ///
+    /// - `path`: The path of the local file to open and stream to ClamD.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::response::ClamScanResult;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     if let Ok(ClamScanResult::Found(location, virus)) = client.scan_file("/etc/hosts") {
+    ///         println!("Found virus: '{}' in {}", virus, location);
+    ///     }
+    /// }
+    /// ```
+    pub fn scan_file<P: AsRef<Path>>(&self, path: P) -> ClamResult<ClamScanResult> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(ClamError::LocalIoError)?;
+
+        match self.scan_stream(file)? {
+            ClamScanResult::Found(_, virus) => {
+                Ok(ClamScanResult::Found(path.display().to_string(), virus))
+            }
+            result => Ok(result),
+        }
+    }
+
+    /// Implements the ClamD `STATS` command, and returns a struct of `ClamStats`. The response is
+    /// parsed leniently if `ClamClientBuilder::lenient_stats` is enabled. Otherwise, if the
+    /// response does not match the 0.100 grammar, ClamD's `VERSION` is requested and the response
+    /// is parsed as that version produces it, see `ClamStats::parse_for_version`.
+    pub fn stats(&self) -> ClamResult<ClamStats> {
+        let result = instrument::command(
+            "STATS",
+            |_| "ok",
+            || {
+                self.timed("STATS", || {
+                    if self.persistent.is_some() {
+                        return self.with_session(true, |s| s.stats());
+                    }
+
+                    let resp: String = self.send_command("STATS")?;
+                    if self.lenient_stats {
+                        return ClamStats::parse_lenient(&resp);
+                    }
+
+                    match ClamStats::parse(&resp) {
+                        Ok(stats) => Ok(stats),
+                        Err(_) => ClamStats::parse_for_version(&resp, &self.version()?),
+                    }
+                })
+            },
+        )
+        .map_err(|e| e.redact(self.redaction));
+        self.observers.finished("STATS", &result, |_| &[]);
+        result
+    }
+
+    /// Implements the ClamD `IDSESSION` command, returning a `ClamSession` through which multiple
+    /// commands can be issued over a single connection. The session is ended when dropped.
+    pub fn session(&self) -> ClamResult<ClamSession> {
+        let (connection, endpoint) = self.retrying(|| self.connect_endpoint())?;
+        ClamSession::open(connection, endpoint, self.session_options())
+    }
+
+    /// Returns the configuration which sessions opened by this client inherit.
+    fn session_options(&self) -> SessionOptions {
+        SessionOptions {
+            stream_max_length: self.stream_max_length,
+            lenient_stats: self.lenient_stats,
+            strict_parsing: self.strict_parsing,
+            redaction: self.redaction,
+            observers: self.observers.clone(),
+            peer: self.describe_endpoint(None),
+        }
+    }
+
+    /// Scans each of `paths` with `SCAN` over a single session, pipelining the scans rather than
+    /// connecting once per path, and returns the outcome for each path. As for `scan_path` with
+    /// `continue_on_virus` unset, each path's result is the first virus found or error hit.
+    ///
+    /// A path which cannot be scanned does not affect the others, its outcome is the error. If
+    /// the session itself fails part way through, every path whose result had not yet been
+    /// received fails with `ClamError::ConnectionError`. Only failing to open the session fails
+    /// the batch as a whole.
+    ///
+    /// At most 32 scans are outstanding at once, so as not to exceed ClamD's `MaxQueue`.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     if let Ok(results) = client.scan_paths(&["/tmp/first", "/tmp/second"]) {
+    ///         for (path, result) in results {
+    ///             match result {
+    ///                 Ok(result) => println!("{}: {}", path, result),
+    ///                 Err(e) => println!("{} could not be scanned: {}", path, e),
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn scan_paths(
+        &self,
+        paths: &[&str],
+    ) -> ClamResult<BTreeMap<String, ClamResult<ClamScanResult>>> {
+        let mut session = self.session()?;
+        let mut queued = HashMap::new();
+        let mut results = BTreeMap::new();
+        let mut next = 0;
+
+        let failure = 'scan: loop {
+            while session.pending_requests() < PIPELINE_DEPTH && next < paths.len() {
+                match session.queue_scan_path(paths[next]) {
+                    Ok(id) => queued.insert(id, paths[next]),
+                    Err(e) => break 'scan Some(e),
+                };
+                next += 1;
+            }
+
+            match session.next_result() {
+                Ok(Some((id, result))) => {
+                    results.insert(queued.remove(&id).unwrap().to_owned(), result);
+                }
+                Ok(None) => break None,
+                Err(e) => break Some(e),
+            }
+        };
+
+        // The results of the paths outstanding on a failed session are lost with it
+        if let Some(e) = failure {
+            for path in queued.into_values().chain(paths[next..].iter().copied()) {
+                results.entry(path.to_owned()).or_insert_with(|| {
+                    let message = format!("the session failed: {}", e);
+                    let e = std::io::Error::new(std::io::ErrorKind::ConnectionAborted, message);
+                    Err(ClamError::ConnectionError(e))
+                });
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Sends `command` to ClamD verbatim over a new connection and returns the raw reply, for
+    /// issuing commands this crate does not yet model. The command is retried and failed over
+    /// like any other.
+    ///
+    /// *Note*: No framing is added, so `command` must include its own prefix and terminator, e.g.
+    /// `b"zVERSIONCOMMANDS\0"`, and the reply is returned unparsed, including its terminator.
+    /// Commands which expect further data, or which leave the connection open such as
+    /// `IDSESSION`, will not behave correctly, since the reply is read until ClamD closes the
+    /// connection.
+    pub fn send_raw(&self, command: &[u8]) -> ClamResult<Vec<u8>> {
+        self.retrying(|| self.send_raw_once(command, ReplyLength::UntilClosed))
+    }
+
+    /// Implements the ClamD `SHUTDOWN` command, and returns the status message - if any -
+    /// from ClamD.
+    ///
+    /// *Note*: Since this shuts down the ClamD instance, it will ensure all future calls to
+    /// this or any other `ClamClient` return errors.
+    pub fn shutdown(&self) -> ClamResult<String> {
+        self.send_command("SHUTDOWN")
+    }
+
+    /// Implements the ClamD `SHUTDOWN` command, then polls the endpoint until it refuses
+    /// connections, returning true once ClamD has exited, or false if it was still accepting
+    /// connections after `timeout`. This allows ClamD to be safely restarted, on the same socket,
+    /// once it has shut down.
+    ///
+    /// *Note*: Only the primary endpoint, or the Unix socket if one is configured, is polled.
+    pub fn shutdown_and_wait(&self, timeout: Duration) -> ClamResult<bool> {
+        let deadline = Instant::now() + timeout;
+        let backoff = RetryPolicy::default()
+            .initial_backoff(Duration::from_millis(50))
+            .max_backoff(Duration::from_secs(1));
+        let mut attempt = 1;
+
+        self.send_command("SHUTDOWN")?;
+
+        loop {
+            if self.connect_to(self.socket).is_err() {
+                return Ok(true);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
+            }
+
+            thread::sleep(backoff.backoff(attempt).min(deadline - now));
+            attempt += 1;
+        }
+    }
+
+    /// Issues any ClamD `Command`, returning ClamD's reply parsed as the equivalent method, such
+    /// as `scan_path` or `stats`, parses it. Each command is issued exactly as that method issues
+    /// it, so the client's retry policy, observers and metrics all apply.
+    ///
+    /// `Command::IdSession` returns the `ClamSession` it starts, whilst `Command::End` is only
+    /// valid within a session, so is rejected with `ClamError::UnsupportedCommand`.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::command::{ClamResponse, Command};
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     if let Ok(ClamResponse::Stream(result)) = client.execute(Command::InStream(&mut &b"data"[..])) {
+    ///         println!("{}", result);
+    ///     }
+    /// }
+    /// ```
+    pub fn execute(&self, command: Command) -> ClamResult<ClamResponse> {
+        match command {
+            Command::Ping => Ok(ClamResponse::Ping(self.ping())),
+            Command::Version => self.version().map(ClamResponse::Version),
+            Command::Reload => self.reload().map(ClamResponse::Reload),
+            Command::Stats => self.stats().map(ClamResponse::Stats),
+            Command::Shutdown => self.shutdown().map(ClamResponse::Shutdown),
+            Command::Scan(path) => self.scan_path(path, false).map(ClamResponse::Scan),
+            Command::ContScan(path) => self.scan_path(path, true).map(ClamResponse::Scan),
+            Command::MultiScan(path) => self.multiscan_path(path).map(ClamResponse::Scan),
+            Command::AllMatchScan(path) => self.scan_path_all_matches(path).map(ClamResponse::Scan),
+            Command::InStream(stream) => self.scan_stream(stream).map(ClamResponse::Stream),
+            Command::IdSession => self.session().map(ClamResponse::Session),
+            Command::End => Err(ClamError::UnsupportedCommand(command.to_string())),
+        }
+    }
+
+    /// Issues a `ClamCommand` defined outside of this crate, returning its reply as parsed by
+    /// `ClamCommand::parse`, or an error if there was an issue talking to ClamD. The command is
+    /// retried and failed over like any other, see `ClamCommand` for an example.
+    pub fn run<C: ClamCommand>(&self, command: C) -> ClamResult<C::Response> {
+        let framed = self.delimiter.command(&command.command());
+        let length = if command.single_reply() {
+            ReplyLength::Single
+        } else {
+            ReplyLength::UntilClosed
+        };
+
+        let reply = self.retrying(|| self.send_command_once(&framed, length))?;
+        command.parse(self.delimiter.normalise(reply, true))
+    }
+
+    /// Runs `command` on the persistent session, opening a new session if there is none or the
+    /// existing one has died, been closed by ClamD or been idle for too long. If `retry` is set
+    /// and a reused session fails, the command is attempted once more on a fresh session since
+    /// ClamD may simply have closed the idle connection.
+    fn with_session<R, F>(&self, retry: bool, mut command: F) -> ClamResult<R>
+    where
+        F: FnMut(&mut ClamSession) -> ClamResult<R>,
+    {
+        let mut persistent = self.persistent.as_ref().unwrap().lock().unwrap();
+
+        let reused = reusable_session(&mut persistent);
+        let was_reused = reused.is_some();
+        let mut session = match reused {
+            Some(session) => session,
+            None => self.session()?,
+        };
+
+        let mut result = command(&mut session);
+
+        if result.is_err() && session.is_broken() && was_reused && retry {
+            session = self.session()?;
+            result = command(&mut session);
+        }
+
+        if !session.is_broken() {
+            *persistent = Some((session, Instant::now()));
+        }
+
+        result
+    }
+
+    /// Takes the persistent session for a caller which holds it across several calls, such as a
+    /// `ClamScanWriter`, opening a new session if the existing one cannot be reused. The session
+    /// is handed back with `restore_session`.
+    pub(crate) fn take_session(&self) -> ClamResult<ClamSession> {
+        let reused = reusable_session(&mut self.persistent.as_ref().unwrap().lock().unwrap());
+        match reused {
+            Some(session) => Ok(session),
+            None => self.session(),
+        }
+    }
+
+    /// Hands back a session taken with `take_session` to be reused, unless it is unusable.
+    pub(crate) fn restore_session(&self, session: ClamSession) {
+        if session.is_broken() || session.pending_requests() > 0 {
+            return;
+        }
+
+        let mut persistent = self.persistent.as_ref().unwrap().lock().unwrap();
+        let replaced = persistent.replace((session, Instant::now()));
+        // Closing a session opened in the meantime writes `END`, which must not hold the lock
+        drop(persistent);
+        drop(replaced);
+    }
+
+    /// Simple reusable wrapper function to send a basic command to the ClamD instance and obtain
+    /// a `ClamResult` that can propagate up the error chain. This is responsible for creating,
+    /// writing to, and managing the connection in all 'one-shot' operations, retrying according
+    /// to the configured `RetryPolicy`.
+    ///
+    /// *Arguments*:
+    ///
+    /// - `command`: The command to issue, without its prefix or terminator, e.g. `SCAN /file`.
+    fn send_command(&self, command: &str) -> ClamResult<String> {
+        let framed = self.delimiter.command(command);
+        let length = ReplyLength::of(command);
+        let reply = self.retrying(|| self.send_command_once(&framed, length))?;
+        Ok(self.delimiter.normalise(reply, command != "STATS"))
+    }
+
+    /// Sends a basic command to the ClamD instance exactly once, see `send_command`.
+    fn send_command_once(&self, command: &[u8], length: ReplyLength) -> ClamResult<String> {
+        let reply = String::from_utf8(self.send_raw_once(command, length)?).map_err(|e| {
+            ClamError::CommandError(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        })?;
+        check_reply(reply)
+    }
+
+    /// Sends a raw command to the ClamD instance exactly once, returning the reply of `length`.
+    /// Only as much of the reply as `length` requires is read, rather than waiting for ClamD to
+    /// close the connection, which some proxies delay.
+    fn send_raw_once(&self, command: &[u8], length: ReplyLength) -> ClamResult<Vec<u8>> {
+        self.failover(|endpoint| {
+            let mut connection = self.connect_to(endpoint)?;
+            let failed = |e, phase| {
+                ClamError::CommandError(e).in_phase(phase, &self.describe_endpoint(Some(endpoint)))
+            };
+
+            match connection.write_all(command) {
+                Ok(_) => {
+                    self.observers.command_sent(command);
+                    let terminator = self.delimiter.terminator();
+                    protocol::read_reply(&mut BufReader::new(connection), terminator, length)
+                        .map_err(|e| failed(e, Phase::Receiving))
+                }
+                Err(e) => Err(failed(e, Phase::Sending)),
+            }
+        })
+        .map_err(|e| e.for_command(&protocol::command_name(command)))
+    }
+
+    /// Runs `operation` against each endpoint in turn until it succeeds, returning the error from
+    /// the last endpoint if it fails on all of them.
+    fn failover<T, F>(&self, mut operation: F) -> ClamResult<T>
+    where
+        F: FnMut(SocketAddr) -> ClamResult<T>,
+    {
+        let mut endpoints = self.endpoints();
+        let mut result = operation(endpoints.next().unwrap());
+
+        for endpoint in endpoints {
+            if result.is_ok() {
+                break;
+            }
+            result = operation(endpoint);
+        }
+
+        result
+    }
+
+    /// Runs the scan `operation` as `command`, instrumenting it and reporting its outcome to the
+    /// registered observers, where `results` extracts the scan results from a successful outcome.
+    fn instrumented<T, R, F>(
+        &self,
+        command: &'static str,
+        results: R,
+        operation: F,
+    ) -> ClamResult<T>
+    where
+        R: Fn(&T) -> &[ClamScanResult],
+        F: FnOnce() -> ClamResult<T>,
+    {
+        let result = instrument::command(
+            command,
+            |r| instrument::classify_scan(results(r)),
+            || self.timed(command, operation),
+        );
+        self.observers.finished(command, &result, results);
+        result
+    }
+
+    /// Records the outcome of a `command` started with `instrument::Command::start` and notifies
+    /// the registered observers, for commands such as an `INSTREAM` written through a
+    /// `ClamScanWriter` which span several calls and so cannot be wrapped with `instrumented`.
+    pub(crate) fn finish_instrumented<T, R>(
+        &self,
+        command: instrument::Command,
+        result: ClamResult<T>,
+        results: R,
+    ) -> ClamResult<T>
+    where
+        R: Fn(&T) -> &[ClamScanResult],
+    {
+        let name = command.name();
+        self.finish_command(command, &result, |r| instrument::classify_scan(results(r)));
+        self.observers.finished(name, &result, results);
+        result
+    }
+
+    /// Records the latency and outcome of a `command` started with `instrument::Command::start`,
+    /// without notifying the observers, classifying a successful result with `classify`.
+    fn finish_command<T, C>(
+        &self,
+        command: instrument::Command,
+        result: &ClamResult<T>,
+        classify: C,
+    ) where
+        C: FnOnce(&T) -> &'static str,
+    {
+        if let Some(latency) = &self.latency {
+            latency.record(command.name(), command.elapsed(), result.is_err());
+        }
+        command.finish(result, classify);
+    }
+
+    /// Returns the observers registered with `ClamClientBuilder::observer`.
+    pub(crate) fn observers(&self) -> &Observers {
+        &self.observers
+    }
+
+    /// Runs `operation`, recording its latency as `command` if latency collection is enabled.
+    fn timed<T, F>(&self, command: &'static str, operation: F) -> ClamResult<T>
+    where
+        F: FnOnce() -> ClamResult<T>,
+    {
+        let Some(latency) = &self.latency else {
+            return operation();
+        };
+
+        let start = Instant::now();
+        let result = operation();
+        latency.record(command, start.elapsed(), result.is_err());
+        result
+    }
+
+    /// Runs `operation` under the configured `RetryPolicy`, or exactly once if there is none,
+    /// guarded by the circuit breaker if one is configured.
+    fn retrying<T, F>(&self, mut operation: F) -> ClamResult<T>
+    where
+        F: FnMut() -> ClamResult<T>,
+    {
+        let run = || match &self.retry_policy {
+            Some(policy) => policy.run(operation),
+            None => operation(),
+        };
+
+        match &self.circuit {
+            Some(circuit) => circuit.call(run),
+            None => run(),
+        }
+    }
+
+    /// Returns the maximum stream length set by `ClamClientBuilder::stream_max_length`, if any.
+    pub(crate) fn stream_max_length(&self) -> Option<u64> {
+        self.stream_max_length
+    }
+
+    /// Returns every endpoint of this client, in the order they are tried.
+    pub(crate) fn endpoints(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        std::iter::once(self.socket).chain(self.fallbacks.iter().copied())
+    }
+
+    /// Returns every resolved address of `endpoint`, in the order they are attempted.
+    pub(crate) fn addresses(&self, endpoint: SocketAddr) -> Vec<SocketAddr> {
+        match self.addresses.get(&endpoint) {
+            Some(addresses) => addresses.clone(),
+            None => vec![endpoint],
+        }
+    }
+
+    /// Issues `PING` to a single endpoint, without retrying or falling back to other endpoints.
+    pub(crate) fn ping_endpoint(&self, endpoint: SocketAddr) -> bool {
+        let mut connection = match self.connect_to(endpoint) {
+            Ok(connection) => connection,
+            Err(_) => return false,
+        };
+
+        let terminator = self.delimiter.terminator();
+        connection
+            .write_all(&self.delimiter.command("PING"))
+            .is_ok()
+            && protocol::read_reply(
+                &mut BufReader::new(connection),
+                terminator,
+                ReplyLength::Single,
+            )
+            .is_ok_and(|resp| resp.strip_suffix(&[terminator]).unwrap_or(&resp) == b"PONG")
+    }
+
+    /// Simple helper function to create a new connection to the first available ClamD endpoint.
+    pub(crate) fn connect(&self) -> ClamResult<Connection> {
+        self.connect_endpoint().map(|(connection, _)| connection)
+    }
+
+    /// Creates a new connection as `connect` does, along with the endpoint connected to, or
+    /// `None` when connecting over a Unix socket.
+    fn connect_endpoint(&self) -> ClamResult<(Connection, Option<SocketAddr>)> {
+        #[cfg(unix)]
+        let unix = self.unix_socket.is_some();
+        #[cfg(not(unix))]
+        let unix = false;
+
+        self.failover(|endpoint| {
+            let connection = self.connect_to(endpoint)?;
+            Ok((connection, Some(endpoint).filter(|_| !unix)))
+        })
+    }
+
+    /// Creates a new connection to the given ClamD endpoint, applying any configured read and
+    /// write timeouts to the established connection, tunnelling through the proxy and performing
+    /// the TLS handshake if either is configured.
+    fn connect_to(&self, endpoint: SocketAddr) -> ClamResult<Connection> {
+        self.open_connection(endpoint)
+            .map_err(|e| e.in_phase(Phase::Connecting, &self.describe_endpoint(Some(endpoint))))
+    }
+
+    /// Describes `endpoint` for the context of an error, or the path of the Unix socket if this
+    /// client connects over one.
+    fn describe_endpoint(&self, endpoint: Option<SocketAddr>) -> String {
+        #[cfg(unix)]
+        if let Some(path) = &self.unix_socket {
+            return path.display().to_string();
+        }
+
+        endpoint.map_or_else(|| "Unix socket".to_owned(), |endpoint| endpoint.to_string())
+    }
+
+    /// Implements `connect_to`, without recording the context of any error.
+    fn open_connection(&self, endpoint: SocketAddr) -> ClamResult<Connection> {
+        if !self.reconnect {
+            return Err(cannot_reconnect());
+        }
+
+        #[cfg(unix)]
+        if let Some(path) = &self.unix_socket {
+            let handle = transport::connect_unix(path)?;
+            handle.set_read_timeout(self.read_timeout)?;
+            handle.set_write_timeout(self.write_timeout)?;
+            self.observers.connected(&path.display());
+            return Ok(Connection::Unix(handle));
+        }
+
+        instrument::record_endpoint(endpoint);
+        let connection = match &self.proxy {
+            Some(proxy) => transport::connect_tcp(proxy.addr(), self.socket_options, self.timeout),
+            None => {
+                transport::connect_any(&self.addresses(endpoint), self.socket_options, self.timeout)
+            }
+        };
+
+        match connection {
+            Ok(mut handle) => {
+                handle.set_read_timeout(self.read_timeout)?;
+                handle.set_write_timeout(self.write_timeout)?;
+                transport::set_tcp_options(&handle, self.nodelay, self.tcp_keepalive)?;
+
+                if let Some(proxy) = &self.proxy {
+                    proxy.tunnel(&mut handle, endpoint)?;
+                }
+                self.observers.connected(&endpoint);
+
+                #[cfg(feature = "tls")]
+                if let Some(tls) = &self.tls {
+                    return tls.connect(handle);
+                }
+
+                Ok(Connection::Tcp(handle))
+            }
+            Err(e) => Err(ClamError::ConnectionError(e)),
+        }
+    }
+}
+
+/// Returns the path a scan result relates to, if ClamD reported one.
+fn result_path(result: &ClamScanResult) -> Option<&str> {
+    match result {
+        ClamScanResult::Found(path, _) => Some(path),
+        ClamScanResult::Error(e) => e.path(),
+        ClamScanResult::Ok => None,
+    }
+}
+
+/// Resolves an IP address or hostname and port into every `SocketAddr` of a ClamD endpoint, in
+/// the order they should be attempted. IP addresses are used as is without a DNS lookup.
+fn resolve(host: &str, port: u16) -> ClamResult<Vec<SocketAddr>> {
+    if let Ok(addr) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(addr, port)]);
+    }
+
+    match (host, port).to_socket_addrs() {
+        Ok(addrs) => {
+            let addrs = transport::interleave(addrs.collect());
+            if addrs.is_empty() {
+                let e = std::io::ErrorKind::NotFound.into();
+                return Err(ClamError::ResolutionError(host.to_owned(), e));
+            }
+            Ok(addrs)
+        }
+        Err(e) => Err(ClamError::ResolutionError(host.to_owned(), e)),
+    }
+}
+
+/// The error for a command which needs a connection of its own on a client created from an
+/// existing stream, which may only use that stream.
+pub(crate) fn cannot_reconnect() -> ClamError {
+    let message = "the client may only use the connection it was created from";
+    ClamError::ConnectionError(std::io::Error::new(
+        std::io::ErrorKind::NotConnected,
+        message,
+    ))
+}
+
+/// Writes the contents of `stream` to an established connection using the `INSTREAM` chunk
+/// format, each chunk is prefixed with its length as a 4 byte big-endian integer and the
+/// stream is terminated with a zero length chunk. The `INSTREAM` command itself must already
+/// have been written to the connection.
+///
+/// *Arguments*:
+///
+/// - `connection`: The established connection to write to.
+/// - `stream`: The object to be streamed.
+/// - `max_length`: If set, the stream is abandoned with `ClamError::StreamTooLarge` before any
+///   chunk which would take the total past this many bytes is written.
+/// - `progress`: Called with the total number of bytes written after each chunk.
+pub(crate) fn write_stream<W: Write, T: Read>(
+    connection: &mut W,
+    stream: T,
+    max_length: Option<u64>,
+    observers: &Observers,
+    progress: &mut dyn FnMut(u64),
+) -> ClamResult<()> {
+    let mut reader = BufReader::new(stream);
+    let mut total: u64 = 0;
+
+    loop {
+        let bytes_read = {
+            let buf = reader.fill_buf().map_err(ClamError::LocalIoError)?;
+            if buf.is_empty() {
+                break;
+            }
+            let buf = &buf[..buf.len().min(protocol::MAX_CHUNK_LEN)];
+            total += buf.len() as u64;
+            if let Some(limit) = max_length {
+                if total > limit {
+                    return Err(ClamError::StreamTooLarge(limit));
+                }
+            }
+            protocol::write_chunk(connection, buf).map_err(ClamError::CommandError)?;
+            buf.len()
+        };
+        reader.consume(bytes_read);
+        observers.streamed(bytes_read as u64);
+        progress(total);
+    }
+
+    connection_write(connection, &protocol::END_OF_STREAM)?;
+    instrument::record_bytes(total);
+    Ok(())
+}
+
+/// The reply ClamD sends when it gives up waiting for the client to send a complete command.
+pub(crate) const COMMAND_READ_TIMED_OUT_REPLY: &str = "COMMAND READ TIMED OUT";
+
+/// Fails with `ClamError::CommandReadTimedOut` if ClamD's reply reports that it timed out waiting
+/// for the command, otherwise returns the reply unchanged.
+pub(crate) fn check_reply(reply: String) -> ClamResult<String> {
+    if reply.trim_end_matches(['\0', '\n']) == COMMAND_READ_TIMED_OUT_REPLY {
+        Err(ClamError::CommandReadTimedOut)
+    } else {
+        Ok(reply)
+    }
+}
+
+/// The reply with which ClamD rejects a stream exceeding its `StreamMaxLength`.
+const STREAM_SIZE_LIMIT_REPLY: &str = "INSTREAM size limit exceeded";
+
+/// Takes the persistent session from `persistent` if it may be reused, that is unless it has
+/// died, been closed by ClamD or been idle for too long.
+fn reusable_session(persistent: &mut Option<(ClamSession, Instant)>) -> Option<ClamSession> {
+    match persistent.take() {
+        Some((session, last_used))
+            if last_used.elapsed() < DEFAULT_IDLE_TIMEOUT && session.is_connected() =>
+        {
+            Some(session)
+        }
+        _ => None,
+    }
+}
+
+/// Parses ClamD's reply to a scan, with `ClamScanResult::parse_strict` if `strict` is set.
+pub(crate) fn parse_results(reply: &str, strict: bool) -> ClamResult<Vec<ClamScanResult>> {
+    if strict {
+        ClamScanResult::parse_strict(reply)
+    } else {
+        Ok(ClamScanResult::parse(reply))
+    }
+}
+
+/// Converts ClamD's reply to an `INSTREAM` into a single `ClamScanResult`, failing with
+/// `ClamError::StreamSizeLimitExceeded` if ClamD rejected the stream as too large, or with
+/// `ClamError::ProtocolError` if `strict` is set and the reply is not recognised.
+pub(crate) fn stream_result(reply: String, strict: bool) -> ClamResult<ClamScanResult> {
+    let reply = check_reply(reply)?;
+    if reply.contains(STREAM_SIZE_LIMIT_REPLY) {
+        let message = reply.trim_end_matches(['\0', '\n']).to_owned();
+        return Err(ClamError::StreamSizeLimitExceeded(message));
+    }
+
+    match parse_results(&reply, strict)?.first() {
+        Some(singular) => Ok(singular.clone()),
+        None => Err(ClamError::InvalidData(reply)),
+    }
+}
+
+/// Called when writing a stream fails, ClamD closes the connection once a stream exceeds its
+/// `StreamMaxLength`, so any reply it sent first is read to report that rather than the broken
+/// connection.
+pub(crate) fn recover_stream_error<R: Read>(connection: &mut R, error: ClamError) -> ClamError {
+    if let ClamError::CommandError(_) = error {
+        let mut reply = Vec::new();
+        let _ = connection.read_to_end(&mut reply);
+        let reply = String::from_utf8_lossy(&reply);
+
+        if reply.contains(STREAM_SIZE_LIMIT_REPLY) {
+            let message = reply.trim_end_matches(['\0', '\n']).to_owned();
+            return ClamError::StreamSizeLimitExceeded(message);
+        }
+    }
+
+    error
+}
+
+/// Simple reusable wrapper function for writing a byte stream to an established connection,
+/// continuing until all of `data` has been written, however little each write accepts.
+///
+/// *Arguments*:
+///
+/// - `connection`: The established connection to write to.
+/// - `data`: The byte stream to send.
+pub(crate) fn connection_write<W: Write>(connection: &mut W, data: &[u8]) -> ClamResult<()> {
+    connection.write_all(data).map_err(ClamError::CommandError)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::client::ClamClient;
+
+    #[test]
+    fn test_client_no_timeout() {
+        let cclient = ClamClient::new("127.0.0.1", 3310).unwrap();
+        let socket_addr = std::net::SocketAddr::new(::std::net::IpAddr::from([127, 0, 0, 1]), 3310);
+        assert_eq!(cclient.socket, socket_addr);
+        assert_eq!(cclient.timeout, None);
+    }
+
+    #[test]
+    fn test_client_with_timeout() {
+        let cclient = ClamClient::new_with_timeout("127.0.0.1", 3310, 60).unwrap();
+        let socket_addr = std::net::SocketAddr::new(::std::net::IpAddr::from([127, 0, 0, 1]), 3310);
+        assert_eq!(cclient.socket, socket_addr);
+        assert_eq!(cclient.timeout, Some(std::time::Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_client_builder_timeouts() {
+        let cclient = ClamClient::builder("127.0.0.1", 3310)
+            .connect_timeout(std::time::Duration::from_secs(5))
+            .read_timeout(std::time::Duration::from_secs(30))
+            .write_timeout(std::time::Duration::from_secs(10))
+            .build()
+            .unwrap();
+        assert_eq!(cclient.timeout, Some(std::time::Duration::from_secs(5)));
+        assert_eq!(
+            cclient.read_timeout,
+            Some(std::time::Duration::from_secs(30))
+        );
+        assert_eq!(
+            cclient.write_timeout,
+            Some(std::time::Duration::from_secs(10))
+        );
+    }
+
+    #[test]
+    fn test_client_builder_zero_timeouts() {
+        let port = crate::testing::fake_clamd(|_| b"PONG".to_vec());
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .connect_timeout(std::time::Duration::ZERO)
+            .read_timeout(std::time::Duration::ZERO)
+            .write_timeout(std::time::Duration::ZERO)
+            .build()
+            .unwrap();
+
+        // A zero timeout, which sockets reject, is treated as no timeout at all
+        assert_eq!(cclient.timeout, None);
+        assert_eq!(cclient.read_timeout, None);
+        assert_eq!(cclient.write_timeout, None);
+        assert!(cclient.ping());
+    }
+
+    #[test]
+    fn test_client_persistent_connection() {
+        let connections = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let counter = connections.clone();
+
+        std::thread::spawn(move || {
+            use std::io::{BufRead, Write};
+
+            for connection in listener.incoming() {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let mut connection = std::io::BufReader::new(connection.unwrap());
+                let mut id = 0;
+                loop {
+                    let mut command = Vec::new();
+                    if connection.read_until(b'\0', &mut command).unwrap_or(0) == 0 {
+                        break;
+                    }
+                    if command == b"zPING\0" {
+                        id += 1;
+                        let reply = format!("{}: PONG\0", id);
+                        connection.get_mut().write_all(reply.as_bytes()).unwrap();
+                    }
+                }
+            }
+        });
+
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .persistent_connection(true)
+            .build()
+            .unwrap();
+
+        assert!(cclient.ping());
+        assert!(cclient.ping());
+        assert!(cclient.ping());
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_client_persistent_connection_reconnects() {
+        let (port, connections) = crate::testing::restarting_clamd(|cmd| {
+            if cmd == b"zSCAN /file" {
+                b"/file: OK".to_vec()
+            } else {
+                b"PONG".to_vec()
+            }
+        });
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .persistent_connection(true)
+            .build()
+            .unwrap();
+
+        assert!(cclient.ping());
+        assert_eq!(
+            cclient.scan_path("/file", false).unwrap(),
+            vec![crate::response::ClamScanResult::Ok]
+        );
+        assert!(cclient.ping());
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_client_socket_options() {
+        let port = crate::testing::fake_clamd(|_| b"PONG".to_vec());
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .nodelay(true)
+            .tcp_keepalive(std::time::Duration::from_secs(30))
+            .send_buffer_size(256 * 1024)
+            .recv_buffer_size(256 * 1024)
+            .build()
+            .unwrap();
+
+        match cclient.connect_to(cclient.socket).unwrap() {
+            crate::transport::Connection::Tcp(stream) => {
+                let socket = socket2::SockRef::from(&stream);
+                assert!(stream.nodelay().unwrap());
+                assert!(socket.keepalive().unwrap());
+                assert!(socket.send_buffer_size().unwrap() >= 256 * 1024);
+                assert!(socket.recv_buffer_size().unwrap() >= 256 * 1024);
+            }
+            _ => panic!("expected a TCP connection"),
+        }
+        assert!(cclient.ping());
+    }
+
+    #[test]
+    fn test_client_local_address() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .local_address("127.0.0.2".parse().unwrap())
+            .build()
+            .unwrap();
+
+        let _connection = cclient.connect_to(cclient.socket).unwrap();
+        let (_, peer) = listener.accept().unwrap();
+        assert_eq!(peer.ip(), "127.0.0.2".parse::<std::net::IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn test_client_shutdown_and_wait() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        std::thread::spawn(move || {
+            use std::io::BufRead;
+
+            let (connection, _) = listener.accept().unwrap();
+            let mut command = Vec::new();
+            std::io::BufReader::new(connection)
+                .read_until(b'\0', &mut command)
+                .unwrap();
+            assert_eq!(command, b"zSHUTDOWN\0");
+            std::thread::sleep(std::time::Duration::from_millis(200));
+        });
+
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+        assert!(cclient
+            .shutdown_and_wait(std::time::Duration::from_secs(5))
+            .unwrap());
+
+        let port = crate::testing::fake_clamd(|_| Vec::new());
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+        assert!(!cclient
+            .shutdown_and_wait(std::time::Duration::from_millis(200))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_client_shared_between_threads() {
+        let port = crate::testing::fake_clamd(|_| b"PONG".to_vec());
+        let cclient = std::sync::Arc::new(
+            ClamClient::builder("127.0.0.1", port)
+                .persistent_connection(true)
+                .build()
+                .unwrap(),
+        );
+
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let cclient = cclient.clone();
+                std::thread::spawn(move || (0..10).all(|_| cclient.ping()))
+            })
+            .collect();
+
+        for worker in workers {
+            assert!(worker.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_client_stream_max_length() {
+        let port = crate::testing::fake_clamd(|_| b"stream: OK".to_vec());
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .stream_max_length(4)
+            .build()
+            .unwrap();
+
+        assert!(cclient.scan_stream(&b"1234"[..]).is_ok());
+        match cclient.scan_stream(&b"12345"[..]) {
+            Err(crate::error::ClamError::StreamTooLarge(4)) => {}
+            other => panic!("Expected StreamTooLarge, got {:?}", other),
         }
     }
-}
 
-/// Creates a new instance of `ClamClient`.
-fn build(ip: &str, port: u16, timeout: Option<Duration>) -> ClamResult<ClamClient> {
-    let addr: IpAddr = match ip.parse() {
-        Ok(v) => v,
-        Err(e) => return Err(ClamError::InvalidIpAddress(e)),
-    };
+    #[test]
+    fn test_client_scan_stream_with_limit() {
+        let port = crate::testing::fake_clamd(|_| b"stream: OK".to_vec());
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .stream_max_length(8)
+            .build()
+            .unwrap();
 
-    let socket = SocketAddr::new(addr, port);
+        assert!(cclient.scan_stream_with_limit(&b"data"[..], 4).is_ok());
+        match cclient.scan_stream_with_limit(&b"payload"[..], 4) {
+            Err(crate::error::ClamError::StreamTooLarge(4)) => {}
+            other => panic!("unexpected result {:?}", other.is_ok()),
+        }
+        match cclient.scan_stream_with_limit(&b"payload-too-long"[..], 100) {
+            Err(crate::error::ClamError::StreamTooLarge(8)) => {}
+            other => panic!("unexpected result {:?}", other.is_ok()),
+        }
+    }
 
-    Ok(ClamClient { timeout, socket })
-}
+    #[test]
+    fn test_client_scan_stream_with_progress() {
+        let port = crate::testing::fake_clamd(|_| b"stream: OK".to_vec());
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+        let data = vec![0u8; 10000];
+        let mut reported = Vec::new();
 
-#[cfg(test)]
-mod test {
-    use crate::client::ClamClient;
+        let result = cclient.scan_stream_with_progress(&data[..], Some(10000), |sent, total| {
+            reported.push((sent, total))
+        });
+
+        assert!(result.is_ok());
+        assert!(reported.len() > 1);
+        assert_eq!(reported.last(), Some(&(10000, Some(10000))));
+    }
 
     #[test]
-    fn test_client_no_timeout() {
-        let cclient = ClamClient::new("127.0.0.1", 3310).unwrap();
-        let socket_addr = std::net::SocketAddr::new(::std::net::IpAddr::from([127, 0, 0, 1]), 3310);
-        assert_eq!(cclient.socket, socket_addr);
-        assert_eq!(cclient.timeout, None);
+    fn test_client_scan_streams_parallel() {
+        let port = crate::testing::fake_clamd(|cmd| {
+            if cmd.ends_with(b"bad") {
+                b"stream: Sig-One FOUND".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+        let inputs: Vec<&[u8]> = vec![b"good", b"bad", b"good", b"bad", b"good"];
+
+        let results = cclient.scan_streams_parallel(inputs, 2);
+
+        assert_eq!(results.len(), 5);
+        for (i, result) in results.into_iter().enumerate() {
+            let expected = if i % 2 == 1 {
+                crate::response::ClamScanResult::Found("stream".to_string(), "Sig-One".to_string())
+            } else {
+                crate::response::ClamScanResult::Ok
+            };
+            assert_eq!(result.unwrap(), expected);
+        }
     }
 
     #[test]
-    fn test_client_with_timeout() {
-        let cclient = ClamClient::new_with_timeout("127.0.0.1", 3310, 60).unwrap();
-        let socket_addr = std::net::SocketAddr::new(::std::net::IpAddr::from([127, 0, 0, 1]), 3310);
-        assert_eq!(cclient.socket, socket_addr);
-        assert_eq!(cclient.timeout, Some(std::time::Duration::from_secs(60)));
+    fn test_client_scan_bytes() {
+        let port = crate::testing::fake_clamd(|cmd| {
+            if cmd == b"zINSTREAMpayload" {
+                b"stream: OK".to_vec()
+            } else {
+                b"stream: Unexpected-Payload FOUND".to_vec()
+            }
+        });
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+        assert_eq!(
+            cclient.scan_bytes(b"payload").unwrap(),
+            crate::response::ClamScanResult::Ok
+        );
+    }
+
+    #[test]
+    fn test_client_scan_file() {
+        let port = crate::testing::fake_clamd(|_| b"stream: Eicar-Test-Signature FOUND".to_vec());
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+        let path = std::env::temp_dir().join("clam_client_test_scan_file");
+        std::fs::write(&path, b"contents").unwrap();
+
+        let result = cclient.scan_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            result.unwrap(),
+            crate::response::ClamScanResult::Found(
+                path.display().to_string(),
+                "Eicar-Test-Signature".to_string()
+            )
+        );
+
+        let missing = cclient.scan_file(std::env::temp_dir().join("clam_client_test_missing"));
+        assert!(matches!(
+            missing,
+            Err(crate::error::ClamError::LocalIoError(ref e)) if e.kind() == std::io::ErrorKind::NotFound
+        ));
+
+        struct Unreadable;
+        impl std::io::Read for Unreadable {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::PermissionDenied.into())
+            }
+        }
+        assert!(matches!(
+            cclient.scan_stream(Unreadable),
+            Err(crate::error::ClamError::LocalIoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_client_scan_stream_reads_single_reply() {
+        let port = crate::testing::lingering_clamd(b"stream: OK");
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .read_timeout(std::time::Duration::from_secs(5))
+            .build()
+            .unwrap();
+
+        // The reply is complete at its terminator, even though the connection is left open
+        let started = std::time::Instant::now();
+        assert_eq!(
+            cclient.scan_bytes(b"data").unwrap(),
+            crate::response::ClamScanResult::Ok
+        );
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_client_scan_path_all_matches() {
+        let port = crate::testing::fake_clamd(|cmd| {
+            if cmd == b"zALLMATCHSCAN /some/file" {
+                b"/some/file: Sig-One FOUND\0/some/file: Sig-Two FOUND".to_vec()
+            } else {
+                b"UNKNOWN COMMAND".to_vec()
+            }
+        });
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .collect_latency(true)
+            .build()
+            .unwrap();
+        let results = cclient.scan_path_all_matches("/some/file").unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            results[1],
+            crate::response::ClamScanResult::Found("/some/file".to_string(), "Sig-Two".to_string())
+        );
+
+        let metrics = cclient.client_metrics().unwrap();
+        assert_eq!(metrics.commands["ALLMATCHSCAN"].count, 1);
+    }
+
+    #[test]
+    fn test_client_scan_path_iter() {
+        let port = crate::testing::fake_clamd(|cmd| {
+            if cmd == b"zCONTSCAN /dir" {
+                b"/dir/a: Sig-One FOUND\0/dir/b: Sig-Two FOUND\0/dir/c: OK".to_vec()
+            } else {
+                b"UNKNOWN COMMAND".to_vec()
+            }
+        });
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+        let results = cclient
+            .scan_path_iter("/dir", true)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[1],
+            crate::response::ClamScanResult::Found("/dir/b".to_string(), "Sig-Two".to_string())
+        );
+        assert_eq!(results[2], crate::response::ClamScanResult::Ok);
+    }
+
+    #[test]
+    fn test_client_scan_path_iter_instrumented() {
+        use crate::circuit::{CircuitBreaker, CircuitState};
+        use crate::observer::ClamObserver;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+
+        impl ClamObserver for Recorder {
+            fn on_result(&self, command: &str, result: &crate::response::ClamScanResult) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {}", command, result));
+            }
+        }
+
+        let port = crate::testing::fake_clamd(|_| b"/dir/a: Sig-One FOUND\0/dir/b: OK".to_vec());
+        let recorder = Arc::new(Recorder::default());
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .observer(recorder.clone())
+            .collect_latency(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(cclient.scan_path_iter("/dir", true).unwrap().count(), 2);
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            ["CONTSCAN /dir/a: Sig-One FOUND", "CONTSCAN OK"]
+        );
+        let metrics = cclient.client_metrics().unwrap();
+        assert_eq!(metrics.commands["CONTSCAN"].count, 1);
+
+        // An open circuit refuses the scan before connecting
+        let cclient = ClamClient::builder("127.0.0.1", 1)
+            .circuit_breaker(CircuitBreaker::new(1))
+            .build()
+            .unwrap();
+        assert!(cclient.scan_path_iter("/dir", true).is_err());
+        assert_eq!(cclient.circuit_state(), Some(CircuitState::Open));
+        assert!(matches!(
+            cclient.scan_path_iter("/dir", true).err().unwrap(),
+            crate::error::ClamError::CircuitOpen
+        ));
+    }
+
+    #[test]
+    fn test_client_newline_delimiter() {
+        let port = crate::testing::fake_clamd(|cmd| match cmd {
+            b"nPING" => b"PONG".to_vec(),
+            b"nCONTSCAN /dir" => b"/dir/a: Sig-One FOUND\n/dir/b: OK".to_vec(),
+            b"nINSTREAMdata" => b"stream: OK".to_vec(),
+            _ => b"UNKNOWN COMMAND".to_vec(),
+        });
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .command_delimiter(super::CommandDelimiter::Newline)
+            .build()
+            .unwrap();
+
+        assert!(cclient.ping());
+        assert_eq!(cclient.scan_path("/dir", true).unwrap().len(), 2);
+        assert_eq!(cclient.scan_path_iter("/dir", true).unwrap().count(), 2);
+        assert_eq!(
+            cclient.scan_bytes(b"data").unwrap(),
+            crate::response::ClamScanResult::Ok
+        );
+    }
+
+    #[test]
+    fn test_client_wait_until_ready() {
+        let port = crate::testing::fake_clamd(|_| b"PONG".to_vec());
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+        assert!(cclient
+            .wait_until_ready(std::time::Duration::from_secs(1))
+            .is_ok());
+
+        let cclient = ClamClient::new("127.0.0.1", 1).unwrap();
+        let start = std::time::Instant::now();
+        match cclient.wait_until_ready(std::time::Duration::from_millis(250)) {
+            Err(crate::error::ClamError::ConnectionError(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::TimedOut)
+            }
+            other => panic!("unexpected result {:?}", other.is_ok()),
+        }
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_client_send_raw() {
+        let port = crate::testing::fake_clamd(|cmd| {
+            if cmd == b"zVERSIONCOMMANDS" {
+                b"ClamAV 1.0.0| COMMANDS: SCAN PING".to_vec()
+            } else {
+                b"UNKNOWN COMMAND".to_vec()
+            }
+        });
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+
+        assert_eq!(
+            cclient.send_raw(b"zVERSIONCOMMANDS\0").unwrap(),
+            b"ClamAV 1.0.0| COMMANDS: SCAN PING\0"
+        );
+    }
+
+    #[test]
+    fn test_client_database_info() {
+        let port = crate::testing::fake_clamd(|cmd| {
+            if cmd == b"zVERSION" {
+                b"ClamAV 0.103.8/26900/Mon May  1 07:10:00 2023".to_vec()
+            } else {
+                b"UNKNOWN COMMAND".to_vec()
+            }
+        });
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+
+        assert_eq!(cclient.database_info().unwrap().version, 26900);
+        #[cfg(feature = "chrono")]
+        assert!(cclient
+            .is_database_older_than(std::time::Duration::from_secs(24 * 3600))
+            .unwrap());
+    }
+
+    #[test]
+    fn test_client_multiscan_path() {
+        let port = crate::testing::fake_clamd(|cmd| {
+            if cmd == b"zMULTISCAN /dir" {
+                b"/dir/c: Sig-One FOUND\0/dir/a: Access denied. ERROR\0/dir/b: Sig-Two FOUND"
+                    .to_vec()
+            } else {
+                b"UNKNOWN COMMAND".to_vec()
+            }
+        });
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .collect_latency(true)
+            .build()
+            .unwrap();
+        let results = cclient.multiscan_path("/dir").unwrap();
+
+        assert_eq!(
+            results,
+            vec![
+                crate::response::ClamScanResult::Error(
+                    crate::response::ClamScanError::AccessDenied(Some("/dir/a".to_string()))
+                ),
+                crate::response::ClamScanResult::Found("/dir/b".to_string(), "Sig-Two".to_string()),
+                crate::response::ClamScanResult::Found("/dir/c".to_string(), "Sig-One".to_string()),
+            ]
+        );
+
+        let metrics = cclient.client_metrics().unwrap();
+        assert_eq!(metrics.commands["MULTISCAN"].count, 1);
+    }
+
+    #[test]
+    fn test_client_scan_path_report() {
+        let port = crate::testing::fake_clamd(|_| {
+            b"/dir/a: Sig-One FOUND\0/dir/b: Access denied. ERROR\0/dir: OK".to_vec()
+        });
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+        let report = cclient.scan_path_report("/dir", true).unwrap();
+
+        assert_eq!(report.infected_count(), 1);
+        assert_eq!(report.error_count(), 1);
+        assert_eq!(report.clean_count(), 1);
+        assert!(report.duration > std::time::Duration::default());
+    }
+
+    #[test]
+    fn test_client_failover() {
+        let port = crate::testing::fake_clamd(|_| b"/some/file: OK".to_vec());
+        let cclient = ClamClient::builder("127.0.0.1", 1)
+            .fallback("127.0.0.1", port)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            cclient.scan_path("/some/file", false).unwrap(),
+            vec![crate::response::ClamScanResult::Ok]
+        );
+        assert_eq!(
+            cclient.scan_bytes(b"data").unwrap(),
+            crate::response::ClamScanResult::Ok
+        );
+    }
+
+    #[test]
+    fn test_client_builder_invalid_ip() {
+        assert!(ClamClient::builder("not an ip", 3310).build().is_err());
+        assert!(ClamClient::builder("127.0.0.1", 3310)
+            .fallback("not an ip", 3310)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn test_client_stream_size_limit_exceeded() {
+        let port = crate::testing::fake_clamd(|_| b"INSTREAM size limit exceeded. ERROR".to_vec());
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+
+        match cclient.scan_stream(&b"data"[..]) {
+            Err(crate::error::ClamError::StreamSizeLimitExceeded(message)) => {
+                assert_eq!(message, "INSTREAM size limit exceeded. ERROR")
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_client_command_read_timed_out() {
+        let port = crate::testing::fake_clamd(|_| b"COMMAND READ TIMED OUT".to_vec());
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+
+        assert!(matches!(
+            cclient.version(),
+            Err(crate::error::ClamError::CommandReadTimedOut)
+        ));
+        assert!(matches!(
+            cclient.scan_stream(&b"data"[..]),
+            Err(crate::error::ClamError::CommandReadTimedOut)
+        ));
+    }
+
+    #[test]
+    fn test_client_scan_paths() {
+        let port = crate::testing::fake_clamd(|cmd| {
+            let path = String::from_utf8_lossy(&cmd[b"zSCAN ".len()..]).into_owned();
+            if path.ends_with("bad") {
+                format!("{}: Sig FOUND", path).into_bytes()
+            } else {
+                format!("{}: OK", path).into_bytes()
+            }
+        });
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+
+        let paths: Vec<String> = (0..40).map(|i| format!("/file{}", i)).collect();
+        let mut paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+        paths.push("/bad");
+
+        let results = cclient.scan_paths(&paths).unwrap();
+        assert_eq!(results.len(), 41);
+        assert_eq!(
+            results["/file39"].as_ref().unwrap(),
+            &crate::response::ClamScanResult::Ok
+        );
+        assert_eq!(
+            results["/bad"].as_ref().unwrap(),
+            &crate::response::ClamScanResult::Found("/bad".to_string(), "Sig".to_string())
+        );
+    }
+
+    #[test]
+    fn test_client_scan_paths_partial_failure() {
+        let port = crate::testing::fake_clamd(|cmd| match cmd {
+            b"zSCAN /odd" => b"/odd: something unexpected".to_vec(),
+            _ => b"/file: OK".to_vec(),
+        });
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .strict_parsing(true)
+            .build()
+            .unwrap();
+
+        let results = cclient.scan_paths(&["/file", "/odd", "/other"]).unwrap();
+        assert!(results["/file"].is_ok());
+        assert!(matches!(
+            results["/odd"],
+            Err(crate::error::ClamError::ProtocolError { .. })
+        ));
+        assert!(results["/other"].is_ok());
+
+        // Every path outstanding when the session dies fails, rather than the whole batch
+        let (port, _) = crate::testing::restarting_clamd(|_| b"/file: OK".to_vec());
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+
+        let results = cclient.scan_paths(&["/file", "/other"]).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results["/file"].is_ok());
+        assert!(matches!(
+            results["/other"],
+            Err(crate::error::ClamError::ConnectionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_client_builder_hostname() {
+        let cclient = ClamClient::builder("localhost", 3310).build().unwrap();
+        assert!(cclient.endpoint().ip().is_loopback());
+        assert_eq!(cclient.endpoint().port(), 3310);
+
+        match ClamClient::new("clamd.invalid", 3310) {
+            Err(crate::error::ClamError::ResolutionError(host, _)) => {
+                assert_eq!(host, "clamd.invalid")
+            }
+            _ => panic!("expected a resolution error"),
+        }
+    }
+
+    #[test]
+    fn test_client_hostname_connect() {
+        // localhost may resolve to ::1 as well as 127.0.0.1, where the fake ClamD listens
+        let port = crate::testing::fake_clamd(|_| b"PONG".to_vec());
+        let cclient = ClamClient::new("localhost", port).unwrap();
+        assert!(cclient.ping());
+    }
+
+    #[test]
+    fn test_client_from_clamd_conf() {
+        let port = crate::testing::fake_clamd(|_| b"PONG".to_vec());
+        let path = std::env::temp_dir().join("clam_client_test_clamd_tcp.conf");
+        std::fs::write(&path, format!("TCPSocket {}\nTCPAddr 127.0.0.1\n", port)).unwrap();
+
+        let cclient = ClamClient::from_clamd_conf(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(cclient.unwrap().ping());
+        assert!(ClamClient::from_clamd_conf("/nonexistent/clamd.conf").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_client_unix_socket() {
+        use std::io::{BufRead, Write};
+        use std::os::unix::net::UnixListener;
+
+        let socket = std::env::temp_dir().join("clam_client_test_clamd.sock");
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut command = Vec::new();
+            std::io::BufReader::new(&stream)
+                .read_until(b'\0', &mut command)
+                .unwrap();
+            assert_eq!(command, b"zPING\0");
+            (&stream).write_all(b"PONG\0").unwrap();
+        });
+
+        let conf = std::env::temp_dir().join("clam_client_test_clamd_unix.conf");
+        std::fs::write(&conf, format!("LocalSocket {}\n", socket.display())).unwrap();
+
+        let cclient = ClamClient::from_clamd_conf(&conf);
+        std::fs::remove_file(&conf).unwrap();
+
+        assert!(cclient.unwrap().ping());
+        std::fs::remove_file(&socket).unwrap();
+    }
+
+    #[test]
+    fn test_client_observer() {
+        use crate::observer::ClamObserver;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+
+        impl ClamObserver for Recorder {
+            fn on_connect(&self, endpoint: &str) {
+                self.0.lock().unwrap().push(format!("connect {}", endpoint));
+            }
+
+            fn on_command_sent(&self, command: &str) {
+                self.0.lock().unwrap().push(format!("sent {}", command));
+            }
+
+            fn on_bytes_streamed(&self, bytes: u64) {
+                self.0.lock().unwrap().push(format!("streamed {}", bytes));
+            }
+
+            fn on_result(&self, command: &str, result: &crate::response::ClamScanResult) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {}", command, result));
+            }
+
+            fn on_error(&self, command: &str, _: &crate::error::ClamError) {
+                self.0.lock().unwrap().push(format!("{} failed", command));
+            }
+        }
+
+        let port = crate::testing::fake_clamd(|cmd| match cmd {
+            b"zSTATS" | b"zVERSION" => b"garbage".to_vec(),
+            _ => b"stream: Eicar-Test-Signature FOUND".to_vec(),
+        });
+        let recorder = Arc::new(Recorder::default());
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .observer(recorder.clone())
+            .build()
+            .unwrap();
+
+        assert!(cclient.scan_stream(&b"infected"[..]).is_ok());
+        let endpoint = format!("127.0.0.1:{}", port);
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                format!("connect {}", endpoint),
+                "sent INSTREAM".to_owned(),
+                "streamed 8".to_owned(),
+                "INSTREAM stream: Eicar-Test-Signature FOUND".to_owned(),
+            ]
+        );
+
+        recorder.0.lock().unwrap().clear();
+        assert!(cclient.stats().is_err());
+        assert_eq!(
+            recorder.0.lock().unwrap().last().map(String::as_str),
+            Some("STATS failed")
+        );
+    }
+
+    #[test]
+    fn test_client_self_test() {
+        use crate::response::SelfTestResult;
+
+        let mock = crate::mock::MockClamd::start().unwrap();
+        let result = mock.client().unwrap().self_test().unwrap();
+        assert_eq!(result, SelfTestResult::Passed("Eicar-Signature".to_owned()));
+        assert!(result.is_passed());
+
+        let port = crate::testing::fake_clamd(|_| b"stream: OK".to_vec());
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+        assert_eq!(
+            cclient.self_test().unwrap(),
+            SelfTestResult::Failed(crate::response::ClamScanResult::Ok)
+        );
+    }
+
+    #[test]
+    fn test_client_scan_stream_outcome() {
+        let port = crate::testing::fake_clamd(|_| b"stream: Eicar-Test-Signature FOUND".to_vec());
+        let endpoint = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+
+        for persistent in [false, true] {
+            let cclient = ClamClient::builder("127.0.0.1", port)
+                .persistent_connection(persistent)
+                .build()
+                .unwrap();
+            let outcome = cclient.scan_stream_outcome(&b"infected"[..]).unwrap();
+
+            assert_eq!(
+                outcome.result,
+                crate::response::ClamScanResult::Found(
+                    "stream".to_owned(),
+                    "Eicar-Test-Signature".to_owned()
+                )
+            );
+            assert_eq!(outcome.bytes_streamed, 8);
+            assert_eq!(outcome.endpoint, Some(endpoint));
+        }
+    }
+
+    #[test]
+    fn test_client_metrics() {
+        let port = crate::testing::fake_clamd(|cmd| match cmd {
+            b"zPING" => b"PONG".to_vec(),
+            b"zSTATS" | b"zVERSION" => b"garbage".to_vec(),
+            _ => b"stream: OK".to_vec(),
+        });
+
+        assert!(ClamClient::new("127.0.0.1", port)
+            .unwrap()
+            .client_metrics()
+            .is_none());
+
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .collect_latency(true)
+            .build()
+            .unwrap();
+        assert!(cclient.ping());
+        assert!(cclient.ping());
+        assert!(cclient.scan_bytes(b"clean").is_ok());
+        assert!(cclient.stats().is_err());
+
+        let metrics = cclient.client_metrics().unwrap();
+        assert_eq!(
+            metrics.commands.keys().collect::<Vec<_>>(),
+            vec!["INSTREAM", "PING", "STATS"]
+        );
+        assert_eq!(metrics.command("PING").unwrap().count, 2);
+        assert_eq!(metrics.command("STATS").unwrap().errors, 1);
+        assert!(metrics.command("INSTREAM").unwrap().p99 > std::time::Duration::ZERO);
+    }
+
+    #[test]
+    fn test_client_scan_path_lines() {
+        let port = crate::testing::fake_clamd(|_| {
+            b"/dir/a b: Sig  One FOUND\0/dir/c: Access denied. ERROR".to_vec()
+        });
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .collect_latency(true)
+            .build()
+            .unwrap();
+
+        let lines = cclient.scan_path_lines("/dir", true).unwrap();
+        let raw: Vec<&str> = lines.iter().map(|line| line.raw()).collect();
+        assert_eq!(
+            raw,
+            ["/dir/a b: Sig  One FOUND", "/dir/c: Access denied. ERROR"]
+        );
+        assert_eq!(
+            lines[0].result(),
+            &crate::response::ClamScanResult::Found("/dir/a b".to_owned(), "Sig  One".to_owned())
+        );
+
+        let metrics = cclient.client_metrics().unwrap();
+        assert_eq!(metrics.commands["CONTSCAN"].count, 1);
+
+        let port = crate::testing::fake_clamd(|_| b"COMMAND READ TIMED OUT".to_vec());
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+        assert!(matches!(
+            cclient.scan_path_lines("/dir", true),
+            Err(crate::error::ClamError::CommandReadTimedOut)
+        ));
+    }
+
+    #[test]
+    fn test_client_reads_reply_without_close() {
+        use std::io::{BufRead, Write};
+
+        // A server, or proxy, which holds the connection open after replying
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let mut held = Vec::new();
+            for stream in listener.incoming() {
+                let mut stream = std::io::BufReader::new(stream.unwrap());
+                let mut command = Vec::new();
+                stream.read_until(b'\0', &mut command).unwrap();
+                let reply: &[u8] = match &command[..] {
+                    b"zSTATS\0" => b"POOLS: 1\n\nSTATE: VALID PRIMARY\nEND\0",
+                    _ => b"PONG\0",
+                };
+                stream.get_mut().write_all(reply).unwrap();
+                held.push(stream);
+            }
+        });
+
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .read_timeout(std::time::Duration::from_secs(5))
+            .lenient_stats(true)
+            .build()
+            .unwrap();
+        let start = std::time::Instant::now();
+        assert!(cclient.ping());
+        assert!(cclient.ping_endpoint(cclient.socket));
+        assert_eq!(cclient.stats().unwrap().pools, 1);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_client_execute() {
+        use crate::command::{ClamResponse, Command};
+
+        let port = crate::testing::fake_clamd(|command| match command {
+            b"zVERSION" => b"ClamAV 0.100.0/24802/Wed Aug  1 08:43:37 2018\0".to_vec(),
+            b"zMULTISCAN /tmp" => b"/tmp/b: OK\0/tmp/a: Eicar-Signature FOUND\0".to_vec(),
+            b"zINSTREAMdata" => b"stream: OK\0".to_vec(),
+            _ => b"UNKNOWN COMMAND\0".to_vec(),
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        match client.execute(Command::Version).unwrap() {
+            ClamResponse::Version(version) => assert_eq!(version.version_tag, "ClamAV 0.100.0"),
+            response => panic!("unexpected response {:?}", response),
+        }
+        match client.execute(Command::MultiScan("/tmp")).unwrap() {
+            ClamResponse::Scan(results) => assert_eq!(results.len(), 2),
+            response => panic!("unexpected response {:?}", response),
+        }
+        match client
+            .execute(Command::InStream(&mut &b"data"[..]))
+            .unwrap()
+        {
+            ClamResponse::Stream(result) => assert_eq!(result, crate::response::ClamScanResult::Ok),
+            response => panic!("unexpected response {:?}", response),
+        }
+        assert!(matches!(
+            client.execute(Command::End),
+            Err(crate::error::ClamError::UnsupportedCommand(_))
+        ));
+    }
+
+    #[test]
+    fn test_client_strict_parsing() {
+        let port = crate::testing::fake_clamd(|command| match command {
+            b"zSCAN /tmp/error" => b"/tmp/error: Access denied. ERROR\0".to_vec(),
+            _ => b"UNKNOWN COMMAND\0".to_vec(),
+        });
+        let lenient = ClamClient::new("127.0.0.1", port).unwrap();
+        let strict = ClamClient::builder("127.0.0.1", port)
+            .strict_parsing(true)
+            .build()
+            .unwrap();
+
+        // Errors ClamD reports are results either way
+        assert_eq!(
+            strict.scan_path("/tmp/error", false).unwrap(),
+            lenient.scan_path("/tmp/error", false).unwrap()
+        );
+
+        // Whilst replies which are not understood are only errors in strict mode
+        assert!(matches!(
+            lenient.scan_path("/tmp/unknown", false).unwrap()[0],
+            crate::response::ClamScanResult::Error(_)
+        ));
+        assert!(matches!(
+            lenient.scan_bytes(b"data").unwrap(),
+            crate::response::ClamScanResult::Error(_)
+        ));
+        for result in [
+            strict.scan_path("/tmp/unknown", false).map(|_| ()),
+            strict.scan_bytes(b"data").map(|_| ()),
+            strict.scan_path_lines("/tmp/unknown", true).map(|_| ()),
+        ] {
+            match result {
+                Err(crate::error::ClamError::ProtocolError { reply }) => {
+                    assert_eq!(reply, "UNKNOWN COMMAND")
+                }
+                result => panic!("unexpected result {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_client_run() {
+        use crate::command::ClamCommand;
+
+        struct Detections(&'static str);
+
+        impl ClamCommand for Detections {
+            type Response = Vec<String>;
+
+            fn command(&self) -> String {
+                format!("DETECTIONS {}", self.0)
+            }
+
+            fn parse(&self, reply: String) -> crate::client::ClamResult<Vec<String>> {
+                Ok(reply.split_terminator('\0').map(str::to_owned).collect())
+            }
+        }
+
+        let port = crate::testing::fake_clamd(|command| match command {
+            b"zDETECTIONS /tmp" => b"Eicar\0Other".to_vec(),
+            _ => b"UNKNOWN COMMAND\0".to_vec(),
+        });
+
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+        assert_eq!(client.run(Detections("/tmp")).unwrap(), ["Eicar", "Other"]);
+    }
+
+    #[test]
+    fn test_client_from_stream() {
+        let port = crate::testing::fake_clamd(|cmd| match cmd {
+            b"zPING" => b"PONG".to_vec(),
+            b"zVERSION" => b"ClamAV 0.100.0/24802/Wed Aug  1 08:43:37 2018".to_vec(),
+            _ => b"stream: OK".to_vec(),
+        });
+        let stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let cclient = ClamClient::from_stream(stream).unwrap();
+        assert_eq!(cclient.endpoint().port(), port);
+
+        // Every command the session supports is issued over the supplied connection
+        assert!(cclient.ping());
+        assert_eq!(cclient.version().unwrap().build_number, 24802);
+        for _ in 0..2 {
+            assert_eq!(
+                cclient.scan_bytes(b"data").unwrap(),
+                crate::response::ClamScanResult::Ok
+            );
+        }
+
+        // Whilst those which need a connection of their own fail, rather than connecting anew
+        match cclient.scan_path("/tmp", true) {
+            Err(crate::error::ClamError::ConnectionError(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::NotConnected)
+            }
+            result => panic!("expected a connection error, got {:?}", result),
+        }
+        assert!(cclient.ping());
+    }
+
+    #[test]
+    fn test_client_scan_path_streaming() {
+        use std::ops::ControlFlow;
+
+        let port = crate::testing::fake_clamd(|cmd| {
+            if cmd == b"zCONTSCAN /dir" {
+                b"/dir/a: Sig-One FOUND\0/dir/b: Sig-Two FOUND\0/dir/c: OK".to_vec()
+            } else {
+                b"UNKNOWN COMMAND".to_vec()
+            }
+        });
+        let cclient = ClamClient::builder("127.0.0.1", port)
+            .collect_latency(true)
+            .build()
+            .unwrap();
+
+        let mut results = Vec::new();
+        let scanned = cclient
+            .scan_path_streaming("/dir", true, |result| {
+                results.push(result);
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+        assert_eq!(scanned, ControlFlow::Continue(()));
+        assert_eq!(results.len(), 3);
+
+        // Breaking at the first detection aborts the scan, so no further results are passed on
+        let mut results = Vec::new();
+        let scanned = cclient
+            .scan_path_streaming("/dir", true, |result| {
+                let found = matches!(result, crate::response::ClamScanResult::Found(..));
+                results.push(result);
+                if found {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })
+            .unwrap();
+        assert_eq!(scanned, ControlFlow::Break(()));
+        assert_eq!(
+            results,
+            [crate::response::ClamScanResult::Found(
+                "/dir/a".to_string(),
+                "Sig-One".to_string()
+            )]
+        );
+
+        // Both scans are recorded, including the one which was aborted
+        let metrics = cclient.client_metrics().unwrap();
+        assert_eq!(metrics.commands["CONTSCAN"].count, 2);
+    }
+
+    #[test]
+    fn test_client_error_context() {
+        use crate::error::Phase;
+
+        let cclient = ClamClient::new("127.0.0.1", 1).unwrap();
+        for (e, command) in [
+            (cclient.version().unwrap_err(), "VERSION"),
+            (cclient.scan_bytes(b"data").unwrap_err(), "INSTREAM"),
+            (
+                cclient.scan_path_iter("/dir", true).err().unwrap(),
+                "CONTSCAN",
+            ),
+        ] {
+            let context = e.context().unwrap();
+            assert_eq!(context.command(), Some(command));
+            assert_eq!(context.endpoint(), "127.0.0.1:1");
+            assert_eq!(context.phase(), Phase::Connecting);
+            assert!(e.to_string().contains("failed connecting to 127.0.0.1:1"));
+        }
+
+        // ClamD closing a session is reported against the command awaiting its reply
+        let (port, _) = crate::testing::restarting_clamd(|_| b"PONG".to_vec());
+        let cclient = ClamClient::new("127.0.0.1", port).unwrap();
+        let mut session = cclient.session().unwrap();
+        assert!(session.ping().unwrap());
+
+        let e = session.ping().unwrap_err();
+        let context = e.context().unwrap();
+        assert_eq!(context.command(), Some("PING"));
+        assert_eq!(context.endpoint(), format!("127.0.0.1:{}", port));
+        assert_eq!(context.phase(), Phase::Receiving);
+    }
+
+    #[test]
+    fn test_client_server_info() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+        let port = crate::testing::fake_clamd(|cmd| match cmd {
+            b"zVERSIONCOMMANDS" => {
+                REQUESTS.fetch_add(1, Ordering::SeqCst);
+                b"ClamAV 1.0.1/26900/Mon May  1 07:10:00 2023| COMMANDS: SCAN PING INSTREAM"
+                    .to_vec()
+            }
+            b"zRELOAD" => b"RELOADING".to_vec(),
+            _ => b"UNKNOWN COMMAND".to_vec(),
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        let info = client.server_info().unwrap();
+        assert_eq!(info.database.version, 26900);
+        assert!(info.supports("INSTREAM"));
+        assert_eq!(client.server_info().unwrap(), info);
+        assert_eq!(REQUESTS.load(Ordering::SeqCst), 1);
+
+        client.refresh_server_info().unwrap();
+        assert_eq!(REQUESTS.load(Ordering::SeqCst), 2);
+
+        client.reload().unwrap();
+        client.server_info().unwrap();
+        assert_eq!(REQUESTS.load(Ordering::SeqCst), 3);
+
+        let client = ClamClient::builder("127.0.0.1", port)
+            .server_info_ttl(std::time::Duration::ZERO)
+            .build()
+            .unwrap();
+        client.server_info().unwrap();
+        client.server_info().unwrap();
+        assert_eq!(REQUESTS.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn test_client_server_info_before_versioncommands() {
+        let port = crate::testing::fake_clamd(|cmd| match cmd {
+            b"zVERSION" => b"ClamAV 0.94.2/9000/Mon May  1 07:10:00 2023".to_vec(),
+            _ => b"UNKNOWN COMMAND".to_vec(),
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        let info = client.server_info().unwrap();
+        assert_eq!(info.version.version_tag, "ClamAV 0.94.2");
+        assert!(info.commands.is_empty());
     }
 }
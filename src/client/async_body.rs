@@ -0,0 +1,114 @@
+//! Scanning of `http_body::Body` implementations, such as `hyper`'s request and response bodies,
+//! enabled by the `hyper` feature. Built on the `tokio` support in `async_tokio`.
+
+use super::{ClamClient, ClamResult, Tokio};
+use crate::error::ClamError;
+use crate::protocol;
+use crate::response::ClamScanResult;
+use bytes::Buf;
+use http_body::Body;
+use http_body_util::BodyExt;
+use std::error::Error;
+use std::io;
+
+impl ClamClient {
+    /// Implements the ClamD `INSTREAM` command for an HTTP body, streaming each data frame to
+    /// ClamD as it arrives and returning the `ClamScanResult` once the body is complete. Trailers
+    /// are ignored. Returns `ClamError::CommandError` if the body itself fails.
+    ///
+    /// This behaves exactly as `ClamClient::scan_stream_async` does, including its lack of
+    /// support for TLS and proxies.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::response::ClamScanResult;
+    /// use http_body::Body;
+    ///
+    /// async fn is_infected<B>(client: &ClamClient, body: B) -> bool
+    /// where
+    ///     B: Body + Unpin,
+    ///     B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    /// {
+    ///     matches!(client.scan_body(body).await, Ok(ClamScanResult::Found(..)))
+    /// }
+    /// ```
+    pub async fn scan_body<B>(&self, mut body: B) -> ClamResult<ClamScanResult>
+    where
+        B: Body + Unpin,
+        B::Error: Into<Box<dyn Error + Send + Sync>>,
+    {
+        let mut instream = self.begin_instream_on::<Tokio>().await?;
+
+        while let Some(frame) = body.frame().await {
+            let frame = frame.map_err(|e| ClamError::CommandError(io::Error::other(e)))?;
+
+            if let Ok(mut data) = frame.into_data() {
+                while data.has_remaining() {
+                    let chunk = data.chunk();
+                    let len = chunk.len().min(protocol::MAX_CHUNK_LEN);
+                    self.write_chunk_async(&mut instream, &chunk[..len]).await?;
+                    data.advance(len);
+                }
+            }
+        }
+
+        self.finish_instream_async(instream).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::response::ClamScanResult;
+    use crate::testing::fake_clamd;
+    use bytes::Bytes;
+    use http_body::{Body, Frame};
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// A body yielding each of its data frames in turn.
+    struct Frames(VecDeque<&'static [u8]>);
+
+    impl Body for Frames {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+            Poll::Ready(
+                self.0
+                    .pop_front()
+                    .map(|data| Ok(Frame::data(Bytes::from_static(data)))),
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan_body() {
+        let port = fake_clamd(|cmd| {
+            if cmd == b"zINSTREAMhello world" {
+                b"stream: OK".to_vec()
+            } else {
+                b"stream: Sig-One FOUND".to_vec()
+            }
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        let body = Frames(VecDeque::from(vec![&b"hello"[..], &b" world"[..]]));
+        assert_eq!(client.scan_body(body).await.unwrap(), ClamScanResult::Ok);
+
+        let body = Frames(VecDeque::from(vec![&b"bad"[..]]));
+        assert_eq!(
+            client.scan_body(body).await.unwrap(),
+            ClamScanResult::Found("stream".to_string(), "Sig-One".to_string())
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! The runtime agnostic half of `ClamClient`'s asynchronous `INSTREAM` support, shared by the
+//! `tokio` and `async-io` backends: the `INSTREAM` framing, the handling of ClamD's reply, and the
+//! circuit breaker, retry policy and instrumentation applied to every scan. Each backend supplies
+//! the connections and timers of an `AsyncRuntime`, and reads the stream being scanned through
+//! an `AsyncSource`.
+
+use super::{cannot_reconnect, stream_result, ClamClient, ClamResult};
+use crate::error::{ClamError, Phase};
+use crate::instrument;
+use crate::protocol;
+use crate::redact::Redact;
+use crate::response::ClamScanResult;
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// `AsyncRuntime` performs the I/O of an asynchronous `INSTREAM` on a particular runtime.
+pub(crate) trait AsyncRuntime {
+    /// An established connection to ClamD
+    type Connection;
+
+    /// Connects to a single address, applying the client's socket and TCP options.
+    async fn connect(client: &ClamClient, addr: SocketAddr) -> io::Result<Self::Connection>;
+
+    /// Writes all of `data` to `connection`.
+    async fn write_all(connection: &mut Self::Connection, data: &[u8]) -> io::Result<()>;
+
+    /// Reads a single reply from `connection`, returning once it is complete as
+    /// `protocol::read_reply` does, rather than waiting for ClamD to close the connection.
+    async fn read_reply(connection: Self::Connection, terminator: u8) -> io::Result<Vec<u8>>;
+
+    /// Awaits `future`, failing with `ErrorKind::TimedOut` if `timeout` elapses first.
+    async fn timeout<T, F>(timeout: Duration, future: F) -> io::Result<T>
+    where
+        F: Future<Output = io::Result<T>>;
+
+    /// Waits for `duration` to elapse.
+    async fn sleep(duration: Duration);
+}
+
+/// `AsyncSource` is a stream being scanned asynchronously.
+pub(crate) trait AsyncSource {
+    /// Reads into `buf`, returning the number of bytes read or 0 at the end of the stream.
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+}
+
+/// `AsyncInstream` is an `INSTREAM` in progress on an asynchronous connection to ClamD, started
+/// with `ClamClient::begin_instream_on`.
+pub(crate) struct AsyncInstream<R: AsyncRuntime> {
+    connection: R::Connection,
+    /// The endpoint connected to, for the context of errors
+    endpoint: String,
+    /// The number of bytes streamed so far
+    total: u64,
+}
+
+impl ClamClient {
+    /// Streams `source` to ClamD with `INSTREAM` on the runtime `R`, instrumented as
+    /// `scan_stream` is.
+    pub(crate) async fn scan_source_async<R, S>(&self, mut source: S) -> ClamResult<ClamScanResult>
+    where
+        R: AsyncRuntime,
+        S: AsyncSource,
+    {
+        let command = instrument::Command::start("INSTREAM");
+        let result = command
+            .instrument(async {
+                let mut instream = self.begin_instream_on::<R>().await?;
+
+                let mut buf = [0; 4096];
+                loop {
+                    let bytes_read = source
+                        .read(&mut buf)
+                        .await
+                        .map_err(ClamError::LocalIoError)?;
+                    if bytes_read == 0 {
+                        break;
+                    }
+                    self.write_chunk_async(&mut instream, &buf[..bytes_read])
+                        .await?;
+                }
+
+                self.finish_instream_async(instream).await
+            })
+            .await;
+        self.finish_instrumented(command, result, std::slice::from_ref)
+    }
+
+    /// Connects to ClamD on the runtime `R` and issues `INSTREAM`, failing if TLS or a proxy is
+    /// configured, or if the client was created from an existing stream and so may not open
+    /// connections. Connecting is guarded by the circuit breaker and retried under the
+    /// `RetryPolicy`, as it is for `scan_stream`.
+    pub(crate) async fn begin_instream_on<R: AsyncRuntime>(&self) -> ClamResult<AsyncInstream<R>> {
+        if !self.reconnect {
+            return Err(cannot_reconnect());
+        }
+
+        if self.proxy.is_some() {
+            return Err(ClamError::ProxyError(
+                "proxies are not supported by asynchronous scans".to_owned(),
+            ));
+        }
+
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() {
+            return Err(ClamError::TlsError(
+                "TLS is not supported by asynchronous scans".to_owned(),
+            ));
+        }
+
+        #[cfg(unix)]
+        if self.unix_socket.is_some() {
+            return Err(ClamError::ConnectionError(io::Error::new(
+                ErrorKind::Unsupported,
+                "Unix sockets are not supported by asynchronous scans",
+            )));
+        }
+
+        let (connection, endpoint) = self
+            .retrying_async::<R, _, _, _>(|| self.async_connect::<R>())
+            .await
+            .map_err(|e| e.for_command("INSTREAM"))?;
+        let mut instream = AsyncInstream {
+            connection,
+            endpoint: self.describe_endpoint(Some(endpoint)),
+            total: 0,
+        };
+
+        let command = self.delimiter.command("INSTREAM");
+        self.async_write(&mut instream, &command).await?;
+        self.observers.command_sent(&command);
+
+        Ok(instream)
+    }
+
+    /// Writes `data` as a single `INSTREAM` chunk, failing with `ClamError::StreamTooLarge` if the
+    /// stream then exceeds the configured maximum stream length. Empty `data` is skipped, since a
+    /// zero length chunk would terminate the stream.
+    pub(crate) async fn write_chunk_async<R: AsyncRuntime>(
+        &self,
+        instream: &mut AsyncInstream<R>,
+        data: &[u8],
+    ) -> ClamResult<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        instream.total += data.len() as u64;
+        if let Some(limit) = self.stream_max_length {
+            if instream.total > limit {
+                return Err(ClamError::StreamTooLarge(limit));
+            }
+        }
+
+        self.async_write(instream, &protocol::chunk_header(data.len()))
+            .await?;
+        self.async_write(instream, data).await?;
+        self.observers.streamed(data.len() as u64);
+        Ok(())
+    }
+
+    /// Terminates the stream and reads back the `ClamScanResult`.
+    pub(crate) async fn finish_instream_async<R: AsyncRuntime>(
+        &self,
+        mut instream: AsyncInstream<R>,
+    ) -> ClamResult<ClamScanResult> {
+        self.async_write(&mut instream, &protocol::END_OF_STREAM)
+            .await?;
+        instrument::record_bytes(instream.total);
+
+        let terminator = self.delimiter.terminator();
+        let reply = with_timeout::<R, _, _>(
+            self.read_timeout,
+            R::read_reply(instream.connection, terminator),
+        )
+        .await
+        .map_err(|e| {
+            ClamError::ConnectionError(e)
+                .in_phase(Phase::Receiving, &instream.endpoint)
+                .for_command("INSTREAM")
+        })?;
+        let result = String::from_utf8_lossy(&reply).into_owned();
+        stream_result(self.delimiter.normalise(result, true), self.strict_parsing)
+            .redact(self.redaction)
+    }
+
+    /// Runs `operation` under the configured `RetryPolicy` and circuit breaker, as `retrying` does
+    /// for blocking commands.
+    async fn retrying_async<R, T, F, O>(&self, mut operation: F) -> ClamResult<T>
+    where
+        R: AsyncRuntime,
+        F: FnMut() -> O,
+        O: Future<Output = ClamResult<T>>,
+    {
+        let _probe = match &self.circuit {
+            Some(circuit) => circuit.admit()?,
+            None => None,
+        };
+
+        let mut attempt = 1;
+        let result = loop {
+            let result = operation().await;
+            let retry = match (&result, &self.retry_policy) {
+                (Err(e), Some(policy)) => policy.retry_after(attempt, e),
+                _ => None,
+            };
+            match retry {
+                Some(backoff) => {
+                    R::sleep(backoff).await;
+                    attempt += 1;
+                }
+                None => break result,
+            }
+        };
+
+        if let Some(circuit) = &self.circuit {
+            circuit.record(&result);
+        }
+        result
+    }
+
+    /// Connects to the first available ClamD endpoint, trying each of its addresses in turn and
+    /// honouring the connect timeout, and returns the connection along with the endpoint.
+    async fn async_connect<R: AsyncRuntime>(&self) -> ClamResult<(R::Connection, SocketAddr)> {
+        let mut result = Err(ClamError::ConnectionError(ErrorKind::NotFound.into()));
+
+        for endpoint in self.endpoints() {
+            instrument::record_endpoint(endpoint);
+            let mut connected = Err(ErrorKind::NotFound.into());
+            for addr in self.addresses(endpoint) {
+                connected = with_timeout::<R, _, _>(self.timeout, R::connect(self, addr)).await;
+                if connected.is_ok() {
+                    self.observers.connected(&addr);
+                    break;
+                }
+            }
+
+            result = connected.map(|stream| (stream, endpoint)).map_err(|e| {
+                ClamError::ConnectionError(e)
+                    .in_phase(Phase::Connecting, &self.describe_endpoint(Some(endpoint)))
+            });
+            if result.is_ok() {
+                break;
+            }
+        }
+
+        result
+    }
+
+    /// Writes `data` to an `INSTREAM` in progress, honouring the write timeout.
+    async fn async_write<R: AsyncRuntime>(
+        &self,
+        instream: &mut AsyncInstream<R>,
+        data: &[u8],
+    ) -> ClamResult<()> {
+        with_timeout::<R, _, _>(
+            self.write_timeout,
+            R::write_all(&mut instream.connection, data),
+        )
+        .await
+        .map_err(|e| {
+            ClamError::CommandError(e)
+                .in_phase(Phase::Sending, &instream.endpoint)
+                .for_command("INSTREAM")
+        })
+    }
+}
+
+/// Awaits `future`, failing with `ErrorKind::TimedOut` if `timeout` is set and elapses first.
+async fn with_timeout<R, T, F>(timeout: Option<Duration>, future: F) -> io::Result<T>
+where
+    R: AsyncRuntime,
+    F: Future<Output = io::Result<T>>,
+{
+    match timeout {
+        Some(t) => R::timeout(t, future).await,
+        None => future.await,
+    }
+}
@@ -0,0 +1,230 @@
+//! Executor agnostic asynchronous `INSTREAM` support for `ClamClient`, enabled by the `async-io`
+//! feature. Connections are driven by the `async-io` reactor and streams are read through the
+//! `futures-io` traits, so this can be used from `async-std`, `smol` or any other executor
+//! without pulling in `tokio`.
+
+use super::async_instream::{AsyncRuntime, AsyncSource};
+use super::{ClamClient, ClamResult};
+use crate::protocol::{self, ReplyLength};
+use crate::response::ClamScanResult;
+use crate::transport;
+use async_io::{Async, Timer};
+use futures_lite::io::BufReader;
+use futures_lite::{future, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt};
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+impl ClamClient {
+    /// Implements the ClamD `INSTREAM` command asynchronously on any executor, allowing the caller
+    /// to stream any `futures_io::AsyncRead` to the ClamD instance without blocking a thread or
+    /// buffering the data. Returns a `ClamScanResult` if the command was successful.
+    ///
+    /// This behaves exactly as `ClamClient::scan_stream_async` does, including its lack of
+    /// support for TLS and proxies.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    ///
+    /// async fn scan(data: &[u8]) {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     match client.scan_stream_async_io(data).await {
+    ///         Ok(result) => println!("{:?}", result),
+    ///         Err(e) => println!("A network error occurred whilst talking to ClamAV:\n{}", e),
+    ///     }
+    /// }
+    /// ```
+    pub async fn scan_stream_async_io<T: AsyncRead + Unpin>(
+        &self,
+        stream: T,
+    ) -> ClamResult<ClamScanResult> {
+        self.scan_source_async::<AsyncIo, _>(Reader(stream)).await
+    }
+}
+
+/// `AsyncIo` drives asynchronous scans with the `async-io` reactor and timers.
+pub(crate) struct AsyncIo;
+
+impl AsyncRuntime for AsyncIo {
+    type Connection = Async<TcpStream>;
+
+    async fn connect(client: &ClamClient, addr: SocketAddr) -> io::Result<Async<TcpStream>> {
+        let stream = if client.socket_options.is_default() {
+            Async::<TcpStream>::connect(addr).await?
+        } else {
+            let socket = transport::new_socket(addr, client.socket_options)?;
+            socket.set_nonblocking(true)?;
+            // A non-blocking connect reports that it is in progress as an error, the outcome is
+            // known once the socket becomes writable
+            let started = socket.connect(&addr.into());
+            let stream = Async::new(TcpStream::from(socket))?;
+
+            if let Err(e) = started {
+                stream.writable().await?;
+                if let Some(e) = stream.get_ref().take_error()? {
+                    return Err(e);
+                }
+                if stream.get_ref().peer_addr().is_err() {
+                    return Err(e);
+                }
+            }
+            stream
+        };
+
+        transport::set_tcp_options(&stream, client.nodelay, client.tcp_keepalive)?;
+        Ok(stream)
+    }
+
+    async fn write_all(connection: &mut Async<TcpStream>, data: &[u8]) -> io::Result<()> {
+        connection.write_all(data).await
+    }
+
+    async fn read_reply(connection: Async<TcpStream>, terminator: u8) -> io::Result<Vec<u8>> {
+        let mut reader = BufReader::new(connection);
+        let mut reply = Vec::new();
+        while reader.read_until(terminator, &mut reply).await? > 0 {
+            if protocol::reply_complete(&reply, terminator, ReplyLength::Single) {
+                break;
+            }
+        }
+        Ok(reply)
+    }
+
+    async fn timeout<T, F>(timeout: Duration, future: F) -> io::Result<T>
+    where
+        F: Future<Output = io::Result<T>>,
+    {
+        let timer = async {
+            Timer::after(timeout).await;
+            Err(ErrorKind::TimedOut.into())
+        };
+        future::or(future, timer).await
+    }
+
+    async fn sleep(duration: Duration) {
+        Timer::after(duration).await;
+    }
+}
+
+/// `Reader` reads the stream being scanned through the `futures-io` `AsyncRead`.
+struct Reader<T>(T);
+
+impl<T: AsyncRead + Unpin> AsyncSource for Reader<T> {
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::error::ClamError;
+    use crate::response::ClamScanResult;
+    use crate::testing::{fake_clamd, lingering_clamd};
+    use futures_lite::future;
+    use std::io::ErrorKind;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_scan_stream_async_io() {
+        let port = fake_clamd(|cmd| {
+            if cmd.ends_with(b"infected") {
+                b"stream: Eicar-Test-Signature FOUND".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        future::block_on(async {
+            assert_eq!(
+                client.scan_stream_async_io(&b"clean"[..]).await.unwrap(),
+                ClamScanResult::Ok
+            );
+            assert_eq!(
+                client.scan_stream_async_io(&b"infected"[..]).await.unwrap(),
+                ClamScanResult::Found("stream".to_string(), "Eicar-Test-Signature".to_string())
+            );
+        });
+    }
+
+    #[test]
+    fn test_scan_stream_async_io_reads_single_reply() {
+        let port = lingering_clamd(b"stream: OK");
+        let client = ClamClient::builder("127.0.0.1", port)
+            .read_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+
+        // The reply is complete at its terminator, even though the connection is left open
+        let started = Instant::now();
+        let result = future::block_on(client.scan_stream_async_io(&b"data"[..]));
+        assert_eq!(result.unwrap(), ClamScanResult::Ok);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_scan_stream_async_io_policies() {
+        use crate::circuit::{CircuitBreaker, CircuitState};
+        use crate::error::Phase;
+
+        let client = ClamClient::builder("127.0.0.1", 1)
+            .circuit_breaker(CircuitBreaker::new(1))
+            .collect_latency(true)
+            .build()
+            .unwrap();
+
+        let e = future::block_on(client.scan_stream_async_io(&b"data"[..])).unwrap_err();
+        let context = e.context().unwrap();
+        assert_eq!(context.command(), Some("INSTREAM"));
+        assert_eq!(context.phase(), Phase::Connecting);
+        assert_eq!(client.circuit_state(), Some(CircuitState::Open));
+        assert!(matches!(
+            future::block_on(client.scan_stream_async_io(&b"data"[..])),
+            Err(ClamError::CircuitOpen)
+        ));
+
+        let metrics = client.client_metrics().unwrap();
+        assert_eq!(metrics.commands["INSTREAM"].count, 2);
+    }
+
+    #[test]
+    fn test_scan_stream_async_io_from_stream() {
+        let port = fake_clamd(|_| b"stream: OK".to_vec());
+        let stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let client = ClamClient::from_stream(stream).unwrap();
+
+        // The client may only use the stream it was created from, never a new connection
+        assert!(matches!(
+            future::block_on(client.scan_stream_async_io(&b"clean"[..])),
+            Err(ClamError::ConnectionError(e)) if e.kind() == ErrorKind::NotConnected
+        ));
+    }
+
+    #[test]
+    fn test_scan_stream_async_io_local_address() {
+        let port = fake_clamd(|_| b"stream: OK".to_vec());
+        let client = ClamClient::builder("127.0.0.1", port)
+            .local_address("127.0.0.1".parse().unwrap())
+            .build()
+            .unwrap();
+
+        let result = future::block_on(client.scan_stream_async_io(&b"clean"[..]));
+        assert_eq!(result.unwrap(), ClamScanResult::Ok);
+
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = closed.local_addr().unwrap().port();
+        drop(closed);
+        let client = ClamClient::builder("127.0.0.1", port)
+            .local_address("127.0.0.1".parse().unwrap())
+            .build()
+            .unwrap();
+        assert!(future::block_on(client.scan_stream_async_io(&b"clean"[..])).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//! Asynchronous `INSTREAM` support for `ClamClient` built on `tokio`, enabled by the `tokio`
+//! feature.
+
+use super::async_instream::{AsyncRuntime, AsyncSource};
+use super::{ClamClient, ClamResult};
+use crate::protocol::{self, ReplyLength};
+use crate::response::ClamScanResult;
+use crate::transport;
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpSocket, TcpStream};
+
+impl ClamClient {
+    /// Implements the ClamD `INSTREAM` command asynchronously, allowing the caller to stream any
+    /// `AsyncRead` - such as an incoming request body - to the ClamD instance without blocking a
+    /// thread or buffering the data. Returns a `ClamScanResult` if the command was successful.
+    ///
+    /// The connect, read and write timeouts, retry policy and circuit breaker configured on the
+    /// client are honoured as they are by `scan_stream`, however TLS and proxies are not
+    /// supported, and an error is returned if either is configured.
+    ///
+    /// *Arguments*:
+    ///
+    /// - `stream`: The object to be scanned, it will be read in chunks of up to 4096 bytes which
+    ///   are written to the ClamD instance as they become available.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    ///
+    /// async fn scan(data: &[u8]) {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     match client.scan_stream_async(data).await {
+    ///         Ok(result) => println!("{:?}", result),
+    ///         Err(e) => println!("A network error occurred whilst talking to ClamAV:\n{}", e),
+    ///     }
+    /// }
+    /// ```
+    pub async fn scan_stream_async<T: AsyncRead + Unpin>(
+        &self,
+        stream: T,
+    ) -> ClamResult<ClamScanResult> {
+        self.scan_source_async::<Tokio, _>(Reader(stream)).await
+    }
+}
+
+/// `Tokio` drives asynchronous scans with `tokio`'s networking and timers.
+pub(crate) struct Tokio;
+
+impl AsyncRuntime for Tokio {
+    type Connection = TcpStream;
+
+    async fn connect(client: &ClamClient, addr: SocketAddr) -> io::Result<TcpStream> {
+        let stream = if client.socket_options.is_default() {
+            TcpStream::connect(addr).await?
+        } else {
+            let socket = transport::new_socket(addr, client.socket_options)?;
+            socket.set_nonblocking(true)?;
+            TcpSocket::from_std_stream(socket.into())
+                .connect(addr)
+                .await?
+        };
+
+        transport::set_tcp_options(&stream, client.nodelay, client.tcp_keepalive)?;
+        Ok(stream)
+    }
+
+    async fn write_all(connection: &mut TcpStream, data: &[u8]) -> io::Result<()> {
+        connection.write_all(data).await
+    }
+
+    async fn read_reply(connection: TcpStream, terminator: u8) -> io::Result<Vec<u8>> {
+        let mut reader = BufReader::new(connection);
+        let mut reply = Vec::new();
+        while reader.read_until(terminator, &mut reply).await? > 0 {
+            if protocol::reply_complete(&reply, terminator, ReplyLength::Single) {
+                break;
+            }
+        }
+        Ok(reply)
+    }
+
+    async fn timeout<T, F>(timeout: Duration, future: F) -> io::Result<T>
+    where
+        F: Future<Output = io::Result<T>>,
+    {
+        match tokio::time::timeout(timeout, future).await {
+            Ok(result) => result,
+            Err(_) => Err(ErrorKind::TimedOut.into()),
+        }
+    }
+
+    async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// `Reader` reads the stream being scanned through `tokio`'s `AsyncRead`.
+struct Reader<T>(T);
+
+impl<T: AsyncRead + Unpin> AsyncSource for Reader<T> {
+    async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::error::ClamError;
+    use crate::response::ClamScanResult;
+    use crate::testing::{fake_clamd, lingering_clamd};
+    use std::io::ErrorKind;
+    use std::time::{Duration, Instant};
+
+    #[tokio::test]
+    async fn test_scan_stream_async() {
+        let port = fake_clamd(|cmd| {
+            if cmd.ends_with(b"infected") {
+                b"stream: Eicar-Test-Signature FOUND".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        assert_eq!(
+            client.scan_stream_async(&b"clean"[..]).await.unwrap(),
+            ClamScanResult::Ok
+        );
+        assert_eq!(
+            client.scan_stream_async(&b"infected"[..]).await.unwrap(),
+            ClamScanResult::Found("stream".to_string(), "Eicar-Test-Signature".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_stream_async_reads_single_reply() {
+        let port = lingering_clamd(b"stream: OK");
+        let client = ClamClient::builder("127.0.0.1", port)
+            .read_timeout(Duration::from_secs(5))
+            .build()
+            .unwrap();
+
+        // The reply is complete at its terminator, even though the connection is left open
+        let started = Instant::now();
+        assert_eq!(
+            client.scan_stream_async(&b"data"[..]).await.unwrap(),
+            ClamScanResult::Ok
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_scan_stream_async_policies() {
+        use crate::circuit::{CircuitBreaker, CircuitState};
+        use crate::error::Phase;
+
+        let client = ClamClient::builder("127.0.0.1", 1)
+            .circuit_breaker(CircuitBreaker::new(1))
+            .collect_latency(true)
+            .build()
+            .unwrap();
+
+        let e = client.scan_stream_async(&b"data"[..]).await.unwrap_err();
+        let context = e.context().unwrap();
+        assert_eq!(context.command(), Some("INSTREAM"));
+        assert_eq!(context.phase(), Phase::Connecting);
+        assert_eq!(client.circuit_state(), Some(CircuitState::Open));
+        assert!(matches!(
+            client.scan_stream_async(&b"data"[..]).await,
+            Err(ClamError::CircuitOpen)
+        ));
+
+        let metrics = client.client_metrics().unwrap();
+        assert_eq!(metrics.commands["INSTREAM"].count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_scan_stream_async_retries_connecting() {
+        use crate::retry::RetryPolicy;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let retried = Arc::new(AtomicUsize::new(0));
+        let counter = retried.clone();
+        let client = ClamClient::builder("127.0.0.1", 1)
+            .retry_policy(
+                RetryPolicy::new(3)
+                    .initial_backoff(Duration::from_millis(1))
+                    .retry_if(move |_| counter.fetch_add(1, Ordering::SeqCst) < 10),
+            )
+            .build()
+            .unwrap();
+
+        assert!(client.scan_stream_async(&b"data"[..]).await.is_err());
+        assert_eq!(retried.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_scan_stream_async_from_stream() {
+        let port = fake_clamd(|_| b"stream: OK".to_vec());
+        let stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let client = ClamClient::from_stream(stream).unwrap();
+
+        // The client may only use the stream it was created from, never a new connection
+        assert!(matches!(
+            client.scan_stream_async(&b"clean"[..]).await,
+            Err(ClamError::ConnectionError(e)) if e.kind() == ErrorKind::NotConnected
+        ));
+    }
+}
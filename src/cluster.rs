@@ -0,0 +1,380 @@
+//! `ClamCluster` spreads commands across several independent ClamD instances, for when a single
+//! instance cannot keep up with the volume of scans. Each endpoint's health is tracked, and
+//! endpoints which repeatedly fail are skipped until a cool-down has passed.
+
+use crate::client::{ClamClient, ClamResult};
+use crate::error::ClamError;
+use crate::response::ClamScanResult;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// `Balancing` determines how `ClamCluster` chooses the endpoint for each command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balancing {
+    /// Endpoints are used in turn.
+    RoundRobin,
+    /// The endpoint which has gone the longest without being used is chosen.
+    LeastRecentlyUsed,
+}
+
+/// `EndpointHealth` is a snapshot of the health of a single endpoint in a `ClamCluster`.
+///
+/// With the `serde` feature it may be serialized, with each `Instant` serialized as the number of
+/// seconds which had elapsed since it when the snapshot was serialized.
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointHealth {
+    /// The address of the endpoint
+    pub endpoint: SocketAddr,
+    /// False once the endpoint has failed `failure_threshold` consecutive times
+    pub healthy: bool,
+    /// The number of consecutive failures, reset by any success
+    pub consecutive_failures: u32,
+    /// When a command last succeeded on this endpoint
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "last_success_secs_ago", serialize_with = "secs_ago")
+    )]
+    pub last_success: Option<Instant>,
+    /// When a command last failed on this endpoint
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "last_failure_secs_ago", serialize_with = "secs_ago")
+    )]
+    pub last_failure: Option<Instant>,
+    /// When this endpoint was last chosen for a command
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "last_used_secs_ago", serialize_with = "secs_ago")
+    )]
+    pub last_used: Option<Instant>,
+}
+
+/// Serializes an `Instant` as the number of seconds which have elapsed since it.
+#[cfg(feature = "serde")]
+fn secs_ago<S: serde::Serializer>(instant: &Option<Instant>, s: S) -> Result<S::Ok, S::Error> {
+    match instant {
+        Some(instant) => s.serialize_some(&instant.elapsed().as_secs_f64()),
+        None => s.serialize_none(),
+    }
+}
+
+/// `ClamCluster` distributes commands across a set of `ClamClient`s, one per ClamD instance.
+///
+/// Commands which fail with connection errors are retried on the next available endpoint,
+/// except for stream scans which cannot be replayed once they have started.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::cluster::{Balancing, ClamCluster};
+///
+/// fn main() {
+///     let cluster = ClamCluster::new(vec![
+///         ClamClient::new("10.0.0.10", 3310).unwrap(),
+///         ClamClient::new("10.0.0.11", 3310).unwrap(),
+///     ])
+///     .balancing(Balancing::LeastRecentlyUsed);
+///
+///     if let Ok(result) = cluster.scan_bytes(b"some data") {
+///         println!("{:?}", result);
+///     }
+/// }
+/// ```
+pub struct ClamCluster {
+    nodes: Vec<Node>,
+    balancing: Balancing,
+    failure_threshold: u32,
+    cool_down: Duration,
+    next: AtomicUsize,
+}
+
+/// A single endpoint of a `ClamCluster`.
+struct Node {
+    client: ClamClient,
+    health: Mutex<EndpointHealth>,
+}
+
+impl ClamCluster {
+    /// Creates a new round-robin `ClamCluster` over the given clients. By default an endpoint is
+    /// marked unhealthy after 3 consecutive failures, and retried after 30 seconds.
+    pub fn new(clients: Vec<ClamClient>) -> ClamCluster {
+        let nodes = clients
+            .into_iter()
+            .map(|client| Node {
+                health: Mutex::new(EndpointHealth {
+                    endpoint: client.endpoint(),
+                    healthy: true,
+                    consecutive_failures: 0,
+                    last_success: None,
+                    last_failure: None,
+                    last_used: None,
+                }),
+                client,
+            })
+            .collect();
+
+        ClamCluster {
+            nodes,
+            balancing: Balancing::RoundRobin,
+            failure_threshold: 3,
+            cool_down: Duration::from_secs(30),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Sets the strategy used to choose an endpoint for each command.
+    pub fn balancing(mut self, balancing: Balancing) -> Self {
+        self.balancing = balancing;
+        self
+    }
+
+    /// Sets the number of consecutive failures after which an endpoint is marked unhealthy.
+    pub fn failure_threshold(mut self, failures: u32) -> Self {
+        self.failure_threshold = failures.max(1);
+        self
+    }
+
+    /// Sets how long an unhealthy endpoint is skipped for before it is tried again.
+    pub fn cool_down(mut self, cool_down: Duration) -> Self {
+        self.cool_down = cool_down;
+        self
+    }
+
+    /// Returns a snapshot of the health of every endpoint, in the order they were given.
+    pub fn health(&self) -> Vec<EndpointHealth> {
+        self.nodes
+            .iter()
+            .map(|node| *node.health.lock().unwrap())
+            .collect()
+    }
+
+    /// Implements the ClamD `SCAN` and `CONTSCAN` commands on the next endpoint, see
+    /// `ClamClient::scan_path`.
+    pub fn scan_path(
+        &self,
+        path: &str,
+        continue_on_virus: bool,
+    ) -> ClamResult<Vec<ClamScanResult>> {
+        self.run(true, |client| client.scan_path(path, continue_on_virus))
+    }
+
+    /// Implements the ClamD `INSTREAM` command on the next endpoint, see
+    /// `ClamClient::scan_stream`.
+    pub fn scan_stream<T: Read>(&self, stream: T) -> ClamResult<ClamScanResult> {
+        let mut stream = Some(stream);
+        self.run(false, |client| client.scan_stream(stream.take().unwrap()))
+    }
+
+    /// Implements the ClamD `INSTREAM` command for in-memory data on the next endpoint, unlike
+    /// `scan_stream` this is retried on another endpoint if the first fails.
+    pub fn scan_bytes(&self, data: &[u8]) -> ClamResult<ClamScanResult> {
+        self.run(true, |client| client.scan_bytes(data))
+    }
+
+    /// Implements the ClamD `INSTREAM` command for a local file on the next endpoint, see
+    /// `ClamClient::scan_file`.
+    pub fn scan_file<P: AsRef<Path>>(&self, path: P) -> ClamResult<ClamScanResult> {
+        self.run(true, |client| client.scan_file(path.as_ref()))
+    }
+
+    /// Runs `command` on the chosen endpoint, recording the outcome against its health. If
+    /// `retry` is set, endpoint failures are retried on the next choice, each endpoint being
+    /// tried at most once.
+    fn run<R, F>(&self, retry: bool, mut command: F) -> ClamResult<R>
+    where
+        F: FnMut(&ClamClient) -> ClamResult<R>,
+    {
+        let mut tried = Vec::new();
+
+        loop {
+            let index = match self.choose(&tried) {
+                Some(index) => index,
+                None => {
+                    return Err(ClamError::ConnectionError(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        "the cluster has no endpoints",
+                    )))
+                }
+            };
+            tried.push(index);
+
+            let node = &self.nodes[index];
+            let result = command(&node.client);
+
+            match &result {
+                Err(e) if e.is_connection_error() => {
+                    self.record(node, false);
+                    if retry && tried.len() < self.nodes.len() {
+                        continue;
+                    }
+                }
+                _ => self.record(node, true),
+            }
+
+            return result;
+        }
+    }
+
+    /// Chooses the next endpoint to use, excluding those already `tried`. Healthy endpoints, and
+    /// unhealthy endpoints whose cool-down has passed, are preferred. If every endpoint is
+    /// unhealthy the one which failed longest ago is chosen rather than failing outright.
+    fn choose(&self, tried: &[usize]) -> Option<usize> {
+        let now = Instant::now();
+        let health = self.health();
+        let untried = (0..self.nodes.len()).filter(|i| !tried.contains(i));
+
+        let available: Vec<usize> = untried
+            .clone()
+            .filter(|&i| {
+                health[i].healthy
+                    || health[i]
+                        .last_failure
+                        .is_none_or(|failed| now.duration_since(failed) >= self.cool_down)
+            })
+            .collect();
+
+        let chosen = if available.is_empty() {
+            untried.min_by_key(|&i| health[i].last_failure)
+        } else {
+            match self.balancing {
+                Balancing::RoundRobin => {
+                    let start = self.next.fetch_add(1, Ordering::Relaxed) % self.nodes.len();
+                    available
+                        .iter()
+                        .copied()
+                        .min_by_key(|&i| (i + self.nodes.len() - start) % self.nodes.len())
+                }
+                Balancing::LeastRecentlyUsed => available
+                    .iter()
+                    .copied()
+                    .min_by_key(|&i| health[i].last_used),
+            }
+        };
+
+        if let Some(index) = chosen {
+            self.nodes[index].health.lock().unwrap().last_used = Some(now);
+        }
+
+        chosen
+    }
+
+    /// Records the outcome of a command against an endpoint's health.
+    fn record(&self, node: &Node, success: bool) {
+        let mut health = node.health.lock().unwrap();
+
+        if success {
+            health.healthy = true;
+            health.consecutive_failures = 0;
+            health.last_success = Some(Instant::now());
+        } else {
+            health.consecutive_failures += 1;
+            health.last_failure = Some(Instant::now());
+            if health.consecutive_failures >= self.failure_threshold {
+                health.healthy = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::cluster::{Balancing, ClamCluster};
+    use crate::response::ClamScanResult;
+    use crate::testing::fake_clamd;
+
+    fn found(virus: &str) -> ClamScanResult {
+        ClamScanResult::Found("stream".to_string(), virus.to_string())
+    }
+
+    #[test]
+    fn test_cluster_round_robin() {
+        let first = fake_clamd(|_| b"stream: First FOUND".to_vec());
+        let second = fake_clamd(|_| b"stream: Second FOUND".to_vec());
+        let cluster = ClamCluster::new(vec![
+            ClamClient::new("127.0.0.1", first).unwrap(),
+            ClamClient::new("127.0.0.1", second).unwrap(),
+        ]);
+
+        assert_eq!(cluster.scan_bytes(b"data").unwrap(), found("First"));
+        assert_eq!(cluster.scan_bytes(b"data").unwrap(), found("Second"));
+        assert_eq!(cluster.scan_bytes(b"data").unwrap(), found("First"));
+    }
+
+    #[test]
+    fn test_cluster_least_recently_used() {
+        let first = fake_clamd(|_| b"stream: First FOUND".to_vec());
+        let second = fake_clamd(|_| b"stream: Second FOUND".to_vec());
+        let cluster = ClamCluster::new(vec![
+            ClamClient::new("127.0.0.1", first).unwrap(),
+            ClamClient::new("127.0.0.1", second).unwrap(),
+        ])
+        .balancing(Balancing::LeastRecentlyUsed);
+
+        assert_eq!(cluster.scan_bytes(b"data").unwrap(), found("First"));
+        assert_eq!(cluster.scan_bytes(b"data").unwrap(), found("Second"));
+        assert_eq!(cluster.scan_bytes(b"data").unwrap(), found("First"));
+    }
+
+    #[test]
+    fn test_cluster_skips_unhealthy_endpoints() {
+        let first = fake_clamd(|_| b"stream: First FOUND".to_vec());
+        let third = fake_clamd(|_| b"stream: Third FOUND".to_vec());
+        let cluster = ClamCluster::new(vec![
+            ClamClient::new("127.0.0.1", first).unwrap(),
+            ClamClient::new("127.0.0.1", 1).unwrap(),
+            ClamClient::new("127.0.0.1", third).unwrap(),
+        ])
+        .failure_threshold(1);
+
+        // The second endpoint fails and the scan is retried on the third
+        assert_eq!(cluster.scan_bytes(b"data").unwrap(), found("First"));
+        assert_eq!(cluster.scan_bytes(b"data").unwrap(), found("Third"));
+        assert_eq!(cluster.scan_bytes(b"data").unwrap(), found("First"));
+
+        // Round robin would now choose the second endpoint, which is skipped whilst unhealthy
+        assert_eq!(cluster.scan_bytes(b"data").unwrap(), found("Third"));
+
+        let health = cluster.health();
+        assert!(!health[1].healthy);
+        assert_eq!(health[1].consecutive_failures, 1);
+        assert!(health[0].healthy && health[2].healthy);
+        assert!(health[2].last_success.is_some());
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_value(health[1]).unwrap();
+            assert_eq!(json["healthy"], false);
+            assert_eq!(json["last_success_secs_ago"], serde_json::Value::Null);
+            assert!(json["last_failure_secs_ago"].as_f64().unwrap() < 60.0);
+        }
+    }
+
+    #[test]
+    fn test_cluster_local_errors_do_not_fail_endpoints() {
+        let port = fake_clamd(|_| b"stream: OK".to_vec());
+        let cluster = ClamCluster::new(vec![
+            ClamClient::new("127.0.0.1", port).unwrap(),
+            ClamClient::new("127.0.0.1", port).unwrap(),
+        ])
+        .failure_threshold(1);
+
+        let missing = std::env::temp_dir().join("clam_client_cluster_missing");
+        assert!(matches!(
+            cluster.scan_file(missing),
+            Err(crate::error::ClamError::LocalIoError(_))
+        ));
+        assert!(cluster
+            .health()
+            .iter()
+            .all(|health| health.healthy && health.consecutive_failures == 0));
+    }
+}
@@ -0,0 +1,237 @@
+//! `Command` models each of the commands ClamD accepts, so that any of them can be issued through
+//! the single `ClamClient::execute` method and answered with a `ClamResponse`. This gives
+//! middleware, such as logging or auditing wrappers, one uniform surface to intercept rather than
+//! a method per command.
+//!
+//! Commands this crate does not model, such as those added by vendor patched or future versions of
+//! ClamD, are implemented with the `ClamCommand` trait and issued with `ClamClient::run`.
+
+use crate::client::ClamResult;
+use crate::response::{ClamScanResult, ClamStats, ClamVersion, ReloadStatus};
+use crate::session::ClamSession;
+use std::fmt;
+use std::io::Read;
+
+/// `Command` is a ClamD command, along with any path or data it operates on.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::command::{ClamResponse, Command};
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///
+///     for command in vec![Command::Version, Command::ContScan("/tmp/")] {
+///         println!("Issuing {}", command);
+///         if let Ok(ClamResponse::Scan(results)) = client.execute(command) {
+///             println!("{:?}", results);
+///         }
+///     }
+/// }
+/// ```
+#[non_exhaustive]
+pub enum Command<'a> {
+    /// `PING`, checks that ClamD is responding
+    Ping,
+    /// `VERSION`, requests the version of ClamD and its signature database
+    Version,
+    /// `RELOAD`, reloads the signature database
+    Reload,
+    /// `STATS`, requests ClamD's statistics
+    Stats,
+    /// `SHUTDOWN`, stops ClamD
+    Shutdown,
+    /// `SCAN`, scans a path on the ClamD server, stopping at the first virus found
+    Scan(&'a str),
+    /// `CONTSCAN`, scans a path on the ClamD server, continuing after a virus is found
+    ContScan(&'a str),
+    /// `MULTISCAN`, scans a path on the ClamD server with multiple threads
+    MultiScan(&'a str),
+    /// `ALLMATCHSCAN`, scans a path on the ClamD server, reporting every matching signature
+    AllMatchScan(&'a str),
+    /// `INSTREAM`, scans the data read from a stream
+    InStream(&'a mut dyn Read),
+    /// `IDSESSION`, starts a session over which multiple commands can be issued
+    IdSession,
+    /// `END`, ends a session, which is only valid within a session
+    End,
+}
+
+impl Command<'_> {
+    /// Returns the name of the command as sent to ClamD, e.g. `CONTSCAN`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Ping => "PING",
+            Command::Version => "VERSION",
+            Command::Reload => "RELOAD",
+            Command::Stats => "STATS",
+            Command::Shutdown => "SHUTDOWN",
+            Command::Scan(_) => "SCAN",
+            Command::ContScan(_) => "CONTSCAN",
+            Command::MultiScan(_) => "MULTISCAN",
+            Command::AllMatchScan(_) => "ALLMATCHSCAN",
+            Command::InStream(_) => "INSTREAM",
+            Command::IdSession => "IDSESSION",
+            Command::End => "END",
+        }
+    }
+
+    /// Returns the path the command scans, if it scans a path on the ClamD server.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            Command::Scan(path)
+            | Command::ContScan(path)
+            | Command::MultiScan(path)
+            | Command::AllMatchScan(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// Formats the command as it is sent to ClamD, without its prefix or terminator, e.g.
+/// `SCAN /some/file`.
+impl fmt::Display for Command<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.path() {
+            Some(path) => write!(f, "{} {}", self.name(), path),
+            None => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+impl fmt::Debug for Command<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Command({})", self)
+    }
+}
+
+/// `ClamResponse` is ClamD's reply to a `Command`, parsed as the equivalent `ClamClient` method
+/// parses it.
+///
+/// With the `serde` feature responses may be serialized, except for `Session` which fails to
+/// serialize since a session is a live connection.
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ClamResponse {
+    /// The reply to `PING`, true if ClamD responded with `PONG`
+    Ping(bool),
+    /// The reply to `VERSION`
+    Version(ClamVersion),
+    /// The reply to `RELOAD`
+    Reload(ReloadStatus),
+    /// The reply to `STATS`
+    Stats(ClamStats),
+    /// The status message, if any, ClamD replied to `SHUTDOWN` with
+    Shutdown(String),
+    /// The results of `SCAN`, `CONTSCAN`, `MULTISCAN` or `ALLMATCHSCAN`
+    Scan(Vec<ClamScanResult>),
+    /// The result of `INSTREAM`
+    Stream(ClamScanResult),
+    /// The session started by `IDSESSION`
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
+    Session(ClamSession),
+}
+
+/// `ClamCommand` is a command defined outside of this crate, along with the parser for its reply,
+/// so that it can be issued with `ClamClient::run` and answered with a typed response.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::{ClamClient, ClamResult};
+/// use clam_client::command::ClamCommand;
+///
+/// struct VersionCommands;
+///
+/// impl ClamCommand for VersionCommands {
+///     type Response = Vec<String>;
+///
+///     fn command(&self) -> String {
+///         "VERSIONCOMMANDS".to_owned()
+///     }
+///
+///     fn single_reply(&self) -> bool {
+///         true
+///     }
+///
+///     fn parse(&self, reply: String) -> ClamResult<Vec<String>> {
+///         let commands = reply.trim_end_matches('\0').split("COMMANDS:").nth(1);
+///         Ok(commands.unwrap_or("").split_whitespace().map(str::to_owned).collect())
+///     }
+/// }
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///
+///     if let Ok(commands) = client.run(VersionCommands) {
+///         println!("ClamD supports {:?}", commands);
+///     }
+/// }
+/// ```
+pub trait ClamCommand {
+    /// The type the reply is parsed into
+    type Response;
+
+    /// Returns the command, without its prefix or terminator, e.g. `VERSIONCOMMANDS`. It is
+    /// framed according to the client's `CommandDelimiter`.
+    fn command(&self) -> String;
+
+    /// Returns true if ClamD sends a single reply to the command, which is then complete at its
+    /// terminator, rather than ClamD closing the connection once it has finished replying.
+    /// Defaults to false.
+    fn single_reply(&self) -> bool {
+        false
+    }
+
+    /// Parses ClamD's reply, with each line NUL terminated whichever `CommandDelimiter` the client
+    /// uses, as the parsers in `clam_client::response` expect.
+    fn parse(&self, reply: String) -> ClamResult<Self::Response>;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Command;
+
+    #[test]
+    fn test_command_display() {
+        assert_eq!(Command::Scan("/tmp/a b").to_string(), "SCAN /tmp/a b");
+        assert_eq!(Command::MultiScan("/tmp").name(), "MULTISCAN");
+        assert_eq!(Command::Stats.to_string(), "STATS");
+        assert_eq!(Command::InStream(&mut &b"data"[..]).path(), None);
+        assert_eq!(
+            format!("{:?}", Command::ContScan("/tmp")),
+            "Command(CONTSCAN /tmp)"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_response_serialize() {
+        use crate::command::ClamResponse;
+        use crate::response::ClamScanResult;
+
+        let response = ClamResponse::Scan(vec![
+            ClamScanResult::Found("/a".to_owned(), "Eicar-Test-Signature".to_owned()),
+            ClamScanResult::Ok,
+        ]);
+        let json = serde_json::to_value(&response).unwrap();
+        assert_eq!(
+            serde_json::from_value::<Vec<ClamScanResult>>(json["Scan"].clone()).unwrap(),
+            [
+                ClamScanResult::Found("/a".to_owned(), "Eicar-Test-Signature".to_owned()),
+                ClamScanResult::Ok,
+            ]
+        );
+        assert_eq!(
+            serde_json::to_value(ClamResponse::Ping(true)).unwrap(),
+            serde_json::json!({"Ping": true})
+        );
+    }
+}
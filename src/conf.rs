@@ -0,0 +1,246 @@
+//! Configuration of `ClamClient` from outside of the code, either from ClamD's own configuration
+//! file, `clamd.conf`, as used by `ClamClient::from_clamd_conf`, or from environment variables,
+//! as used by `ClamClient::from_env`.
+
+use crate::client::{ClamClient, ClamClientBuilder, ClamResult};
+use crate::error::ClamError;
+use std::net::IpAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The options from a `clamd.conf` which are relevant to a client.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ClamdConf {
+    tcp_socket: Option<u16>,
+    tcp_addr: Option<String>,
+    local_socket: Option<String>,
+    stream_max_length: Option<u64>,
+}
+
+impl ClamdConf {
+    /// Parses the contents of a `clamd.conf`, each line of which is either a comment starting
+    /// with `#` or an option name followed by its value. Where an option is repeated, as
+    /// `TCPAddr` may be, the first value is used.
+    pub(crate) fn parse(contents: &str) -> ClamResult<ClamdConf> {
+        let mut conf = ClamdConf::default();
+
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, value) = match line.split_once(char::is_whitespace) {
+                Some((name, value)) => (name, value.trim()),
+                None => continue,
+            };
+
+            if name.eq_ignore_ascii_case("TCPSocket") && conf.tcp_socket.is_none() {
+                let port = value.parse().map_err(|_| invalid(name, value))?;
+                conf.tcp_socket = Some(port);
+            } else if name.eq_ignore_ascii_case("TCPAddr") && conf.tcp_addr.is_none() {
+                conf.tcp_addr = Some(value.to_owned());
+            } else if name.eq_ignore_ascii_case("LocalSocket") && conf.local_socket.is_none() {
+                conf.local_socket = Some(value.to_owned());
+            } else if name.eq_ignore_ascii_case("StreamMaxLength") {
+                conf.stream_max_length =
+                    Some(parse_size(value).ok_or_else(|| invalid(name, value))?);
+            }
+        }
+
+        Ok(conf)
+    }
+
+    /// Creates a `ClamClientBuilder` connecting to the socket ClamD is configured to listen on.
+    pub(crate) fn builder(&self) -> ClamResult<ClamClientBuilder> {
+        let mut builder = match (&self.local_socket, self.tcp_socket) {
+            #[cfg(unix)]
+            (Some(path), _) => {
+                ClamClient::builder("127.0.0.1", 3310).unix_socket(PathBuf::from(path))
+            }
+            (_, Some(port)) => ClamClient::builder(&self.tcp_host(), port),
+            #[cfg(not(unix))]
+            (Some(_), None) => {
+                return Err(ClamError::ConfigError(
+                    "LocalSocket is only supported on Unix platforms".to_owned(),
+                ))
+            }
+            (None, None) => {
+                return Err(ClamError::ConfigError(
+                    "neither LocalSocket nor TCPSocket is set".to_owned(),
+                ))
+            }
+        };
+
+        if let Some(bytes) = self.stream_max_length {
+            builder = builder.stream_max_length(bytes);
+        }
+
+        Ok(builder)
+    }
+
+    /// Returns the address to connect to `TCPSocket` on, ClamD listening on every address is
+    /// reached over loopback.
+    fn tcp_host(&self) -> String {
+        match self.tcp_addr.as_deref().map(str::parse::<IpAddr>) {
+            None => "127.0.0.1".to_owned(),
+            Some(Ok(IpAddr::V4(ip))) if ip.is_unspecified() => "127.0.0.1".to_owned(),
+            Some(Ok(IpAddr::V6(ip))) if ip.is_unspecified() => "::1".to_owned(),
+            Some(_) => self.tcp_addr.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Creates a `ClamClientBuilder` from the variables described by `ClamClient::from_env`, each of
+/// which is looked up with `var`.
+pub(crate) fn env_builder<F>(var: F) -> ClamResult<ClamClientBuilder>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let parse = |name: &str| -> ClamResult<Option<u64>> {
+        match var(name) {
+            Some(value) => match value.trim().parse() {
+                Ok(n) => Ok(Some(n)),
+                Err(_) => Err(invalid(name, &value)),
+            },
+            None => Ok(None),
+        }
+    };
+
+    let host = var("CLAMD_HOST").unwrap_or_else(|| "127.0.0.1".to_owned());
+    let port = match parse("CLAMD_PORT")? {
+        Some(port) => u16::try_from(port).map_err(|_| invalid("CLAMD_PORT", &port.to_string()))?,
+        None => 3310,
+    };
+    let mut builder = ClamClient::builder(&host, port);
+
+    if let Some(socket) = var("CLAMD_SOCKET") {
+        #[cfg(unix)]
+        {
+            builder = builder.unix_socket(socket);
+        }
+        #[cfg(not(unix))]
+        return Err(ClamError::ConfigError(format!(
+            "CLAMD_SOCKET is only supported on Unix platforms: {}",
+            socket
+        )));
+    }
+    if let Some(secs) = parse("CLAMD_TIMEOUT_SECS")? {
+        builder = builder.connect_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = parse("CLAMD_READ_TIMEOUT_SECS")? {
+        builder = builder.read_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = parse("CLAMD_WRITE_TIMEOUT_SECS")? {
+        builder = builder.write_timeout(Duration::from_secs(secs));
+    }
+    if let Some(value) = var("CLAMD_STREAM_MAX_LENGTH") {
+        let bytes =
+            parse_size(value.trim()).ok_or_else(|| invalid("CLAMD_STREAM_MAX_LENGTH", &value))?;
+        builder = builder.stream_max_length(bytes);
+    }
+
+    Ok(builder)
+}
+
+/// Parses a ClamD size option, such as `25M`, which may be suffixed with `K`, `M` or `G`.
+fn parse_size(value: &str) -> Option<u64> {
+    let (digits, multiplier) = match value.chars().last()?.to_ascii_uppercase() {
+        'K' => (&value[..value.len() - 1], 1 << 10),
+        'M' => (&value[..value.len() - 1], 1 << 20),
+        'G' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Creates the error for an option whose value could not be parsed.
+fn invalid(name: &str, value: &str) -> ClamError {
+    ClamError::ConfigError(format!("invalid value for {}: {}", name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf::{env_builder, parse_size, ClamdConf};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("100k"), Some(100 * 1024));
+        assert_eq!(parse_size("25M"), Some(25 * 1024 * 1024));
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("lots"), None);
+    }
+
+    #[test]
+    fn test_parse_conf() {
+        let conf = ClamdConf::parse(
+            "# Comment out or remove the Example line to use this file\n\
+             #Example\n\
+             LogFile /var/log/clamav/clamd.log\n\
+             TCPSocket 3311\n\
+             TCPAddr 10.0.0.5\n\
+             TCPAddr 10.0.0.6\n\
+             StreamMaxLength 50M\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            conf,
+            ClamdConf {
+                tcp_socket: Some(3311),
+                tcp_addr: Some("10.0.0.5".to_owned()),
+                local_socket: None,
+                stream_max_length: Some(50 * 1024 * 1024),
+            }
+        );
+        assert_eq!(conf.tcp_host(), "10.0.0.5");
+    }
+
+    #[test]
+    fn test_parse_conf_invalid() {
+        assert!(ClamdConf::parse("TCPSocket clamd").is_err());
+        assert!(ClamdConf::parse("StreamMaxLength big").is_err());
+        assert!(ClamdConf::parse("LogFile /tmp/clamd.log")
+            .unwrap()
+            .builder()
+            .is_err());
+    }
+
+    #[test]
+    fn test_unspecified_tcp_addr() {
+        let conf = ClamdConf::parse("TCPSocket 3310\nTCPAddr 0.0.0.0").unwrap();
+        assert_eq!(conf.tcp_host(), "127.0.0.1");
+        assert_eq!(
+            ClamdConf::parse("TCPSocket 3310").unwrap().tcp_host(),
+            "127.0.0.1"
+        );
+    }
+
+    #[test]
+    fn test_env_builder() {
+        let port = crate::testing::fake_clamd(|_| b"PONG".to_vec());
+        let vars = HashMap::from([
+            ("CLAMD_PORT", port.to_string()),
+            ("CLAMD_TIMEOUT_SECS", "5".to_owned()),
+            ("CLAMD_STREAM_MAX_LENGTH", "25M".to_owned()),
+        ]);
+
+        let client = env_builder(|name| vars.get(name).cloned())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert!(client.ping());
+    }
+
+    #[test]
+    fn test_env_builder_invalid() {
+        let vars = HashMap::from([("CLAMD_PORT", "70000".to_owned())]);
+        assert!(env_builder(|name| vars.get(name).cloned()).is_err());
+
+        let vars = HashMap::from([("CLAMD_TIMEOUT_SECS", "soon".to_owned())]);
+        assert!(env_builder(|name| vars.get(name).cloned()).is_err());
+    }
+}
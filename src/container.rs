@@ -0,0 +1,176 @@
+//! `ClamdContainer` runs a real ClamD in a Docker container using `testcontainers`, so that
+//! integration tests can exercise genuine protocol behaviour rather than a mock. Requires the
+//! `testcontainers` feature and a reachable Docker daemon.
+//!
+//! The official `clamav/clamav` image downloads its signature database on first start, which can
+//! take a minute or more, so containers are given up to five minutes to become ready.
+
+use crate::client::{ClamClient, ClamClientBuilder, ClamResult};
+use crate::error::ClamError;
+use std::time::Duration;
+use testcontainers::core::{ContainerPort, WaitFor};
+use testcontainers::runners::SyncRunner;
+use testcontainers::{Container, Image, ImageExt};
+
+/// The port ClamD listens on within the container.
+const CLAMD_PORT: ContainerPort = ContainerPort::Tcp(3310);
+
+/// How long a container is given to start ClamD, including loading its signature database.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// `ClamdImage` is the `clamav/clamav` Docker image, by default its `stable` tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClamdImage {
+    tag: String,
+}
+
+impl ClamdImage {
+    /// Creates a `ClamdImage` for the `clamav/clamav` image with `tag`, such as `1.4`.
+    pub fn with_tag(tag: &str) -> ClamdImage {
+        ClamdImage {
+            tag: tag.to_owned(),
+        }
+    }
+}
+
+impl Default for ClamdImage {
+    fn default() -> ClamdImage {
+        ClamdImage::with_tag("stable")
+    }
+}
+
+impl Image for ClamdImage {
+    fn name(&self) -> &str {
+        "clamav/clamav"
+    }
+
+    fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    fn ready_conditions(&self) -> Vec<WaitFor> {
+        vec![WaitFor::message_on_either_std(
+            "socket found, clamd started",
+        )]
+    }
+
+    fn expose_ports(&self) -> &[ContainerPort] {
+        &[CLAMD_PORT]
+    }
+}
+
+/// `ClamdContainer` is a running ClamD container which is ready to accept commands. The container
+/// is stopped and removed when dropped.
+///
+/// *Example*
+///
+/// ```rust,no_run
+/// extern crate clam_client;
+///
+/// use clam_client::container::ClamdContainer;
+/// use clam_client::response::{ClamScanResult, EICAR};
+///
+/// fn main() {
+///     let clamd = ClamdContainer::start().unwrap();
+///     let client = clamd.client().unwrap();
+///
+///     assert!(matches!(
+///         client.scan_bytes(EICAR).unwrap(),
+///         ClamScanResult::Found(..)
+///     ));
+/// }
+/// ```
+pub struct ClamdContainer {
+    container: Container<ClamdImage>,
+    host: String,
+    port: u16,
+}
+
+impl ClamdContainer {
+    /// Starts a container from the default `ClamdImage`, see `ClamdContainer::start_image`.
+    pub fn start() -> ClamResult<ClamdContainer> {
+        ClamdContainer::start_image(ClamdImage::default())
+    }
+
+    /// Starts a container from `image`, pulling it if need be, and blocks until ClamD has started
+    /// and responds to `PING`. Returns `ClamError::ContainerError` if the container cannot be
+    /// started, or a timeout if ClamD does not become ready.
+    pub fn start_image(image: ClamdImage) -> ClamResult<ClamdContainer> {
+        let container = image
+            .with_startup_timeout(STARTUP_TIMEOUT)
+            .start()
+            .map_err(container_error)?;
+        let host = container.get_host().map_err(container_error)?.to_string();
+        let port = container
+            .get_host_port_ipv4(CLAMD_PORT)
+            .map_err(container_error)?;
+
+        let clamd = ClamdContainer {
+            container,
+            host,
+            port,
+        };
+        // ClamD may log that it has started shortly before the mapped port accepts connections
+        clamd.client()?.wait_until_ready(STARTUP_TIMEOUT)?;
+
+        Ok(clamd)
+    }
+
+    /// Returns the host on which the container's ClamD port is published.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Returns the host port to which ClamD's port is published.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the ID of the container.
+    pub fn id(&self) -> &str {
+        self.container.id()
+    }
+
+    /// Creates a `ClamClient` connected to the container's ClamD.
+    pub fn client(&self) -> ClamResult<ClamClient> {
+        self.builder().build()
+    }
+
+    /// Returns a `ClamClientBuilder` for the container's ClamD, for tests which need a client
+    /// configured other than by default.
+    pub fn builder(&self) -> ClamClientBuilder {
+        ClamClient::builder(&self.host, self.port)
+    }
+}
+
+/// Wraps an error from `testcontainers` in a `ClamError`.
+fn container_error(e: testcontainers::TestcontainersError) -> ClamError {
+    ClamError::ContainerError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::container::ClamdContainer;
+    use crate::response::{ClamScanResult, EICAR};
+
+    #[test]
+    #[ignore = "requires Docker"]
+    fn test_clamd_container() {
+        let clamd = ClamdContainer::start().unwrap();
+        let client = clamd.client().unwrap();
+
+        assert!(client.ping());
+        assert!(client.version().is_ok());
+        assert_eq!(
+            client.scan_bytes(b"clean data").unwrap(),
+            ClamScanResult::Ok
+        );
+        assert!(matches!(
+            client.scan_bytes(EICAR).unwrap(),
+            ClamScanResult::Found(..)
+        ));
+
+        let mut session = client.session().unwrap();
+        assert!(session.ping().unwrap());
+    }
+}
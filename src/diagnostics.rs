@@ -0,0 +1,372 @@
+//! `DiagnosticsReport` is the outcome of `ClamClient::diagnose`, which runs a battery of checks
+//! against ClamD - connecting, `PING`, `VERSION`, `STATS` and a small `INSTREAM` - and records
+//! what failed along with its likely cause, for troubleshooting a client which "doesn't work".
+
+use crate::error::ClamError;
+use std::fmt;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+
+/// `Check` is one of the checks run by `ClamClient::diagnose`, in the order they are run.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Check {
+    /// Establishing a connection to ClamD, including any proxy tunnel or TLS handshake
+    Connect,
+    /// `PING`, which ClamD answers with `PONG`
+    Ping,
+    /// `VERSION`, and parsing ClamD's reply
+    Version,
+    /// `STATS`, and parsing ClamD's reply
+    Stats,
+    /// Streaming the EICAR test file with `INSTREAM`, which ClamD should detect
+    Stream,
+}
+
+/// `CheckStatus` is the outcome of a single `Check`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CheckStatus {
+    /// The check succeeded
+    Passed,
+    /// The check failed
+    Failed,
+    /// The check was not run, since connecting to ClamD or `PING` failed
+    Skipped,
+}
+
+/// `LikelyCause` is the most probable explanation for a failed `Check`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LikelyCause {
+    /// Nothing is accepting connections, ClamD is not running, is still loading its signature
+    /// database, or the host, port or socket path is wrong
+    NotListening,
+    /// Connecting timed out, the host is unreachable or a firewall is dropping the connection
+    Unreachable,
+    /// The Unix socket exists, but this process is not permitted to connect to it
+    PermissionDenied,
+    /// The proxy or TLS configuration does not match the way ClamD is exposed
+    Transport,
+    /// Something other than ClamD is listening, such as another service on the port
+    NotClamD,
+    /// ClamD accepted the connection but did not reply in time, as when it is reloading its
+    /// database or every thread is busy
+    NotResponding,
+    /// ClamD replied in a format which was not recognised, as from an unsupported version
+    UnsupportedReply,
+    /// ClamD rejected the stream, its `StreamMaxLength` or the client's maximum stream length
+    /// is smaller than the few bytes streamed
+    StreamLimit,
+    /// ClamD did not detect the EICAR test file, its signature database may not have loaded
+    SignaturesNotLoaded,
+    /// The client's circuit breaker is open after repeated failures
+    CircuitOpen,
+}
+
+/// `CheckResult` is the outcome of a single `Check` run by `ClamClient::diagnose`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    /// The check which was run
+    pub check: Check,
+    /// Whether the check passed, failed or was skipped
+    pub status: CheckStatus,
+    /// What the check found, such as ClamD's version, or the error if it failed
+    pub detail: String,
+    /// The likely cause of a failure, if one could be determined
+    pub cause: Option<LikelyCause>,
+    /// How long the check took
+    pub duration: Duration,
+}
+
+/// `DiagnosticsReport` records the outcome of every check run by `ClamClient::diagnose`. Its
+/// `Display` implementation lists them, one per line, suitable for attaching to a support ticket.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///     let report = client.diagnose();
+///
+///     if !report.is_healthy() {
+///         println!("{}", report);
+///     }
+/// }
+/// ```
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiagnosticsReport {
+    /// The outcome of each check, in the order they were run
+    pub checks: Vec<CheckResult>,
+}
+
+impl DiagnosticsReport {
+    /// Returns true if every check passed.
+    pub fn is_healthy(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status == CheckStatus::Passed)
+    }
+
+    /// Returns the checks which failed.
+    pub fn failures(&self) -> impl Iterator<Item = &CheckResult> {
+        self.checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Failed)
+    }
+
+    /// Returns the outcome of `check`, if it was run.
+    pub fn get(&self, check: Check) -> Option<&CheckResult> {
+        self.checks.iter().find(|result| result.check == check)
+    }
+
+    /// Runs `check` and records its outcome, unless connecting to ClamD or `PING` has already
+    /// failed, in which case it is skipped since it could only fail in the same way.
+    pub(crate) fn run<F>(&mut self, check: Check, run: F)
+    where
+        F: FnOnce() -> Result<String, Failure>,
+    {
+        let blocked = self
+            .failures()
+            .any(|failed| matches!(failed.check, Check::Connect | Check::Ping));
+        if blocked {
+            self.checks.push(CheckResult {
+                check,
+                status: CheckStatus::Skipped,
+                detail: "an earlier check failed".to_owned(),
+                cause: None,
+                duration: Duration::ZERO,
+            });
+            return;
+        }
+
+        let started = Instant::now();
+        let outcome = run();
+        let duration = started.elapsed();
+
+        self.checks.push(match outcome {
+            Ok(detail) => CheckResult {
+                check,
+                status: CheckStatus::Passed,
+                detail,
+                cause: None,
+                duration,
+            },
+            Err(Failure::Error(e)) => CheckResult {
+                check,
+                status: CheckStatus::Failed,
+                detail: e.to_string(),
+                cause: LikelyCause::of(check, &e),
+                duration,
+            },
+            Err(Failure::Diagnosed(detail, cause)) => CheckResult {
+                check,
+                status: CheckStatus::Failed,
+                detail,
+                cause: Some(cause),
+                duration,
+            },
+        });
+    }
+}
+
+/// The reason a check failed, either an error whose cause is inferred from its kind, or a
+/// reply which was received but is wrong, such as `PING` not being answered with `PONG`.
+pub(crate) enum Failure {
+    Error(ClamError),
+    Diagnosed(String, LikelyCause),
+}
+
+impl From<ClamError> for Failure {
+    fn from(e: ClamError) -> Failure {
+        Failure::Error(e)
+    }
+}
+
+impl LikelyCause {
+    /// Infers the likely cause of `check` failing with `error`.
+    fn of(check: Check, error: &ClamError) -> Option<LikelyCause> {
+        match error {
+            ClamError::ConnectionError(e) | ClamError::CommandError(e) => match e.kind() {
+                ErrorKind::ConnectionRefused | ErrorKind::NotFound => {
+                    Some(LikelyCause::NotListening)
+                }
+                ErrorKind::PermissionDenied => Some(LikelyCause::PermissionDenied),
+                ErrorKind::TimedOut | ErrorKind::WouldBlock if check == Check::Connect => {
+                    Some(LikelyCause::Unreachable)
+                }
+                ErrorKind::TimedOut | ErrorKind::WouldBlock => Some(LikelyCause::NotResponding),
+                ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::UnexpectedEof
+                | ErrorKind::InvalidData
+                    if check == Check::Ping =>
+                {
+                    Some(LikelyCause::NotClamD)
+                }
+                _ => None,
+            },
+            ClamError::ProxyError(_) => Some(LikelyCause::Transport),
+            #[cfg(feature = "tls")]
+            ClamError::TlsError(_) => Some(LikelyCause::Transport),
+            ClamError::StreamTooLarge(_) | ClamError::StreamSizeLimitExceeded(_) => {
+                Some(LikelyCause::StreamLimit)
+            }
+            ClamError::InvalidData(_)
+            | ClamError::IntParseError(_)
+            | ClamError::ProtocolError { .. } => Some(LikelyCause::UnsupportedReply),
+            #[cfg(feature = "chrono")]
+            ClamError::DateParseError(_) => Some(LikelyCause::UnsupportedReply),
+            ClamError::CircuitOpen => Some(LikelyCause::CircuitOpen),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Check::Connect => "connect",
+            Check::Ping => "PING",
+            Check::Version => "VERSION",
+            Check::Stats => "STATS",
+            Check::Stream => "INSTREAM",
+        })
+    }
+}
+
+impl fmt::Display for LikelyCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LikelyCause::NotListening => {
+                "nothing is listening: ClamD may not be running or may still be loading its \
+                 database, or the host, port or socket path may be wrong"
+            }
+            LikelyCause::Unreachable => {
+                "the host is unreachable, or a firewall is dropping the connection"
+            }
+            LikelyCause::PermissionDenied => "this process may not connect to the Unix socket",
+            LikelyCause::Transport => "the proxy or TLS configuration does not match ClamD's",
+            LikelyCause::NotClamD => "something other than ClamD is listening on the port",
+            LikelyCause::NotResponding => {
+                "ClamD did not reply in time, it may be reloading its database or overloaded"
+            }
+            LikelyCause::UnsupportedReply => "ClamD's reply was not recognised",
+            LikelyCause::StreamLimit => "ClamD's StreamMaxLength rejected a tiny stream",
+            LikelyCause::SignaturesNotLoaded => {
+                "ClamD did not detect EICAR, its signature database may not have loaded"
+            }
+            LikelyCause::CircuitOpen => "the client's circuit breaker is open",
+        })
+    }
+}
+
+impl fmt::Display for DiagnosticsReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for result in &self.checks {
+            let status = match result.status {
+                CheckStatus::Passed => "PASS",
+                CheckStatus::Failed => "FAIL",
+                CheckStatus::Skipped => "SKIP",
+            };
+            write!(
+                f,
+                "{} {} ({:?}): {}",
+                status, result.check, result.duration, result.detail
+            )?;
+            if let Some(cause) = result.cause {
+                write!(f, " - likely cause: {}", cause)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::diagnostics::{Check, CheckStatus, LikelyCause};
+    use crate::testing::fake_clamd;
+
+    #[test]
+    fn test_diagnose_healthy() {
+        let port = fake_clamd(|cmd| {
+            match cmd {
+            b"zPING" => b"PONG".to_vec(),
+            b"zVERSION" => b"ClamAV 0.100.0/24802/Wed Aug  1 08:43:37 2018".to_vec(),
+            b"zSTATS" => b"POOLS: 1\n\nSTATE: VALID PRIMARY\nTHREADS: live 1  idle 0 max 12 idle-timeout 30\nQUEUE: 0 items\nEND".to_vec(),
+            _ => b"stream: Eicar-Signature FOUND".to_vec(),
+        }
+        });
+        let report = ClamClient::new("127.0.0.1", port).unwrap().diagnose();
+
+        assert!(report.is_healthy(), "{}", report);
+        assert_eq!(report.checks.len(), 5);
+        assert_eq!(report.get(Check::Version).unwrap().detail, "ClamAV 0.100.0");
+    }
+
+    #[test]
+    fn test_diagnose_not_listening() {
+        let report = ClamClient::new("127.0.0.1", 1).unwrap().diagnose();
+
+        let connect = report.get(Check::Connect).unwrap();
+        assert_eq!(connect.status, CheckStatus::Failed);
+        assert_eq!(connect.cause, Some(LikelyCause::NotListening));
+        assert!(report.checks[1..]
+            .iter()
+            .all(|check| check.status == CheckStatus::Skipped));
+        assert!(report
+            .to_string()
+            .contains("likely cause: nothing is listening"));
+    }
+
+    #[test]
+    fn test_diagnose_not_clamd() {
+        let port = fake_clamd(|_| b"HTTP/1.1 400 Bad Request".to_vec());
+        let report = ClamClient::new("127.0.0.1", port).unwrap().diagnose();
+
+        let ping = report.get(Check::Ping).unwrap();
+        assert_eq!(ping.status, CheckStatus::Failed);
+        assert_eq!(ping.cause, Some(LikelyCause::NotClamD));
+        assert_eq!(report.failures().count(), 1);
+    }
+
+    #[test]
+    fn test_diagnose_failures() {
+        let port = fake_clamd(|cmd| match cmd {
+            b"zPING" => b"PONG".to_vec(),
+            b"zVERSION" | b"zSTATS" => b"garbage".to_vec(),
+            _ => b"INSTREAM size limit exceeded. ERROR".to_vec(),
+        });
+        let report = ClamClient::new("127.0.0.1", port).unwrap().diagnose();
+
+        let causes: Vec<_> = report.failures().map(|f| (f.check, f.cause)).collect();
+        assert_eq!(
+            causes,
+            [
+                (Check::Version, Some(LikelyCause::UnsupportedReply)),
+                (Check::Stats, Some(LikelyCause::UnsupportedReply)),
+                (Check::Stream, Some(LikelyCause::StreamLimit)),
+            ]
+        );
+
+        let port = fake_clamd(|cmd| {
+            match cmd {
+            b"zPING" => b"PONG".to_vec(),
+            b"zVERSION" => b"ClamAV 0.100.0/24802/Wed Aug  1 08:43:37 2018".to_vec(),
+            b"zSTATS" => b"POOLS: 1\n\nSTATE: VALID PRIMARY\nTHREADS: live 1  idle 0 max 12 idle-timeout 30\nQUEUE: 0 items\nEND".to_vec(),
+            _ => b"stream: OK".to_vec(),
+        }
+        });
+        let report = ClamClient::new("127.0.0.1", port).unwrap().diagnose();
+        let stream = report.get(Check::Stream).unwrap();
+        assert_eq!(stream.cause, Some(LikelyCause::SignaturesNotLoaded));
+    }
+}
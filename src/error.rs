@@ -7,30 +7,416 @@
 //! Whilst this may not be the most optimal approach, and is subject to change, it does make
 //! client side handling and result propagation very simple.
 
+use std::fmt;
+use std::io::{self, ErrorKind};
 use thiserror::Error;
 
-/// `ClamError` is the primary interface for all errors emitted by `clam_client`.
+/// `ClamError` is the primary interface for all errors emitted by `clam_client`. It implements
+/// `std::error::Error`, with `source` returning the underlying I/O or parse error where there is
+/// one, and is marked `#[non_exhaustive]` so that new variants are not a breaking change.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum ClamError {
     /// Generated when an invalid IP address is supplied to `ClamClient::new(..)`
     #[error("{0}")]
-    InvalidIpAddress(std::net::AddrParseError),
+    InvalidIpAddress(#[source] std::net::AddrParseError),
+    /// Generated when a hostname supplied to `ClamClient::new(..)` cannot be resolved
+    #[error("Could not resolve {0}: {1}")]
+    ResolutionError(String, #[source] std::io::Error),
     /// Generated when a`ClamClient` is unable to connect to the specified ClamAV socket
     #[error("{0}")]
     ConnectionError(#[from] std::io::Error),
     /// Generated when the command issued cannot be successfully written to the ClamAV socket
     #[error("{0}")]
-    CommandError(std::io::Error),
+    CommandError(#[source] std::io::Error),
+    /// Generated when a local file or reader supplied to be scanned cannot be read, as opposed to
+    /// a failure talking to ClamD
+    #[error("Could not read the data to scan: {0}")]
+    LocalIoError(#[source] std::io::Error),
     /// Generated when the ClamAV response cannot be parsed by `clam_client::response::T`
     #[error("Could not parse: {0}")]
     InvalidData(String),
     /// Generated when an integer cannot be parsed, wrapped in `ClamError` for ease
     #[error("{0}")]
-    IntParseError(std::num::ParseIntError),
+    IntParseError(#[source] std::num::ParseIntError),
     /// Generated when a date cannot be parsed by `chrono`, wrapped in `ClamError` for ease
+    #[cfg(feature = "chrono")]
     #[error("{0}")]
-    DateParseError(chrono::format::ParseError),
+    DateParseError(#[source] chrono::format::ParseError),
     /// Generated when the data length written to the ClamD socket exceeds 2^32
     #[error("Invalid data length sent: {0}")]
     InvalidDataLengthError(usize),
+    /// Generated when a stream exceeds the configured maximum stream length, before it is
+    /// rejected by ClamD
+    #[error("Stream exceeds the maximum length of {0} bytes")]
+    StreamTooLarge(u64),
+    /// Generated when ClamD rejects a stream for exceeding its `StreamMaxLength`, carrying
+    /// ClamD's reply
+    #[error("ClamD rejected the stream: {0}")]
+    StreamSizeLimitExceeded(String),
+    /// Generated when ClamD replies `COMMAND READ TIMED OUT`, having given up waiting for the
+    /// client to finish sending a command
+    #[error("ClamD timed out waiting for the command")]
+    CommandReadTimedOut,
+    /// Generated when a proxy refuses to open a tunnel to ClamD, or responds unexpectedly
+    #[error("Proxy error: {0}")]
+    ProxyError(String),
+    /// Generated when a `clamd.conf` cannot be read, or it or the environment does not configure
+    /// a usable client
+    #[error("Invalid configuration: {0}")]
+    ConfigError(String),
+    /// Generated when ClamD's reply is not one it is known to send, rather than ClamD reporting
+    /// an error, if `ClamClientBuilder::strict_parsing` is enabled. Carries the reply.
+    #[error("Unrecognised reply from ClamD: {reply}")]
+    ProtocolError {
+        /// The reply, or the line of it, which was not recognised
+        reply: String,
+    },
+    /// Generated when a command is issued where it is not valid, such as `END` outside of a
+    /// session
+    #[error("{0} is not supported here")]
+    UnsupportedCommand(String),
+    /// Generated when a command is not issued because the client's circuit breaker is open
+    #[error("Circuit breaker is open, ClamD is considered unavailable")]
+    CircuitOpen,
+    /// Generated when a job submitted to a `scanner::Queue` is abandoned without being scanned
+    #[error("The scan job was abandoned before it completed")]
+    JobAbandoned,
+    /// Generated when a job is submitted to a `scanner::Queue` which has been shut down
+    #[error("The scan queue has been shut down")]
+    QueueClosed,
+    /// Generated when a job submitted to a `scanner::Queue` is not scanned by its deadline
+    #[error("The scan job was not completed by its deadline")]
+    TimedOut,
+    /// Generated when TLS is misconfigured, or a TLS connection cannot be established
+    #[cfg(feature = "tls")]
+    #[error("TLS error: {0}")]
+    TlsError(String),
+    /// Generated when an infected file cannot be moved into quarantine, carrying its path
+    #[cfg(feature = "quarantine")]
+    #[error("Could not quarantine {0}: {1}")]
+    QuarantineError(String, #[source] std::io::Error),
+    /// Generated when a ClamD container cannot be started for integration testing
+    #[cfg(feature = "testcontainers")]
+    #[error("Container error: {0}")]
+    ContainerError(String),
+    /// Generated when an archive cannot be read for client side extraction
+    #[cfg(feature = "zip")]
+    #[error("Archive error: {0}")]
+    ArchiveError(String),
+}
+
+/// Serializes the error as its kind, the name of its variant, along with its message, since the
+/// I/O and parse errors it wraps cannot themselves be serialized, e.g.
+/// `{"kind":"CircuitOpen","message":"Circuit breaker is open, ClamD is considered unavailable"}`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ClamError {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut error = s.serialize_struct("ClamError", 2)?;
+        error.serialize_field("kind", self.kind())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.end()
+    }
+}
+
+impl ClamError {
+    /// Returns the name of the variant, as serialized.
+    #[cfg(feature = "serde")]
+    fn kind(&self) -> &'static str {
+        match self {
+            ClamError::InvalidIpAddress(_) => "InvalidIpAddress",
+            ClamError::ResolutionError(..) => "ResolutionError",
+            ClamError::ConnectionError(_) => "ConnectionError",
+            ClamError::CommandError(_) => "CommandError",
+            ClamError::LocalIoError(_) => "LocalIoError",
+            ClamError::InvalidData(_) => "InvalidData",
+            ClamError::IntParseError(_) => "IntParseError",
+            #[cfg(feature = "chrono")]
+            ClamError::DateParseError(_) => "DateParseError",
+            ClamError::InvalidDataLengthError(_) => "InvalidDataLengthError",
+            ClamError::StreamTooLarge(_) => "StreamTooLarge",
+            ClamError::StreamSizeLimitExceeded(_) => "StreamSizeLimitExceeded",
+            ClamError::CommandReadTimedOut => "CommandReadTimedOut",
+            ClamError::ProxyError(_) => "ProxyError",
+            ClamError::ConfigError(_) => "ConfigError",
+            ClamError::ProtocolError { .. } => "ProtocolError",
+            ClamError::UnsupportedCommand(_) => "UnsupportedCommand",
+            ClamError::CircuitOpen => "CircuitOpen",
+            ClamError::JobAbandoned => "JobAbandoned",
+            ClamError::QueueClosed => "QueueClosed",
+            ClamError::TimedOut => "TimedOut",
+            #[cfg(feature = "tls")]
+            ClamError::TlsError(_) => "TlsError",
+            #[cfg(feature = "quarantine")]
+            ClamError::QuarantineError(..) => "QuarantineError",
+            #[cfg(feature = "testcontainers")]
+            ClamError::ContainerError(_) => "ContainerError",
+            #[cfg(feature = "zip")]
+            ClamError::ArchiveError(_) => "ArchiveError",
+        }
+    }
+
+    /// Returns true if the error indicates a problem reaching or talking to ClamD itself, such as
+    /// a refused or reset connection, rather than a problem with the command or data sent to it.
+    pub fn is_connection_error(&self) -> bool {
+        matches!(
+            self,
+            ClamError::ConnectionError(_) | ClamError::CommandError(_) | ClamError::ProxyError(_)
+        )
+    }
+
+    /// Returns true if the error is a connect, read or write timeout, ClamD timed out waiting for
+    /// the command, or a queued scan job was not completed by its deadline.
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            ClamError::CommandReadTimedOut | ClamError::TimedOut => true,
+            ClamError::ConnectionError(e) | ClamError::CommandError(e) => {
+                matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if the error is likely to be resolved by simply trying again, such as ClamD
+    /// refusing connections whilst it reloads its signature database. This is the predicate used
+    /// by `RetryPolicy` by default.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClamError::CommandReadTimedOut => true,
+            ClamError::ConnectionError(e) | ClamError::CommandError(e) => matches!(
+                e.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::TimedOut
+                    | ErrorKind::Interrupted
+            ),
+            _ => false,
+        }
+    }
+
+    /// Returns what the client was doing when a `ConnectionError` or `CommandError` occurred, such
+    /// as the command being issued and the endpoint it was issued to. The context is also
+    /// included when the error is displayed.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            ClamError::ConnectionError(e) | ClamError::CommandError(e) => e
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<Contextual>())
+                .map(|contextual| &contextual.context),
+            _ => None,
+        }
+    }
+
+    /// Records that the error occurred in `phase` of talking to `endpoint`, unless its context
+    /// is already known. Only `ConnectionError` and `CommandError` carry context.
+    pub(crate) fn in_phase(self, phase: Phase, endpoint: &dyn fmt::Display) -> ClamError {
+        if self.context().is_some() {
+            return self;
+        }
+
+        let wrap = |source: io::Error| {
+            let context = ErrorContext {
+                command: None,
+                endpoint: endpoint.to_string(),
+                phase,
+            };
+            io::Error::new(source.kind(), Contextual { context, source })
+        };
+        match self {
+            ClamError::ConnectionError(e) => ClamError::ConnectionError(wrap(e)),
+            ClamError::CommandError(e) => ClamError::CommandError(wrap(e)),
+            e => e,
+        }
+    }
+
+    /// Records that the error occurred whilst issuing `command`, if it carries context without a
+    /// command already.
+    pub(crate) fn for_command(mut self, command: &str) -> ClamError {
+        if let ClamError::ConnectionError(e) | ClamError::CommandError(e) = &mut self {
+            let contextual = e
+                .get_mut()
+                .and_then(|inner| inner.downcast_mut::<Contextual>());
+            if let Some(contextual) = contextual {
+                contextual
+                    .context
+                    .command
+                    .get_or_insert_with(|| command.to_owned());
+            }
+        }
+        self
+    }
+
+    /// Returns true if ClamD replied, but the reply could not be understood.
+    pub fn is_protocol_error(&self) -> bool {
+        match self {
+            ClamError::InvalidData(_)
+            | ClamError::IntParseError(_)
+            | ClamError::InvalidDataLengthError(_) => true,
+            #[cfg(feature = "chrono")]
+            ClamError::DateParseError(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// `Phase` is the stage of talking to ClamD at which an error occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    /// Establishing the connection, including any proxy tunnel or TLS handshake
+    Connecting,
+    /// Writing the command, or the data streamed with it
+    Sending,
+    /// Reading ClamD's reply
+    Receiving,
+}
+
+/// `ErrorContext` describes what the client was doing when an I/O error occurred, it is obtained
+/// via `ClamError::context`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    command: Option<String>,
+    endpoint: String,
+    phase: Phase,
+}
+
+impl ErrorContext {
+    /// Returns the name of the command being issued, such as `INSTREAM`, if known. Arguments,
+    /// such as the path being scanned, are omitted.
+    pub fn command(&self) -> Option<&str> {
+        self.command.as_deref()
+    }
+
+    /// Returns the endpoint being talked to, its address or the path of its Unix socket.
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Returns the stage of talking to ClamD at which the error occurred.
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(command) = &self.command {
+            write!(f, "{} ", command)?;
+        }
+        let phase = match self.phase {
+            Phase::Connecting => "connecting to",
+            Phase::Sending => "sending to",
+            Phase::Receiving => "receiving from",
+        };
+        write!(f, "failed {} {}", phase, self.endpoint)
+    }
+}
+
+/// An I/O error along with its context. It is carried within the `io::Error` of a
+/// `ConnectionError` or `CommandError`, so that the error's kind and variant are unchanged.
+#[derive(Debug)]
+struct Contextual {
+    context: ErrorContext,
+    source: io::Error,
+}
+
+impl fmt::Display for Contextual {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.context, self.source)
+    }
+}
+
+impl std::error::Error for Contextual {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{ClamError, Phase};
+    use std::error::Error;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn test_error_source() {
+        let e = ClamError::CommandError(ErrorKind::BrokenPipe.into());
+        let source = e
+            .source()
+            .unwrap()
+            .downcast_ref::<std::io::Error>()
+            .unwrap();
+        assert_eq!(source.kind(), ErrorKind::BrokenPipe);
+
+        let e = ClamError::IntParseError("x".parse::<u64>().unwrap_err());
+        assert!(e.source().unwrap().is::<std::num::ParseIntError>());
+
+        assert!(ClamError::InvalidData("x".to_owned()).source().is_none());
+    }
+
+    #[test]
+    fn test_error_classification() {
+        let refused = ClamError::ConnectionError(ErrorKind::ConnectionRefused.into());
+        assert!(refused.is_connection_error() && refused.is_retryable());
+        assert!(!refused.is_timeout() && !refused.is_protocol_error());
+
+        let timed_out = ClamError::CommandError(ErrorKind::WouldBlock.into());
+        assert!(timed_out.is_connection_error() && timed_out.is_timeout());
+
+        let invalid = ClamError::InvalidData("garbage".to_owned());
+        assert!(invalid.is_protocol_error());
+        assert!(!invalid.is_connection_error() && !invalid.is_retryable());
+
+        assert!(!ClamError::StreamTooLarge(10).is_retryable());
+        let missing = ClamError::LocalIoError(ErrorKind::NotFound.into());
+        assert!(!missing.is_connection_error() && !missing.is_retryable());
+        assert!(ClamError::CommandReadTimedOut.is_timeout());
+        assert!(ClamError::CommandReadTimedOut.is_retryable());
+    }
+
+    #[test]
+    fn test_error_context() {
+        let e = ClamError::ConnectionError(ErrorKind::ConnectionRefused.into())
+            .in_phase(Phase::Connecting, &"127.0.0.1:3310")
+            .for_command("INSTREAM")
+            .for_command("PING");
+
+        let context = e.context().unwrap();
+        assert_eq!(context.command(), Some("INSTREAM"));
+        assert_eq!(context.endpoint(), "127.0.0.1:3310");
+        assert_eq!(context.phase(), Phase::Connecting);
+        assert!(e
+            .to_string()
+            .starts_with("INSTREAM failed connecting to 127.0.0.1:3310: "));
+
+        // The error's kind, and so its classification, is unchanged
+        assert!(e.is_retryable());
+        assert!(
+            matches!(&e, ClamError::ConnectionError(io) if io.kind() == ErrorKind::ConnectionRefused)
+        );
+
+        // Context is only recorded once, and never for errors other than I/O errors
+        let e = e.in_phase(Phase::Receiving, &"127.0.0.1:3311");
+        assert_eq!(e.context().unwrap().endpoint(), "127.0.0.1:3310");
+        let e = ClamError::StreamTooLarge(10).in_phase(Phase::Sending, &"127.0.0.1:3310");
+        assert!(e.context().is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_error_serialize() {
+        let e = ClamError::CommandError(ErrorKind::BrokenPipe.into());
+        assert_eq!(
+            serde_json::to_value(&e).unwrap(),
+            serde_json::json!({"kind": "CommandError", "message": e.to_string()})
+        );
+        assert_eq!(
+            serde_json::to_string(&ClamError::CircuitOpen).unwrap(),
+            r#"{"kind":"CircuitOpen","message":"Circuit breaker is open, ClamD is considered unavailable"}"#
+        );
+    }
 }
@@ -0,0 +1,69 @@
+//! The request body extractor and rejection shared by the `actix` and `axum` integrations, which
+//! each implement their framework's extraction for `ScannedBytes`.
+
+use crate::error::ClamError;
+use crate::instrument;
+use bytes::Bytes;
+use std::fmt;
+use std::ops::Deref;
+
+/// `ScannedBytes` is a request body which ClamD has reported clean. It is extracted by the
+/// `actix` and `axum` integrations, see `clam_client::actix` and `clam_client::axum`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedBytes(pub Bytes);
+
+impl ScannedBytes {
+    /// Returns the scanned body.
+    pub fn into_inner(self) -> Bytes {
+        self.0
+    }
+}
+
+impl Deref for ScannedBytes {
+    type Target = Bytes;
+
+    fn deref(&self) -> &Bytes {
+        &self.0
+    }
+}
+
+/// `ScanRejection` is the reason a body was rejected. The default response is
+/// `422 Unprocessable Entity` for infected bodies, or `503 Service Unavailable` if the body
+/// could not be scanned. The response to a failed scan never includes the error itself, which
+/// may describe the ClamD endpoint, it is instead logged when the `tracing` feature is enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanRejection {
+    /// ClamD found a virus, holding the name of the signature matched.
+    Infected(String),
+    /// The body could not be scanned, holding a description of the error for logging.
+    Failed(String),
+}
+
+impl ScanRejection {
+    /// Returns the body of the default response to the rejection, logging the error of a failed
+    /// scan in place of disclosing it.
+    pub(crate) fn response_body(&self) -> String {
+        match self {
+            ScanRejection::Infected(_) => self.to_string(),
+            ScanRejection::Failed(error) => {
+                instrument::body_scan_failed(error);
+                "Body could not be scanned".to_owned()
+            }
+        }
+    }
+}
+
+impl fmt::Display for ScanRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanRejection::Infected(signature) => write!(f, "Body infected: {}", signature),
+            ScanRejection::Failed(error) => write!(f, "Body could not be scanned: {}", error),
+        }
+    }
+}
+
+impl From<ClamError> for ScanRejection {
+    fn from(e: ClamError) -> Self {
+        ScanRejection::Failed(e.to_string())
+    }
+}
@@ -0,0 +1,154 @@
+//! `ContentHasher` computes a digest of data as it is streamed to ClamD, so that the same bytes
+//! which were scanned can be looked up elsewhere, such as in a threat intelligence feed, without
+//! reading the source a second time. See `ClamClient::scan_stream_hashed`.
+//!
+//! With the `hash` feature every `digest::Digest`, including the re-exported `Sha256` and `Md5`,
+//! is a `ContentHasher`.
+
+#[cfg(feature = "hash")]
+pub use md5::Md5;
+#[cfg(feature = "hash")]
+pub use sha2::Sha256;
+
+use std::io::{self, Read};
+
+/// `ContentHasher` is fed every byte streamed to ClamD, in order, and then produces its digest.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::hash::ContentHasher;
+/// use std::fs::File;
+///
+/// /// Computes the Adler-32 checksum of the data.
+/// struct Adler32(u32, u32);
+///
+/// impl ContentHasher for Adler32 {
+///     type Output = u32;
+///
+///     fn update(&mut self, data: &[u8]) {
+///         for byte in data {
+///             self.0 = (self.0 + *byte as u32) % 65521;
+///             self.1 = (self.1 + self.0) % 65521;
+///         }
+///     }
+///
+///     fn finish(self) -> u32 {
+///         (self.1 << 16) | self.0
+///     }
+/// }
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///     let file = File::open("/etc/hosts").unwrap();
+///
+///     if let Ok((result, checksum)) = client.scan_stream_hashed(file, Adler32(1, 0)) {
+///         println!("{}: {:08x}", result, checksum);
+///     }
+/// }
+/// ```
+pub trait ContentHasher {
+    /// The digest produced
+    type Output;
+
+    /// Feeds the next `data` streamed to the hasher.
+    fn update(&mut self, data: &[u8]);
+
+    /// Consumes the hasher, returning the digest of everything fed to it.
+    fn finish(self) -> Self::Output;
+}
+
+#[cfg(feature = "hash")]
+impl<D: digest::Digest> ContentHasher for D {
+    type Output = Vec<u8>;
+
+    fn update(&mut self, data: &[u8]) {
+        digest::Digest::update(self, data);
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.finalize().to_vec()
+    }
+}
+
+/// Passes everything read from `inner` to `hasher` on its way through.
+pub(crate) struct HashingReader<'a, R, H> {
+    inner: R,
+    hasher: &'a mut H,
+}
+
+impl<'a, R: Read, H: ContentHasher> HashingReader<'a, R, H> {
+    pub(crate) fn new(inner: R, hasher: &'a mut H) -> HashingReader<'a, R, H> {
+        HashingReader { inner, hasher }
+    }
+}
+
+impl<R: Read, H: ContentHasher> Read for HashingReader<'_, R, H> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::hash::ContentHasher;
+    use crate::response::ClamScanResult;
+    use crate::testing::fake_clamd;
+
+    /// Collects everything fed to it, so the bytes hashed can be compared with those streamed.
+    struct Collect(Vec<u8>);
+
+    impl ContentHasher for Collect {
+        type Output = Vec<u8>;
+
+        fn update(&mut self, data: &[u8]) {
+            self.0.extend(data);
+        }
+
+        fn finish(self) -> Vec<u8> {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_scan_stream_hashed() {
+        let port = fake_clamd(|cmd| {
+            if cmd.len() > 9 && cmd[9..].iter().all(|b| *b == 7) {
+                b"stream: OK".to_vec()
+            } else {
+                b"stream: Sig-One FOUND".to_vec()
+            }
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        // Larger than a single chunk, so that it is read and hashed in several pieces
+        let data = vec![7; 20_000];
+        let (result, hashed) = client
+            .scan_stream_hashed(&data[..], Collect(Vec::new()))
+            .unwrap();
+        assert_eq!(result, ClamScanResult::Ok);
+        assert_eq!(hashed, data);
+    }
+
+    #[cfg(feature = "hash")]
+    #[test]
+    fn test_scan_stream_sha256() {
+        let port = fake_clamd(|_| b"stream: OK".to_vec());
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        let (_, digest) = client
+            .scan_stream_hashed(&b"abc"[..], <crate::hash::Sha256 as digest::Digest>::new())
+            .unwrap();
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(
+            hex,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
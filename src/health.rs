@@ -0,0 +1,119 @@
+//! Background health checking for `ClamClient`, enabled with
+//! `ClamClientBuilder::health_check_interval`. A background thread periodically issues `PING` to
+//! each configured endpoint, so that readiness can be reported without issuing a scan.
+
+use crate::client::ClamClient;
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// `EndpointStatus` is the result of the most recent health check of a single endpoint.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointStatus {
+    /// The address of the endpoint
+    pub endpoint: SocketAddr,
+    /// True if the endpoint responded to the most recent `PING` with `PONG`
+    pub healthy: bool,
+    /// When the endpoint last responded to a `PING`, if ever
+    pub last_success: Option<SystemTime>,
+    /// When the endpoint was last checked, if ever
+    pub last_check: Option<SystemTime>,
+}
+
+/// `HealthMonitor` owns the background thread performing the health checks, the thread exits
+/// when the monitor is dropped along with its `ClamClient`.
+pub(crate) struct HealthMonitor {
+    statuses: Arc<Mutex<Vec<EndpointStatus>>>,
+    _stop: Sender<()>,
+}
+
+impl HealthMonitor {
+    /// Starts checking every endpoint of `probe` immediately, and then every `interval`.
+    pub(crate) fn start(probe: ClamClient, interval: Duration) -> HealthMonitor {
+        let statuses = Arc::new(Mutex::new(
+            probe
+                .endpoints()
+                .map(|endpoint| EndpointStatus {
+                    endpoint,
+                    healthy: false,
+                    last_success: None,
+                    last_check: None,
+                })
+                .collect::<Vec<EndpointStatus>>(),
+        ));
+        let (stop, stopped) = mpsc::channel();
+        let shared = statuses.clone();
+
+        thread::spawn(move || loop {
+            for endpoint in probe.endpoints() {
+                let healthy = probe.ping_endpoint(endpoint);
+                let now = SystemTime::now();
+
+                let mut statuses = shared.lock().unwrap();
+                if let Some(status) = statuses.iter_mut().find(|s| s.endpoint == endpoint) {
+                    status.healthy = healthy;
+                    status.last_check = Some(now);
+                    if healthy {
+                        status.last_success = Some(now);
+                    }
+                }
+            }
+
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+        });
+
+        HealthMonitor {
+            statuses,
+            _stop: stop,
+        }
+    }
+
+    /// Returns the most recent status of every endpoint.
+    pub(crate) fn statuses(&self) -> Vec<EndpointStatus> {
+        self.statuses.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::testing::fake_clamd;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_health_check() {
+        let port = fake_clamd(|_| b"PONG".to_vec());
+        let client = ClamClient::builder("127.0.0.1", port)
+            .fallback("127.0.0.1", 1)
+            .health_check_interval(Duration::from_millis(10))
+            .build()
+            .unwrap();
+
+        for _ in 0..100 {
+            if client.is_healthy() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let statuses = client.health().unwrap();
+        assert!(client.is_healthy());
+        assert!(statuses[0].healthy);
+        assert!(statuses[0].last_success.is_some());
+        assert!(!statuses[1].healthy);
+    }
+
+    #[test]
+    fn test_health_check_disabled() {
+        let client = ClamClient::new("127.0.0.1", 1).unwrap();
+        assert!(client.health().is_none());
+        assert!(!client.is_healthy());
+    }
+}
@@ -0,0 +1,189 @@
+//! Optional instrumentation of ClamD commands, enabled with the `tracing` and `metrics` features.
+//! With `tracing`, each instrumented command is wrapped in a `clamd` span recording the command,
+//! the endpoint used, the number of bytes streamed, the duration and a classification of the
+//! result. With `metrics`, the same classification and duration are recorded as client-side
+//! metrics, see `clam_client::metrics`. Without either feature these helpers compile away to
+//! nothing.
+
+use crate::client::ClamResult;
+#[cfg(any(feature = "tracing", feature = "metrics"))]
+use crate::error::ClamError;
+use crate::response::ClamScanResult;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Runs `operation` inside a span for the ClamD `command`, classifying a successful result with
+/// `classify`.
+pub(crate) fn command<T, F, C>(command: &'static str, classify: C, operation: F) -> ClamResult<T>
+where
+    F: FnOnce() -> ClamResult<T>,
+    C: FnOnce(&T) -> &'static str,
+{
+    let command = Command::start(command);
+    let result = command.in_scope(operation);
+    command.finish(&result, classify);
+    result
+}
+
+/// `Command` is an instrumented ClamD command which may span several calls, such as an
+/// `INSTREAM` written through a `ClamScanWriter`. Its span is entered for each call made on the
+/// command's behalf, and its duration and result are recorded once it finishes.
+pub(crate) struct Command {
+    name: &'static str,
+    start: Instant,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl Command {
+    /// Starts timing the ClamD command `name`.
+    pub(crate) fn start(name: &'static str) -> Command {
+        Command {
+            name,
+            start: Instant::now(),
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "clamd",
+                command = name,
+                endpoint = tracing::field::Empty,
+                bytes = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+                result = tracing::field::Empty,
+            ),
+        }
+    }
+
+    /// Returns the name of the command, such as `INSTREAM`.
+    pub(crate) fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns how long has passed since the command started.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Runs `operation` inside the command's span.
+    pub(crate) fn in_scope<T, F: FnOnce() -> T>(&self, operation: F) -> T {
+        #[cfg(feature = "tracing")]
+        return self.span.in_scope(operation);
+        #[cfg(not(feature = "tracing"))]
+        operation()
+    }
+
+    /// Awaits `future` inside the command's span.
+    #[cfg(any(feature = "tokio", feature = "async-io"))]
+    pub(crate) async fn instrument<F: std::future::Future>(&self, future: F) -> F::Output {
+        #[cfg(feature = "tracing")]
+        return tracing::Instrument::instrument(future, self.span.clone()).await;
+        #[cfg(not(feature = "tracing"))]
+        future.await
+    }
+
+    /// Records the duration of the command and its `result`, classifying a successful result
+    /// with `classify`.
+    pub(crate) fn finish<T, C>(self, result: &ClamResult<T>, classify: C)
+    where
+        C: FnOnce(&T) -> &'static str,
+    {
+        #[cfg(any(feature = "tracing", feature = "metrics"))]
+        {
+            let elapsed = self.elapsed();
+            let classification = match result {
+                Ok(value) => classify(value),
+                Err(e) => classify_error(e),
+            };
+
+            #[cfg(feature = "tracing")]
+            {
+                self.span.record("duration_ms", elapsed.as_millis() as u64);
+                self.span.record("result", classification);
+            }
+            #[cfg(feature = "metrics")]
+            crate::metrics::record_command(self.name, classification, elapsed);
+        }
+
+        #[cfg(not(any(feature = "tracing", feature = "metrics")))]
+        let _ = (result, classify);
+    }
+}
+
+/// Records the endpoint a command is being issued to on the current span.
+pub(crate) fn record_endpoint(endpoint: SocketAddr) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("endpoint", tracing::field::display(endpoint));
+    #[cfg(not(feature = "tracing"))]
+    let _ = endpoint;
+}
+
+/// Records the number of bytes streamed to ClamD on the current span.
+pub(crate) fn record_bytes(bytes: u64) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("bytes", bytes);
+    #[cfg(feature = "metrics")]
+    crate::metrics::record_bytes(bytes);
+    #[cfg(not(any(feature = "tracing", feature = "metrics")))]
+    let _ = bytes;
+}
+
+/// Logs why a request body could not be scanned, since the error is not disclosed in the
+/// response to the request.
+#[cfg(any(feature = "actix", feature = "axum"))]
+pub(crate) fn body_scan_failed(error: &str) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(error, "request body could not be scanned");
+    #[cfg(not(feature = "tracing"))]
+    let _ = error;
+}
+
+/// Classifies the results of a scan, a single detection or error classifies the whole scan.
+pub(crate) fn classify_scan(results: &[ClamScanResult]) -> &'static str {
+    if results
+        .iter()
+        .any(|r| matches!(r, ClamScanResult::Found(..)))
+    {
+        "found"
+    } else if results
+        .iter()
+        .any(|r| matches!(r, ClamScanResult::Error(..)))
+    {
+        "error"
+    } else {
+        "clean"
+    }
+}
+
+/// Classifies an error returned by a command.
+#[cfg(any(feature = "tracing", feature = "metrics"))]
+fn classify_error(error: &ClamError) -> &'static str {
+    match error {
+        e if e.is_timeout() => "timeout",
+        ClamError::ConnectionError(_) | ClamError::ProxyError(_) | ClamError::CircuitOpen => {
+            "unavailable"
+        }
+        ClamError::StreamTooLarge(_) | ClamError::StreamSizeLimitExceeded(_) => "too_large",
+        _ => "failed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instrument::classify_scan;
+    use crate::response::{ClamScanError, ClamScanResult};
+
+    #[test]
+    fn test_classify_scan() {
+        assert_eq!(classify_scan(&[ClamScanResult::Ok]), "clean");
+        assert_eq!(
+            classify_scan(&[
+                ClamScanResult::Error(ClamScanError::LstatFailed(None)),
+                ClamScanResult::Found("/a".to_owned(), "Eicar".to_owned()),
+            ]),
+            "found"
+        );
+        assert_eq!(
+            classify_scan(&[ClamScanResult::Error(ClamScanError::LstatFailed(None))]),
+            "error"
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! `ClientMetrics` is a snapshot of the commands a `ClamClient` has issued and how long they
+//! took, collected in process when `ClamClientBuilder::collect_latency` is enabled, so that
+//! capacity can be planned without any external instrumentation.
+//!
+//! Latencies are recorded in a histogram per command, with buckets no more than 12.5% wide, so
+//! memory use is fixed regardless of how many commands are issued and each reported percentile
+//! is within 12.5% of the true value.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The number of sub-buckets each power of two is divided into, which bounds the error of the
+/// reported percentiles.
+const SUB_BUCKET_BITS: u32 = 3;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = ((64 - SUB_BUCKET_BITS + 1) as usize) * SUB_BUCKETS as usize;
+
+/// `ClientMetrics` is a snapshot of the latency of each command issued by a `ClamClient`, it is
+/// obtained via `ClamClient::client_metrics`.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+///
+/// fn main() {
+///     let client = ClamClient::builder("127.0.0.1", 3310)
+///         .collect_latency(true)
+///         .build()
+///         .unwrap();
+///     client.ping();
+///
+///     if let Some(metrics) = client.client_metrics() {
+///         for (command, latency) in &metrics.commands {
+///             println!("{}: {} issued, p99 {:?}", command, latency.count, latency.p99);
+///         }
+///     }
+/// }
+/// ```
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientMetrics {
+    /// The latency of each command issued at least once, keyed by its name, e.g. `INSTREAM`
+    pub commands: BTreeMap<String, CommandMetrics>,
+}
+
+/// `CommandMetrics` summarises the latency of every issue of a single command.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandMetrics {
+    /// The number of times the command was issued
+    pub count: u64,
+    /// The number of those which failed
+    pub errors: u64,
+    /// The median latency
+    pub p50: Duration,
+    /// The 90th percentile latency
+    pub p90: Duration,
+    /// The 99th percentile latency
+    pub p99: Duration,
+    /// The highest latency recorded
+    pub max: Duration,
+}
+
+impl ClientMetrics {
+    /// Returns the metrics for `command`, or `None` if it has not been issued.
+    pub fn command(&self, command: &str) -> Option<&CommandMetrics> {
+        self.commands.get(command)
+    }
+}
+
+/// Records the latency of each command issued by a client.
+#[derive(Default)]
+pub(crate) struct LatencyRecorder {
+    histograms: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl LatencyRecorder {
+    /// Records an issue of `command` which took `elapsed`, and whether it failed.
+    pub(crate) fn record(&self, command: &'static str, elapsed: Duration, failed: bool) {
+        let mut histograms = self.histograms.lock().unwrap();
+        let histogram = histograms.entry(command).or_insert_with(Histogram::new);
+        histogram.record(elapsed);
+        if failed {
+            histogram.errors += 1;
+        }
+    }
+
+    /// Takes a snapshot of the latencies recorded so far.
+    pub(crate) fn snapshot(&self) -> ClientMetrics {
+        let histograms = self.histograms.lock().unwrap();
+        ClientMetrics {
+            commands: histograms
+                .iter()
+                .map(|(command, histogram)| (command.to_string(), histogram.summarise()))
+                .collect(),
+        }
+    }
+}
+
+/// A log-linear histogram of latencies in microseconds, where each power of two is divided into
+/// `SUB_BUCKETS` equal buckets.
+struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    errors: u64,
+    max: u64,
+}
+
+impl Histogram {
+    fn new() -> Histogram {
+        Histogram {
+            buckets: vec![0; BUCKETS],
+            count: 0,
+            errors: 0,
+            max: 0,
+        }
+    }
+
+    fn record(&mut self, elapsed: Duration) {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket(micros)] += 1;
+        self.count += 1;
+        self.max = self.max.max(micros);
+    }
+
+    fn summarise(&self) -> CommandMetrics {
+        CommandMetrics {
+            count: self.count,
+            errors: self.errors,
+            p50: self.percentile(0.5),
+            p90: self.percentile(0.9),
+            p99: self.percentile(0.99),
+            max: Duration::from_micros(self.max),
+        }
+    }
+
+    /// Returns the upper bound of the bucket containing the `quantile`th latency, capped at the
+    /// highest latency recorded.
+    fn percentile(&self, quantile: f64) -> Duration {
+        let rank = ((quantile * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(upper_bound(index).min(self.max));
+            }
+        }
+
+        Duration::from_micros(self.max)
+    }
+}
+
+/// Returns the index of the bucket holding `value`.
+fn bucket(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+
+    let exponent = 63 - value.leading_zeros();
+    let shift = exponent - SUB_BUCKET_BITS;
+    let sub_bucket = (value >> shift) & (SUB_BUCKETS - 1);
+    ((shift + 1) as u64 * SUB_BUCKETS + sub_bucket) as usize
+}
+
+/// Returns the largest value held by the bucket at `index`.
+fn upper_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+
+    let shift = index / SUB_BUCKETS - 1;
+    let sub_bucket = index % SUB_BUCKETS;
+    // The final bucket ends at 2^64, which overflows
+    u64::try_from(u128::from(SUB_BUCKETS + sub_bucket + 1) << shift).map_or(u64::MAX, |end| end - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::latency::{bucket, upper_bound, LatencyRecorder, BUCKETS};
+    use std::time::Duration;
+
+    #[test]
+    fn test_buckets() {
+        for value in [0, 1, 7, 8, 9, 15, 16, 17, 1000, 123_456_789, u64::MAX] {
+            let index = bucket(value);
+            assert!(index < BUCKETS);
+            assert!(upper_bound(index) >= value);
+            assert!(index == 0 || upper_bound(index - 1) < value);
+        }
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let recorder = LatencyRecorder::default();
+        for ms in 1..=100 {
+            recorder.record("INSTREAM", Duration::from_millis(ms), ms > 98);
+        }
+        recorder.record("PING", Duration::from_micros(250), false);
+
+        let metrics = recorder.snapshot();
+        let instream = metrics.command("INSTREAM").unwrap();
+        assert_eq!(instream.count, 100);
+        assert_eq!(instream.errors, 2);
+        assert_eq!(instream.max, Duration::from_millis(100));
+
+        for (percentile, expected) in [(instream.p50, 50), (instream.p90, 90), (instream.p99, 99)] {
+            let expected = Duration::from_millis(expected);
+            assert!(percentile >= expected && percentile <= expected + expected / 8);
+        }
+
+        assert_eq!(
+            metrics.command("PING").unwrap().p99,
+            Duration::from_micros(250)
+        );
+        assert!(metrics.command("STATS").is_none());
+    }
+}
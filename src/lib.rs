@@ -1,9 +1,8 @@
 #![deny(missing_docs)]
 
 //! # clam_client - a client implementation for ClamAV written in Rust.
-//! `clam_client`, provides a simple interface to all basic ClamAV functionality, currently
-//! the only thing missing is sessions/multi threaded scanning, which may or may not be added
-//! depending on demand.
+//! `clam_client`, provides a simple interface to all basic ClamAV functionality, including
+//! sessions and pooling of session connections via `ClamPool`.
 //!
 //! ## Example
 //! ```rust
@@ -33,6 +32,66 @@ extern crate serde;
 #[macro_use]
 extern crate nom;
 
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "zip")]
+mod archive;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "hash")]
+pub mod cache;
+pub mod circuit;
 pub mod client;
+pub mod cluster;
+pub mod command;
+mod conf;
+#[cfg(feature = "testcontainers")]
+pub mod container;
+pub mod diagnostics;
 pub mod error;
+#[cfg(any(feature = "actix", feature = "axum"))]
+pub mod extract;
+pub mod hash;
+pub mod health;
+mod instrument;
+pub mod latency;
+pub mod local;
+#[cfg(feature = "mail")]
+pub mod mail;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
+pub mod observer;
+#[cfg(feature = "hash")]
+pub mod policy;
+pub mod pool;
+mod protocol;
+pub mod proxy;
+#[cfg(feature = "quarantine")]
+pub mod quarantine;
+pub mod redact;
+pub mod report;
 pub mod response;
+pub mod retry;
+pub mod scan;
+pub mod scanner;
+pub mod session;
+#[cfg(feature = "hash")]
+mod singleflight;
+pub mod stream;
+#[cfg(feature = "tls")]
+pub mod tls;
+mod transport;
+
+#[cfg(test)]
+mod testing;
+
+// The clients are shared between threads, typically behind an `Arc`, so must remain thread safe
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<client::ClamClient>();
+    assert_send_sync::<cluster::ClamCluster>();
+    assert_send_sync::<pool::ClamPool>();
+    assert_send_sync::<scanner::Queue>();
+};
@@ -0,0 +1,734 @@
+//! Scanning of local directory trees, which ClamD cannot see when it runs on another host or in
+//! another container. The tree is walked on the client and every file is streamed to ClamD with
+//! `INSTREAM`, filling the gap between `ClamClient::scan_path`, which only scans paths on the
+//! ClamD host, and `ClamClient::scan_file`, which scans a single file.
+//!
+//! Long running scans can report their progress through `ClamClient::scan_dir_local_with_progress`.
+
+use crate::client::{ClamClient, ClamResult};
+use crate::response::{ClamScanError, ClamScanResult};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// `LocalScanOptions` controls how `ClamClient::scan_dir_local` walks a directory tree.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::local::LocalScanOptions;
+///
+/// fn main() {
+///     let options = LocalScanOptions::new()
+///         .follow_symlinks(true)
+///         .max_depth(4)
+///         .max_file_size(64 << 20)
+///         .patterns(["*.exe", "*.dll", "!node_modules/**"]);
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LocalScanOptions {
+    follow_symlinks: bool,
+    max_depth: Option<usize>,
+    max_file_size: Option<u64>,
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+}
+
+impl LocalScanOptions {
+    /// Creates the default options, which scan the whole tree without following symbolic links.
+    pub fn new() -> LocalScanOptions {
+        LocalScanOptions::default()
+    }
+
+    /// When enabled, symbolic links to files and directories are followed, each directory being
+    /// walked at most once. Otherwise symbolic links are skipped. Disabled by default.
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Limits how far the walk descends, files more than `depth` directories below the root are
+    /// not scanned, so a depth of 0 scans only the files directly within it. Unlimited by
+    /// default.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Skips files larger than `bytes` rather than streaming them to ClamD, reporting them as
+    /// `LocalScanResult::Skipped`. Defaults to the client's
+    /// `ClamClientBuilder::stream_max_length`, if set, since ClamD would reject such files anyway.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    /// Only scans files matching the glob `pattern`, or any other included pattern. All files
+    /// are included if no pattern is given.
+    ///
+    /// Patterns are matched against paths relative to the root of the scan, using `/` as the
+    /// separator. A pattern without a `/`, such as `*.exe`, matches the file name in any
+    /// directory, otherwise it matches the whole path, where `*` and `?` match any characters and
+    /// any single character within a path component, and `**` matches any number of components.
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.include.push(Glob::new(pattern));
+        self
+    }
+
+    /// Skips files and directories matching the glob `pattern`, see `LocalScanOptions::include`
+    /// for the syntax. Directories matched by a pattern such as `node_modules/**` are not walked
+    /// at all. Exclusions take precedence over inclusions.
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(Glob::new(pattern));
+        self
+    }
+
+    /// Adds several patterns at once, those prefixed with `!` are excluded, as by
+    /// `LocalScanOptions::exclude`, and the remainder are included, as by
+    /// `LocalScanOptions::include`.
+    pub fn patterns<I, S>(self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        patterns.into_iter().fold(self, |options, pattern| {
+            match pattern.as_ref().strip_prefix('!') {
+                Some(pattern) => options.exclude(pattern),
+                None => options.include(pattern.as_ref()),
+            }
+        })
+    }
+
+    /// Returns true if the file at `relative`, relative to the root, should be scanned.
+    fn scans_file(&self, relative: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.matches(relative)))
+            && !self.exclude.iter().any(|glob| glob.matches(relative))
+    }
+
+    /// Returns true if the directory at `relative`, relative to the root, should be walked.
+    fn walks_dir(&self, relative: &str) -> bool {
+        !self.exclude.iter().any(|glob| glob.matches_dir(relative))
+    }
+}
+
+/// `LocalScanResult` is the outcome for a single file of `ClamClient::scan_dir_local`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum LocalScanResult {
+    /// The file was streamed to ClamD, or could not be read locally, in which case this holds a
+    /// `ClamScanResult::Error`
+    Scanned(ClamScanResult),
+    /// The file was not sent to ClamD, for the given reason
+    Skipped(SkipReason),
+}
+
+impl LocalScanResult {
+    /// Returns the `ClamScanResult` if the file was scanned.
+    pub fn scan_result(&self) -> Option<&ClamScanResult> {
+        match self {
+            LocalScanResult::Scanned(result) => Some(result),
+            LocalScanResult::Skipped(_) => None,
+        }
+    }
+
+    /// Returns true if ClamD found a virus in the file.
+    pub fn is_found(&self) -> bool {
+        matches!(self, LocalScanResult::Scanned(ClamScanResult::Found(..)))
+    }
+}
+
+impl From<ClamScanResult> for LocalScanResult {
+    fn from(result: ClamScanResult) -> LocalScanResult {
+        LocalScanResult::Scanned(result)
+    }
+}
+
+impl fmt::Display for LocalScanResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LocalScanResult::Scanned(result) => result.fmt(f),
+            LocalScanResult::Skipped(reason) => write!(f, "{} SKIPPED", reason),
+        }
+    }
+}
+
+/// `SkipReason` describes why `ClamClient::scan_dir_local` did not scan a file.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SkipReason {
+    /// The file, of `size` bytes, exceeds the `limit` set by `LocalScanOptions::max_file_size`
+    TooLarge {
+        /// The size of the file in bytes
+        size: u64,
+        /// The maximum size of file scanned in bytes
+        limit: u64,
+    },
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SkipReason::TooLarge { size, limit } => {
+                write!(f, "File size {} exceeds the limit of {} bytes", size, limit)
+            }
+        }
+    }
+}
+
+/// `LocalScanProgress` is the progress of `ClamClient::scan_dir_local_with_progress`, as passed to
+/// its callback each time it changes.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalScanProgress {
+    /// The number of files found so far which will be scanned, or skipped, as the tree is walked
+    pub files_discovered: u64,
+    /// The number of files which have a result, including those skipped or which could not be
+    /// read locally
+    pub files_scanned: u64,
+    /// The total number of bytes streamed to ClamD
+    pub bytes_streamed: u64,
+    /// The file currently being streamed to ClamD, if any
+    pub current_file: Option<PathBuf>,
+}
+
+/// A glob pattern, as accepted by `LocalScanOptions::include`.
+#[derive(Debug, Clone)]
+struct Glob {
+    components: Vec<String>,
+    /// Patterns without a `/` match the file name alone
+    name_only: bool,
+}
+
+impl Glob {
+    fn new(pattern: &str) -> Glob {
+        let pattern = pattern.trim_start_matches('/');
+        Glob {
+            components: pattern.split('/').map(str::to_owned).collect(),
+            name_only: !pattern.contains('/'),
+        }
+    }
+
+    /// Returns true if the `/` separated `path` matches the pattern.
+    fn matches(&self, path: &str) -> bool {
+        let components: Vec<&str> = path.split('/').collect();
+        if self.name_only {
+            return match_component(&self.components[0], components[components.len() - 1]);
+        }
+
+        match_components(&self.components, &components)
+    }
+
+    /// Returns true if the pattern matches the directory at `path`, or everything within it as
+    /// `dir/**` does.
+    fn matches_dir(&self, path: &str) -> bool {
+        if self.matches(path) {
+            return true;
+        }
+
+        match self.components.split_last() {
+            Some((last, parent)) if last == "**" && !self.name_only => {
+                let components: Vec<&str> = path.split('/').collect();
+                match_components(parent, &components)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Matches path components against pattern components, where `**` matches any number of them.
+fn match_components(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| match_components(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((component, path)) => {
+                match_component(first, component) && match_components(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Matches a single path component against a pattern of literal characters, `*` and `?`.
+fn match_component(pattern: &str, component: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let component: Vec<char> = component.chars().collect();
+    let (mut p, mut c) = (0, 0);
+    // The position of the last `*`, and of the component when it was reached, to backtrack to
+    let mut star = None;
+
+    while c < component.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, c));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                c += 1;
+            }
+            Some(&ch) if ch == component[c] => {
+                p += 1;
+                c += 1;
+            }
+            _ => match star {
+                Some((star_p, star_c)) => {
+                    p = star_p + 1;
+                    c = star_c + 1;
+                    star = Some((star_p, star_c + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
+
+impl ClamClient {
+    /// Walks the local directory tree at `path` and scans each file with `INSTREAM`, returning the
+    /// result for every file keyed by its path. If `path` is a file, only it is scanned. Files or
+    /// directories which cannot be read locally are reported as `ClamScanResult::Error`, and
+    /// files over `LocalScanOptions::max_file_size` as `LocalScanResult::Skipped`, whilst any
+    /// error talking to ClamD ends the scan.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::local::{LocalScanOptions, LocalScanResult};
+    /// use clam_client::response::ClamScanResult;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///
+    ///     if let Ok(results) = client.scan_dir_local("/srv/uploads", &LocalScanOptions::new()) {
+    ///         for (path, result) in results {
+    ///             if let LocalScanResult::Scanned(ClamScanResult::Found(_, virus)) = result {
+    ///                 println!("Found virus: '{}' in {}", virus, path.display())
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn scan_dir_local<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &LocalScanOptions,
+    ) -> ClamResult<BTreeMap<PathBuf, LocalScanResult>> {
+        self.scan_dir_local_with_progress(path, options, |_| {})
+    }
+
+    /// Scans the local directory tree at `path` as `scan_dir_local` does, calling `progress` as
+    /// files are discovered, after each chunk of a file is streamed to ClamD, and as each file
+    /// completes, so that a long running scan can drive a progress bar or liveness probe. To
+    /// receive progress on another thread, send it from `progress` over a channel.
+    ///
+    /// Files are discovered as each directory is listed, so `files_discovered` grows as the walk
+    /// proceeds rather than being known up front.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::local::LocalScanOptions;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///     let options = LocalScanOptions::new();
+    ///
+    ///     let results = client.scan_dir_local_with_progress("/srv/uploads", &options, |p| {
+    ///         if let Some(file) = &p.current_file {
+    ///             println!(
+    ///                 "{}/{} files, {} bytes: {}",
+    ///                 p.files_scanned,
+    ///                 p.files_discovered,
+    ///                 p.bytes_streamed,
+    ///                 file.display()
+    ///             );
+    ///         }
+    ///     });
+    ///     println!("{:?}", results);
+    /// }
+    /// ```
+    pub fn scan_dir_local_with_progress<P, F>(
+        &self,
+        path: P,
+        options: &LocalScanOptions,
+        mut progress: F,
+    ) -> ClamResult<BTreeMap<PathBuf, LocalScanResult>>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&LocalScanProgress),
+    {
+        let max_file_size = options.max_file_size.or(self.stream_max_length());
+        let mut results = BTreeMap::new();
+        let mut visited = HashSet::new();
+        let root = path.as_ref();
+        let mut pending = vec![(root.to_path_buf(), 0)];
+        let mut status = LocalScanProgress::default();
+
+        while let Some((path, depth)) = pending.pop() {
+            let relative = relative_path(root, &path);
+
+            let metadata = match metadata(&path, depth == 0 || options.follow_symlinks) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    results.insert(path.clone(), local_error(&path, e).into());
+                    continue;
+                }
+            };
+
+            if metadata.is_file() {
+                if depth > 0 && !options.scans_file(&relative) {
+                    continue;
+                }
+                if depth == 0 {
+                    status.files_discovered += 1;
+                    progress(&status);
+                }
+
+                let result = match max_file_size.filter(|&limit| metadata.len() > limit) {
+                    Some(limit) => LocalScanResult::Skipped(SkipReason::TooLarge {
+                        size: metadata.len(),
+                        limit,
+                    }),
+                    None => self
+                        .scan_local_file(&path, &mut status, &mut progress)?
+                        .into(),
+                };
+                results.insert(path, result);
+                status.files_scanned += 1;
+                progress(&status);
+                continue;
+            }
+
+            if !metadata.is_dir()
+                || options.max_depth.is_some_and(|max| depth > max)
+                || (depth > 0 && !options.walks_dir(&relative))
+            {
+                continue;
+            }
+
+            // Guards against cycles when following symbolic links
+            if options.follow_symlinks {
+                match fs::canonicalize(&path) {
+                    Ok(canonical) => {
+                        if !visited.insert(canonical) {
+                            continue;
+                        }
+                    }
+                    Err(e) => {
+                        results.insert(path.clone(), local_error(&path, e).into());
+                        continue;
+                    }
+                }
+            }
+
+            match read_dir(&path) {
+                Ok(entries) => {
+                    let files = entries.iter().filter(|entry| {
+                        self::metadata(entry, options.follow_symlinks).is_ok_and(|m| m.is_file())
+                            && options.scans_file(&relative_path(root, entry))
+                    });
+                    status.files_discovered += files.count() as u64;
+                    progress(&status);
+
+                    // Reversed, so that entries are popped, and so scanned, in order
+                    pending.extend(entries.into_iter().rev().map(|entry| (entry, depth + 1)));
+                }
+                Err(e) => {
+                    results.insert(path.clone(), local_error(&path, e).into());
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Streams the local file at `path` to ClamD, reporting the bytes streamed to `progress`.
+    fn scan_local_file(
+        &self,
+        path: &Path,
+        status: &mut LocalScanProgress,
+        progress: &mut dyn FnMut(&LocalScanProgress),
+    ) -> ClamResult<ClamScanResult> {
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) => return Ok(local_error(path, e)),
+        };
+
+        status.current_file = Some(path.to_path_buf());
+        progress(status);
+
+        let streamed = status.bytes_streamed;
+        let result = self.scan_stream_with_progress(file, None, |sent, _| {
+            status.bytes_streamed = streamed + sent;
+            progress(status);
+        });
+        status.current_file = None;
+
+        Ok(match result? {
+            ClamScanResult::Found(_, virus) => {
+                ClamScanResult::Found(path.display().to_string(), virus)
+            }
+            result => result,
+        })
+    }
+}
+
+/// Returns `path` relative to `root`, with components separated by `/` for glob matching.
+fn relative_path(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let components: Vec<_> = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect();
+    components.join("/")
+}
+
+/// Reads the metadata of `path`, of the file a symbolic link points to if `follow` is set, or of
+/// the link itself otherwise.
+fn metadata(path: &Path, follow: bool) -> io::Result<fs::Metadata> {
+    if follow {
+        fs::metadata(path)
+    } else {
+        fs::symlink_metadata(path)
+    }
+}
+
+/// Lists the entries of the directory at `path`, sorted by name.
+fn read_dir(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+/// Reports a failure to read `path` locally as ClamD would report failing to read it.
+fn local_error(path: &Path, error: io::Error) -> ClamScanResult {
+    let path = Some(path.display().to_string());
+    ClamScanResult::Error(match error.kind() {
+        ErrorKind::NotFound => ClamScanError::LstatFailed(path),
+        ErrorKind::PermissionDenied => ClamScanError::AccessDenied(path),
+        _ => ClamScanError::CannotOpen(path),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::local::{Glob, LocalScanOptions, LocalScanProgress, LocalScanResult, SkipReason};
+    use crate::response::{ClamScanError, ClamScanResult};
+    use crate::testing::fake_clamd;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_tree(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("clam-local-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("nested").join("deeper")).unwrap();
+        fs::write(dir.join("clean.txt"), b"clean").unwrap();
+        fs::write(dir.join("nested").join("bad.exe"), b"infected").unwrap();
+        fs::write(dir.join("nested").join("deeper").join("also.txt"), b"clean").unwrap();
+        dir
+    }
+
+    fn client() -> ClamClient {
+        let port = fake_clamd(|cmd| {
+            if cmd.ends_with(b"infected") {
+                b"stream: Eicar-Test-Signature FOUND".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        ClamClient::new("127.0.0.1", port).unwrap()
+    }
+
+    #[test]
+    fn test_scan_dir_local() {
+        let dir = temp_tree("walk");
+        let results = client()
+            .scan_dir_local(&dir, &LocalScanOptions::new())
+            .unwrap();
+
+        let bad = dir.join("nested").join("bad.exe");
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[&dir.join("clean.txt")], ClamScanResult::Ok.into());
+        assert_eq!(
+            results[&bad],
+            ClamScanResult::Found(bad.display().to_string(), "Eicar-Test-Signature".to_owned())
+                .into()
+        );
+
+        let shallow = client()
+            .scan_dir_local(&dir, &LocalScanOptions::new().max_depth(1))
+            .unwrap();
+        assert_eq!(shallow.len(), 2);
+        assert!(!shallow.contains_key(&dir.join("nested").join("deeper").join("also.txt")));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_scan_dir_local_patterns() {
+        let dir = temp_tree("patterns");
+        fs::create_dir_all(dir.join("node_modules").join("pkg")).unwrap();
+        fs::write(dir.join("node_modules").join("pkg").join("x.exe"), b"x").unwrap();
+
+        let results = client()
+            .scan_dir_local(
+                &dir,
+                &LocalScanOptions::new().patterns(["*.exe", "!node_modules/**"]),
+            )
+            .unwrap();
+        let paths: Vec<_> = results.keys().cloned().collect();
+        assert_eq!(paths, vec![dir.join("nested").join("bad.exe")]);
+
+        let results = client()
+            .scan_dir_local(&dir, &LocalScanOptions::new().exclude("nested/**/*.txt"))
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(!results.contains_key(&dir.join("nested").join("deeper").join("also.txt")));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_scan_dir_local_max_file_size() {
+        let dir = temp_tree("size");
+        let results = client()
+            .scan_dir_local(&dir, &LocalScanOptions::new().max_file_size(6))
+            .unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert!(results[&dir.join("clean.txt")].scan_result().is_some());
+        assert_eq!(
+            results[&dir.join("nested").join("bad.exe")],
+            LocalScanResult::Skipped(SkipReason::TooLarge { size: 8, limit: 6 })
+        );
+        assert!(!results.values().any(LocalScanResult::is_found));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_local_scan_result_serde() {
+        for result in [
+            LocalScanResult::Scanned(ClamScanResult::Found(
+                "/a".to_owned(),
+                "Eicar-Test-Signature".to_owned(),
+            )),
+            LocalScanResult::Skipped(SkipReason::TooLarge { size: 8, limit: 6 }),
+        ] {
+            let json = serde_json::to_string(&result).unwrap();
+            assert_eq!(
+                serde_json::from_str::<LocalScanResult>(&json).unwrap(),
+                result
+            );
+        }
+
+        let progress = LocalScanProgress {
+            files_discovered: 3,
+            files_scanned: 2,
+            bytes_streamed: 10,
+            current_file: Some(PathBuf::from("/a")),
+        };
+        let json = serde_json::to_string(&progress).unwrap();
+        assert_eq!(
+            serde_json::from_str::<LocalScanProgress>(&json).unwrap(),
+            progress
+        );
+    }
+
+    #[test]
+    fn test_scan_dir_local_progress() {
+        let dir = temp_tree("progress");
+        let mut updates: Vec<LocalScanProgress> = Vec::new();
+        let results = client()
+            .scan_dir_local_with_progress(&dir, &LocalScanOptions::new().max_file_size(6), |p| {
+                updates.push(p.clone())
+            })
+            .unwrap();
+        assert_eq!(results.len(), 3);
+
+        // Every file is discovered before it completes, and the skipped file is never streamed
+        for update in &updates {
+            assert!(update.files_scanned <= update.files_discovered);
+        }
+        let streaming: Vec<_> = updates
+            .iter()
+            .filter_map(|u| u.current_file.clone())
+            .collect();
+        assert!(streaming.contains(&dir.join("clean.txt")));
+        assert!(!streaming.contains(&dir.join("nested").join("bad.exe")));
+
+        let last = updates.last().unwrap();
+        assert_eq!(last.files_discovered, 3);
+        assert_eq!(last.files_scanned, 3);
+        assert_eq!(last.bytes_streamed, 10);
+        assert_eq!(last.current_file, None);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_glob() {
+        let glob = |pattern: &str, path: &str| Glob::new(pattern).matches(path);
+
+        assert!(glob("*.exe", "a/b/setup.exe"));
+        assert!(!glob("*.exe", "a/b/setup.exe.txt"));
+        assert!(glob("a?c*.txt", "abcdef.txt"));
+        assert!(glob("src/**/*.rs", "src/lib.rs"));
+        assert!(glob("src/**/*.rs", "src/a/b/lib.rs"));
+        assert!(!glob("src/**/*.rs", "test/lib.rs"));
+        assert!(glob("**/node_modules/**", "a/node_modules/b"));
+        assert!(Glob::new("node_modules/**").matches_dir("node_modules"));
+        assert!(!Glob::new("node_modules/**").matches_dir("src"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_scan_dir_local_symlinks() {
+        let dir = temp_tree("symlinks");
+        std::os::unix::fs::symlink(&dir, dir.join("nested").join("loop")).unwrap();
+
+        let results = client()
+            .scan_dir_local(&dir, &LocalScanOptions::new())
+            .unwrap();
+        assert_eq!(results.len(), 3);
+
+        let followed = client()
+            .scan_dir_local(&dir, &LocalScanOptions::new().follow_symlinks(true))
+            .unwrap();
+        assert_eq!(followed.len(), 3);
+
+        let missing = dir.join("missing");
+        let results = client()
+            .scan_dir_local(&missing, &LocalScanOptions::new())
+            .unwrap();
+        assert_eq!(
+            results[&missing],
+            ClamScanResult::Error(ClamScanError::LstatFailed(Some(
+                missing.display().to_string()
+            )))
+            .into()
+        );
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,115 @@
+//! Scanning of email attachments, enabled by the `mail` feature. An RFC 822 / MIME message is
+//! parsed locally and each attachment is streamed to ClamD individually, so that results can be
+//! attributed to the attachment that caused them.
+
+use crate::client::{ClamClient, ClamResult};
+use crate::error::ClamError;
+use crate::response::ClamScanResult;
+use mail_parser::{MessageParser, MimeHeaders};
+use std::collections::BTreeMap;
+
+impl ClamClient {
+    /// Parses the raw RFC 822 `message` and scans each of its attachments with `INSTREAM`,
+    /// returning the result for every attachment keyed by its filename. Attachments without a
+    /// filename are keyed as `attachment-<n>`, and repeated filenames have `#<n>` appended, where
+    /// `n` is the position of the attachment within the message.
+    ///
+    /// Returns `ClamError::InvalidData` if the message cannot be parsed, or the first error from
+    /// scanning an attachment.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::response::ClamScanResult;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///     let message = b"From: a@example.com\r\nSubject: Hi\r\n\r\nHello";
+    ///
+    ///     if let Ok(results) = client.scan_mail_attachments(message) {
+    ///         for (filename, result) in results {
+    ///             if let ClamScanResult::Found(_, virus) = result {
+    ///                 println!("Found virus: '{}' in {}", virus, filename)
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn scan_mail_attachments(
+        &self,
+        message: &[u8],
+    ) -> ClamResult<BTreeMap<String, ClamScanResult>> {
+        let message = MessageParser::default()
+            .parse(message)
+            .ok_or_else(|| ClamError::InvalidData("could not parse mail message".to_owned()))?;
+        let mut results = BTreeMap::new();
+
+        for (index, attachment) in message.attachments().enumerate() {
+            let mut name = match attachment.attachment_name() {
+                Some(name) => name.to_owned(),
+                None => format!("attachment-{}", index),
+            };
+            if results.contains_key(&name) {
+                name = format!("{}#{}", name, index);
+            }
+
+            let result = self.scan_bytes(attachment.contents())?;
+            results.insert(name, result);
+        }
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::response::ClamScanResult;
+    use crate::testing::fake_clamd;
+
+    static MESSAGE: &str = "From: a@example.com\r\n\
+        To: b@example.com\r\n\
+        Subject: Attachments\r\n\
+        MIME-Version: 1.0\r\n\
+        Content-Type: multipart/mixed; boundary=\"sep\"\r\n\
+        \r\n\
+        --sep\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        See attached.\r\n\
+        --sep\r\n\
+        Content-Type: application/octet-stream\r\n\
+        Content-Disposition: attachment; filename=\"invoice.exe\"\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        YmFk\r\n\
+        --sep\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Disposition: attachment; filename=\"notes.txt\"\r\n\
+        \r\n\
+        clean\r\n\
+        --sep--\r\n";
+
+    #[test]
+    fn test_scan_mail_attachments() {
+        let port = fake_clamd(|cmd| {
+            if cmd.ends_with(b"bad") {
+                b"stream: Sig-One FOUND".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+        let results = client.scan_mail_attachments(MESSAGE.as_bytes()).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            results["invoice.exe"],
+            ClamScanResult::Found("stream".to_owned(), "Sig-One".to_owned())
+        );
+        assert_eq!(results["notes.txt"], ClamScanResult::Ok);
+    }
+}
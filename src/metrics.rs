@@ -0,0 +1,171 @@
+//! Prometheus style metrics for ClamD, recorded through the `metrics` crate facade so that they
+//! can be exported by whichever recorder the application installs, such as
+//! `metrics-exporter-prometheus`. Requires the `metrics` feature.
+//!
+//! `record_stats` publishes the results of a `STATS` command as gauges:
+//!
+//! - `clamd_pools`, `clamd_queue_length`
+//! - `clamd_threads_live`, `clamd_threads_idle`, `clamd_threads_max`
+//! - `clamd_threads_idle_timeout_seconds`
+//! - `clamd_memory_heap_bytes`, `clamd_memory_mmap_bytes`, `clamd_memory_used_bytes`,
+//!   `clamd_memory_free_bytes`, `clamd_memory_releasable_bytes`
+//! - `clamd_pools_used_bytes`, `clamd_pools_total_bytes`, which along with the other memory
+//!   gauges are only set when ClamD reports them
+//! - `clamd_state`, which is always 1 and labelled with the reported `state`
+//!
+//! Whilst `ClamClient` itself records client-side metrics for each `SCAN`, `CONTSCAN`,
+//! `INSTREAM` and `STATS` command:
+//!
+//! - `clam_client_commands_total`, a counter labelled with `command` and `result`
+//! - `clam_client_command_duration_seconds`, a histogram labelled with `command`
+//! - `clam_client_stream_bytes_total`, a counter of the bytes streamed by `INSTREAM`
+
+use crate::response::ClamStats;
+use ::metrics::{counter, gauge, histogram, Label};
+use std::time::Duration;
+
+/// Records `stats` as gauges, with the given labels attached to every gauge, e.g. to identify the
+/// ClamD instance the statistics were obtained from.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::metrics;
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///
+///     if let Ok(stats) = client.stats() {
+///         metrics::record_stats(&stats, &[("instance", "clamd-0")]);
+///     }
+/// }
+/// ```
+pub fn record_stats(stats: &ClamStats, labels: &[(&str, &str)]) {
+    let labels: Vec<Label> = labels
+        .iter()
+        .map(|(key, value)| Label::new(key.to_string(), value.to_string()))
+        .collect();
+
+    let gauges = [
+        ("clamd_pools", stats.pools as f64),
+        ("clamd_queue_length", stats.queue as f64),
+        ("clamd_threads_live", stats.threads_live as f64),
+        ("clamd_threads_idle", stats.threads_idle as f64),
+        ("clamd_threads_max", stats.threads_max as f64),
+        (
+            "clamd_threads_idle_timeout_seconds",
+            stats.threads_idle_timeout.as_secs_f64(),
+        ),
+    ];
+    let memory_gauges = [
+        ("clamd_memory_heap_bytes", stats.mem_heap),
+        ("clamd_memory_mmap_bytes", stats.mem_mmap),
+        ("clamd_memory_used_bytes", stats.mem_used),
+        ("clamd_memory_free_bytes", stats.mem_free),
+        ("clamd_memory_releasable_bytes", stats.mem_releasable),
+        ("clamd_pools_used_bytes", stats.pools_used),
+        ("clamd_pools_total_bytes", stats.pools_total),
+    ];
+
+    for (name, value) in gauges {
+        gauge!(name, labels.clone()).set(value);
+    }
+
+    for (name, size) in memory_gauges {
+        if let Some(size) = size {
+            gauge!(name, labels.clone()).set(size.bytes() as f64);
+        }
+    }
+
+    let mut state_labels = labels;
+    state_labels.push(Label::new("state", stats.state.clone()));
+    gauge!("clamd_state", state_labels).set(1.0);
+}
+
+/// Records the outcome and duration of a single command.
+pub(crate) fn record_command(command: &'static str, result: &'static str, duration: Duration) {
+    counter!("clam_client_commands_total", "command" => command, "result" => result).increment(1);
+    histogram!("clam_client_command_duration_seconds", "command" => command)
+        .record(duration.as_secs_f64());
+}
+
+/// Records the number of bytes streamed by a single `INSTREAM` command.
+pub(crate) fn record_bytes(bytes: u64) {
+    counter!("clam_client_stream_bytes_total").increment(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::metrics::record_stats;
+    use crate::response::{ClamStats, MemSize};
+    use crate::testing::fake_clamd;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    #[test]
+    fn test_record_stats() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let stats = ClamStats {
+            threads_live: 1,
+            mem_used: Some(MemSize::from_bytes(4096)),
+            state: "VALID PRIMARY".to_owned(),
+            ..ClamStats::default()
+        };
+
+        ::metrics::with_local_recorder(&recorder, || record_stats(&stats, &[("instance", "a")]));
+
+        let snapshot = snapshotter.snapshot().into_hashmap();
+        let value = |name: &str| {
+            snapshot
+                .iter()
+                .find(|(key, _)| key.key().name() == name)
+                .map(|(key, (_, _, value))| (key.key().labels().count(), value))
+                .unwrap()
+        };
+
+        assert_eq!(
+            value("clamd_threads_live").1,
+            &DebugValue::Gauge(1.0.into())
+        );
+        assert_eq!(
+            value("clamd_memory_used_bytes").1,
+            &DebugValue::Gauge(4096.0.into())
+        );
+        assert_eq!(value("clamd_state"), (2, &DebugValue::Gauge(1.0.into())));
+        // Memory statistics ClamD did not report are not recorded
+        assert!(!snapshot
+            .iter()
+            .any(|(key, _)| key.key().name() == "clamd_memory_heap_bytes"));
+    }
+
+    #[test]
+    fn test_record_commands() {
+        let port = fake_clamd(|_| b"stream: OK".to_vec());
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        ::metrics::with_local_recorder(&recorder, || client.scan_bytes(b"data").unwrap());
+
+        let snapshot = snapshotter.snapshot().into_hashmap();
+        let (key, (_, _, value)) = snapshot
+            .iter()
+            .find(|(key, _)| key.key().name() == "clam_client_commands_total")
+            .unwrap();
+        assert!(key
+            .key()
+            .labels()
+            .any(|l| l.key() == "result" && l.value() == "clean"));
+        assert_eq!(value, &DebugValue::Counter(1));
+
+        let (_, (_, _, bytes)) = snapshot
+            .iter()
+            .find(|(key, _)| key.key().name() == "clam_client_stream_bytes_total")
+            .unwrap();
+        assert_eq!(bytes, &DebugValue::Counter(4));
+    }
+}
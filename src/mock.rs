@@ -0,0 +1,350 @@
+//! `MockClamd` is a small in-process stand in for ClamD, for unit testing code which uses
+//! `ClamClient` without a running ClamAV. Requires the `test-util` feature.
+//!
+//! The mock speaks enough of the ClamD protocol to answer `PING`, `VERSION`, `SCAN`, `CONTSCAN`,
+//! `INSTREAM` and `IDSESSION`, with scriptable responses.
+
+use crate::client::{ClamClient, ClamResult};
+use crate::protocol;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The EICAR test signature, which the default `INSTREAM` handler reports as infected.
+pub use crate::response::EICAR;
+
+/// A handler producing the reply to a raw command, for `INSTREAM` the streamed bytes are appended
+/// to the command.
+pub(crate) type Handler = Arc<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+/// A handler producing the result for the bytes streamed by `INSTREAM`.
+type StreamHandler = Arc<dyn Fn(&[u8]) -> String + Send + Sync>;
+
+/// `MockClamdBuilder` scripts the responses of a `MockClamd`, it is obtained via
+/// `MockClamd::builder`.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::mock::MockClamd;
+/// use clam_client::response::ClamScanResult;
+///
+/// fn main() {
+///     let clamd = MockClamd::builder()
+///         .scan("/tmp/infected", "Eicar-Signature FOUND")
+///         .start()
+///         .unwrap();
+///     let client = clamd.client().unwrap();
+///
+///     assert!(client.ping());
+///     assert_eq!(
+///         client.scan_path("/tmp/infected", false).unwrap(),
+///         vec![ClamScanResult::Found("/tmp/infected".to_owned(), "Eicar-Signature".to_owned())]
+///     );
+///     assert_eq!(client.scan_bytes(b"clean").unwrap(), ClamScanResult::Ok);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct MockClamdBuilder {
+    version: String,
+    scans: HashMap<String, String>,
+    instream: StreamHandler,
+    replies: HashMap<String, String>,
+}
+
+impl MockClamdBuilder {
+    /// Sets the reply to `VERSION`.
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = version.to_owned();
+        self
+    }
+
+    /// Sets the result reported when `path` is scanned, e.g. `OK` or `Eicar-Signature FOUND`.
+    /// Paths without a scripted result are reported as `OK`.
+    pub fn scan(mut self, path: &str, result: &str) -> Self {
+        self.scans.insert(path.to_owned(), result.to_owned());
+        self
+    }
+
+    /// Sets the handler deciding the result of each `INSTREAM` from the streamed bytes. By
+    /// default streams containing the `EICAR` test signature are reported as
+    /// `Eicar-Signature FOUND`, and all others as `OK`.
+    pub fn instream<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&[u8]) -> String + Send + Sync + 'static,
+    {
+        self.instream = Arc::new(handler);
+        self
+    }
+
+    /// Sets the raw reply to `command`, given without the `z` prefix or NUL terminator, e.g.
+    /// `reply("RELOAD", "UNKNOWN COMMAND")`. This takes precedence over any other scripted response.
+    pub fn reply(mut self, command: &str, reply: &str) -> Self {
+        self.replies.insert(command.to_owned(), reply.to_owned());
+        self
+    }
+
+    /// Starts the mock listening on an ephemeral local port.
+    pub fn start(self) -> io::Result<MockClamd> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let received = commands.clone();
+
+        let handler: Handler = Arc::new(move |command: &[u8]| {
+            let (name, data) = split_command(command);
+            received.lock().unwrap().push(name.clone());
+            self.respond(&name, data).into_bytes()
+        });
+        let stop = serve(listener, handler);
+
+        Ok(MockClamd {
+            addr,
+            commands,
+            stop,
+        })
+    }
+
+    /// Produces the reply to a single command.
+    fn respond(&self, command: &str, data: &[u8]) -> String {
+        if let Some(reply) = self.replies.get(command) {
+            return reply.clone();
+        }
+
+        let (name, argument) = match command.split_once(' ') {
+            Some((name, argument)) => (name, argument),
+            None => (command, ""),
+        };
+
+        match name {
+            "PING" => "PONG".to_owned(),
+            "VERSION" => self.version.clone(),
+            "RELOAD" => "RELOADING".to_owned(),
+            "SCAN" | "CONTSCAN" | "MULTISCAN" | "ALLMATCHSCAN" => {
+                let result = self.scans.get(argument).map_or("OK", |r| r.as_str());
+                format!("{}: {}", argument, result)
+            }
+            "INSTREAM" => format!("stream: {}", (self.instream)(data)),
+            _ => "UNKNOWN COMMAND".to_owned(),
+        }
+    }
+}
+
+/// `MockClamd` is a running mock ClamD, which stops listening when dropped. See
+/// `MockClamdBuilder` for an example.
+pub struct MockClamd {
+    addr: SocketAddr,
+    commands: Arc<Mutex<Vec<String>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl MockClamd {
+    /// Creates a `MockClamdBuilder` with the default responses.
+    pub fn builder() -> MockClamdBuilder {
+        MockClamdBuilder {
+            version: "ClamAV 0.103.8/26830/Mon Mar  6 09:20:26 2023".to_owned(),
+            scans: HashMap::new(),
+            instream: Arc::new(|data: &[u8]| {
+                if data.windows(EICAR.len()).any(|w| w == EICAR) {
+                    "Eicar-Signature FOUND".to_owned()
+                } else {
+                    "OK".to_owned()
+                }
+            }),
+            replies: HashMap::new(),
+        }
+    }
+
+    /// Starts a mock with the default responses.
+    pub fn start() -> io::Result<MockClamd> {
+        MockClamd::builder().start()
+    }
+
+    /// Returns the address the mock is listening on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the port the mock is listening on.
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+
+    /// Creates a `ClamClient` connected to the mock, use `ClamClient::builder` with `port` for
+    /// further configuration.
+    pub fn client(&self) -> ClamResult<ClamClient> {
+        ClamClient::new("127.0.0.1", self.port())
+    }
+
+    /// Returns every command received so far, without the `z` prefix or NUL terminator, e.g.
+    /// `SCAN /tmp/file`. `IDSESSION` and `END` are not included.
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockClamd {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wake the listener so that it notices it has been stopped
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+/// Splits a raw command into its name, without the `z` or `n` prefix, and any streamed bytes.
+fn split_command(command: &[u8]) -> (String, &[u8]) {
+    let command = match command.first() {
+        Some(b'z') | Some(b'n') => &command[1..],
+        _ => command,
+    };
+
+    match command.strip_prefix(b"INSTREAM") {
+        Some(data) => ("INSTREAM".to_owned(), data),
+        None => (String::from_utf8_lossy(command).into_owned(), &[]),
+    }
+}
+
+/// Accepts connections on `listener` until the returned flag is set, passing every command
+/// received to `handler`, along with the streamed bytes for `INSTREAM`, and writing the reply
+/// back terminated as the command was, with NUL for `z` commands or a newline for `n` commands.
+/// `IDSESSION` is honoured by prefixing replies with request IDs.
+pub(crate) fn serve(listener: TcpListener, handler: Handler) -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+
+    thread::spawn(move || {
+        for connection in listener.incoming() {
+            if stopped.load(Ordering::SeqCst) {
+                return;
+            }
+            let connection = match connection {
+                Ok(c) => c,
+                Err(_) => return,
+            };
+
+            let handler = handler.clone();
+            thread::spawn(move || {
+                let _ = handle(connection, handler);
+            });
+        }
+    });
+
+    stop
+}
+
+/// Serves the commands on a single connection.
+fn handle(connection: TcpStream, handler: Handler) -> io::Result<()> {
+    let mut writer = connection.try_clone()?;
+    let mut reader = BufReader::new(connection);
+    let mut session = false;
+    let mut id = 0;
+
+    loop {
+        let mut command = vec![0];
+        if reader.read(&mut command)? == 0 {
+            return Ok(());
+        }
+        let terminator = if command[0] == b'n' { b'\n' } else { b'\0' };
+        reader.read_until(terminator, &mut command)?;
+        if command.last() == Some(&terminator) {
+            command.pop();
+        }
+
+        match &command[1..] {
+            b"IDSESSION" => {
+                session = true;
+                continue;
+            }
+            b"END" => return Ok(()),
+            b"INSTREAM" => {
+                while let Some(chunk) = protocol::read_chunk(&mut reader)? {
+                    command.extend(chunk);
+                }
+            }
+            _ => {}
+        }
+
+        let mut reply = Vec::new();
+        if session {
+            id += 1;
+            reply.extend(format!("{}: ", id).into_bytes());
+        }
+        reply.extend(handler(&command));
+        reply.push(terminator);
+
+        writer.write_all(&reply)?;
+        if !session {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::{MockClamd, EICAR};
+    use crate::response::{ClamScanError, ClamScanResult};
+
+    #[test]
+    fn test_mock_defaults() {
+        let clamd = MockClamd::start().unwrap();
+        let client = clamd.client().unwrap();
+
+        assert!(client.ping());
+        assert_eq!(client.version().unwrap().version_tag, "ClamAV 0.103.8");
+        assert_eq!(client.scan_bytes(b"clean").unwrap(), ClamScanResult::Ok);
+        assert_eq!(
+            client.scan_bytes(EICAR).unwrap(),
+            ClamScanResult::Found("stream".to_owned(), "Eicar-Signature".to_owned())
+        );
+        assert_eq!(
+            clamd.commands(),
+            vec!["PING", "VERSION", "INSTREAM", "INSTREAM"]
+        );
+    }
+
+    #[test]
+    fn test_mock_scripted() {
+        let clamd = MockClamd::builder()
+            .scan("/infected", "Eicar-Signature FOUND")
+            .instream(|data| format!("{} ERROR", data.len()))
+            .reply("RELOAD", "UNKNOWN COMMAND")
+            .start()
+            .unwrap();
+        let client = clamd.client().unwrap();
+
+        assert_eq!(
+            client.scan_path("/infected", false).unwrap(),
+            vec![ClamScanResult::Found(
+                "/infected".to_owned(),
+                "Eicar-Signature".to_owned()
+            )]
+        );
+        assert_eq!(
+            client.scan_path("/clean", true).unwrap(),
+            vec![ClamScanResult::Ok]
+        );
+        assert_eq!(
+            client.scan_bytes(b"data").unwrap(),
+            ClamScanResult::Error(ClamScanError::Other("stream: 4 ERROR".to_owned()))
+        );
+        assert!(client.reload().is_err());
+    }
+
+    #[test]
+    fn test_mock_session() {
+        let clamd = MockClamd::start().unwrap();
+        let client = clamd.client().unwrap();
+        let mut session = client.session().unwrap();
+
+        assert!(session.ping().unwrap());
+        assert_eq!(
+            session.scan_stream(&b"clean"[..]).unwrap(),
+            ClamScanResult::Ok
+        );
+    }
+}
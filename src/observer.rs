@@ -0,0 +1,130 @@
+//! `ClamObserver` receives lifecycle events from a `ClamClient`, such as each connection made
+//! and each command sent, for auditing and debugging without wrapping every public method. Any
+//! number of observers are registered with `ClamClientBuilder::observer`.
+
+use crate::client::ClamResult;
+use crate::error::ClamError;
+use crate::response::ClamScanResult;
+use std::fmt::Display;
+use std::sync::Arc;
+
+/// `ClamObserver` is notified of the events occurring on a `ClamClient`, and on the sessions and
+/// pools created from it. Every method has an empty default implementation, so only the events
+/// of interest need be implemented. Observers are called synchronously on the thread issuing the
+/// command, so should return quickly.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::observer::ClamObserver;
+/// use clam_client::response::ClamScanResult;
+///
+/// struct AuditLog;
+///
+/// impl ClamObserver for AuditLog {
+///     fn on_result(&self, command: &str, result: &ClamScanResult) {
+///         println!("{}: {}", command, result);
+///     }
+/// }
+///
+/// fn main() {
+///     let client = ClamClient::builder("127.0.0.1", 3310)
+///         .observer(AuditLog)
+///         .build()
+///         .unwrap();
+/// }
+/// ```
+pub trait ClamObserver: Send + Sync {
+    /// Called when a connection to ClamD is established, with the address connected to, or the
+    /// path of the Unix socket.
+    fn on_connect(&self, _endpoint: &str) {}
+
+    /// Called when a command, such as `SCAN /some/file` or `INSTREAM`, has been written to ClamD,
+    /// without its prefix or terminator.
+    fn on_command_sent(&self, _command: &str) {}
+
+    /// Called after each chunk of an `INSTREAM` is written to ClamD, with the size of the chunk.
+    fn on_bytes_streamed(&self, _bytes: u64) {}
+
+    /// Called with each result of a `SCAN`, `CONTSCAN`, `ALLMATCHSCAN`, `MULTISCAN` or `INSTREAM`
+    /// issued through the client.
+    fn on_result(&self, _command: &str, _result: &ClamScanResult) {}
+
+    /// Called when a `SCAN`, `CONTSCAN`, `ALLMATCHSCAN`, `MULTISCAN`, `INSTREAM` or `STATS` issued
+    /// through the client fails.
+    fn on_error(&self, _command: &str, _error: &ClamError) {}
+}
+
+impl<T: ClamObserver + ?Sized> ClamObserver for Arc<T> {
+    fn on_connect(&self, endpoint: &str) {
+        (**self).on_connect(endpoint)
+    }
+
+    fn on_command_sent(&self, command: &str) {
+        (**self).on_command_sent(command)
+    }
+
+    fn on_bytes_streamed(&self, bytes: u64) {
+        (**self).on_bytes_streamed(bytes)
+    }
+
+    fn on_result(&self, command: &str, result: &ClamScanResult) {
+        (**self).on_result(command, result)
+    }
+
+    fn on_error(&self, command: &str, error: &ClamError) {
+        (**self).on_error(command, error)
+    }
+}
+
+/// The observers registered on a client, shared with the sessions created from it.
+#[derive(Clone, Default)]
+pub(crate) struct Observers(Vec<Arc<dyn ClamObserver>>);
+
+impl Observers {
+    pub(crate) fn push(&mut self, observer: Arc<dyn ClamObserver>) {
+        self.0.push(observer);
+    }
+
+    pub(crate) fn connected(&self, endpoint: &dyn Display) {
+        if !self.0.is_empty() {
+            let endpoint = endpoint.to_string();
+            self.0.iter().for_each(|o| o.on_connect(&endpoint));
+        }
+    }
+
+    /// Reports a framed command, such as `zSCAN /some/file\0`, as sent.
+    pub(crate) fn command_sent(&self, framed: &[u8]) {
+        if !self.0.is_empty() {
+            let framed = String::from_utf8_lossy(framed);
+            let command = framed.trim_end_matches(['\0', '\n']);
+            let command = command.strip_prefix(['z', 'n']).unwrap_or(command);
+            self.0.iter().for_each(|o| o.on_command_sent(command));
+        }
+    }
+
+    pub(crate) fn streamed(&self, bytes: u64) {
+        self.0.iter().for_each(|o| o.on_bytes_streamed(bytes));
+    }
+
+    /// Reports the outcome of `command`, where `results` extracts the scan results from a
+    /// successful outcome.
+    pub(crate) fn finished<T>(
+        &self,
+        command: &str,
+        outcome: &ClamResult<T>,
+        results: impl FnOnce(&T) -> &[ClamScanResult],
+    ) {
+        match outcome {
+            Ok(value) => {
+                for result in results(value) {
+                    self.0.iter().for_each(|o| o.on_result(command, result));
+                }
+            }
+            Err(e) => self.0.iter().for_each(|o| o.on_error(command, e)),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! `HashPolicy` overrides ClamD's verdict for specific payloads, identified by their SHA-256:
+//! allowlisted payloads are always clean, such as to work around a false positive, whilst
+//! denylisted payloads are always infected, such as those on a list of known bad indicators of
+//! compromise. Requires the `hash` feature.
+//!
+//! The policy is applied by `ClamClient::scan_bytes_with_policy`, before ClamD is consulted, and
+//! by `ClamClient::scan_stream_with_policy`, once the stream has been scanned and its hash is
+//! known. Either way the `PolicyVerdict` records which mechanism decided the result.
+
+use crate::client::ClamResult;
+use crate::error::ClamError;
+use crate::response::ClamScanResult;
+use std::collections::{HashMap, HashSet};
+
+/// The SHA-256 of a payload.
+pub type Sha256Hash = [u8; 32];
+
+/// `HashPolicy` is a set of allowlisted and denylisted SHA-256 hashes. A hash which is on both
+/// lists is denied.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::policy::{DecidedBy, HashPolicy};
+///
+/// fn main() {
+///     let mut policy = HashPolicy::new();
+///     policy
+///         .deny(
+///             "275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f",
+///             "IOC.Known-Bad",
+///         )
+///         .unwrap();
+///
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///     if let Ok(verdict) = client.scan_bytes_with_policy(b"some upload", &policy) {
+///         if verdict.decided_by != DecidedBy::ClamD {
+///             println!("{} by {:?}", verdict.result, verdict.decided_by);
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashPolicy {
+    allowed: HashSet<Sha256Hash>,
+    denied: HashMap<Sha256Hash, String>,
+}
+
+/// `DecidedBy` is the mechanism which decided a `PolicyVerdict`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DecidedBy {
+    /// ClamD's verdict, the hash is on neither list
+    ClamD,
+    /// The hash is allowlisted, so the payload is clean whatever ClamD reported
+    Allowlist,
+    /// The hash is denylisted, so the payload is infected whatever ClamD reported
+    Denylist,
+}
+
+/// `PolicyVerdict` is the result of a scan subject to a `HashPolicy`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyVerdict {
+    /// The result of the scan, which for a denylisted payload is `Found` with the name it was
+    /// denylisted under
+    pub result: ClamScanResult,
+    /// The mechanism which decided the result
+    pub decided_by: DecidedBy,
+    /// The SHA-256 of the payload
+    pub sha256: Sha256Hash,
+}
+
+impl HashPolicy {
+    /// Creates an empty `HashPolicy`, under which ClamD decides every verdict.
+    pub fn new() -> HashPolicy {
+        HashPolicy::default()
+    }
+
+    /// Allowlists the payload with the hex encoded SHA-256 `sha256`, returning
+    /// `ClamError::InvalidData` if it is not a valid hash.
+    pub fn allow(&mut self, sha256: &str) -> ClamResult<()> {
+        self.allowed.insert(parse_sha256(sha256)?);
+        Ok(())
+    }
+
+    /// Denylists the payload with the hex encoded SHA-256 `sha256`, reporting it as infected
+    /// with the signature `name`. Returns `ClamError::InvalidData` if it is not a valid hash.
+    pub fn deny(&mut self, sha256: &str, name: &str) -> ClamResult<()> {
+        self.denied.insert(parse_sha256(sha256)?, name.to_owned());
+        Ok(())
+    }
+
+    /// Returns the verdict the policy forces for the payload with `sha256`, or `None` if ClamD
+    /// should decide.
+    pub(crate) fn decide(&self, sha256: Sha256Hash) -> Option<PolicyVerdict> {
+        let (result, decided_by) = match self.denied.get(&sha256) {
+            Some(name) => (
+                ClamScanResult::Found("stream".to_owned(), name.clone()),
+                DecidedBy::Denylist,
+            ),
+            None if self.allowed.contains(&sha256) => (ClamScanResult::Ok, DecidedBy::Allowlist),
+            None => return None,
+        };
+
+        Some(PolicyVerdict {
+            result,
+            decided_by,
+            sha256,
+        })
+    }
+
+    /// Applies the policy to ClamD's `result` for the payload with `sha256`.
+    pub(crate) fn apply(&self, sha256: Sha256Hash, result: ClamScanResult) -> PolicyVerdict {
+        self.decide(sha256).unwrap_or(PolicyVerdict {
+            result,
+            decided_by: DecidedBy::ClamD,
+            sha256,
+        })
+    }
+}
+
+/// Parses a hex encoded SHA-256.
+fn parse_sha256(hex: &str) -> ClamResult<Sha256Hash> {
+    let invalid = || ClamError::InvalidData(hex.to_owned());
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+
+    let mut hash = [0; 32];
+    for (byte, pair) in hash.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| invalid())?;
+    }
+
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::policy::{parse_sha256, DecidedBy, HashPolicy};
+    use crate::response::ClamScanResult;
+    use crate::testing::fake_clamd;
+
+    /// The SHA-256 of `infected` and `false positive`.
+    const INFECTED: &str = "c810e76f2125db71bfbdd7e29ce902f37f5b2250c48c16d241bd46c70aed1a91";
+    const FALSE_POSITIVE: &str = "594d50685604cf6cc55c4560bc72029111a7855f3cffac4a8d292d72195361da";
+
+    #[test]
+    fn test_parse_sha256() {
+        let hash = parse_sha256(&"0f".repeat(32)).unwrap();
+        assert_eq!(hash, [0x0f; 32]);
+        assert!(parse_sha256("0f").is_err());
+        assert!(parse_sha256(&"zz".repeat(32)).is_err());
+        assert!(parse_sha256(&"é".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_scan_with_policy() {
+        let port = fake_clamd(|cmd| {
+            if cmd.ends_with(b"false positive") {
+                b"stream: Heuristic FOUND".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        let mut policy = HashPolicy::new();
+        policy.allow(FALSE_POSITIVE).unwrap();
+        policy.deny(INFECTED, "IOC.Known-Bad").unwrap();
+
+        for (data, result, decided_by) in [
+            (&b"clean"[..], ClamScanResult::Ok, DecidedBy::ClamD),
+            (b"false positive", ClamScanResult::Ok, DecidedBy::Allowlist),
+            (
+                b"infected",
+                ClamScanResult::Found("stream".to_owned(), "IOC.Known-Bad".to_owned()),
+                DecidedBy::Denylist,
+            ),
+        ] {
+            let verdict = client.scan_bytes_with_policy(data, &policy).unwrap();
+            assert_eq!((&verdict.result, verdict.decided_by), (&result, decided_by));
+
+            let verdict = client.scan_stream_with_policy(data, &policy).unwrap();
+            #[cfg(feature = "serde")]
+            {
+                let json = serde_json::to_string(&verdict).unwrap();
+                assert_eq!(
+                    serde_json::from_str::<crate::policy::PolicyVerdict>(&json).unwrap(),
+                    verdict
+                );
+            }
+            assert_eq!((verdict.result, verdict.decided_by), (result, decided_by));
+        }
+
+        // Whilst without a policy ClamD's verdict stands
+        let verdict = client
+            .scan_bytes_with_policy(b"false positive", &HashPolicy::new())
+            .unwrap();
+        assert_eq!(
+            verdict.result,
+            ClamScanResult::Found("stream".to_owned(), "Heuristic".to_owned())
+        );
+        assert_eq!(verdict.sha256, parse_sha256(FALSE_POSITIVE).unwrap());
+    }
+}
@@ -0,0 +1,422 @@
+//! `ClamPool` maintains a set of open `ClamSession`s to a single ClamD instance and hands them
+//! out per request, this avoids the cost of establishing a new connection for every command
+//! which dominates the latency of small scans.
+
+use crate::client::{ClamClient, ClamResult};
+use crate::error::ClamError;
+use crate::response::ClamScanResult;
+use crate::session::{ClamSession, DEFAULT_IDLE_TIMEOUT};
+use std::io::Read;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// `ClamPool` hands out at most `max_size` concurrent sessions, callers requesting a session
+/// whilst all are in use will block until one is returned to the pool. `ClamPool` is
+/// `Send + Sync`, so may be shared between threads behind an `Arc`.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::pool::ClamPool;
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///     let pool = ClamPool::new(client, 8);
+///
+///     if let Ok(result) = pool.scan_stream(&b"some data"[..]) {
+///         println!("{:?}", result);
+///     }
+/// }
+/// ```
+pub struct ClamPool {
+    client: ClamClient,
+    max_size: usize,
+    min_idle: usize,
+    verify_sessions: bool,
+    idle_timeout: Duration,
+    state: Mutex<PoolState>,
+    returned: Condvar,
+}
+
+/// The book keeping for a `ClamPool`, guarded by its mutex.
+struct PoolState {
+    idle: Vec<(ClamSession, Instant)>,
+    active: usize,
+}
+
+/// `PooledSession` is a `ClamSession` on loan from a `ClamPool`, it is returned to the pool when
+/// dropped unless an error has left the session unusable.
+pub struct PooledSession<'a> {
+    pool: &'a ClamPool,
+    session: Option<ClamSession>,
+}
+
+impl ClamPool {
+    /// Creates a new `ClamPool` which will open up to `max_size` sessions using `client`.
+    /// Sessions are opened lazily, as and when they are first required.
+    ///
+    /// *Arguments*
+    ///
+    /// - `client`: The client used to connect to ClamD
+    /// - `max_size`: The maximum number of sessions open at any one time, at least 1
+    pub fn new(client: ClamClient, max_size: usize) -> ClamPool {
+        ClamPool {
+            client,
+            max_size: max_size.max(1),
+            min_idle: 0,
+            verify_sessions: false,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                active: 0,
+            }),
+            returned: Condvar::new(),
+        }
+    }
+
+    /// Sets how long a session may sit unused in the pool before it is discarded rather than
+    /// reused. This should be less than ClamD's `IdleTimeout` (30 seconds by default), after
+    /// which ClamD closes the connection. Defaults to 25 seconds.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the number of sessions the pool keeps open and idle, at most `max_size`. The pool is
+    /// filled by `warm_up`, and topped up again whenever sessions which have expired or been
+    /// closed by ClamD are discarded. Defaults to 0.
+    ///
+    /// *Note*: Idle sessions are only found to have expired when the pool is next used, so
+    /// `warm_up` may also be called periodically to keep a pool which sees little use warm.
+    pub fn min_idle(mut self, sessions: usize) -> Self {
+        self.min_idle = sessions.min(self.max_size);
+        self
+    }
+
+    /// Sets whether `warm_up` completes each session it opens with a `PING`, confirming that
+    /// ClamD has accepted the `IDSESSION` before the session is handed out. Defaults to false.
+    pub fn verify_sessions(mut self, verify: bool) -> Self {
+        self.verify_sessions = verify;
+        self
+    }
+
+    /// Opens sessions until `min_idle` sit idle in the pool, or `max_size` are open, so that the
+    /// first requests after startup are not penalised by connection setup. Idle sessions which
+    /// have expired or been closed by ClamD are discarded and replaced, so calling this more
+    /// often than the idle timeout keeps the pool warm. Returns the number of sessions opened,
+    /// or the first error encountered, in which case the sessions which were opened are kept.
+    pub fn warm_up(&self) -> ClamResult<usize> {
+        let (wanted, expired) = {
+            let mut state = self.state.lock().unwrap();
+            let idle_timeout = self.idle_timeout;
+            let (idle, expired): (Vec<_>, Vec<_>) =
+                state.idle.drain(..).partition(|(session, returned_at)| {
+                    returned_at.elapsed() < idle_timeout && session.is_connected()
+                });
+            state.idle = idle;
+
+            let open = state.idle.len() + state.active;
+            let wanted = self
+                .min_idle
+                .saturating_sub(state.idle.len())
+                .min(self.max_size.saturating_sub(open));
+            // Each session is counted as active until opened, so `get` cannot exceed `max_size`
+            state.active += wanted;
+            (wanted, expired)
+        };
+        // Closing a session writes `END`, which must not block other callers on the pool's lock
+        drop(expired);
+
+        let mut opened = 0;
+        let mut result = Ok(());
+        for _ in 0..wanted {
+            if result.is_err() {
+                self.release(None);
+                continue;
+            }
+
+            match self.open_session() {
+                Ok(session) => {
+                    self.release(Some(session));
+                    opened += 1;
+                }
+                Err(e) => {
+                    self.release(None);
+                    result = Err(e);
+                }
+            }
+        }
+
+        result.map(|_| opened)
+    }
+
+    /// Returns the number of sessions currently sat idle in the pool.
+    pub fn idle_sessions(&self) -> usize {
+        self.state.lock().unwrap().idle.len()
+    }
+
+    /// Obtains a session from the pool, reusing an idle session where possible and opening a
+    /// new one otherwise. Blocks whilst `max_size` sessions are already in use.
+    pub fn get(&self) -> ClamResult<PooledSession<'_>> {
+        // Expired sessions are closed once the lock is released, since closing writes `END`
+        let mut expired = Vec::new();
+        let mut state = self.state.lock().unwrap();
+
+        let reused = 'wait: loop {
+            while let Some((session, returned_at)) = state.idle.pop() {
+                // Sessions closed by ClamD, e.g. when it restarted, are dropped rather than reused
+                if returned_at.elapsed() < self.idle_timeout && session.is_connected() {
+                    break 'wait Some(session);
+                }
+                expired.push(session);
+            }
+
+            if state.active < self.max_size {
+                break None;
+            }
+
+            state = self.returned.wait(state).unwrap();
+        };
+
+        state.active += 1;
+        drop(state);
+        let discarded = !expired.is_empty();
+        drop(expired);
+
+        let result = match reused.map_or_else(|| self.client.session(), Ok) {
+            Ok(session) => Ok(PooledSession {
+                pool: self,
+                session: Some(session),
+            }),
+            Err(e) => {
+                self.release(None);
+                Err(e)
+            }
+        };
+
+        if discarded {
+            self.top_up();
+        }
+        result
+    }
+
+    /// Implements the ClamD `PING` command on a pooled session, returns true if ClamD responds
+    /// with `PONG`, or false if there was an error.
+    pub fn ping(&self) -> bool {
+        self.retrying(|s| s.ping()).unwrap_or(false)
+    }
+
+    /// Implements the ClamD `SCAN` command on a pooled session, see `ClamSession::scan_path`.
+    pub fn scan_path(&self, path: &str) -> ClamResult<ClamScanResult> {
+        self.retrying(|s| s.scan_path(path))
+    }
+
+    /// Implements the ClamD `INSTREAM` command on a pooled session, see `ClamClient::scan_stream`.
+    pub fn scan_stream<T: Read>(&self, stream: T) -> ClamResult<ClamScanResult> {
+        self.get()?.scan_stream(stream)
+    }
+
+    /// Runs `command` on a pooled session, attempting it once more on another session if the
+    /// connection failed, since ClamD may have closed it whilst the command was being issued.
+    fn retrying<R, F>(&self, mut command: F) -> ClamResult<R>
+    where
+        F: FnMut(&mut ClamSession) -> ClamResult<R>,
+    {
+        let mut session = self.get()?;
+        match command(&mut session) {
+            Err(_) if session.is_broken() => {
+                drop(session);
+                command(&mut *self.get()?)
+            }
+            result => result,
+        }
+    }
+
+    /// Opens a new session for `warm_up`, confirming it with `PING` if configured to.
+    fn open_session(&self) -> ClamResult<ClamSession> {
+        let mut session = self.client.session()?;
+        if self.verify_sessions && !session.ping()? {
+            return Err(ClamError::InvalidData(
+                "ClamD did not reply PONG to a new session".to_owned(),
+            ));
+        }
+        Ok(session)
+    }
+
+    /// Returns a session to the pool, or discards it if it is no longer usable, and wakes any
+    /// caller waiting for a session.
+    fn release(&self, session: Option<ClamSession>) {
+        let mut state = self.state.lock().unwrap();
+        state.active -= 1;
+
+        let discarded = match session {
+            // Replies to requests left outstanding would be read by the session's next user
+            Some(session) if !session.is_broken() && session.pending_requests() == 0 => {
+                state.idle.push((session, Instant::now()));
+                None
+            }
+            session => session,
+        };
+
+        self.returned.notify_one();
+        drop(state);
+
+        // Closing the discarded session writes `END`, so is done once the lock is released
+        if discarded.is_some() {
+            drop(discarded);
+            self.top_up();
+        }
+    }
+
+    /// Opens sessions until `min_idle` sit idle in the pool, as `warm_up` does, once sessions have
+    /// been discarded. A failure to open a session is left for the next caller to encounter.
+    fn top_up(&self) {
+        if self.min_idle > 0 {
+            let _ = self.warm_up();
+        }
+    }
+}
+
+impl<'a> Deref for PooledSession<'a> {
+    type Target = ClamSession;
+
+    fn deref(&self) -> &ClamSession {
+        self.session.as_ref().unwrap()
+    }
+}
+
+impl<'a> DerefMut for PooledSession<'a> {
+    fn deref_mut(&mut self) -> &mut ClamSession {
+        self.session.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledSession<'a> {
+    fn drop(&mut self) {
+        self.pool.release(self.session.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::pool::ClamPool;
+    use crate::response::ClamScanResult;
+    use crate::testing::fake_clamd;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_pool_reuses_sessions() {
+        let port = fake_clamd(|_| b"stream: OK".to_vec());
+        let pool = ClamPool::new(ClamClient::new("127.0.0.1", port).unwrap(), 2);
+
+        assert_eq!(pool.idle_sessions(), 0);
+        assert_eq!(pool.scan_stream(&b"data"[..]).unwrap(), ClamScanResult::Ok);
+        assert_eq!(pool.idle_sessions(), 1);
+        assert_eq!(pool.scan_stream(&b"data"[..]).unwrap(), ClamScanResult::Ok);
+        assert_eq!(pool.idle_sessions(), 1);
+    }
+
+    #[test]
+    fn test_pool_opens_up_to_max_size() {
+        let port = fake_clamd(|_| b"PONG".to_vec());
+        let pool = ClamPool::new(ClamClient::new("127.0.0.1", port).unwrap(), 2);
+
+        {
+            let _first = pool.get().unwrap();
+            let _second = pool.get().unwrap();
+        }
+
+        assert_eq!(pool.idle_sessions(), 2);
+    }
+
+    #[test]
+    fn test_pool_discards_failed_connections() {
+        let pool = ClamPool::new(ClamClient::new("127.0.0.1", 1).unwrap(), 1);
+        assert!(pool.get().is_err());
+        assert!(!pool.ping());
+        assert_eq!(pool.idle_sessions(), 0);
+    }
+
+    #[test]
+    fn test_pool_reconnects_after_restart() {
+        let (port, connections) = crate::testing::restarting_clamd(|cmd| {
+            if cmd == b"zSCAN /file" {
+                b"/file: OK".to_vec()
+            } else {
+                b"PONG".to_vec()
+            }
+        });
+        let pool = ClamPool::new(ClamClient::new("127.0.0.1", port).unwrap(), 1);
+
+        assert!(pool.ping());
+        assert_eq!(pool.scan_path("/file").unwrap(), ClamScanResult::Ok);
+        assert!(pool.ping());
+        assert_eq!(connections.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_pool_warm_up() {
+        static PINGS: AtomicUsize = AtomicUsize::new(0);
+
+        let port = fake_clamd(|_| {
+            PINGS.fetch_add(1, Ordering::SeqCst);
+            b"PONG".to_vec()
+        });
+        let pool = ClamPool::new(ClamClient::new("127.0.0.1", port).unwrap(), 3)
+            .min_idle(5)
+            .verify_sessions(true);
+
+        // `min_idle` is capped at `max_size`, and each new session is confirmed with `PING`
+        assert_eq!(pool.warm_up().unwrap(), 3);
+        assert_eq!(pool.idle_sessions(), 3);
+        assert_eq!(PINGS.load(Ordering::SeqCst), 3);
+
+        // Sessions in use count towards `max_size`, so only those returned are replaced
+        {
+            let _session = pool.get().unwrap();
+            assert_eq!(pool.warm_up().unwrap(), 0);
+        }
+        assert_eq!(pool.warm_up().unwrap(), 0);
+        assert_eq!(pool.idle_sessions(), 3);
+    }
+
+    #[test]
+    fn test_pool_warm_up_replaces_expired_sessions() {
+        let port = fake_clamd(|_| b"PONG".to_vec());
+        let pool = ClamPool::new(ClamClient::new("127.0.0.1", port).unwrap(), 4)
+            .min_idle(2)
+            .idle_timeout(Duration::ZERO);
+
+        assert_eq!(pool.warm_up().unwrap(), 2);
+        assert_eq!(pool.warm_up().unwrap(), 2);
+        assert_eq!(pool.idle_sessions(), 2);
+
+        let pool = ClamPool::new(ClamClient::new("127.0.0.1", 1).unwrap(), 2).min_idle(2);
+        assert!(pool.warm_up().is_err());
+        assert_eq!(pool.idle_sessions(), 0);
+        assert!(pool.get().is_err());
+    }
+
+    #[test]
+    fn test_pool_tops_up_min_idle() {
+        let port = fake_clamd(|_| b"PONG".to_vec());
+        let pool = ClamPool::new(ClamClient::new("127.0.0.1", port).unwrap(), 4)
+            .min_idle(2)
+            .idle_timeout(Duration::from_millis(200));
+
+        assert_eq!(pool.warm_up().unwrap(), 2);
+        std::thread::sleep(Duration::from_millis(300));
+
+        // Both idle sessions have expired, and are replaced alongside the one handed out
+        let session = pool.get().unwrap();
+        assert_eq!(pool.idle_sessions(), 2);
+        drop(session);
+        assert_eq!(pool.idle_sessions(), 3);
+    }
+}
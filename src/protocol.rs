@@ -0,0 +1,303 @@
+//! The framing of the data exchanged with ClamD. The stream sent with the `INSTREAM` command is
+//! a sequence of chunks, each prefixed with its length as a 4 byte unsigned integer in network
+//! byte order, and is terminated by a zero length chunk. ClamD's replies are terminated by the
+//! delimiter the command was sent with, NUL or newline.
+
+#[cfg(any(test, feature = "test-util"))]
+use std::io::Read;
+use std::io::{self, BufRead, IoSlice, Write};
+
+/// The length of the header preceding each chunk.
+pub(crate) const HEADER_LEN: usize = 4;
+
+/// The largest chunk which can be framed, since its length must fit in the header.
+pub(crate) const MAX_CHUNK_LEN: usize = u32::MAX as usize;
+
+/// The zero length chunk which terminates a stream.
+pub(crate) const END_OF_STREAM: [u8; HEADER_LEN] = [0; HEADER_LEN];
+
+/// `ReplyLength` is how much of ClamD's reply to a command must be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReplyLength {
+    /// A single reply, complete at the first terminator, as for `PING`
+    Single,
+    /// A reply spanning several lines which is complete once a line reads `END`, as for `STATS`
+    UntilEnd,
+    /// Any number of replies, complete once ClamD closes the connection, as for `CONTSCAN`
+    UntilClosed,
+}
+
+impl ReplyLength {
+    /// Returns how much of the reply to `command`, such as `VERSION` or `SCAN /some/file`, must
+    /// be read.
+    pub(crate) fn of(command: &str) -> ReplyLength {
+        match command {
+            "PING" | "VERSION" | "VERSIONCOMMANDS" | "RELOAD" => ReplyLength::Single,
+            "STATS" => ReplyLength::UntilEnd,
+            _ => ReplyLength::UntilClosed,
+        }
+    }
+}
+
+/// Reads a reply of `length` from `reader`, where each reply ends with `terminator`. Unless ClamD
+/// closes the connection after every reply, the reply is returned as soon as it is complete, so
+/// that the connection remains usable. The reply is returned with its terminator, or as much of
+/// it as was received if the connection is closed early.
+pub(crate) fn read_reply<R: BufRead + ?Sized>(
+    reader: &mut R,
+    terminator: u8,
+    length: ReplyLength,
+) -> io::Result<Vec<u8>> {
+    let mut reply = Vec::new();
+
+    if length == ReplyLength::UntilClosed {
+        reader.read_to_end(&mut reply)?;
+        return Ok(reply);
+    }
+
+    while reader.read_until(terminator, &mut reply)? > 0 {
+        if reply_complete(&reply, terminator, length) {
+            break;
+        }
+    }
+
+    Ok(reply)
+}
+
+/// Returns true if `reply`, as read so far up to a `terminator`, is a complete reply of `length`.
+/// This is shared by `read_reply` and the asynchronous clients, which read replies the same way.
+pub(crate) fn reply_complete(reply: &[u8], terminator: u8, length: ReplyLength) -> bool {
+    if reply.last() != Some(&terminator) {
+        return false;
+    }
+
+    match length {
+        ReplyLength::Single => true,
+        ReplyLength::UntilEnd => reply[..reply.len() - 1].ends_with(b"END"),
+        ReplyLength::UntilClosed => false,
+    }
+}
+
+/// Returns the name of the framed `command`, without its prefix, arguments or terminator, e.g.
+/// `SCAN` for `zSCAN /file\0`.
+pub(crate) fn command_name(command: &[u8]) -> String {
+    let command = command
+        .strip_prefix(b"z")
+        .or_else(|| command.strip_prefix(b"n"));
+    let command = command.unwrap_or_default();
+    let end = command
+        .iter()
+        .position(|b| matches!(b, b' ' | b'\0' | b'\n'))
+        .unwrap_or(command.len());
+    String::from_utf8_lossy(&command[..end]).into_owned()
+}
+
+/// Returns the header for a chunk of `len` bytes. A chunk must not be empty, since a zero length
+/// header terminates the stream, nor longer than `MAX_CHUNK_LEN`.
+pub(crate) fn chunk_header(len: usize) -> [u8; HEADER_LEN] {
+    assert!(
+        len > 0 && len <= MAX_CHUNK_LEN,
+        "invalid chunk length {}",
+        len
+    );
+    (len as u32).to_be_bytes()
+}
+
+/// Returns the length of the chunk following `header`, zero at the end of the stream.
+#[cfg(any(test, feature = "test-util"))]
+pub(crate) fn decode_header(header: [u8; HEADER_LEN]) -> usize {
+    u32::from_be_bytes(header) as usize
+}
+
+/// Writes `chunk` to `writer` preceded by its header, see `chunk_header`. The two are written
+/// with vectored writes, so that both are usually sent to the socket in a single call, and
+/// writing continues until everything has been written, however little each call accepts.
+pub(crate) fn write_chunk<W: Write + ?Sized>(writer: &mut W, chunk: &[u8]) -> io::Result<()> {
+    let header = chunk_header(chunk.len());
+    let mut slices = [IoSlice::new(&header), IoSlice::new(chunk)];
+    let mut slices = &mut slices[..];
+
+    while !slices.is_empty() {
+        match writer.write_vectored(slices) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(written) => IoSlice::advance_slices(&mut slices, written),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Frames `chunk`, returning its header followed by its contents.
+#[cfg(test)]
+pub(crate) fn encode_chunk(chunk: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(HEADER_LEN + chunk.len());
+    framed.extend(chunk_header(chunk.len()));
+    framed.extend(chunk);
+    framed
+}
+
+/// Reads the next chunk of a stream from `reader`, returning `None` once the terminating zero
+/// length chunk has been read.
+#[cfg(any(test, feature = "test-util"))]
+pub(crate) fn read_chunk<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+
+    match decode_header(header) {
+        0 => Ok(None),
+        len => {
+            let mut chunk = vec![0; len];
+            reader.read_exact(&mut chunk)?;
+            Ok(Some(chunk))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::write_stream;
+    use crate::observer::Observers;
+    use crate::protocol::{
+        chunk_header, encode_chunk, read_chunk, read_reply, write_chunk, ReplyLength, END_OF_STREAM,
+    };
+    use std::io::{self, Cursor, IoSlice, Write};
+
+    /// A writer which accepts at most `limit` bytes per call, as a socket under load may.
+    struct Trickle {
+        written: Vec<u8>,
+        limit: usize,
+        calls: usize,
+    }
+
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+            self.calls += 1;
+            let before = self.written.len();
+            for buf in bufs {
+                let remaining = self.limit - (self.written.len() - before);
+                self.written.extend(&buf[..buf.len().min(remaining)]);
+            }
+            Ok(self.written.len() - before)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Decodes every chunk of a framed stream, checking that nothing follows its terminator.
+    fn decode(framed: &[u8]) -> Vec<Vec<u8>> {
+        let mut reader = Cursor::new(framed);
+        let mut chunks = Vec::new();
+        while let Some(chunk) = read_chunk(&mut reader).unwrap() {
+            chunks.push(chunk);
+        }
+        assert_eq!(reader.position() as usize, framed.len());
+        chunks
+    }
+
+    #[test]
+    fn test_chunk_header() {
+        assert_eq!(chunk_header(1), [0, 0, 0, 1]);
+        assert_eq!(chunk_header(4096), [0, 0, 0x10, 0]);
+        assert_eq!(chunk_header(u32::MAX as usize), [0xff; 4]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_empty_chunk_header() {
+        chunk_header(0);
+    }
+
+    #[test]
+    fn test_chunk_roundtrip() {
+        let mut framed = encode_chunk(b"hello");
+        framed.extend(encode_chunk(&[7; 5000]));
+        framed.extend(END_OF_STREAM);
+
+        assert_eq!(&framed[..9], b"\0\0\0\x05hello");
+        assert_eq!(decode(&framed), vec![b"hello".to_vec(), vec![7; 5000]]);
+        assert!(read_chunk(&mut &framed[..6]).is_err());
+    }
+
+    #[test]
+    fn test_write_chunk() {
+        let chunk: Vec<u8> = (0..100).collect();
+
+        // Everything is coalesced into a single write when the writer accepts it
+        let mut writer = Trickle {
+            written: Vec::new(),
+            limit: usize::MAX,
+            calls: 0,
+        };
+        write_chunk(&mut writer, &chunk).unwrap();
+        assert_eq!(writer.calls, 1);
+        assert_eq!(writer.written, encode_chunk(&chunk));
+
+        // And continues after partial writes, including those splitting the header
+        let mut writer = Trickle {
+            written: Vec::new(),
+            limit: 3,
+            calls: 0,
+        };
+        write_chunk(&mut writer, &chunk).unwrap();
+        write_chunk(&mut writer, b"more").unwrap();
+        writer.written.extend(END_OF_STREAM);
+        assert_eq!(decode(&writer.written), vec![chunk, b"more".to_vec()]);
+    }
+
+    #[test]
+    fn test_read_reply() {
+        let mut replies = Cursor::new(&b"PONG\0POOLS: 1\n\nSTATE: VALID PRIMARY\nEND\nOK\nERR"[..]);
+
+        let read = |replies: &mut Cursor<&[u8]>, terminator, length| {
+            String::from_utf8(read_reply(replies, terminator, length).unwrap()).unwrap()
+        };
+        assert_eq!(read(&mut replies, b'\0', ReplyLength::Single), "PONG\0");
+        assert_eq!(
+            read(&mut replies, b'\n', ReplyLength::UntilEnd),
+            "POOLS: 1\n\nSTATE: VALID PRIMARY\nEND\n"
+        );
+        assert_eq!(read(&mut replies, b'\n', ReplyLength::Single), "OK\n");
+        // A reply cut short by the connection closing is returned as is
+        assert_eq!(read(&mut replies, b'\n', ReplyLength::Single), "ERR");
+        assert_eq!(read(&mut replies, b'\n', ReplyLength::Single), "");
+
+        let mut replies = Cursor::new(&b"/a: OK\0/b: OK\0"[..]);
+        assert_eq!(
+            read(&mut replies, b'\0', ReplyLength::UntilClosed),
+            "/a: OK\0/b: OK\0"
+        );
+        assert_eq!(ReplyLength::of("STATS"), ReplyLength::UntilEnd);
+        assert_eq!(ReplyLength::of("CONTSCAN /a"), ReplyLength::UntilClosed);
+    }
+
+    #[test]
+    fn test_write_stream_framing() {
+        // A short final read must be framed with its own length, not the size of the buffer
+        let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+
+        let mut framed = Vec::new();
+        write_stream(
+            &mut framed,
+            &data[..],
+            None,
+            &Observers::default(),
+            &mut |_| {},
+        )
+        .unwrap();
+
+        let chunks = decode(&framed);
+        assert_eq!(
+            chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+            [8192, 1808]
+        );
+        assert_eq!(chunks.concat(), data);
+    }
+}
@@ -0,0 +1,384 @@
+//! `Proxy` allows `ClamClient` to reach ClamD through an egress proxy, the TCP connection is
+//! tunnelled through the proxy before any ClamD commands (or the TLS handshake) are issued.
+
+use crate::client::ClamResult;
+use crate::error::ClamError;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+
+/// `Proxy` describes the proxy to tunnel connections through, it is supplied to
+/// `ClamClientBuilder::proxy`.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::proxy::Proxy;
+///
+/// fn main() {
+///     let client = ClamClient::builder("10.0.0.10", 3310)
+///         .proxy(Proxy::socks5("127.0.0.1:1080".parse().unwrap()))
+///         .build()
+///         .unwrap();
+///
+///     println!("{:?}", client.ping());
+/// }
+/// ```
+#[derive(Clone, PartialEq)]
+pub enum Proxy {
+    /// A SOCKS5 proxy, with optional username/password authentication.
+    Socks5 {
+        /// The address of the proxy
+        addr: SocketAddr,
+        /// The username and password to authenticate with, if required
+        credentials: Option<(String, String)>,
+    },
+    /// An HTTP proxy supporting the `CONNECT` method, with optional basic authentication.
+    HttpConnect {
+        /// The address of the proxy
+        addr: SocketAddr,
+        /// The username and password to authenticate with, if required
+        credentials: Option<(String, String)>,
+    },
+}
+
+impl Proxy {
+    /// Creates an unauthenticated SOCKS5 proxy.
+    pub fn socks5(addr: SocketAddr) -> Proxy {
+        Proxy::Socks5 {
+            addr,
+            credentials: None,
+        }
+    }
+
+    /// Creates an unauthenticated HTTP `CONNECT` proxy.
+    pub fn http_connect(addr: SocketAddr) -> Proxy {
+        Proxy::HttpConnect {
+            addr,
+            credentials: None,
+        }
+    }
+
+    /// Sets the username and password used to authenticate with the proxy.
+    pub fn with_credentials(self, username: &str, password: &str) -> Proxy {
+        let credentials = Some((username.to_owned(), password.to_owned()));
+
+        match self {
+            Proxy::Socks5 { addr, .. } => Proxy::Socks5 { addr, credentials },
+            Proxy::HttpConnect { addr, .. } => Proxy::HttpConnect { addr, credentials },
+        }
+    }
+
+    /// Returns the address of the proxy itself.
+    pub fn addr(&self) -> SocketAddr {
+        match self {
+            Proxy::Socks5 { addr, .. } | Proxy::HttpConnect { addr, .. } => *addr,
+        }
+    }
+
+    /// Asks the proxy, over an established connection to it, to open a tunnel to `target`.
+    pub(crate) fn tunnel(&self, stream: &mut TcpStream, target: SocketAddr) -> ClamResult<()> {
+        match self {
+            Proxy::Socks5 { credentials, .. } => socks5_tunnel(stream, target, credentials),
+            Proxy::HttpConnect { credentials, .. } => http_tunnel(stream, target, credentials),
+        }
+    }
+}
+
+impl fmt::Debug for Proxy {
+    /// Formats the proxy with its password masked, so that it is not leaked into logs.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, addr, credentials) = match self {
+            Proxy::Socks5 { addr, credentials } => ("Socks5", addr, credentials),
+            Proxy::HttpConnect { addr, credentials } => ("HttpConnect", addr, credentials),
+        };
+        let credentials = credentials
+            .as_ref()
+            .map(|(username, _)| (username.as_str(), "***"));
+
+        f.debug_struct(name)
+            .field("addr", addr)
+            .field("credentials", &credentials)
+            .finish()
+    }
+}
+
+/// Performs the SOCKS5 handshake (RFC 1928), authenticating with RFC 1929 if credentials are
+/// supplied, and requests a connection to `target`.
+fn socks5_tunnel(
+    stream: &mut TcpStream,
+    target: SocketAddr,
+    credentials: &Option<(String, String)>,
+) -> ClamResult<()> {
+    let greeting: &[u8] = match credentials {
+        Some(_) => &[5, 2, 0, 2],
+        None => &[5, 1, 0],
+    };
+    stream
+        .write_all(greeting)
+        .map_err(ClamError::ConnectionError)?;
+
+    let mut choice = [0; 2];
+    stream
+        .read_exact(&mut choice)
+        .map_err(ClamError::ConnectionError)?;
+
+    match (choice, credentials) {
+        ([5, 0], _) => {}
+        ([5, 2], Some((username, password))) => {
+            if username.len() > 255 || password.len() > 255 {
+                return Err(ClamError::ProxyError(
+                    "SOCKS5 credentials must not exceed 255 bytes".to_owned(),
+                ));
+            }
+
+            let mut auth = vec![1, username.len() as u8];
+            auth.extend(username.as_bytes());
+            auth.push(password.len() as u8);
+            auth.extend(password.as_bytes());
+            stream
+                .write_all(&auth)
+                .map_err(ClamError::ConnectionError)?;
+
+            let mut status = [0; 2];
+            stream
+                .read_exact(&mut status)
+                .map_err(ClamError::ConnectionError)?;
+            if status[1] != 0 {
+                return Err(ClamError::ProxyError(
+                    "SOCKS5 authentication failed".to_owned(),
+                ));
+            }
+        }
+        _ => {
+            return Err(ClamError::ProxyError(
+                "SOCKS5 proxy did not accept any offered authentication method".to_owned(),
+            ))
+        }
+    }
+
+    let mut request = vec![5, 1, 0];
+    match target.ip() {
+        IpAddr::V4(ip) => {
+            request.push(1);
+            request.extend(ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            request.push(4);
+            request.extend(ip.octets());
+        }
+    }
+    request.extend(target.port().to_be_bytes());
+    stream
+        .write_all(&request)
+        .map_err(ClamError::ConnectionError)?;
+
+    let mut reply = [0; 4];
+    stream
+        .read_exact(&mut reply)
+        .map_err(ClamError::ConnectionError)?;
+    if reply[1] != 0 {
+        return Err(ClamError::ProxyError(format!(
+            "SOCKS5 proxy refused the connection with reply code {}",
+            reply[1]
+        )));
+    }
+
+    // Discard the bound address, whose length depends on its type
+    let bound_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut len = [0; 1];
+            stream
+                .read_exact(&mut len)
+                .map_err(ClamError::ConnectionError)?;
+            len[0] as usize
+        }
+        other => {
+            return Err(ClamError::ProxyError(format!(
+                "SOCKS5 proxy replied with unknown address type {}",
+                other
+            )))
+        }
+    };
+    let mut bound = vec![0; bound_len + 2];
+    stream
+        .read_exact(&mut bound)
+        .map_err(ClamError::ConnectionError)
+}
+
+/// Issues an HTTP `CONNECT` request for `target`, succeeding if the proxy responds with a 2xx
+/// status.
+fn http_tunnel(
+    stream: &mut TcpStream,
+    target: SocketAddr,
+    credentials: &Option<(String, String)>,
+) -> ClamResult<()> {
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some((username, password)) = credentials {
+        let token = base64_encode(format!("{}:{}", username, password).as_bytes());
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream
+        .write_all(request.as_bytes())
+        .map_err(ClamError::ConnectionError)?;
+
+    // Read the response a byte at a time so nothing beyond the headers is consumed
+    let mut response = Vec::new();
+    let mut byte = [0; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            return Err(ClamError::ProxyError(
+                "HTTP proxy response headers too large".to_owned(),
+            ));
+        }
+        stream
+            .read_exact(&mut byte)
+            .map_err(ClamError::ConnectionError)?;
+        response.push(byte[0]);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(ClamError::ProxyError(format!(
+            "HTTP proxy refused the connection: {}",
+            status_line
+        ))),
+    }
+}
+
+/// Standard base64 encoding, as used by HTTP basic authentication.
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proxy::{base64_encode, Proxy};
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread;
+
+    /// Starts a one-shot proxy which, for each exchange, checks the request it receives and
+    /// sends the reply.
+    fn fake_proxy(exchanges: Vec<(Vec<u8>, Vec<u8>)>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for (expected, reply) in exchanges {
+                let mut received = vec![0; expected.len()];
+                stream.read_exact(&mut received).unwrap();
+                assert_eq!(received, expected);
+                stream.write_all(&reply).unwrap();
+            }
+        });
+
+        addr
+    }
+
+    #[test]
+    fn test_proxy_debug_masks_password() {
+        let proxy =
+            Proxy::socks5("127.0.0.1:1080".parse().unwrap()).with_credentials("user", "hunter2");
+        let debug = format!("{:?}", proxy);
+
+        assert!(!debug.contains("hunter2"), "{}", debug);
+        assert_eq!(
+            debug,
+            r#"Socks5 { addr: 127.0.0.1:1080, credentials: Some(("user", "***")) }"#
+        );
+    }
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b"user:pass"), "dXNlcjpwYXNz");
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+        assert_eq!(base64_encode(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn test_socks5_tunnel() {
+        let target: SocketAddr = "10.1.2.3:3310".parse().unwrap();
+        let mut request = vec![5, 1, 0, 1, 10, 1, 2, 3];
+        request.extend(3310u16.to_be_bytes());
+        let addr = fake_proxy(vec![
+            (vec![5, 1, 0], vec![5, 0]),
+            (request, vec![5, 0, 0, 1, 0, 0, 0, 0, 0, 0]),
+        ]);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert!(Proxy::socks5(addr).tunnel(&mut stream, target).is_ok());
+    }
+
+    #[test]
+    fn test_socks5_tunnel_refused() {
+        let target: SocketAddr = "10.1.2.3:3310".parse().unwrap();
+        let mut request = vec![5, 1, 0, 1, 10, 1, 2, 3];
+        request.extend(3310u16.to_be_bytes());
+        let addr = fake_proxy(vec![
+            (vec![5, 1, 0], vec![5, 0]),
+            (request, vec![5, 5, 0, 1, 0, 0, 0, 0, 0, 0]),
+        ]);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert!(Proxy::socks5(addr).tunnel(&mut stream, target).is_err());
+    }
+
+    #[test]
+    fn test_http_tunnel() {
+        let target: SocketAddr = "10.1.2.3:3310".parse().unwrap();
+        let expected = b"CONNECT 10.1.2.3:3310 HTTP/1.1\r\nHost: 10.1.2.3:3310\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n";
+        let addr = fake_proxy(vec![(
+            expected.to_vec(),
+            b"HTTP/1.1 200 Connection established\r\n\r\n".to_vec(),
+        )]);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let proxy = Proxy::http_connect(addr).with_credentials("user", "pass");
+        assert!(proxy.tunnel(&mut stream, target).is_ok());
+    }
+
+    #[test]
+    fn test_http_tunnel_refused() {
+        let target: SocketAddr = "10.1.2.3:3310".parse().unwrap();
+        let expected = b"CONNECT 10.1.2.3:3310 HTTP/1.1\r\nHost: 10.1.2.3:3310\r\n\r\n";
+        let addr = fake_proxy(vec![(
+            expected.to_vec(),
+            b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n".to_vec(),
+        )]);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert!(Proxy::http_connect(addr)
+            .tunnel(&mut stream, target)
+            .is_err());
+    }
+}
@@ -0,0 +1,338 @@
+//! `Quarantine` moves local files which ClamD reports as infected into a quarantine directory,
+//! enabled by the `quarantine` feature. Each file is moved atomically under a name which cannot
+//! collide with anything already quarantined, optionally has its permissions stripped, and is
+//! recorded, along with the signature that matched, in the directory's manifest.
+
+use crate::client::ClamResult;
+use crate::error::ClamError;
+use crate::response::ClamScanResult;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The name of the manifest within the quarantine directory.
+pub const MANIFEST: &str = "MANIFEST";
+
+/// `Quarantine` is a directory into which infected files are moved.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::quarantine::Quarantine;
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///     let quarantine = Quarantine::new("/var/lib/quarantine").strip_permissions(true);
+///
+///     if let Ok(results) = client.scan_path("/srv/uploads", true) {
+///         for result in &results {
+///             if let Err(e) = quarantine.quarantine_result(result) {
+///                 println!("Failed to quarantine: {}", e);
+///             }
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Quarantine {
+    dir: PathBuf,
+    strip_permissions: bool,
+}
+
+/// `QuarantinedFile` describes a file moved into quarantine, as recorded in the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuarantinedFile {
+    /// Where the file was found
+    pub original: PathBuf,
+    /// Where the file now is, within the quarantine directory
+    pub quarantined: PathBuf,
+    /// The signature ClamD matched against the file
+    pub signature: String,
+    /// When the file was quarantined
+    pub quarantined_at: SystemTime,
+}
+
+impl Quarantine {
+    /// Creates a new `Quarantine` which moves files into `dir`, the directory is created when the
+    /// first file is quarantined if it does not already exist.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Quarantine {
+        Quarantine {
+            dir: dir.as_ref().to_path_buf(),
+            strip_permissions: false,
+        }
+    }
+
+    /// When enabled, quarantined files are made read only by their owner, so that they can no
+    /// longer be executed or modified. Disabled by default.
+    pub fn strip_permissions(mut self, strip: bool) -> Self {
+        self.strip_permissions = strip;
+        self
+    }
+
+    /// Returns the quarantine directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Quarantines the file reported by a `SCAN` or `CONTSCAN` result, returning `None` unless the
+    /// result is `ClamScanResult::Found`. The path reported by ClamD must be valid locally.
+    pub fn quarantine_result(
+        &self,
+        result: &ClamScanResult,
+    ) -> ClamResult<Option<QuarantinedFile>> {
+        match result {
+            ClamScanResult::Found(path, signature) => self.quarantine(path, signature).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Moves the file at `path`, which matched `signature`, into quarantine. This is typically
+    /// used for files scanned with `INSTREAM`, where ClamD does not know the path.
+    pub fn quarantine<P: AsRef<Path>>(
+        &self,
+        path: P,
+        signature: &str,
+    ) -> ClamResult<QuarantinedFile> {
+        let original = path.as_ref();
+        let error = |e: io::Error| ClamError::QuarantineError(original.display().to_string(), e);
+
+        fs::create_dir_all(&self.dir).map_err(error)?;
+        let quarantined = self.reserve(original).map_err(error)?;
+
+        if let Err(e) = move_file(original, &quarantined) {
+            let _ = fs::remove_file(&quarantined);
+            return Err(error(e));
+        }
+
+        if self.strip_permissions {
+            strip_permissions(&quarantined).map_err(error)?;
+        }
+
+        let file = QuarantinedFile {
+            original: original.to_path_buf(),
+            quarantined,
+            signature: signature.to_owned(),
+            quarantined_at: SystemTime::now(),
+        };
+        self.record(&file).map_err(error)?;
+
+        Ok(file)
+    }
+
+    /// Reserves a name in the quarantine directory for `original` by creating an empty file,
+    /// which is then replaced by the quarantined file. Creating the file fails if it already
+    /// exists, so concurrent quarantines can never overwrite each other.
+    fn reserve(&self, original: &Path) -> io::Result<PathBuf> {
+        let name = original
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "unnamed".to_owned());
+
+        for n in 0u32.. {
+            let candidate = match n {
+                0 => self.dir.join(&name),
+                n => self.dir.join(format!("{}.{}", name, n)),
+            };
+
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&candidate)
+            {
+                Ok(_) => return Ok(candidate),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        unreachable!("ran out of quarantine names")
+    }
+
+    /// Appends `file` to the manifest, as a tab separated line of the time it was quarantined in
+    /// seconds since the Unix epoch, the original path, the quarantined path and the signature.
+    /// Each field is escaped with `escape_field`, since paths may contain tabs or newlines.
+    fn record(&self, file: &QuarantinedFile) -> io::Result<()> {
+        let at = file
+            .quarantined_at
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let line = format!(
+            "{}\t{}\t{}\t{}\n",
+            at,
+            escape_field(&file.original.display().to_string()),
+            escape_field(&file.quarantined.display().to_string()),
+            escape_field(&file.signature)
+        );
+
+        // Each line is written at once, so that concurrent quarantines do not interleave
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(MANIFEST))?
+            .write_all(line.as_bytes())
+    }
+}
+
+/// Escapes a manifest field, so that it cannot break the manifest's tab separated lines. A
+/// backslash, tab, carriage return or newline is written as `\\`, `\t`, `\r` or `\n`.
+fn escape_field(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Moves `from` to `to` atomically, falling back to copying the file into place when they are on
+/// different filesystems, in which case the copy is renamed into place once complete.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            let mut partial = to.as_os_str().to_owned();
+            partial.push(".partial");
+            let partial = PathBuf::from(partial);
+
+            let mut source = File::open(from)?;
+            let copied = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&partial)
+                .and_then(|mut dest| {
+                    io::copy(&mut source, &mut dest)?;
+                    dest.sync_all()
+                })
+                .and_then(|_| fs::rename(&partial, to));
+
+            match copied {
+                Ok(()) => fs::remove_file(from),
+                Err(e) => {
+                    let _ = fs::remove_file(&partial);
+                    Err(e)
+                }
+            }
+        }
+        result => result,
+    }
+}
+
+/// Makes the file at `path` readable only by its owner.
+#[cfg(unix)]
+fn strip_permissions(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o400))
+}
+
+/// Makes the file at `path` read only.
+#[cfg(not(unix))]
+fn strip_permissions(path: &Path) -> io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::quarantine::{Quarantine, MANIFEST};
+    use crate::response::ClamScanResult;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("clam-quarantine-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_quarantine_moves_files() {
+        let dir = temp_dir("moves");
+        let infected = dir.join("infected.exe");
+        fs::write(&infected, b"X5O!P%@AP").unwrap();
+
+        let quarantine = Quarantine::new(dir.join("quarantine")).strip_permissions(true);
+        let found = ClamScanResult::Found(
+            infected.display().to_string(),
+            "Eicar-Test-Signature".to_owned(),
+        );
+        let file = quarantine.quarantine_result(&found).unwrap().unwrap();
+
+        assert!(!infected.exists());
+        assert_eq!(
+            file.quarantined,
+            dir.join("quarantine").join("infected.exe")
+        );
+        assert_eq!(fs::read(&file.quarantined).unwrap(), b"X5O!P%@AP");
+        assert!(fs::metadata(&file.quarantined)
+            .unwrap()
+            .permissions()
+            .readonly());
+
+        let manifest = fs::read_to_string(dir.join("quarantine").join(MANIFEST)).unwrap();
+        assert!(manifest.ends_with(&format!(
+            "\t{}\t{}\tEicar-Test-Signature\n",
+            infected.display(),
+            file.quarantined.display()
+        )));
+
+        assert_eq!(
+            quarantine.quarantine_result(&ClamScanResult::Ok).unwrap(),
+            None
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_quarantine_manifest_escapes_fields() {
+        let dir = temp_dir("escapes");
+        let infected = dir.join("infected");
+        fs::write(&infected, b"X5O!P%@AP").unwrap();
+
+        let quarantine = Quarantine::new(dir.join("quarantine"));
+        quarantine
+            .quarantine(&infected, "Sig\twith\nbreaks\\")
+            .unwrap();
+
+        let manifest = fs::read_to_string(dir.join("quarantine").join(MANIFEST)).unwrap();
+        assert_eq!(manifest.lines().count(), 1);
+        assert!(manifest.ends_with("\tSig\\twith\\nbreaks\\\\\n"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_quarantine_avoids_collisions() {
+        let dir = temp_dir("collisions");
+        let quarantine = Quarantine::new(dir.join("quarantine"));
+
+        let mut quarantined = Vec::new();
+        for i in 0..3 {
+            let infected = dir.join(format!("{}", i)).join("upload");
+            fs::create_dir_all(infected.parent().unwrap()).unwrap();
+            fs::write(&infected, [i]).unwrap();
+            quarantined.push(quarantine.quarantine(&infected, "Sig").unwrap().quarantined);
+        }
+
+        let names: Vec<_> = quarantined
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["upload", "upload.1", "upload.2"]);
+        assert_eq!(fs::read(&quarantined[2]).unwrap(), [2]);
+
+        assert!(quarantine.quarantine(dir.join("missing"), "Sig").is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
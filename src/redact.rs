@@ -0,0 +1,218 @@
+//! `Redaction` keeps file paths and fragments of ClamD's replies out of errors, for services
+//! whose error reports leave the organisation, such as when exception text is shipped to a
+//! third party. It is supplied to `ClamClientBuilder::redaction`, or applied to a value directly
+//! with `Redact::redact`.
+//!
+//! Redaction applies to the text `ClamError` carries, such as the reply in
+//! `ClamError::InvalidData`, and to the path and message of every `ClamScanError`. The paths of
+//! detections reported as `ClamScanResult::Found` are left intact, since they are needed to act
+//! on the detection, as are the lines returned verbatim by `ClamClient::scan_path_lines`.
+
+use crate::error::ClamError;
+use crate::response::{ClamScanError, ClamScanResult};
+
+/// `Redaction` is how paths and reply fragments are embedded in errors.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::redact::Redaction;
+///
+/// fn main() {
+///     let client = ClamClient::builder("127.0.0.1", 3310)
+///         .redaction(Redaction::Hash)
+///         .build()
+///         .unwrap();
+///
+///     // Safe to report, the path is not included in the error's text
+///     if let Err(e) = client.scan_path("/home/jbloggs/passport.pdf", false) {
+///         println!("{}", e);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Redaction {
+    /// Paths and replies are included verbatim
+    #[default]
+    Off,
+    /// Paths and replies are replaced with `[redacted]`
+    Redact,
+    /// Paths and replies are replaced with a fingerprint, such as `[redacted:af63dc4c8601ec8c]`,
+    /// so that reports of the same path can still be correlated. The fingerprint is the 64-bit
+    /// FNV-1a hash of the text, which is stable between builds but is not a cryptographic hash.
+    Hash,
+}
+
+impl Redaction {
+    /// Returns `text` as it should be embedded in an error.
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Redaction::Off => text.to_owned(),
+            Redaction::Redact => "[redacted]".to_owned(),
+            Redaction::Hash => format!("[redacted:{:016x}]", fnv1a(text.as_bytes())),
+        }
+    }
+}
+
+/// Returns the 64-bit FNV-1a hash of `data`.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// `Redact` is implemented by the values which may embed paths or reply fragments, replacing
+/// them according to a `Redaction`.
+pub trait Redact {
+    /// Returns the value with its paths and reply fragments replaced according to `redaction`.
+    fn redact(self, redaction: Redaction) -> Self;
+}
+
+impl Redact for ClamError {
+    fn redact(self, redaction: Redaction) -> ClamError {
+        if redaction == Redaction::Off {
+            return self;
+        }
+
+        match self {
+            ClamError::InvalidData(reply) => ClamError::InvalidData(redaction.apply(&reply)),
+            ClamError::ProtocolError { reply } => ClamError::ProtocolError {
+                reply: redaction.apply(&reply),
+            },
+            ClamError::StreamSizeLimitExceeded(reply) => {
+                ClamError::StreamSizeLimitExceeded(redaction.apply(&reply))
+            }
+            #[cfg(feature = "quarantine")]
+            ClamError::QuarantineError(path, e) => {
+                ClamError::QuarantineError(redaction.apply(&path), e)
+            }
+            #[cfg(feature = "zip")]
+            ClamError::ArchiveError(message) => ClamError::ArchiveError(redaction.apply(&message)),
+            e => e,
+        }
+    }
+}
+
+impl Redact for ClamScanError {
+    fn redact(self, redaction: Redaction) -> ClamScanError {
+        if redaction == Redaction::Off {
+            return self;
+        }
+
+        let path = |path: Option<String>| path.map(|path| redaction.apply(&path));
+        match self {
+            ClamScanError::LstatFailed(p) => ClamScanError::LstatFailed(path(p)),
+            ClamScanError::AccessDenied(p) => ClamScanError::AccessDenied(path(p)),
+            ClamScanError::SizeLimitExceeded(p) => ClamScanError::SizeLimitExceeded(path(p)),
+            ClamScanError::CannotOpen(p) => ClamScanError::CannotOpen(path(p)),
+            ClamScanError::Other(raw) => ClamScanError::Other(redaction.apply(&raw)),
+        }
+    }
+}
+
+impl Redact for ClamScanResult {
+    fn redact(self, redaction: Redaction) -> ClamScanResult {
+        match self {
+            ClamScanResult::Error(e) => ClamScanResult::Error(e.redact(redaction)),
+            result => result,
+        }
+    }
+}
+
+impl<T: Redact> Redact for Vec<T> {
+    fn redact(self, redaction: Redaction) -> Vec<T> {
+        if redaction == Redaction::Off {
+            return self;
+        }
+        self.into_iter()
+            .map(|value| value.redact(redaction))
+            .collect()
+    }
+}
+
+impl<T: Redact> Redact for Result<T, ClamError> {
+    fn redact(self, redaction: Redaction) -> Result<T, ClamError> {
+        match self {
+            Ok(value) => Ok(value.redact(redaction)),
+            Err(e) => Err(e.redact(redaction)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::error::ClamError;
+    use crate::redact::{Redact, Redaction};
+    use crate::response::{ClamScanError, ClamScanResult};
+    use crate::testing::fake_clamd;
+
+    #[test]
+    fn test_redaction() {
+        assert_eq!(Redaction::Off.apply("/home/a"), "/home/a");
+        assert_eq!(Redaction::Redact.apply("/home/a"), "[redacted]");
+        assert_eq!(Redaction::Hash.apply(""), "[redacted:cbf29ce484222325]");
+        assert_eq!(Redaction::Hash.apply("a"), "[redacted:af63dc4c8601ec8c]");
+
+        let e = ClamScanError::LstatFailed(Some("/home/a".to_owned())).redact(Redaction::Redact);
+        assert_eq!(e.to_string(), "[redacted]: lstat() failed");
+
+        let e = ClamError::InvalidData("/home/a: garbage".to_owned()).redact(Redaction::Redact);
+        assert_eq!(e.to_string(), "Could not parse: [redacted]");
+
+        // Detections are left intact
+        let found = ClamScanResult::Found("/home/a".to_owned(), "Sig-One".to_owned());
+        assert_eq!(found.clone().redact(Redaction::Hash), found);
+    }
+
+    #[test]
+    fn test_client_redaction() {
+        let port = fake_clamd(|cmd| {
+            match cmd {
+            b"zVERSION" => b"/home/jbloggs/passport.pdf".to_vec(),
+            b"zCONTSCAN /home/jbloggs" => b"/home/jbloggs/passport.pdf: Access denied. ERROR\0/home/jbloggs/virus: Sig-One FOUND".to_vec(),
+            _ => b"stream: /home/jbloggs/passport.pdf FAILED".to_vec(),
+        }
+        });
+        let client = ClamClient::builder("127.0.0.1", port)
+            .redaction(Redaction::Redact)
+            .strict_parsing(true)
+            .build()
+            .unwrap();
+
+        for e in [
+            client.version().unwrap_err(),
+            client.scan_bytes(b"data").unwrap_err(),
+        ] {
+            assert!(!e.to_string().contains("jbloggs"), "{}", e);
+        }
+
+        let results = client.scan_path("/home/jbloggs", true).unwrap();
+        assert_eq!(
+            results,
+            [
+                ClamScanResult::Error(ClamScanError::AccessDenied(Some("[redacted]".to_owned()))),
+                ClamScanResult::Found("/home/jbloggs/virus".to_owned(), "Sig-One".to_owned()),
+            ]
+        );
+
+        let results: Vec<_> = client
+            .scan_path_iter("/home/jbloggs", true)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(results[0].to_string(), "[redacted]: Access denied ERROR");
+
+        let port = fake_clamd(|_| b"/home/jbloggs/passport.pdf: garbage".to_vec());
+        let client = ClamClient::builder("127.0.0.1", port)
+            .redaction(Redaction::Redact)
+            .strict_parsing(true)
+            .build()
+            .unwrap();
+        let e = client.scan_path_lines("/home/jbloggs", true).unwrap_err();
+        assert!(!e.to_string().contains("jbloggs"), "{}", e);
+    }
+}
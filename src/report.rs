@@ -0,0 +1,166 @@
+//! `ScanReport` summarises the results of a scan, such as those returned by
+//! `ClamClient::scan_path`, so that callers do not each have to tally the results themselves.
+//! `ScanOutcome` records how a single stream scan was performed, alongside its result.
+
+use crate::response::{ClamDetections, ClamScanError, ClamScanResult};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// `ScanReport` aggregates the results of a single scan along with how long it took, it is
+/// obtained via `ClamClient::scan_path_report`, or built from any `Vec<ClamScanResult>`.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///
+///     if let Ok(report) = client.scan_path_report("/tmp/", true) {
+///         if !report.is_clean() {
+///             for detection in report.detections() {
+///                 println!("{}: {:?}", detection.path, detection.signatures);
+///             }
+///         }
+///     }
+/// }
+/// ```
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScanReport {
+    /// Every result returned by the scan, in the order ClamD reported them
+    pub results: Vec<ClamScanResult>,
+    /// How long the scan took, zero if the report was built from results alone
+    pub duration: Duration,
+}
+
+impl ScanReport {
+    /// Creates a `ScanReport` from the results of a scan which took `duration`.
+    pub fn new(results: Vec<ClamScanResult>, duration: Duration) -> ScanReport {
+        ScanReport { results, duration }
+    }
+
+    /// Returns the number of `Ok` results.
+    pub fn clean_count(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|r| matches!(r, ClamScanResult::Ok))
+            .count()
+    }
+
+    /// Returns the number of distinct paths with at least one detection.
+    pub fn infected_count(&self) -> usize {
+        self.detections().len()
+    }
+
+    /// Returns the number of `Error` results.
+    pub fn error_count(&self) -> usize {
+        self.errors().len()
+    }
+
+    /// Returns the detections grouped by path, see `ClamScanResult::group_detections`.
+    pub fn detections(&self) -> Vec<ClamDetections> {
+        ClamScanResult::group_detections(&self.results)
+    }
+
+    /// Returns every error ClamD reported.
+    pub fn errors(&self) -> Vec<&ClamScanError> {
+        self.results
+            .iter()
+            .filter_map(|r| match r {
+                ClamScanResult::Error(e) => Some(e),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns true if nothing was found and no errors occurred.
+    pub fn is_clean(&self) -> bool {
+        self.results.iter().all(|r| matches!(r, ClamScanResult::Ok))
+    }
+
+    /// Returns true if at least one signature was found.
+    pub fn is_infected(&self) -> bool {
+        self.results
+            .iter()
+            .any(|r| matches!(r, ClamScanResult::Found(..)))
+    }
+}
+
+impl From<Vec<ClamScanResult>> for ScanReport {
+    fn from(results: Vec<ClamScanResult>) -> ScanReport {
+        ScanReport::new(results, Duration::default())
+    }
+}
+
+/// `ScanOutcome` is the result of an `INSTREAM` along with how long it took, how much was
+/// streamed and where it was sent, it is obtained via `ClamClient::scan_stream_outcome`.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use std::fs::File;
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///     let file = File::open("/etc/hosts").unwrap();
+///
+///     if let Ok(outcome) = client.scan_stream_outcome(file) {
+///         println!(
+///             "{} after streaming {} bytes to {:?} in {:?}",
+///             outcome.result, outcome.bytes_streamed, outcome.endpoint, outcome.duration
+///         );
+///     }
+/// }
+/// ```
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanOutcome {
+    /// The verdict reported by ClamD
+    pub result: ClamScanResult,
+    /// How long the scan took, from connecting to ClamD until its verdict was read
+    pub duration: Duration,
+    /// The number of bytes streamed to ClamD
+    pub bytes_streamed: u64,
+    /// The ClamD endpoint which performed the scan, `None` when connected over a Unix socket
+    pub endpoint: Option<SocketAddr>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::ScanReport;
+    use crate::response::{ClamScanError, ClamScanResult};
+
+    #[test]
+    fn test_report_counts() {
+        let report = ScanReport::from(ClamScanResult::parse(
+            "/a: Sig-1 FOUND\0/a: Sig-2 FOUND\0/b: Access denied. ERROR\0/c: OK\0",
+        ));
+
+        assert_eq!(report.clean_count(), 1);
+        assert_eq!(report.infected_count(), 1);
+        assert_eq!(report.error_count(), 1);
+        assert_eq!(report.detections()[0].signatures, vec!["Sig-1", "Sig-2"]);
+        assert_eq!(
+            report.errors(),
+            vec![&ClamScanError::AccessDenied(Some("/b".to_owned()))]
+        );
+        assert!(report.is_infected());
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn test_report_clean() {
+        let report = ScanReport::from(vec![ClamScanResult::Ok]);
+
+        assert!(report.is_clean());
+        assert!(!report.is_infected());
+        assert_eq!(report.infected_count(), 0);
+    }
+}
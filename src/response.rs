@@ -1,17 +1,29 @@
 //! The `response` module is responsible for parsing the responses issued to use
-//! by ClamAV. To do so, it relies on two external crates, namely, `nom` and `chrono`.
+//! by ClamAV. To do so, it relies on two external crates, namely, `nom` and, with the default
+//! `chrono` feature, `chrono`.
 //!
 //! All structs and enums derive `Debug` for ease of client send debugging and development.
 
 use crate::client::ClamResult;
 use crate::error::ClamError;
-use chrono::{DateTime, TimeZone, Utc};
+#[cfg(feature = "chrono")]
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 use std::str::FromStr;
+use std::time::Duration;
 
 /// `ClamStats` provides all of the metrics that Clam provides via the `STATS` command
 /// as at version 0.100.
+///
+/// Times are `Duration`s, so they cannot be mistaken for values in other units, with the whole
+/// seconds ClamD reported available through `Duration::as_secs`.
+///
+/// The memory statistics are `None` when ClamD does not report them, since ClamD built without
+/// `mallinfo`, or with an allocator such as jemalloc, omits the `MEMSTATS` line or reports `N/A`
+/// in place of each value.
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, Default, PartialEq, PartialOrd)]
 pub struct ClamStats {
     /// The number of `pools` available to ClamAV
     pub pools: u64,
@@ -23,35 +35,275 @@ pub struct ClamStats {
     pub threads_idle: u64,
     /// The maximum number of threads the Clam Daemon can spawn
     pub threads_max: u64,
-    /// The timeout (seconds) before a thread is determined to be idle
-    pub threads_idle_timeout_secs: u64,
+    /// The time after which an idle thread is stopped
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "threads_idle_timeout_secs", with = "duration_secs")
+    )]
+    pub threads_idle_timeout: Duration,
     /// The number of items in the queue awaiting processing
     pub queue: u64,
     /// Total memory allocated to the heap
-    pub mem_heap: String,
+    pub mem_heap: Option<MemSize>,
     /// Amount of mmap'd memory used
-    pub mem_mmap: String,
+    pub mem_mmap: Option<MemSize>,
     /// Total memory used by the daemon
-    pub mem_used: String,
+    pub mem_used: Option<MemSize>,
     /// Total memory available to the daemon not in use
-    pub mem_free: String,
-    /// Total memory re
-    pub mem_releasable: String,
+    pub mem_free: Option<MemSize>,
+    /// Total memory releasable back to the operating system
+    pub mem_releasable: Option<MemSize>,
     /// Total number of pools in use by the daemon
-    pub pools_used: String,
+    pub pools_used: Option<MemSize>,
     /// Total number of pools available to the daemon
-    pub pools_total: String,
+    pub pools_total: Option<MemSize>,
+    /// Any fields not recognised by `ClamStats::parse_lenient`, keyed by the section they appear
+    /// in, e.g. `THREADS.busy` for a `busy` entry on the `THREADS` line. This is always empty when
+    /// parsed with `ClamStats::parse`.
+    pub extra: BTreeMap<String, String>,
+}
+
+/// `MemSize` is a quantity of memory as reported by the `STATS` command, ClamD reports these
+/// as strings such as `9.082M`, which are parsed into a number of bytes so they can be compared
+/// and aggregated. The `Display` implementation reproduces ClamD's format.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MemSize(u64);
+
+impl MemSize {
+    /// Creates a `MemSize` from a number of bytes.
+    pub fn from_bytes(bytes: u64) -> MemSize {
+        MemSize(bytes)
+    }
+
+    /// Returns the size in bytes.
+    pub fn bytes(self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for MemSize {
+    type Err = ClamError;
+
+    /// Parses a ClamD memory size, a decimal number with an optional binary unit suffix of
+    /// `B`, `K`, `M`, `G` or `T`. Values without a suffix are taken to be bytes.
+    fn from_str(s: &str) -> ClamResult<Self> {
+        let s = s.trim();
+        let (number, multiplier) = match s.chars().last() {
+            Some('B') => (&s[..s.len() - 1], 1u64),
+            Some('K') => (&s[..s.len() - 1], 1 << 10),
+            Some('M') => (&s[..s.len() - 1], 1 << 20),
+            Some('G') => (&s[..s.len() - 1], 1 << 30),
+            Some('T') => (&s[..s.len() - 1], 1 << 40),
+            _ => (s, 1),
+        };
+
+        match number.parse::<f64>() {
+            Ok(v) if v.is_finite() && v >= 0.0 => {
+                Ok(MemSize((v * multiplier as f64).round() as u64))
+            }
+            _ => Err(ClamError::InvalidData(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for MemSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.3}M", self.0 as f64 / (1u64 << 20) as f64)
+    }
+}
+
+/// (De)serializes a `Duration` as a whole number of seconds, as ClamD reports it.
+#[cfg(feature = "serde")]
+mod duration_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub(super) fn serialize<S: Serializer>(duration: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(duration.as_secs())
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        u64::deserialize(d).map(Duration::from_secs)
+    }
+}
+
+/// The release date reported by ClamD's `VERSION` command, kept exactly as ClamD formats it
+/// (`%a %b %e %T %Y`, e.g. `Wed Aug  1 08:43:37 2018`) having been validated against that format.
+///
+/// Release dates order chronologically. With the `chrono` feature, `ReleaseDate::to_datetime`
+/// converts the date to a `chrono::DateTime<Utc>`.
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ReleaseDate(String);
+
+#[cfg(feature = "chrono")]
+const RELEASE_DATE_FORMAT: &str = "%a %b %e %T %Y";
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+impl ReleaseDate {
+    /// Returns the date as ClamD formatted it.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the date as a `chrono::DateTime<Utc>`.
+    #[cfg(feature = "chrono")]
+    pub fn to_datetime(&self) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(&self.0, RELEASE_DATE_FORMAT)
+            .expect("release dates are validated when parsed")
+            .and_utc()
+    }
+
+    /// The year, month, date and time of day, which order release dates chronologically.
+    fn sort_key(&self) -> (&str, usize, u32, &str) {
+        let fields: Vec<&str> = self.0.split_whitespace().collect();
+        let month = MONTHS.iter().position(|m| *m == fields[1]).unwrap_or(0);
+        (fields[4], month, fields[2].parse().unwrap_or(0), fields[3])
+    }
+}
+
+impl FromStr for ReleaseDate {
+    type Err = ClamError;
+
+    #[cfg(feature = "chrono")]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match NaiveDateTime::parse_from_str(s, RELEASE_DATE_FORMAT) {
+            Ok(_) => Ok(ReleaseDate(s.to_owned())),
+            Err(e) => Err(ClamError::DateParseError(e)),
+        }
+    }
+
+    /// Validates `s` against ClamD's `%a %b %e %T %Y` date format without `chrono`.
+    #[cfg(not(feature = "chrono"))]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const DAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+        let in_range = |v: &str, len: usize, max: u32| {
+            v.len() <= len && v.parse::<u32>().is_ok_and(|n| n <= max)
+        };
+
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let valid = match fields[..] {
+            [day, month, date, time, year] => {
+                let hms: Vec<&str> = time.split(':').collect();
+                DAYS.contains(&day)
+                    && MONTHS.contains(&month)
+                    && in_range(date, 2, 31)
+                    && date != "0"
+                    && hms.len() == 3
+                    && hms.iter().all(|v| v.len() == 2)
+                    && in_range(hms[0], 2, 23)
+                    && in_range(hms[1], 2, 59)
+                    && in_range(hms[2], 2, 60)
+                    && year.len() == 4
+                    && year.parse::<u32>().is_ok()
+            }
+            _ => false,
+        };
+
+        if valid {
+            Ok(ReleaseDate(s.to_owned()))
+        } else {
+            Err(ClamError::InvalidData(s.to_owned()))
+        }
+    }
+}
+
+impl TryFrom<String> for ReleaseDate {
+    type Error = ClamError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ReleaseDate> for String {
+    fn from(date: ReleaseDate) -> Self {
+        date.0
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<DateTime<Utc>> for ReleaseDate {
+    fn from(date: DateTime<Utc>) -> Self {
+        ReleaseDate(date.format(RELEASE_DATE_FORMAT).to_string())
+    }
+}
+
+impl Ord for ReleaseDate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.sort_key()
+            .cmp(&other.sort_key())
+            .then_with(|| self.0.cmp(&other.0))
+    }
+}
+
+impl PartialOrd for ReleaseDate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for ReleaseDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
 }
 
 /// `ClamVersion` provides all of the Clam meta-information provided by the `VERSION` command
-#[derive(Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct ClamVersion {
     /// The name and version number of the responding daemon
     pub version_tag: String,
     /// The build number of the responding daemon
     pub build_number: u64,
     /// The release date for the responding daemon
-    pub release_date: DateTime<Utc>,
+    pub release_date: ReleaseDate,
+}
+
+/// `DatabaseInfo` describes the signature database loaded by ClamD, as reported alongside the
+/// daemon version by the `VERSION` command.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct DatabaseInfo {
+    /// The version of the signature database
+    pub version: u64,
+    /// The date the signature database was published
+    pub release_date: ReleaseDate,
+}
+
+impl DatabaseInfo {
+    /// Returns true if the signature database was published more than `max_age` ago, e.g. to
+    /// alert when ClamD's definitions have not been updated.
+    #[cfg(feature = "chrono")]
+    pub fn is_older_than(&self, max_age: Duration) -> bool {
+        match chrono::Duration::from_std(max_age) {
+            Ok(max_age) => Utc::now() - self.release_date.to_datetime() > max_age,
+            Err(_) => false,
+        }
+    }
+}
+
+/// `ServerInfo` describes the ClamD a client is connected to: its version, the signature database
+/// it has loaded and the commands it supports, as reported by the `VERSIONCOMMANDS` command.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerInfo {
+    /// The version of the responding daemon
+    pub version: ClamVersion,
+    /// The signature database loaded by the daemon
+    pub database: DatabaseInfo,
+    /// The commands the daemon supports, such as `INSTREAM` or `ALLMATCHSCAN`, which is empty if
+    /// the daemon predates `VERSIONCOMMANDS`
+    pub commands: BTreeSet<String>,
 }
 
 /// `ClamScanResult` Provides a `match` 'friendly' interface for receiving the result of a scan.
@@ -68,7 +320,95 @@ pub enum ClamScanResult {
     Found(String, String),
     /// An `Error` response means that Clam encountered an error whilst processing the request,
     /// for example, if the given file/directory couldn't be found.
-    Error(String),
+    Error(ClamScanError),
+}
+
+/// `ClamScanError` classifies the errors ClamD reports whilst scanning, so that callers can
+/// branch on the common cases without matching on strings. Each known error carries the path it
+/// relates to, if ClamD reported one.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ClamScanError {
+    /// ClamD could not `lstat()` the path, usually because it does not exist
+    LstatFailed(Option<String>),
+    /// ClamD does not have permission to read the path
+    AccessDenied(Option<String>),
+    /// The file or stream exceeds one of ClamD's size limits, such as `StreamMaxLength`
+    SizeLimitExceeded(Option<String>),
+    /// ClamD could not open the file or directory
+    CannotOpen(Option<String>),
+    /// Any other error, holding the response from ClamD verbatim
+    Other(String),
+}
+
+impl ClamScanError {
+    /// `ClamScanError::parse` classifies a single error response from ClamD, such as
+    /// `/some/file: lstat() failed: No such file or directory. ERROR`, falling back to
+    /// `ClamScanError::Other` if it is not recognised.
+    pub fn parse<T: AsRef<str>>(s_string: T) -> ClamScanError {
+        let raw = s_string.as_ref();
+        let lower = raw.to_lowercase();
+
+        type Kind = fn(Option<String>) -> ClamScanError;
+        let kinds: [(&str, Kind); 5] = [
+            ("lstat() failed", ClamScanError::LstatFailed),
+            ("access denied", ClamScanError::AccessDenied),
+            ("size limit exceeded", ClamScanError::SizeLimitExceeded),
+            ("size limit reached", ClamScanError::SizeLimitExceeded),
+            ("can't open file or directory", ClamScanError::CannotOpen),
+        ];
+
+        for (pattern, kind) in kinds {
+            if let Some(position) = lower.find(pattern) {
+                let path = raw[..position]
+                    .strip_suffix(": ")
+                    .filter(|path| !path.is_empty())
+                    .map(|path| path.to_owned());
+                return kind(path);
+            }
+        }
+
+        ClamScanError::Other(raw.to_owned())
+    }
+
+    /// Returns the path the error relates to, if ClamD reported one.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            ClamScanError::LstatFailed(path)
+            | ClamScanError::AccessDenied(path)
+            | ClamScanError::SizeLimitExceeded(path)
+            | ClamScanError::CannotOpen(path) => path.as_deref(),
+            ClamScanError::Other(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ClamScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self {
+            ClamScanError::LstatFailed(_) => "lstat() failed",
+            ClamScanError::AccessDenied(_) => "Access denied",
+            ClamScanError::SizeLimitExceeded(_) => "Size limit exceeded",
+            ClamScanError::CannotOpen(_) => "Can't open file or directory",
+            ClamScanError::Other(raw) => return write!(f, "{}", raw),
+        };
+
+        match self.path() {
+            Some(path) => write!(f, "{}: {}", path, message),
+            None => write!(f, "{}", message),
+        }
+    }
+}
+
+/// `ClamDetections` groups every signature ClamD reported for a single path, as produced by
+/// `ClamScanResult::group_detections`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ClamDetections {
+    /// The location the signatures were found in
+    pub path: String,
+    /// The name of each signature found, in the order ClamD reported them
+    pub signatures: Vec<String>,
 }
 
 impl ClamScanResult {
@@ -79,28 +419,204 @@ impl ClamScanResult {
     /// *Note*: If performing a stream scan, the result will be converted to a single `ClamScanResult` by
     /// the caller.
     pub fn parse<T: AsRef<str>>(s_string: T) -> Vec<ClamScanResult> {
+        ClamScanResult::parse_lines(s_string)
+            .into_iter()
+            .map(ScanLine::into_result)
+            .collect()
+    }
+
+    /// `ClamScanResult::parse_strict` parses a Clam scan result string as `parse` does, but fails
+    /// with `ClamError::ProtocolError` if any line is not an `OK`, `FOUND` or `ERROR` result,
+    /// rather than returning it as a `ClamScanResult::Error`. This distinguishes ClamD reporting an
+    /// error from a reply which could not be understood.
+    pub fn parse_strict<T: AsRef<str>>(s_string: T) -> ClamResult<Vec<ClamScanResult>> {
+        ClamScanResult::parse_lines(s_string)
+            .into_iter()
+            .map(|line| {
+                if line.is_recognised() {
+                    Ok(line.result)
+                } else {
+                    Err(ClamError::ProtocolError { reply: line.raw })
+                }
+            })
+            .collect()
+    }
+
+    /// `ClamScanResult::parse_lines` parses a Clam scan result string as `parse` does, but keeps
+    /// the exact line ClamD sent for each result alongside it, see `ScanLine`.
+    pub fn parse_lines<T: AsRef<str>>(s_string: T) -> Vec<ScanLine> {
         s_string
             .as_ref()
             .split('\0')
             .filter(|s| s != &"")
-            .map(|s| {
-                if s.ends_with("OK") {
-                    return ClamScanResult::Ok;
-                }
+            .map(|s| ScanLine {
+                result: ClamScanResult::parse_line(s),
+                raw: s.to_owned(),
+            })
+            .collect()
+    }
+
+    /// Parses a single line of a scan reply, such as `/some/file: Eicar-Test-Signature FOUND`.
+    fn parse_line(s: &str) -> ClamScanResult {
+        if s.ends_with("OK") {
+            return ClamScanResult::Ok;
+        }
+
+        if let Some(found) = s.strip_suffix("FOUND") {
+            // Paths may contain spaces, and signatures occasionally do, but ClamD only ever
+            // separates the two with the last `: `
+            if let Some((path, virus)) = found.trim_end().rsplit_once(": ") {
+                return ClamScanResult::Found(path.to_owned(), virus.to_owned());
+            }
+        }
+
+        ClamScanResult::Error(ClamScanError::parse(s))
+    }
 
-                if s.contains("FOUND") {
-                    let mut split = s.split_whitespace();
-                    let path: String = split.next().unwrap().trim_end_matches(':').to_owned();
-                    let virus = split
-                        .take_while(|s| !s.starts_with("FOUND"))
-                        .collect::<String>();
+    /// Groups the `Found` results by path, such that a path reported several times - as happens
+    /// with `ALLMATCHSCAN`, or when scanning directories - yields a single `ClamDetections` with
+    /// every signature. Paths are returned in the order they were first reported, `Ok` and
+    /// `Error` results are ignored.
+    pub fn group_detections(results: &[ClamScanResult]) -> Vec<ClamDetections> {
+        let mut grouped: Vec<ClamDetections> = Vec::new();
 
-                    return ClamScanResult::Found(path, virus);
+        for result in results {
+            if let ClamScanResult::Found(path, signature) = result {
+                match grouped.iter_mut().find(|d| &d.path == path) {
+                    Some(detections) => detections.signatures.push(signature.clone()),
+                    None => grouped.push(ClamDetections {
+                        path: path.clone(),
+                        signatures: vec![signature.clone()],
+                    }),
                 }
+            }
+        }
 
-                ClamScanResult::Error(s.to_owned())
-            })
-            .collect::<Vec<ClamScanResult>>()
+        grouped
+    }
+}
+
+impl FromStr for ClamScanResult {
+    type Err = ClamError;
+
+    /// Parses a single result line exactly as ClamD reports it, such as
+    /// `/some/file: Eicar-Test-Signature FOUND`, with or without its terminator. Unlike
+    /// `ClamScanResult::parse`, which never fails, this returns `ClamError::InvalidData` if the
+    /// line holds more than one result, or is not an `OK`, `FOUND` or `ERROR` result.
+    fn from_str(s: &str) -> ClamResult<Self> {
+        let line = s.strip_suffix(['\0', '\n']).unwrap_or(s);
+        if line.contains(['\0', '\n']) {
+            return Err(ClamError::InvalidData(s.to_owned()));
+        }
+
+        let line = ScanLine {
+            result: ClamScanResult::parse_line(line),
+            raw: line.to_owned(),
+        };
+
+        if line.is_recognised() {
+            Ok(line.result)
+        } else {
+            Err(ClamError::InvalidData(s.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for ClamScanResult {
+    /// Formats the result as `clamdscan` reports it, e.g. `/path: Eicar-Test-Signature FOUND`.
+    /// `Ok` carries no path, so is formatted as just `OK`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClamScanResult::Ok => write!(f, "OK"),
+            ClamScanResult::Found(path, virus) => write!(f, "{}: {} FOUND", path, virus),
+            ClamScanResult::Error(e) => write!(f, "{} ERROR", e),
+        }
+    }
+}
+
+/// `ScanLine` is a single result of a scan along with the exact line ClamD sent for it, as
+/// produced by `ClamScanResult::parse_lines`, for auditing and forensics where the text ClamD
+/// reported must be kept verbatim.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanLine {
+    result: ClamScanResult,
+    raw: String,
+}
+
+impl ScanLine {
+    /// Returns the parsed result.
+    pub fn result(&self) -> &ClamScanResult {
+        &self.result
+    }
+
+    /// Returns the line exactly as ClamD sent it, without its terminator.
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// Consumes the line, returning the parsed result.
+    pub fn into_result(self) -> ClamScanResult {
+        self.result
+    }
+
+    /// Creates a `ScanLine` from a result and the line it was parsed from.
+    pub(crate) fn from_parts(result: ClamScanResult, raw: String) -> ScanLine {
+        ScanLine { result, raw }
+    }
+
+    /// Splits the line into the parsed result and the line it was parsed from.
+    pub(crate) fn into_parts(self) -> (ClamScanResult, String) {
+        (self.result, self.raw)
+    }
+
+    /// Returns true if the line is an `OK`, `FOUND` or `ERROR` result, false if it was not
+    /// recognised and has been parsed as a `ClamScanResult::Error` for want of anything better.
+    pub fn is_recognised(&self) -> bool {
+        match self.result {
+            ClamScanResult::Ok | ClamScanResult::Found(..) => true,
+            ClamScanResult::Error(_) => self.raw.ends_with("ERROR"),
+        }
+    }
+}
+
+/// The EICAR test file, a harmless string which every anti-virus engine, including ClamAV,
+/// detects as infected, so that detection can be tested safely.
+pub const EICAR: &[u8] = br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+/// `SelfTestResult` is the outcome of `ClamClient::self_test`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelfTestResult {
+    /// ClamD detected the EICAR test file, the value is the name of the signature which matched.
+    Passed(String),
+    /// ClamD did not detect the EICAR test file, the value is the result it reported instead.
+    Failed(ClamScanResult),
+}
+
+impl SelfTestResult {
+    /// Returns true if ClamD detected the EICAR test file.
+    pub fn is_passed(&self) -> bool {
+        matches!(self, SelfTestResult::Passed(_))
+    }
+}
+
+/// `ReloadStatus` is the state ClamD reports in reply to the `RELOAD` command.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReloadStatus {
+    /// ClamD has accepted the request and is reloading its signature database in the background.
+    Reloading,
+}
+
+impl ReloadStatus {
+    /// `ReloadStatus::parse` takes the string returned from the Clam `RELOAD` command and parses
+    /// it into a `ReloadStatus`, returning `ClamError::InvalidData` for any unexpected reply.
+    pub fn parse<T: AsRef<str>>(r_string: T) -> ClamResult<Self> {
+        match r_string.as_ref().trim_end_matches('\0') {
+            "RELOADING" => Ok(ReloadStatus::Reloading),
+            other => Err(ClamError::InvalidData(other.to_owned())),
+        }
     }
 }
 
@@ -124,10 +640,7 @@ impl ClamVersion {
             Err(e) => return Err(ClamError::IntParseError(e)),
         };
 
-        let dt = match Utc.datetime_from_str(&parts[2], "%a %b %e %T %Y") {
-            Ok(v) => v,
-            Err(e) => return Err(ClamError::DateParseError(e)),
-        };
+        let dt = parts[2].parse()?;
 
         Ok(ClamVersion {
             version_tag: parts[0].to_owned(),
@@ -135,6 +648,82 @@ impl ClamVersion {
             release_date: dt,
         })
     }
+
+    /// Returns the version and release date of the signature database loaded by ClamD, which
+    /// `VERSION` reports as the build number and date.
+    pub fn database_info(&self) -> DatabaseInfo {
+        DatabaseInfo {
+            version: self.build_number,
+            release_date: self.release_date.clone(),
+        }
+    }
+
+    /// Returns the major, minor and patch version of the ClamAV engine from `version_tag`, e.g.
+    /// `(0, 103, 8)` for `ClamAV 0.103.8`, or `None` if the tag is not in that form.
+    pub fn engine_version(&self) -> Option<(u32, u32, u32)> {
+        let number = self.version_tag.strip_prefix("ClamAV ")?;
+        let mut parts = number.split(|c: char| !c.is_ascii_digit());
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let patch = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+
+        Some((major, minor, patch))
+    }
+}
+
+impl fmt::Display for ClamVersion {
+    /// Formats the version as ClamD reports it, e.g.
+    /// `ClamAV 0.100.0/24802/Wed Aug  1 08:43:37 2018`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}",
+            self.version_tag, self.build_number, self.release_date
+        )
+    }
+}
+
+impl ServerInfo {
+    /// `ServerInfo::parse` takes the string returned from the Clam `VERSIONCOMMANDS` command, the
+    /// `VERSION` reply followed by `| COMMANDS:` and a space separated list of commands, and
+    /// parses it into a `ServerInfo`.
+    pub fn parse<T: AsRef<str>>(r_string: T) -> ClamResult<Self> {
+        let reply = r_string.as_ref().trim_end_matches(['\0', '\n']);
+        let (version, commands) = match reply.split_once("| COMMANDS:") {
+            Some(parts) => parts,
+            None => return Err(ClamError::InvalidData(reply.to_owned())),
+        };
+
+        let version = ClamVersion::parse(version.trim_end().to_owned())?;
+        Ok(ServerInfo {
+            database: version.database_info(),
+            version,
+            commands: commands.split_whitespace().map(str::to_owned).collect(),
+        })
+    }
+
+    /// Creates a `ServerInfo` from the reply to `VERSION`, for daemons which predate
+    /// `VERSIONCOMMANDS` and so cannot report the commands they support.
+    pub(crate) fn from_version(version: ClamVersion) -> Self {
+        ServerInfo {
+            database: version.database_info(),
+            version,
+            commands: BTreeSet::new(),
+        }
+    }
+
+    /// Returns true if the daemon reported that it supports `command`, such as `ALLMATCHSCAN`.
+    pub fn supports(&self, command: &str) -> bool {
+        self.commands.contains(command)
+    }
+
+    /// Returns true if the ClamAV engine is at least version `major.minor.patch`, or false if
+    /// its version could not be determined, see `ClamVersion::engine_version`.
+    pub fn is_at_least(&self, major: u32, minor: u32, patch: u32) -> bool {
+        self.version
+            .engine_version()
+            .is_some_and(|version| version >= (major, minor, patch))
+    }
 }
 
 impl ClamStats {
@@ -146,13 +735,221 @@ impl ClamStats {
     /// version 0.100.0. If it cannot parse the data, then the result is returned in its
     /// raw form inside `ClamError::InvalidData`.
     pub fn parse(s_string: &str) -> ClamResult<Self> {
-        match parse_stats(s_string) {
-            Ok(v) => Ok(v.1),
-            Err(_) => Err(ClamError::InvalidData(s_string.to_owned())),
+        let invalid = || ClamError::InvalidData(s_string.to_owned());
+        let (rest, mut stats) = parse_stats(s_string).map_err(|_| invalid())?;
+
+        // The memory statistics are optional, see `ClamStats`
+        if let Some(position) = rest.find("MEMSTATS: ") {
+            let (_, memstats) = parse_memstats(&rest[position..]).map_err(|_| invalid())?;
+            (
+                stats.mem_heap,
+                stats.mem_mmap,
+                stats.mem_used,
+                stats.mem_free,
+                stats.mem_releasable,
+                stats.pools_used,
+                stats.pools_total,
+            ) = memstats;
         }
+
+        Ok(stats)
+    }
+
+    /// `ClamStats::parse_for_version` parses the output of the Clam `STATS` command as produced by
+    /// the given version of ClamD. Versions up to 0.100 are parsed against the fixed grammar of
+    /// `ClamStats::parse`, falling back to `ClamStats::parse_lenient` if that fails, whilst later
+    /// versions, which add, rename and omit fields (reporting `N/A` for unavailable memory
+    /// statistics), are always parsed leniently.
+    pub fn parse_for_version(s_string: &str, version: &ClamVersion) -> ClamResult<Self> {
+        match version.engine_version() {
+            Some((0, minor, _)) if minor <= 100 => {
+                ClamStats::parse(s_string).or_else(|_| ClamStats::parse_lenient(s_string))
+            }
+            _ => ClamStats::parse_lenient(s_string),
+        }
+    }
+
+    /// `ClamStats::parse_lenient` parses the output of the Clam `STATS` command line by line,
+    /// rather than against a fixed grammar, so that added or reordered fields do not cause the
+    /// whole response to be rejected.
+    ///
+    /// Recognised fields are populated as they are found, any that are missing are left at their
+    /// default (zero or empty) value, and unrecognised key/value pairs are collected into `extra`.
+    /// Only a response with no recognisable `STATS` lines at all is returned as
+    /// `ClamError::InvalidData`.
+    pub fn parse_lenient(s_string: &str) -> ClamResult<Self> {
+        let mut stats = ClamStats::default();
+        let mut recognised = false;
+
+        for line in s_string.trim_end_matches('\0').lines() {
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) if !key.starts_with(char::is_whitespace) => {
+                    (key.trim(), value.trim())
+                }
+                _ => continue,
+            };
+
+            match key {
+                "POOLS" => stats.pools = parse_lenient_field(value, &mut recognised),
+                "STATE" => {
+                    stats.state = value.to_owned();
+                    recognised = true;
+                }
+                "QUEUE" => {
+                    let count = value.trim_end_matches("items").trim();
+                    stats.queue = parse_lenient_field(count, &mut recognised);
+                }
+                "THREADS" | "MEMSTATS" => {
+                    for (name, value) in key_value_pairs(value) {
+                        match (key, name) {
+                            ("THREADS", "live") => {
+                                stats.threads_live = parse_lenient_field(value, &mut recognised)
+                            }
+                            ("THREADS", "idle") => {
+                                stats.threads_idle = parse_lenient_field(value, &mut recognised)
+                            }
+                            ("THREADS", "max") => {
+                                stats.threads_max = parse_lenient_field(value, &mut recognised)
+                            }
+                            ("THREADS", "idle-timeout") => {
+                                stats.threads_idle_timeout =
+                                    Duration::from_secs(parse_lenient_field(value, &mut recognised))
+                            }
+                            ("MEMSTATS", "heap") => {
+                                stats.mem_heap = parse_lenient_mem_size(value, &mut recognised)
+                            }
+                            ("MEMSTATS", "mmap") => {
+                                stats.mem_mmap = parse_lenient_mem_size(value, &mut recognised)
+                            }
+                            ("MEMSTATS", "used") => {
+                                stats.mem_used = parse_lenient_mem_size(value, &mut recognised)
+                            }
+                            ("MEMSTATS", "free") => {
+                                stats.mem_free = parse_lenient_mem_size(value, &mut recognised)
+                            }
+                            ("MEMSTATS", "releasable") => {
+                                stats.mem_releasable =
+                                    parse_lenient_mem_size(value, &mut recognised)
+                            }
+                            // Duplicates `POOLS`, so is not retained
+                            ("MEMSTATS", "pools") => {}
+                            ("MEMSTATS", "pools_used") => {
+                                stats.pools_used = parse_lenient_mem_size(value, &mut recognised)
+                            }
+                            ("MEMSTATS", "pools_total") => {
+                                stats.pools_total = parse_lenient_mem_size(value, &mut recognised)
+                            }
+                            _ => {
+                                stats
+                                    .extra
+                                    .insert(format!("{}.{}", key, name), value.to_owned());
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    stats.extra.insert(key.to_owned(), value.to_owned());
+                }
+            }
+        }
+
+        if recognised {
+            Ok(stats)
+        } else {
+            Err(ClamError::InvalidData(s_string.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for ClamStats {
+    /// Formats the statistics in the layout of ClamD's `STATS` reply, one section per line.
+    /// Fields in `extra` are not included.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "POOLS: {}", self.pools)?;
+        writeln!(f, "STATE: {}", self.state)?;
+        writeln!(
+            f,
+            "THREADS: live {} idle {} max {} idle-timeout {}",
+            self.threads_live,
+            self.threads_idle,
+            self.threads_max,
+            self.threads_idle_timeout.as_secs()
+        )?;
+        writeln!(f, "QUEUE: {} items", self.queue)?;
+
+        let memstats = [
+            self.mem_heap,
+            self.mem_mmap,
+            self.mem_used,
+            self.mem_free,
+            self.mem_releasable,
+            self.pools_used,
+            self.pools_total,
+        ];
+        // As ClamD does, the line is omitted when there are no memory statistics, and `N/A` is
+        // reported for any which are individually unavailable
+        if memstats.iter().any(Option::is_some) {
+            let [heap, mmap, used, free, releasable, pools_used, pools_total] =
+                memstats.map(|size| size.map_or("N/A".to_owned(), |size| size.to_string()));
+            writeln!(
+                f,
+                "MEMSTATS: heap {} mmap {} used {} free {} releasable {} pools {} pools_used {} pools_total {}",
+                heap, mmap, used, free, releasable, self.pools, pools_used, pools_total
+            )?;
+        }
+        write!(f, "END")
+    }
+}
+
+/// Splits a `STATS` line such as `live 1  idle 0 max 12` into its name/value pairs.
+fn key_value_pairs(line: &str) -> Vec<(&str, &str)> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    words
+        .chunks(2)
+        .filter(|pair| pair.len() == 2)
+        .map(|pair| (pair[0], pair[1]))
+        .collect()
+}
+
+/// Parses a single value for `ClamStats::parse_lenient`, falling back to the default for the
+/// type if the value cannot be parsed, and recording whether it was successful.
+fn parse_lenient_field<T: FromStr + Default>(value: &str, recognised: &mut bool) -> T {
+    match value.parse() {
+        Ok(v) => {
+            *recognised = true;
+            v
+        }
+        Err(_) => T::default(),
+    }
+}
+
+/// Parses a single memory statistic for `ClamStats::parse_lenient`, recording whether it was
+/// successful. `N/A`, or any other value which cannot be parsed, is `None`.
+fn parse_lenient_mem_size(value: &str, recognised: &mut bool) -> Option<MemSize> {
+    let size = value.parse().ok();
+    *recognised |= size.is_some();
+    size
+}
+
+/// Parses a single memory statistic for `ClamStats::parse`, which is `None` if reported as `N/A`.
+fn parse_mem_size(value: &str) -> ClamResult<Option<MemSize>> {
+    match value {
+        "N/A" => Ok(None),
+        value => value.parse().map(Some),
     }
 }
 
+/// The memory statistics reported on the `MEMSTATS` line, in the order `ClamStats` declares them.
+type MemStats = (
+    Option<MemSize>,
+    Option<MemSize>,
+    Option<MemSize>,
+    Option<MemSize>,
+    Option<MemSize>,
+    Option<MemSize>,
+    Option<MemSize>,
+);
+
 named!(parse_stats<&str, ClamStats>,
     do_parse!(
         tag!("POOLS: ") >>
@@ -163,15 +960,6 @@ named!(parse_stats<&str, ClamStats>,
         threads_max: map_res!(take_until_and_consume!(" idle-timeout "), u64::from_str) >>
         threads_idle_timeout_secs: map_res!(take_until_and_consume!("\nQUEUE: "), u64::from_str) >>
         queue: map_res!(take_until_and_consume!(" items\n"), u64::from_str) >>
-        take_until_and_consume!("heap ") >>
-        mem_heap: map_res!(take_until_and_consume!(" mmap "), FromStr::from_str) >>
-        mem_mmap: map_res!(take_until_and_consume!(" used "), FromStr::from_str) >>
-        mem_used: map_res!(take_until_and_consume!(" free "), FromStr::from_str) >>
-        mem_free: map_res!(take_until_and_consume!(" releasable "), FromStr::from_str) >>
-        mem_releasable: map_res!(take_until_and_consume!(" pools "), FromStr::from_str) >>
-        take_until_and_consume!("pools_used ") >>
-        pools_used: map_res!(take_until_and_consume!(" pools_total "), FromStr::from_str) >>
-        pools_total: map_res!(take_until!("\n"), FromStr::from_str) >>
         (
             ClamStats {
                 pools,
@@ -179,27 +967,45 @@ named!(parse_stats<&str, ClamStats>,
                 threads_live,
                 threads_idle,
                 threads_max,
-                threads_idle_timeout_secs,
+                threads_idle_timeout: Duration::from_secs(threads_idle_timeout_secs),
                 queue,
-                mem_heap,
-                mem_mmap,
-                mem_used,
-                mem_free,
-                mem_releasable,
-                pools_used,
-                pools_total
+                mem_heap: None,
+                mem_mmap: None,
+                mem_used: None,
+                mem_free: None,
+                mem_releasable: None,
+                pools_used: None,
+                pools_total: None,
+                extra: BTreeMap::new()
             }
         )
     )
 );
 
+named!(parse_memstats<&str, MemStats>,
+    do_parse!(
+        tag!("MEMSTATS: heap ") >>
+        mem_heap: map_res!(take_until_and_consume!(" mmap "), parse_mem_size) >>
+        mem_mmap: map_res!(take_until_and_consume!(" used "), parse_mem_size) >>
+        mem_used: map_res!(take_until_and_consume!(" free "), parse_mem_size) >>
+        mem_free: map_res!(take_until_and_consume!(" releasable "), parse_mem_size) >>
+        mem_releasable: map_res!(take_until_and_consume!(" pools "), parse_mem_size) >>
+        take_until_and_consume!("pools_used ") >>
+        pools_used: map_res!(take_until_and_consume!(" pools_total "), parse_mem_size) >>
+        pools_total: map_res!(take_until!("\n"), parse_mem_size) >>
+        (mem_heap, mem_mmap, mem_used, mem_free, mem_releasable, pools_used, pools_total)
+    )
+);
+
 #[cfg(test)]
 mod tests {
     use crate::response;
+    #[cfg(feature = "chrono")]
     use chrono::prelude::*;
+    use std::time::Duration;
 
-    static VERSION_STRING: &'static str = "ClamAV 0.100.0/24802/Wed Aug  1 08:43:37 2018\0";
-    static STATS_STRING: &'static str = "POOLS: 1\n\nSTATE: VALID PRIMARY\nTHREADS: live 1  idle 0 max 12 idle-timeout 30\nQUEUE: 0 items\n\tSTATS 0.000394\n\nMEMSTATS: heap 9.082M mmap 0.000M used 6.902M free 2.184M releasable 0.129M pools 1 pools_used 565.979M pools_total 565.999M\nEND\0";
+    static VERSION_STRING: &str = "ClamAV 0.100.0/24802/Wed Aug  1 08:43:37 2018\0";
+    static STATS_STRING: &str = "POOLS: 1\n\nSTATE: VALID PRIMARY\nTHREADS: live 1  idle 0 max 12 idle-timeout 30\nQUEUE: 0 items\n\tSTATS 0.000394\n\nMEMSTATS: heap 9.082M mmap 0.000M used 6.902M free 2.184M releasable 0.129M pools 1 pools_used 565.979M pools_total 565.999M\nEND\0";
 
     #[test]
     fn test_version_parse_version_tag() {
@@ -215,15 +1021,51 @@ mod tests {
         assert_eq!(parsed.build_number, 24802);
     }
 
+    #[cfg(feature = "chrono")]
     #[test]
     fn test_version_parse_publish_dt() {
         let raw = VERSION_STRING.to_owned();
         let parsed = response::ClamVersion::parse(raw).unwrap();
         assert_eq!(
-            parsed.release_date,
-            Utc.datetime_from_str("Wed Aug  1 08:43:37 2018", "%a %b %e %T %Y")
+            parsed.release_date.to_datetime(),
+            NaiveDateTime::parse_from_str("Wed Aug  1 08:43:37 2018", "%a %b %e %T %Y")
                 .unwrap()
+                .and_utc()
+        );
+        assert_eq!(
+            response::ReleaseDate::from(parsed.release_date.to_datetime()),
+            parsed.release_date
+        );
+    }
+
+    #[cfg(not(feature = "chrono"))]
+    #[test]
+    fn test_version_parse_publish_dt() {
+        let raw = VERSION_STRING.to_owned();
+        let parsed = response::ClamVersion::parse(raw).unwrap();
+        assert_eq!(parsed.release_date.as_str(), "Wed Aug  1 08:43:37 2018");
+
+        let raw = "ClamAV 0.100.0/24802/Wed Aug 32 08:43:37 2018".to_owned();
+        assert!(response::ClamVersion::parse(raw).is_err());
+    }
+
+    #[test]
+    fn test_release_date_order() {
+        let date = |s: &str| s.parse::<response::ReleaseDate>().unwrap();
+        let dates = [
+            date("Mon Dec 31 23:59:59 2018"),
+            date("Wed Aug  1 08:43:37 2018"),
+            date("Tue Jan  1 00:00:00 2019"),
+            date("Wed Aug 15 08:43:37 2018"),
+        ];
+        let mut sorted = dates.clone();
+        sorted.sort();
+        assert_eq!(
+            sorted,
+            [&dates[1], &dates[3], &dates[0], &dates[2]].map(Clone::clone)
         );
+        assert_eq!(dates[1].to_string(), "Wed Aug  1 08:43:37 2018");
+        assert!("yesterday".parse::<response::ReleaseDate>().is_err());
     }
 
     #[test]
@@ -243,6 +1085,82 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_result_parse_strict() {
+        let raw = "/a: OK\0/b: Access denied. ERROR\0/c: Eicar-Signature FOUND\0";
+        assert_eq!(
+            response::ClamScanResult::parse_strict(raw).unwrap(),
+            response::ClamScanResult::parse(raw)
+        );
+
+        let raw = "/a: OK\0UNKNOWN COMMAND\0";
+        assert_eq!(
+            response::ClamScanResult::parse(raw)[1],
+            response::ClamScanResult::Error(response::ClamScanError::Other(
+                "UNKNOWN COMMAND".to_string()
+            ))
+        );
+        match response::ClamScanResult::parse_strict(raw) {
+            Err(crate::error::ClamError::ProtocolError { reply }) => {
+                assert_eq!(reply, "UNKNOWN COMMAND")
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_result_from_str() {
+        let found: response::ClamScanResult = "/some/file: SOME_BAD-Virus FOUND\0".parse().unwrap();
+        assert_eq!(
+            found,
+            response::ClamScanResult::Found("/some/file".to_string(), "SOME_BAD-Virus".to_string())
+        );
+        assert_eq!(
+            found
+                .to_string()
+                .parse::<response::ClamScanResult>()
+                .unwrap(),
+            found
+        );
+
+        assert_eq!(
+            "stream: OK".parse::<response::ClamScanResult>().unwrap(),
+            response::ClamScanResult::Ok
+        );
+        assert_eq!(
+            "/some/file: Access denied. ERROR\n"
+                .parse::<response::ClamScanResult>()
+                .unwrap(),
+            response::ClamScanResult::Error(response::ClamScanError::AccessDenied(Some(
+                "/some/file".to_string()
+            )))
+        );
+
+        for invalid in ["", "PONG", "SOME_BAD-Virus FOUND", "/a: OK\0/b: OK"] {
+            assert!(invalid.parse::<response::ClamScanResult>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_result_parse_lines_keeps_raw() {
+        let raw = "/some/my file: Unusual  Signature FOUND\0/some/other: Access denied. ERROR\0";
+        let lines = response::ClamScanResult::parse_lines(raw);
+
+        assert_eq!(lines[0].raw(), "/some/my file: Unusual  Signature FOUND");
+        assert_eq!(
+            lines[0].result(),
+            &response::ClamScanResult::Found(
+                "/some/my file".to_string(),
+                "Unusual  Signature".to_string()
+            )
+        );
+        assert_eq!(lines[1].raw(), "/some/other: Access denied. ERROR");
+        assert_eq!(
+            lines[1].clone().into_result(),
+            response::ClamScanResult::parse(raw)[1]
+        );
+    }
+
     #[test]
     fn test_result_parse_multi_found() {
         let raw = "/some/file: SOME_BAD-Virus FOUND\0/some/other_file: SOME_V*BAD-Virus FOUND\0";
@@ -260,15 +1178,71 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_reload_status_parse() {
+        assert_eq!(
+            response::ReloadStatus::parse("RELOADING\0").unwrap(),
+            response::ReloadStatus::Reloading
+        );
+        assert!(response::ReloadStatus::parse("UNKNOWN COMMAND\0").is_err());
+    }
+
+    #[test]
+    fn test_result_group_detections() {
+        let raw = "/a: Sig-1 FOUND\0/b: OK\0/a: Sig-2 FOUND\0/c: Sig-1 FOUND\0";
+        let grouped =
+            response::ClamScanResult::group_detections(&response::ClamScanResult::parse(raw));
+        assert_eq!(
+            grouped,
+            vec![
+                response::ClamDetections {
+                    path: "/a".to_string(),
+                    signatures: vec!["Sig-1".to_string(), "Sig-2".to_string()],
+                },
+                response::ClamDetections {
+                    path: "/c".to_string(),
+                    signatures: vec!["Sig-1".to_string()],
+                },
+            ]
+        );
+    }
+
     #[test]
     fn test_result_parse_error() {
         let raw = "/some/file: lstat() failed or some other random error\0";
         let parsed = response::ClamScanResult::parse(raw);
         assert_eq!(
             parsed[0],
-            response::ClamScanResult::Error(
-                "/some/file: lstat() failed or some other random error".to_string()
-            )
+            response::ClamScanResult::Error(response::ClamScanError::LstatFailed(Some(
+                "/some/file".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn test_scan_error_parse() {
+        use response::ClamScanError;
+
+        assert_eq!(
+            ClamScanError::parse("/a b: Access denied. ERROR"),
+            ClamScanError::AccessDenied(Some("/a b".to_string()))
+        );
+        assert_eq!(
+            ClamScanError::parse("INSTREAM size limit exceeded. ERROR"),
+            ClamScanError::SizeLimitExceeded(None)
+        );
+        assert_eq!(
+            ClamScanError::parse("/a: Can't open file or directory ERROR"),
+            ClamScanError::CannotOpen(Some("/a".to_string()))
+        );
+        assert_eq!(
+            ClamScanError::parse("/a: Something else ERROR"),
+            ClamScanError::Other("/a: Something else ERROR".to_string())
+        );
+        assert_eq!(
+            ClamScanError::parse("/a: lstat() failed: No such file or directory. ERROR")
+                .to_string(),
+            "/a: lstat() failed"
         );
     }
 
@@ -305,7 +1279,7 @@ mod tests {
     #[test]
     fn test_stats_parse_threads_timeout() {
         let parsed = response::ClamStats::parse(STATS_STRING).unwrap();
-        assert_eq!(parsed.threads_idle_timeout_secs, 30);
+        assert_eq!(parsed.threads_idle_timeout, Duration::from_secs(30));
     }
 
     #[test]
@@ -317,42 +1291,241 @@ mod tests {
     #[test]
     fn test_stats_parse_mem_heap() {
         let parsed = response::ClamStats::parse(STATS_STRING).unwrap();
-        assert_eq!(parsed.mem_heap, "9.082M".to_string());
+        assert_eq!(parsed.mem_heap.unwrap().to_string(), "9.082M".to_string());
     }
 
     #[test]
     fn test_stats_parse_mem_mmap() {
         let parsed = response::ClamStats::parse(STATS_STRING).unwrap();
-        assert_eq!(parsed.mem_mmap, "0.000M".to_string());
+        assert_eq!(parsed.mem_mmap.unwrap().to_string(), "0.000M".to_string());
     }
 
     #[test]
     fn test_stats_parse_mem_used() {
         let parsed = response::ClamStats::parse(STATS_STRING).unwrap();
-        assert_eq!(parsed.mem_used, "6.902M".to_string());
+        assert_eq!(parsed.mem_used.unwrap().to_string(), "6.902M".to_string());
     }
 
     #[test]
     fn test_stats_parse_mem_free() {
         let parsed = response::ClamStats::parse(STATS_STRING).unwrap();
-        assert_eq!(parsed.mem_free, "2.184M".to_string());
+        assert_eq!(parsed.mem_free.unwrap().to_string(), "2.184M".to_string());
     }
 
     #[test]
     fn test_stats_parse_mem_releaseable() {
         let parsed = response::ClamStats::parse(STATS_STRING).unwrap();
-        assert_eq!(parsed.mem_releasable, "0.129M".to_string());
+        assert_eq!(
+            parsed.mem_releasable.unwrap().to_string(),
+            "0.129M".to_string()
+        );
+    }
+
+    #[test]
+    fn test_stats_parse_mem_heap_bytes() {
+        let parsed = response::ClamStats::parse(STATS_STRING).unwrap();
+        assert_eq!(parsed.mem_heap.unwrap().bytes(), 9_523_167);
+    }
+
+    #[test]
+    fn test_mem_size_parse_units() {
+        assert_eq!("512".parse::<response::MemSize>().unwrap().bytes(), 512);
+        assert_eq!("2K".parse::<response::MemSize>().unwrap().bytes(), 2048);
+        assert_eq!(
+            "1.5G".parse::<response::MemSize>().unwrap().bytes(),
+            1_610_612_736
+        );
+        assert!("lots".parse::<response::MemSize>().is_err());
+    }
+
+    #[test]
+    fn test_stats_parse_lenient_matches_strict() {
+        let strict = response::ClamStats::parse(STATS_STRING).unwrap();
+        let lenient = response::ClamStats::parse_lenient(STATS_STRING).unwrap();
+        assert_eq!(strict, lenient);
+    }
+
+    #[test]
+    fn test_stats_parse_lenient_unknown_fields() {
+        let raw = "POOLS: 1\n\nSTATE: VALID PRIMARY\nTHREADS: live 2  idle 0 max 12 idle-timeout 30 busy 2\nQUEUE: 0 items\nUPTIME: 3600\nMEMSTATS: heap N/A mmap N/A used N/A\nEND\0";
+        let parsed = response::ClamStats::parse_lenient(raw).unwrap();
+        assert!(response::ClamStats::parse(raw).is_err());
+        assert_eq!(parsed.threads_live, 2);
+        assert_eq!(parsed.extra.get("THREADS.busy"), Some(&"2".to_string()));
+        assert_eq!(parsed.extra.get("UPTIME"), Some(&"3600".to_string()));
+        assert_eq!(parsed.mem_heap, None);
+    }
+
+    #[test]
+    fn test_stats_parse_without_memstats() {
+        let raw = "POOLS: 1\n\nSTATE: VALID PRIMARY\nTHREADS: live 1  idle 0 max 12 idle-timeout 30\nQUEUE: 0 items\nEND\0";
+        let parsed = response::ClamStats::parse(raw).unwrap();
+        assert_eq!(parsed.threads_max, 12);
+        assert_eq!(parsed.mem_used, None);
+        assert_eq!(parsed.pools_total, None);
+        assert_eq!(response::ClamStats::parse_lenient(raw).unwrap(), parsed);
+        assert!(!parsed.to_string().contains("MEMSTATS"));
+
+        // Individually unavailable statistics are reported as `N/A`
+        let raw = STATS_STRING.replace("heap 9.082M", "heap N/A");
+        let parsed = response::ClamStats::parse(&raw).unwrap();
+        assert_eq!(parsed.mem_heap, None);
+        assert_eq!(parsed.mem_used.unwrap().to_string(), "6.902M");
+        assert!(parsed
+            .to_string()
+            .contains("MEMSTATS: heap N/A mmap 0.000M"));
+
+        // Whilst a malformed section is still rejected
+        let raw = STATS_STRING.replace("used 6.902M", "used lots");
+        assert!(response::ClamStats::parse(&raw).is_err());
+    }
+
+    #[test]
+    fn test_stats_parse_lenient_garbage() {
+        assert!(response::ClamStats::parse_lenient("UNKNOWN COMMAND\0").is_err());
     }
 
     #[test]
     fn test_stats_parse_pools_used() {
         let parsed = response::ClamStats::parse(STATS_STRING).unwrap();
-        assert_eq!(parsed.pools_used, "565.979M".to_string());
+        assert_eq!(
+            parsed.pools_used.unwrap().to_string(),
+            "565.979M".to_string()
+        );
     }
 
     #[test]
     fn test_stats_parse_pools_total() {
         let parsed = response::ClamStats::parse(STATS_STRING).unwrap();
-        assert_eq!(parsed.pools_total, "565.999M".to_string());
+        assert_eq!(
+            parsed.pools_total.unwrap().to_string(),
+            "565.999M".to_string()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let version = response::ClamVersion::parse(VERSION_STRING.to_owned()).unwrap();
+        let json = serde_json::to_string(&version).unwrap();
+        assert_eq!(
+            serde_json::from_str::<response::ClamVersion>(&json).unwrap(),
+            version
+        );
+
+        let results = response::ClamScanResult::parse(
+            "/a: Eicar-Test-Signature FOUND\0/b: Access denied. ERROR\0",
+        );
+        let json = serde_json::to_string(&results).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<response::ClamScanResult>>(&json).unwrap(),
+            results
+        );
+
+        // Durations are serialized as the whole seconds ClamD reported
+        let stats = response::ClamStats::parse(STATS_STRING).unwrap();
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["threads_idle_timeout_secs"], 30);
+        assert_eq!(
+            serde_json::from_value::<response::ClamStats>(json).unwrap(),
+            stats
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let version = response::ClamVersion::parse(VERSION_STRING.to_owned()).unwrap();
+        assert_eq!(version.to_string(), VERSION_STRING.trim_end_matches('\0'));
+
+        let results = response::ClamScanResult::parse(
+            "stream: OK\0/a: Eicar-Test-Signature FOUND\0/b: Access denied. ERROR\0",
+        );
+        let lines: Vec<String> = results.iter().map(|r| r.to_string()).collect();
+        assert_eq!(
+            lines,
+            [
+                "OK",
+                "/a: Eicar-Test-Signature FOUND",
+                "/b: Access denied ERROR"
+            ]
+        );
+
+        let stats = response::ClamStats::parse(STATS_STRING).unwrap();
+        assert_eq!(
+            stats.to_string(),
+            "POOLS: 1\n\
+             STATE: VALID PRIMARY\n\
+             THREADS: live 1 idle 0 max 12 idle-timeout 30\n\
+             QUEUE: 0 items\n\
+             MEMSTATS: heap 9.082M mmap 0.000M used 6.902M free 2.184M releasable 0.129M pools 1 pools_used 565.979M pools_total 565.999M\n\
+             END"
+        );
+    }
+
+    #[test]
+    fn test_version_database_info() {
+        let parsed = response::ClamVersion::parse(VERSION_STRING.to_owned()).unwrap();
+        let info = parsed.database_info();
+        assert_eq!(info.version, 24802);
+        assert_eq!(info.release_date, parsed.release_date);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_database_info_is_older_than() {
+        let info = response::DatabaseInfo {
+            version: 26900,
+            release_date: (Utc::now() - chrono::Duration::hours(2)).into(),
+        };
+        assert!(info.is_older_than(Duration::from_secs(3600)));
+        assert!(!info.is_older_than(Duration::from_secs(24 * 3600)));
+    }
+
+    #[test]
+    fn test_version_engine_version() {
+        let parsed = response::ClamVersion::parse(VERSION_STRING.to_owned()).unwrap();
+        assert_eq!(parsed.engine_version(), Some((0, 100, 0)));
+
+        let parsed =
+            response::ClamVersion::parse("ClamAV 1.0.1/26900/Mon May  1 07:10:00 2023".to_owned())
+                .unwrap();
+        assert_eq!(parsed.engine_version(), Some((1, 0, 1)));
+    }
+
+    #[test]
+    fn test_server_info_parse() {
+        let info = response::ServerInfo::parse(
+            "ClamAV 1.0.1/26900/Mon May  1 07:10:00 2023| COMMANDS: SCAN PING INSTREAM ALLMATCHSCAN\0",
+        )
+        .unwrap();
+        assert_eq!(info.version.version_tag, "ClamAV 1.0.1");
+        assert_eq!(info.database.version, 26900);
+        assert!(info.supports("ALLMATCHSCAN"));
+        assert!(!info.supports("MULTISCAN"));
+        assert!(info.is_at_least(0, 103, 8));
+        assert!(!info.is_at_least(1, 4, 0));
+
+        assert!(response::ServerInfo::parse(VERSION_STRING).is_err());
+    }
+
+    #[test]
+    fn test_stats_parse_for_version() {
+        let stats_0_103 = "POOLS: 1\n\nSTATE: VALID PRIMARY\nTHREADS: live 1  idle 0 max 10 idle-timeout 30\nQUEUE: 0 items\n\tSTATS 0.000066 \n\nMEMSTATS: heap N/A mmap N/A used N/A free N/A releasable N/A pools 1 pools_used 1306.837M pools_total 1306.882M\nEND\0";
+        let version = response::ClamVersion::parse(
+            "ClamAV 0.103.8/26900/Mon May  1 07:10:00 2023".to_owned(),
+        )
+        .unwrap();
+
+        let parsed = response::ClamStats::parse_for_version(stats_0_103, &version).unwrap();
+        assert_eq!(response::ClamStats::parse(stats_0_103).unwrap(), parsed);
+        assert_eq!(parsed.threads_max, 10);
+        assert_eq!(parsed.mem_heap, None);
+        assert_eq!(parsed.pools_total.unwrap().bytes(), 1370365100);
+
+        let version = response::ClamVersion::parse(VERSION_STRING.to_owned()).unwrap();
+        assert_eq!(
+            response::ClamStats::parse_for_version(STATS_STRING, &version).unwrap(),
+            response::ClamStats::parse(STATS_STRING).unwrap()
+        );
     }
 }
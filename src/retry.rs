@@ -0,0 +1,203 @@
+//! `RetryPolicy` describes how `ClamClient` retries commands which fail with transient errors,
+//! such as ClamD refusing connections whilst it reloads its signature database.
+
+use crate::client::ClamResult;
+use crate::error::ClamError;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// `RetryPolicy` retries a failed operation up to `max_attempts` times in total, sleeping for an
+/// exponentially increasing backoff between attempts. Only errors accepted by the policy's
+/// predicate are retried, by default these are connection level errors such as a refused or
+/// reset connection.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::retry::RetryPolicy;
+/// use std::time::Duration;
+///
+/// fn main() {
+///     let client = ClamClient::builder("127.0.0.1", 3310)
+///         .retry_policy(
+///             RetryPolicy::new(5)
+///                 .initial_backoff(Duration::from_millis(200))
+///                 .max_backoff(Duration::from_secs(2)),
+///         )
+///         .build()
+///         .unwrap();
+///
+///     println!("{:?}", client.version());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    retryable: Arc<dyn Fn(&ClamError) -> bool + Send + Sync>,
+}
+
+impl RetryPolicy {
+    /// Creates a new `RetryPolicy` which makes at most `max_attempts` attempts in total, with a
+    /// backoff starting at 100ms and doubling on each attempt up to a maximum of 5 seconds.
+    pub fn new(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            retryable: Arc::new(ClamError::is_retryable),
+        }
+    }
+
+    /// Sets the backoff used after the first failed attempt.
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Sets the upper bound on the backoff between attempts.
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Sets the factor the backoff is multiplied by after each failed attempt.
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Replaces the predicate which decides whether an error should be retried.
+    pub fn retry_if<F>(mut self, retryable: F) -> Self
+    where
+        F: Fn(&ClamError) -> bool + Send + Sync + 'static,
+    {
+        self.retryable = Arc::new(retryable);
+        self
+    }
+
+    /// Returns the backoff to sleep for after the given (1 based) failed attempt.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.saturating_sub(1) as i32);
+        let backoff = self.initial_backoff.as_secs_f64() * factor;
+        Duration::from_secs_f64(backoff.min(self.max_backoff.as_secs_f64()))
+    }
+
+    /// Returns the backoff to sleep for before retrying after the given (1 based) attempt failed
+    /// with `error`, or `None` if it should not be retried.
+    pub(crate) fn retry_after(&self, attempt: u32, error: &ClamError) -> Option<Duration> {
+        (attempt < self.max_attempts && (self.retryable)(error)).then(|| self.backoff(attempt))
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error which is not retryable, or the
+    /// maximum number of attempts is reached, returning the last result.
+    pub(crate) fn run<T, F>(&self, mut operation: F) -> ClamResult<T>
+    where
+        F: FnMut() -> ClamResult<T>,
+    {
+        let mut attempt = 1;
+
+        loop {
+            let result = operation();
+            match result
+                .as_ref()
+                .err()
+                .and_then(|e| self.retry_after(attempt, e))
+            {
+                Some(backoff) => {
+                    thread::sleep(backoff);
+                    attempt += 1;
+                }
+                None => return result,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    /// A policy of 3 attempts with the default backoff.
+    fn default() -> Self {
+        RetryPolicy::new(3)
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("multiplier", &self.multiplier)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ClamError;
+    use crate::retry::RetryPolicy;
+    use std::io::ErrorKind;
+    use std::time::Duration;
+
+    fn refused() -> ClamError {
+        ClamError::ConnectionError(ErrorKind::ConnectionRefused.into())
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy::new(5)
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(300));
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(300));
+        assert_eq!(policy.backoff(10), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn test_retry_until_success() {
+        let policy = RetryPolicy::new(3).initial_backoff(Duration::from_millis(1));
+        let mut attempts = 0;
+        let result = policy.run(|| {
+            attempts += 1;
+            if attempts < 3 {
+                Err(refused())
+            } else {
+                Ok(attempts)
+            }
+        });
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let policy = RetryPolicy::new(2).initial_backoff(Duration::from_millis(1));
+        let mut attempts = 0;
+        let result: Result<(), ClamError> = policy.run(|| {
+            attempts += 1;
+            Err(refused())
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 2);
+    }
+
+    #[test]
+    fn test_retry_skips_non_retryable() {
+        let policy = RetryPolicy::new(3).initial_backoff(Duration::from_millis(1));
+        let mut attempts = 0;
+        let result: Result<(), ClamError> = policy.run(|| {
+            attempts += 1;
+            Err(ClamError::InvalidData("garbage".to_string()))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+}
@@ -0,0 +1,168 @@
+//! `ClamScan` is the interface to ClamD's core commands, implemented by `ClamClient`, so that
+//! application code can depend on the trait rather than on a live ClamD and substitute a test
+//! double in its own tests.
+
+use crate::client::{ClamClient, ClamResult};
+use crate::response::{ClamScanResult, ClamStats, ClamVersion};
+use std::io::Read;
+use std::sync::Arc;
+
+/// `ClamScan` covers the commands most applications issue, with the same behaviour as the
+/// `ClamClient` methods of the same name. It is object safe, so a service may hold an
+/// `Arc<dyn ClamScan + Send + Sync>` and be given either a `ClamClient` or a test double.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::{ClamClient, ClamResult};
+/// use clam_client::response::{ClamScanResult, ClamStats, ClamVersion};
+/// use clam_client::scan::ClamScan;
+/// use std::io::Read;
+///
+/// fn is_clean<S: ClamScan>(scanner: &S, upload: &[u8]) -> bool {
+///     matches!(scanner.scan_bytes(upload), Ok(ClamScanResult::Ok))
+/// }
+///
+/// struct AlwaysClean;
+///
+/// impl ClamScan for AlwaysClean {
+///     fn ping(&self) -> bool {
+///         true
+///     }
+///
+///     fn version(&self) -> ClamResult<ClamVersion> {
+///         ClamVersion::parse("ClamAV 1.0.1/26900/Mon May  1 07:10:00 2023".to_owned())
+///     }
+///
+///     fn scan_path(&self, _: &str, _: bool) -> ClamResult<Vec<ClamScanResult>> {
+///         Ok(vec![ClamScanResult::Ok])
+///     }
+///
+///     fn scan_stream(&self, _: &mut dyn Read) -> ClamResult<ClamScanResult> {
+///         Ok(ClamScanResult::Ok)
+///     }
+///
+///     fn stats(&self) -> ClamResult<ClamStats> {
+///         Ok(ClamStats::default())
+///     }
+/// }
+///
+/// fn main() {
+///     assert!(is_clean(&AlwaysClean, b"hello"));
+///
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///     println!("{}", is_clean(&client, b"hello"));
+/// }
+/// ```
+pub trait ClamScan {
+    /// Issues `PING`, returning true if ClamD responds with `PONG`, see `ClamClient::ping`.
+    fn ping(&self) -> bool;
+
+    /// Issues `VERSION`, see `ClamClient::version`.
+    fn version(&self) -> ClamResult<ClamVersion>;
+
+    /// Issues `SCAN`, or `CONTSCAN` if `continue_on_virus` is set, for a path on the ClamD
+    /// server, see `ClamClient::scan_path`.
+    fn scan_path(&self, path: &str, continue_on_virus: bool) -> ClamResult<Vec<ClamScanResult>>;
+
+    /// Streams `stream` to ClamD with `INSTREAM`, see `ClamClient::scan_stream`.
+    fn scan_stream(&self, stream: &mut dyn Read) -> ClamResult<ClamScanResult>;
+
+    /// Issues `STATS`, see `ClamClient::stats`.
+    fn stats(&self) -> ClamResult<ClamStats>;
+
+    /// Streams `data` to ClamD with `INSTREAM`, by default through `ClamScan::scan_stream`.
+    fn scan_bytes(&self, mut data: &[u8]) -> ClamResult<ClamScanResult> {
+        self.scan_stream(&mut data)
+    }
+}
+
+impl ClamScan for ClamClient {
+    fn ping(&self) -> bool {
+        ClamClient::ping(self)
+    }
+
+    fn version(&self) -> ClamResult<ClamVersion> {
+        ClamClient::version(self)
+    }
+
+    fn scan_path(&self, path: &str, continue_on_virus: bool) -> ClamResult<Vec<ClamScanResult>> {
+        ClamClient::scan_path(self, path, continue_on_virus)
+    }
+
+    fn scan_stream(&self, stream: &mut dyn Read) -> ClamResult<ClamScanResult> {
+        ClamClient::scan_stream(self, stream)
+    }
+
+    fn stats(&self) -> ClamResult<ClamStats> {
+        ClamClient::stats(self)
+    }
+
+    fn scan_bytes(&self, data: &[u8]) -> ClamResult<ClamScanResult> {
+        ClamClient::scan_bytes(self, data)
+    }
+}
+
+impl<T: ClamScan + ?Sized> ClamScan for Arc<T> {
+    fn ping(&self) -> bool {
+        (**self).ping()
+    }
+
+    fn version(&self) -> ClamResult<ClamVersion> {
+        (**self).version()
+    }
+
+    fn scan_path(&self, path: &str, continue_on_virus: bool) -> ClamResult<Vec<ClamScanResult>> {
+        (**self).scan_path(path, continue_on_virus)
+    }
+
+    fn scan_stream(&self, stream: &mut dyn Read) -> ClamResult<ClamScanResult> {
+        (**self).scan_stream(stream)
+    }
+
+    fn stats(&self) -> ClamResult<ClamStats> {
+        (**self).stats()
+    }
+
+    fn scan_bytes(&self, data: &[u8]) -> ClamResult<ClamScanResult> {
+        (**self).scan_bytes(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::response::{ClamScanResult, EICAR};
+    use crate::scan::ClamScan;
+    use crate::testing::fake_clamd;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_client_as_clam_scan() {
+        let port = fake_clamd(|cmd| match cmd {
+            b"zPING" => b"PONG".to_vec(),
+            b"zVERSION" => b"ClamAV 1.0.1/26900/Mon May  1 07:10:00 2023".to_vec(),
+            b"zSCAN /some/file" => b"/some/file: OK".to_vec(),
+            _ => b"stream: Eicar-Signature FOUND".to_vec(),
+        });
+        let scanner: Arc<dyn ClamScan + Send + Sync> =
+            Arc::new(ClamClient::new("127.0.0.1", port).unwrap());
+
+        assert!(scanner.ping());
+        assert_eq!(scanner.version().unwrap().build_number, 26900);
+        assert_eq!(
+            scanner.scan_path("/some/file", false).unwrap(),
+            [ClamScanResult::Ok]
+        );
+        assert_eq!(
+            scanner.scan_bytes(EICAR).unwrap(),
+            ClamScanResult::Found("stream".to_owned(), "Eicar-Signature".to_owned())
+        );
+        assert_eq!(
+            scanner.scan_stream(&mut &EICAR[..]).unwrap(),
+            scanner.scan_bytes(EICAR).unwrap()
+        );
+    }
+}
@@ -0,0 +1,947 @@
+//! `Queue` runs scan jobs submitted from anywhere in an application on a fixed set of worker
+//! threads, so that the number of concurrent connections to ClamD is bounded however many callers
+//! there are. The queue itself is bounded too: once it is full, submitting a job blocks until a
+//! worker has taken one, pushing back on producers which outpace ClamD.
+//!
+//! Each job's result is delivered either to the `JobHandle` returned when it was submitted, or to
+//! a channel shared by many jobs, see `Queue::submit_to`.
+//!
+//! Jobs are submitted with a `Priority`, so that latency sensitive scans, such as a file a user is
+//! waiting to upload, are taken ahead of bulk work, such as re-scanning stored files after a
+//! signature update, whenever ClamD cannot keep up with both.
+//!
+//! A job may also be given a deadline, such as the time by which an API request must be answered,
+//! after which it is abandoned and its result is `ClamError::TimedOut`, see
+//! `Queue::submit_with_deadline`.
+//!
+//! `Queue::shutdown` stops a queue gracefully, such as when a service is restarting, draining the
+//! jobs already submitted for as long as it is allowed and reporting those it had to abandon.
+
+use crate::client::{ClamClient, ClamResult};
+use crate::error::ClamError;
+use crate::response::ClamScanResult;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+/// The results of a job, a single result for `Job::Bytes` and `Job::Reader`, or one per file for
+/// `Job::Path`.
+pub type JobResult = ClamResult<Vec<ClamScanResult>>;
+
+/// `Job` is the payload of a scan submitted to a `Queue`.
+pub enum Job {
+    /// A path on the ClamD server, scanned with `CONTSCAN`
+    Path(String),
+    /// Data held in memory, scanned with `INSTREAM`
+    Bytes(Vec<u8>),
+    /// Data read from a stream, such as an open file, scanned with `INSTREAM`
+    Reader(Box<dyn Read + Send>),
+}
+
+impl Job {
+    /// Creates a job scanning the data read from `reader`.
+    pub fn reader<R: Read + Send + 'static>(reader: R) -> Job {
+        Job::Reader(Box::new(reader))
+    }
+
+    /// Scans the job with `client`, aborting a `Job::Reader` at its next read once `cancelled`
+    /// is set.
+    fn run(self, client: &ClamClient, cancelled: &AtomicBool) -> JobResult {
+        match self {
+            Job::Path(path) => client.scan_path(&path, true),
+            Job::Bytes(data) => client.scan_bytes(&data).map(|r| vec![r]),
+            Job::Reader(reader) => client
+                .scan_stream(Cancellable { reader, cancelled })
+                .map(|r| vec![r]),
+        }
+    }
+}
+
+/// Fails every read once `cancelled` is set, so that streaming stops and the connection to ClamD
+/// is closed rather than left to finish.
+struct Cancellable<'a, R> {
+    reader: R,
+    cancelled: &'a AtomicBool,
+}
+
+impl<R: Read> Read for Cancellable<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(io::Error::other("scan job cancelled"));
+        }
+        self.reader.read(buf)
+    }
+}
+
+impl fmt::Debug for Job {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Job::Path(path) => write!(f, "Job::Path({:?})", path),
+            Job::Bytes(data) => write!(f, "Job::Bytes({} bytes)", data.len()),
+            Job::Reader(_) => write!(f, "Job::Reader"),
+        }
+    }
+}
+
+/// `Priority` is the class of a job submitted to a `Queue`. Workers always take the oldest job of
+/// the highest priority waiting, so a steady stream of higher priority jobs delays lower priority
+/// ones indefinitely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// Bulk work which can wait, such as periodic re-scans
+    Low,
+    /// The priority of jobs submitted without one
+    #[default]
+    Normal,
+    /// Latency sensitive work, such as scans a user is waiting on
+    High,
+}
+
+impl Priority {
+    /// Every priority, lowest first.
+    const ALL: [Priority; 3] = [Priority::Low, Priority::Normal, Priority::High];
+}
+
+/// `CompletedJob` is the result of a job submitted with `Queue::submit_to`, tagged with the id it
+/// was submitted under.
+#[derive(Debug)]
+pub struct CompletedJob {
+    /// The id of the job, as returned by `Queue::submit_to`
+    pub id: u64,
+    /// The results of the job
+    pub result: JobResult,
+}
+
+/// `ShutdownReport` records the jobs a `Queue` abandoned when it was shut down. The result of each
+/// is delivered as `ClamError::JobAbandoned`.
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    /// The jobs which were still waiting to be scanned at the deadline, along with their ids, so
+    /// that they may be persisted and resubmitted once the service restarts
+    pub dropped: Vec<(u64, Job)>,
+    /// The ids of the jobs which were being scanned at the deadline. A `Job::Reader` still
+    /// streaming is aborted, whilst ClamD's verdict for any other job is discarded when it
+    /// arrives.
+    pub cancelled: Vec<u64>,
+}
+
+impl ShutdownReport {
+    /// Returns true if every job submitted was scanned before the deadline.
+    pub fn is_complete(&self) -> bool {
+        self.dropped.is_empty() && self.cancelled.is_empty()
+    }
+}
+
+/// `JobHandle` receives the result of a single job submitted with `Queue::submit`.
+#[derive(Debug)]
+pub struct JobHandle {
+    id: u64,
+    result: Receiver<JobResult>,
+}
+
+impl JobHandle {
+    /// Returns the id of the job.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Blocks until the job has been scanned, returning its results.
+    pub fn wait(self) -> JobResult {
+        self.result.recv().unwrap_or(Err(ClamError::JobAbandoned))
+    }
+
+    /// Returns the results of the job if it has been scanned, or `None` if it is still queued or
+    /// being scanned.
+    pub fn try_result(&self) -> Option<JobResult> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(ClamError::JobAbandoned)),
+        }
+    }
+}
+
+/// Where the result of a job is delivered.
+enum Delivery {
+    Handle(SyncSender<JobResult>),
+    Channel(Sender<CompletedJob>),
+}
+
+impl Delivery {
+    /// Delivers the result of the job with `id`, ignoring receivers which have since been
+    /// dropped.
+    fn deliver(self, id: u64, result: JobResult) {
+        match self {
+            Delivery::Handle(sender) => {
+                let _ = sender.send(result);
+            }
+            Delivery::Channel(sender) => {
+                let _ = sender.send(CompletedJob { id, result });
+            }
+        }
+    }
+}
+
+/// A job waiting in the queue.
+struct Task {
+    id: u64,
+    job: Job,
+    deadline: Option<Instant>,
+    delivery: Delivery,
+}
+
+impl Task {
+    /// Returns true if the job's deadline has passed.
+    fn expired(&self, now: Instant) -> bool {
+        self.deadline.is_some_and(|deadline| deadline <= now)
+    }
+}
+
+/// A job being scanned by a worker. Its delivery is taken from it if it is cancelled or times
+/// out, in which case the worker discards its result.
+struct Running {
+    delivery: Option<Delivery>,
+    deadline: Option<Instant>,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// The state shared between a queue and its workers.
+struct Shared {
+    client: ClamClient,
+    capacity: usize,
+    state: Mutex<QueueState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    finished: Condvar,
+    timer: Condvar,
+}
+
+/// The book keeping for a `Queue`, guarded by its mutex.
+struct QueueState {
+    /// The jobs waiting at each priority, indexed by `Priority as usize`
+    tasks: [VecDeque<Task>; Priority::ALL.len()],
+    running: HashMap<u64, Running>,
+    closed: bool,
+}
+
+impl QueueState {
+    /// Removes the oldest job of the highest priority waiting.
+    fn pop(&mut self) -> Option<Task> {
+        self.tasks.iter_mut().rev().find_map(VecDeque::pop_front)
+    }
+
+    /// Returns true once no jobs are waiting or being scanned.
+    fn is_idle(&self) -> bool {
+        self.running.is_empty() && self.tasks.iter().all(VecDeque::is_empty)
+    }
+}
+
+/// `Queue` holds up to `capacity` scan jobs of each `Priority` whilst `workers` threads take them,
+/// highest priority first and otherwise in the order they were submitted, and scan them with a
+/// shared `ClamClient`. As each priority has its own capacity, a backlog of low priority jobs
+/// never blocks the submission of a high priority one. Dropping the queue stops it accepting
+/// jobs, waits for those already submitted to be scanned, and then stops the workers, whilst
+/// `shutdown` bounds how long that may take. `Queue` is `Send + Sync`, so may be shared between
+/// threads behind an `Arc`.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::scanner::{Job, Priority, Queue};
+/// use std::fs::File;
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///     let queue = Queue::new(client, 4, 64);
+///
+///     let hosts = queue.submit(Job::reader(File::open("/etc/hosts").unwrap()));
+///     let upload = queue.submit_with_priority(
+///         Job::Bytes(b"an uploaded file".to_vec()),
+///         Priority::High,
+///     );
+///
+///     for handle in [upload, hosts] {
+///         if let Ok(results) = handle.wait() {
+///             println!("{:?}", results);
+///         }
+///     }
+/// }
+/// ```
+pub struct Queue {
+    shared: Arc<Shared>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+    next_id: AtomicU64,
+}
+
+impl Queue {
+    /// Creates a new `Queue`, starting its worker threads.
+    ///
+    /// *Arguments*
+    ///
+    /// - `client`: The client used to scan jobs
+    /// - `workers`: The number of jobs scanned concurrently, at least 1
+    /// - `capacity`: The number of jobs of each priority which may wait to be scanned before
+    ///   submitting another of that priority blocks, at least 1
+    pub fn new(client: ClamClient, workers: usize, capacity: usize) -> Queue {
+        let shared = Arc::new(Shared {
+            client,
+            capacity: capacity.max(1),
+            state: Mutex::new(QueueState {
+                tasks: Default::default(),
+                running: HashMap::new(),
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            finished: Condvar::new(),
+            timer: Condvar::new(),
+        });
+
+        let mut workers: Vec<_> = (0..workers.max(1))
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || shared.work())
+            })
+            .collect();
+        let timer = shared.clone();
+        workers.push(thread::spawn(move || timer.time_out()));
+
+        Queue {
+            shared,
+            workers: Mutex::new(workers),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of jobs waiting to be scanned, excluding those being scanned.
+    pub fn len(&self) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        state.tasks.iter().map(VecDeque::len).sum()
+    }
+
+    /// Returns true if no jobs are waiting to be scanned.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Submits `job` with `Priority::Normal`, blocking whilst the queue is full, and returns a
+    /// handle to its result.
+    pub fn submit(&self, job: Job) -> JobHandle {
+        self.submit_with_priority(job, Priority::Normal)
+    }
+
+    /// Submits `job` with `priority`, blocking whilst the queue holds `capacity` jobs of that
+    /// priority, and returns a handle to its result. Once the queue is shut down the result is
+    /// `ClamError::QueueClosed`.
+    pub fn submit_with_priority(&self, job: Job, priority: Priority) -> JobHandle {
+        let (sender, result) = mpsc::sync_channel(1);
+        let id = self.enqueue(job, priority, None, Delivery::Handle(sender), true);
+        JobHandle {
+            id: id.unwrap_or_else(|_| unreachable!("blocking submissions always succeed")),
+            result,
+        }
+    }
+
+    /// Submits `job` as `submit` does, unless the queue is full or shut down, in which case the
+    /// job is handed back rather than blocking.
+    pub fn try_submit(&self, job: Job) -> Result<JobHandle, Job> {
+        self.try_submit_with_priority(job, Priority::Normal)
+    }
+
+    /// Submits `job` as `submit_with_priority` does, unless the queue is full or shut down, in
+    /// which case the job is handed back rather than blocking.
+    pub fn try_submit_with_priority(&self, job: Job, priority: Priority) -> Result<JobHandle, Job> {
+        let (sender, result) = mpsc::sync_channel(1);
+        let id = self.enqueue(job, priority, None, Delivery::Handle(sender), false)?;
+        Ok(JobHandle { id, result })
+    }
+
+    /// Submits `job` with `Priority::Normal`, blocking whilst the queue is full, and returns its
+    /// id. The result is sent to `results` as a `CompletedJob` tagged with the id, so that many
+    /// jobs can be collected from one channel as they complete, in whatever order that is.
+    pub fn submit_to(&self, job: Job, results: &Sender<CompletedJob>) -> u64 {
+        self.submit_to_with_priority(job, Priority::Normal, results)
+    }
+
+    /// Submits `job` as `submit_to` does, but with `priority`.
+    pub fn submit_to_with_priority(
+        &self,
+        job: Job,
+        priority: Priority,
+        results: &Sender<CompletedJob>,
+    ) -> u64 {
+        let delivery = Delivery::Channel(results.clone());
+        let id = self.enqueue(job, priority, None, delivery, true);
+        id.unwrap_or_else(|_| unreachable!("blocking submissions always succeed"))
+    }
+
+    /// Submits `job` as `submit_with_priority` does, but abandons it if it has not been scanned
+    /// by `deadline`, in which case its result is `ClamError::TimedOut`. A job still waiting at
+    /// the deadline is never scanned, whilst a `Job::Reader` still streaming is aborted, closing
+    /// its connection to ClamD. ClamD's verdict for any other job is discarded when it arrives.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::error::ClamError;
+    /// use clam_client::scanner::{Job, Priority, Queue};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///     let queue = Queue::new(client, 4, 64);
+    ///
+    ///     let deadline = Instant::now() + Duration::from_secs(2);
+    ///     let upload = b"an uploaded file".to_vec();
+    ///     match queue
+    ///         .submit_with_deadline(Job::Bytes(upload), Priority::High, deadline)
+    ///         .wait()
+    ///     {
+    ///         Err(ClamError::TimedOut) => println!("Not scanned in time, try again later"),
+    ///         result => println!("{:?}", result),
+    ///     }
+    /// }
+    /// ```
+    pub fn submit_with_deadline(
+        &self,
+        job: Job,
+        priority: Priority,
+        deadline: Instant,
+    ) -> JobHandle {
+        let (sender, result) = mpsc::sync_channel(1);
+        let id = self.enqueue(
+            job,
+            priority,
+            Some(deadline),
+            Delivery::Handle(sender),
+            true,
+        );
+        JobHandle {
+            id: id.unwrap_or_else(|_| unreachable!("blocking submissions always succeed")),
+            result,
+        }
+    }
+
+    /// Submits `job` as `submit_to_with_priority` does, but with a `deadline`, as with
+    /// `submit_with_deadline`.
+    pub fn submit_to_with_deadline(
+        &self,
+        job: Job,
+        priority: Priority,
+        deadline: Instant,
+        results: &Sender<CompletedJob>,
+    ) -> u64 {
+        let delivery = Delivery::Channel(results.clone());
+        let id = self.enqueue(job, priority, Some(deadline), delivery, true);
+        id.unwrap_or_else(|_| unreachable!("blocking submissions always succeed"))
+    }
+
+    /// Adds a job to the back of the queue for its priority, waiting for space if `block` is set
+    /// and otherwise handing the job back if that queue is full. A blocking submission to a queue
+    /// which has been shut down is delivered `ClamError::QueueClosed`, and one still waiting for
+    /// space at its deadline `ClamError::TimedOut`.
+    fn enqueue(
+        &self,
+        job: Job,
+        priority: Priority,
+        deadline: Option<Instant>,
+        delivery: Delivery,
+        block: bool,
+    ) -> Result<u64, Job> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            let rejected = if state.closed {
+                ClamError::QueueClosed
+            } else if state.tasks[priority as usize].len() < self.shared.capacity {
+                break;
+            } else if !block {
+                return Err(job);
+            } else {
+                match deadline.map(|d| d.checked_duration_since(Instant::now())) {
+                    None => {
+                        state = self.shared.not_full.wait(state).unwrap();
+                        continue;
+                    }
+                    Some(Some(remaining)) => {
+                        state = self
+                            .shared
+                            .not_full
+                            .wait_timeout(state, remaining)
+                            .unwrap()
+                            .0;
+                        continue;
+                    }
+                    Some(None) => ClamError::TimedOut,
+                }
+            };
+
+            if !block {
+                return Err(job);
+            }
+            drop(state);
+            delivery.deliver(id, Err(rejected));
+            return Ok(id);
+        }
+
+        state.tasks[priority as usize].push_back(Task {
+            id,
+            job,
+            deadline,
+            delivery,
+        });
+        self.shared.not_empty.notify_one();
+        if deadline.is_some() {
+            self.shared.timer.notify_one();
+        }
+        Ok(id)
+    }
+
+    /// Stops the queue accepting jobs and waits until `deadline` for those already submitted to
+    /// be scanned. Any which are still waiting at the deadline are dropped and any still being
+    /// scanned are cancelled, both are listed in the returned `ShutdownReport`. Further
+    /// submissions fail with `ClamError::QueueClosed`.
+    ///
+    /// *Example*
+    ///
+    /// ```rust
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    /// use clam_client::scanner::{Job, Queue};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///     let queue = Queue::new(client, 4, 64);
+    ///     queue.submit(Job::Path("/srv/uploads".to_owned()));
+    ///
+    ///     let report = queue.shutdown(Instant::now() + Duration::from_secs(10));
+    ///     for (id, job) in report.dropped {
+    ///         println!("Job {} was not scanned: {:?}", id, job);
+    ///     }
+    /// }
+    /// ```
+    pub fn shutdown(&self, deadline: Instant) -> ShutdownReport {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        self.shared.not_empty.notify_all();
+        self.shared.not_full.notify_all();
+        self.shared.timer.notify_one();
+
+        while !state.is_idle() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self
+                .shared
+                .finished
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        let mut report = ShutdownReport::default();
+        for task in state
+            .tasks
+            .iter_mut()
+            .rev()
+            .flat_map(|tasks| tasks.drain(..))
+        {
+            task.delivery.deliver(task.id, Err(ClamError::JobAbandoned));
+            report.dropped.push((task.id, task.job));
+        }
+        for (id, running) in state.running.iter_mut() {
+            running.cancelled.store(true, Ordering::SeqCst);
+            if let Some(delivery) = running.delivery.take() {
+                delivery.deliver(*id, Err(ClamError::JobAbandoned));
+                report.cancelled.push(*id);
+            }
+        }
+        let idle = state.running.is_empty();
+        drop(state);
+
+        // Workers which have finished exit straight away, whilst those cancelled are left to
+        // exit once ClamD replies rather than holding up the shutdown
+        let mut workers = self.workers.lock().unwrap();
+        if idle {
+            for worker in workers.drain(..) {
+                let _ = worker.join();
+            }
+        } else {
+            workers.clear();
+        }
+
+        report.cancelled.sort_unstable();
+        report
+    }
+}
+
+impl Shared {
+    /// Scans jobs until the queue is closed and empty.
+    fn work(&self) {
+        loop {
+            let (id, job, cancelled) = {
+                let mut state = self.state.lock().unwrap();
+                let Task {
+                    id,
+                    job,
+                    deadline,
+                    delivery,
+                } = loop {
+                    if let Some(task) = state.pop() {
+                        // Submitters of other priorities may be waiting, so wake them all
+                        self.not_full.notify_all();
+                        if task.expired(Instant::now()) {
+                            task.delivery.deliver(task.id, Err(ClamError::TimedOut));
+                            continue;
+                        }
+                        break task;
+                    }
+                    if state.closed {
+                        return;
+                    }
+                    state = self.not_empty.wait(state).unwrap();
+                };
+
+                // Recorded as running under the same lock, so a shutdown never sees it as neither
+                let cancelled = Arc::new(AtomicBool::new(false));
+                state.running.insert(
+                    id,
+                    Running {
+                        delivery: Some(delivery),
+                        deadline,
+                        cancelled: cancelled.clone(),
+                    },
+                );
+                (id, job, cancelled)
+            };
+
+            let result = job.run(&self.client, &cancelled);
+
+            let running = self.state.lock().unwrap().running.remove(&id);
+            if let Some(delivery) = running.and_then(|r| r.delivery) {
+                delivery.deliver(id, result);
+            }
+            self.finished.notify_all();
+            self.timer.notify_one();
+        }
+    }
+
+    /// Times out jobs as their deadlines pass, until the queue is closed and idle. Jobs still
+    /// waiting are removed from the queue, whilst those being scanned are cancelled.
+    fn time_out(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+
+            for tasks in state.tasks.iter_mut() {
+                if !tasks.iter().any(|task| task.expired(now)) {
+                    continue;
+                }
+                let (expired, waiting) = tasks.drain(..).partition(|task| task.expired(now));
+                *tasks = waiting;
+                for task in expired {
+                    task.delivery.deliver(task.id, Err(ClamError::TimedOut));
+                }
+                self.not_full.notify_all();
+            }
+            for (id, running) in state.running.iter_mut() {
+                if running.deadline.is_some_and(|deadline| deadline <= now) {
+                    running.cancelled.store(true, Ordering::SeqCst);
+                    if let Some(delivery) = running.delivery.take() {
+                        delivery.deliver(*id, Err(ClamError::TimedOut));
+                    }
+                }
+            }
+
+            let waiting = state
+                .tasks
+                .iter()
+                .flatten()
+                .filter_map(|task| task.deadline);
+            let running = state.running.values().filter(|r| r.delivery.is_some());
+            let next = waiting.chain(running.filter_map(|r| r.deadline)).min();
+
+            if state.closed && state.is_idle() {
+                return;
+            }
+            state = match next {
+                Some(next) => {
+                    let remaining = next.saturating_duration_since(now);
+                    self.timer.wait_timeout(state, remaining).unwrap().0
+                }
+                None => self.timer.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.not_empty.notify_all();
+        self.shared.not_full.notify_all();
+        self.shared.timer.notify_one();
+
+        for worker in self.workers.get_mut().unwrap().drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::error::ClamError;
+    use crate::response::ClamScanResult;
+    use crate::scanner::{Job, Priority, Queue};
+    use crate::testing::fake_clamd;
+    use std::io::{self, Read};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_queue_jobs() {
+        let port = fake_clamd(|cmd| {
+            if cmd.starts_with(b"zCONTSCAN") {
+                b"/tmp/a: OK\0/tmp/b: Sig-One FOUND".to_vec()
+            } else if cmd.ends_with(b"bad") {
+                b"stream: Sig-One FOUND".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        let queue = Queue::new(ClamClient::new("127.0.0.1", port).unwrap(), 2, 4);
+
+        let path = queue.submit(Job::Path("/tmp".to_owned()));
+        let bytes = queue.submit(Job::Bytes(b"bad".to_vec()));
+        let reader = queue.submit(Job::reader(&b"good"[..]));
+        assert_ne!(path.id(), bytes.id());
+
+        assert_eq!(
+            path.wait().unwrap(),
+            vec![
+                ClamScanResult::Ok,
+                ClamScanResult::Found("/tmp/b".to_owned(), "Sig-One".to_owned())
+            ]
+        );
+        assert_eq!(
+            bytes.wait().unwrap(),
+            vec![ClamScanResult::Found(
+                "stream".to_owned(),
+                "Sig-One".to_owned()
+            )]
+        );
+        assert_eq!(reader.wait().unwrap(), vec![ClamScanResult::Ok]);
+
+        let (results, completed) = mpsc::channel();
+        let ids: Vec<_> = (0..8)
+            .map(|_| queue.submit_to(Job::Bytes(b"good".to_vec()), &results))
+            .collect();
+        let mut received: Vec<_> = completed.iter().take(8).map(|c| c.id).collect();
+        received.sort_unstable();
+        assert_eq!(received, ids);
+    }
+
+    #[test]
+    fn test_queue_back_pressure() {
+        static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+        static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+        let port = fake_clamd(|_| {
+            let active = ACTIVE.fetch_add(1, Ordering::SeqCst) + 1;
+            PEAK.fetch_max(active, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(100));
+            ACTIVE.fetch_sub(1, Ordering::SeqCst);
+            b"stream: OK".to_vec()
+        });
+        let queue = Queue::new(ClamClient::new("127.0.0.1", port).unwrap(), 1, 1);
+
+        // The first job is taken by the worker and the second fills the queue
+        let first = queue.submit(Job::Bytes(b"one".to_vec()));
+        thread::sleep(Duration::from_millis(20));
+        let second = queue.submit(Job::Bytes(b"two".to_vec()));
+        let third = match queue.try_submit(Job::Bytes(b"three".to_vec())) {
+            Err(Job::Bytes(data)) => data,
+            _ => panic!("expected the full queue to hand the job back"),
+        };
+        assert_eq!(queue.len(), 1);
+
+        // Whilst a blocking submission waits for space
+        let third = queue.submit(Job::Bytes(third));
+        for handle in [first, second, third] {
+            assert_eq!(handle.wait().unwrap(), vec![ClamScanResult::Ok]);
+        }
+        assert_eq!(PEAK.load(Ordering::SeqCst), 1);
+
+        // Dropping the queue scans the jobs already submitted
+        let (results, completed) = mpsc::channel();
+        queue.submit_to(Job::Bytes(b"four".to_vec()), &results);
+        drop(queue);
+        assert!(completed.try_recv().unwrap().result.is_ok());
+    }
+
+    #[test]
+    fn test_queue_priority() {
+        let port = fake_clamd(|_| {
+            thread::sleep(Duration::from_millis(50));
+            b"stream: OK".to_vec()
+        });
+        let queue = Queue::new(ClamClient::new("127.0.0.1", port).unwrap(), 1, 2);
+
+        // Occupy the only worker, then fill the low priority queue
+        let (results, completed) = mpsc::channel();
+        let busy = queue.submit_to(Job::Bytes(b"busy".to_vec()), &results);
+        thread::sleep(Duration::from_millis(20));
+        let low: Vec<_> = (0..2)
+            .map(|_| queue.submit_to_with_priority(Job::Bytes(vec![]), Priority::Low, &results))
+            .collect();
+        assert!(queue
+            .try_submit_with_priority(Job::Bytes(vec![]), Priority::Low)
+            .is_err());
+
+        // Whilst high and normal priority jobs are still accepted, and jump ahead
+        let normal = queue.submit_to(Job::Bytes(vec![]), &results);
+        let high = queue.submit_to_with_priority(Job::Bytes(vec![]), Priority::High, &results);
+        assert_eq!(queue.len(), 4);
+
+        let order: Vec<_> = completed.iter().take(5).map(|c| c.id).collect();
+        assert_eq!(order, vec![busy, high, normal, low[0], low[1]]);
+    }
+
+    #[test]
+    fn test_queue_shutdown() {
+        let port = fake_clamd(|cmd| {
+            if cmd.ends_with(b"slow") {
+                thread::sleep(Duration::from_millis(500));
+            }
+            b"stream: OK".to_vec()
+        });
+
+        // A queue which drains within the deadline completes every job
+        let queue = Queue::new(ClamClient::new("127.0.0.1", port).unwrap(), 2, 8);
+        let handles: Vec<_> = (0..4)
+            .map(|_| queue.submit(Job::Bytes(b"fast".to_vec())))
+            .collect();
+        let report = queue.shutdown(Instant::now() + Duration::from_secs(5));
+        assert!(report.is_complete());
+        for handle in handles {
+            assert_eq!(handle.wait().unwrap(), vec![ClamScanResult::Ok]);
+        }
+
+        // Whilst one which does not drops the waiting jobs and cancels the running ones
+        let queue = Queue::new(ClamClient::new("127.0.0.1", port).unwrap(), 1, 8);
+        let running = queue.submit(Job::Bytes(b"slow".to_vec()));
+        thread::sleep(Duration::from_millis(50));
+        let waiting = queue.submit(Job::Bytes(b"fast".to_vec()));
+
+        let report = queue.shutdown(Instant::now() + Duration::from_millis(100));
+        assert_eq!(report.cancelled, vec![running.id()]);
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0].0, waiting.id());
+        assert!(matches!(report.dropped[0].1, Job::Bytes(_)));
+        assert!(matches!(running.wait(), Err(ClamError::JobAbandoned)));
+        assert!(matches!(waiting.wait(), Err(ClamError::JobAbandoned)));
+
+        // And neither accepts further jobs
+        assert!(queue.try_submit(Job::Bytes(vec![])).is_err());
+        assert!(matches!(
+            queue.submit(Job::Bytes(vec![])).wait(),
+            Err(ClamError::QueueClosed)
+        ));
+    }
+
+    #[test]
+    fn test_queue_deadlines() {
+        static STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+        let port = fake_clamd(|cmd| {
+            STREAMS.fetch_add(1, Ordering::SeqCst);
+            if cmd.ends_with(b"slow") {
+                thread::sleep(Duration::from_millis(500));
+            }
+            b"stream: OK".to_vec()
+        });
+        let queue = Queue::new(ClamClient::new("127.0.0.1", port).unwrap(), 1, 8);
+
+        // The running job times out at its deadline, rather than when ClamD replies, and the job
+        // waiting behind it is never scanned
+        let start = Instant::now();
+        let running = queue.submit_with_deadline(
+            Job::Bytes(b"slow".to_vec()),
+            Priority::Normal,
+            start + Duration::from_millis(100),
+        );
+        let waiting = queue.submit_with_deadline(
+            Job::Bytes(b"fast".to_vec()),
+            Priority::Normal,
+            start + Duration::from_millis(50),
+        );
+        let unbounded = queue.submit(Job::Bytes(b"fast".to_vec()));
+
+        assert!(matches!(waiting.wait(), Err(ClamError::TimedOut)));
+        assert!(matches!(running.wait(), Err(ClamError::TimedOut)));
+        assert!(start.elapsed() < Duration::from_millis(400));
+        assert_eq!(unbounded.wait().unwrap(), vec![ClamScanResult::Ok]);
+        assert_eq!(STREAMS.load(Ordering::SeqCst), 2);
+
+        // Whilst a job which meets its deadline is unaffected
+        let (results, completed) = mpsc::channel();
+        queue.submit_to_with_deadline(
+            Job::Bytes(b"fast".to_vec()),
+            Priority::High,
+            Instant::now() + Duration::from_secs(5),
+            &results,
+        );
+        assert!(completed.recv().unwrap().result.is_ok());
+    }
+
+    #[test]
+    fn test_queue_deadline_aborts_stream() {
+        static READS: AtomicUsize = AtomicUsize::new(0);
+
+        /// Trickles out a byte at a time, for up to two seconds.
+        struct Trickle;
+
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if READS.fetch_add(1, Ordering::SeqCst) == 100 {
+                    return Ok(0);
+                }
+                thread::sleep(Duration::from_millis(20));
+                buf[0] = b'x';
+                Ok(1)
+            }
+        }
+
+        let port = fake_clamd(|_| b"stream: OK".to_vec());
+        let queue = Queue::new(ClamClient::new("127.0.0.1", port).unwrap(), 1, 8);
+
+        let handle = queue.submit_with_deadline(
+            Job::reader(Trickle),
+            Priority::Normal,
+            Instant::now() + Duration::from_millis(100),
+        );
+        assert!(matches!(handle.wait(), Err(ClamError::TimedOut)));
+
+        // The worker stops reading once the job is cancelled, freeing it for the next job
+        let next = queue.submit(Job::Bytes(b"next".to_vec()));
+        assert_eq!(next.wait().unwrap(), vec![ClamScanResult::Ok]);
+        assert!(READS.load(Ordering::SeqCst) < 20);
+    }
+}
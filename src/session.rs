@@ -0,0 +1,592 @@
+//! `ClamSession` implements the ClamD `IDSESSION` / `END` commands, allowing multiple commands
+//! to be issued over a single connection rather than reconnecting for every command.
+//!
+//! Whilst in a session ClamD prefixes each reply with the ID of the request it answers, this
+//! module strips and validates that prefix so the results can be handled exactly as they are
+//! from `ClamClient`.
+//!
+//! Scans may also be pipelined, several being queued with `ClamSession::queue_scan_path` or
+//! `ClamSession::queue_stream` before any of their results are read. ClamD answers pipelined
+//! requests as each completes, which may not be the order they were queued in, so every reply is
+//! matched back to its request by ID.
+//!
+//! *Note*: ClamD gives no indication of when a multi-part reply has finished, so only commands
+//! which produce a single reply are supported within a session.
+
+use crate::client::{
+    check_reply, connection_write, parse_results, recover_stream_error, stream_result,
+    write_stream, ClamResult, COMMAND_READ_TIMED_OUT_REPLY,
+};
+use crate::error::{ClamError, Phase};
+use crate::observer::Observers;
+use crate::protocol;
+use crate::redact::{Redact, Redaction};
+use crate::response::{ClamScanResult, ClamStats, ClamVersion, ServerInfo};
+use crate::transport::Connection;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::io::{ErrorKind, Read};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// How long a session may be left idle before it is considered stale and is no longer reused,
+/// this is kept below ClamD's default `IdleTimeout` of 30 seconds.
+pub(crate) const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// `ClamSession` is an open connection to ClamD on which `IDSESSION` has been issued, it is
+/// obtained from `ClamClient::session` or from a `ClamPool`.
+///
+/// The session is closed with `END` when it is dropped.
+pub struct ClamSession {
+    reader: BufReader<Connection>,
+    endpoint: Option<SocketAddr>,
+    next_id: u64,
+    broken: bool,
+    options: SessionOptions,
+    outstanding: BTreeSet<u64>,
+    streams: BTreeSet<u64>,
+    received: HashMap<u64, String>,
+}
+
+/// `RequestId` identifies a request queued on a `ClamSession`, matching it to its result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RequestId(u64);
+
+/// The subset of `ClamClient`'s configuration which also applies to its sessions.
+pub(crate) struct SessionOptions {
+    pub(crate) stream_max_length: Option<u64>,
+    pub(crate) lenient_stats: bool,
+    pub(crate) strict_parsing: bool,
+    pub(crate) redaction: Redaction,
+    pub(crate) observers: Observers,
+    /// Describes the endpoint in the context of errors when the session has no `endpoint`, as
+    /// when connected over a Unix socket
+    pub(crate) peer: String,
+}
+
+impl ClamSession {
+    /// Starts a new session on an established connection to `endpoint` by issuing `IDSESSION`.
+    pub(crate) fn open(
+        connection: Connection,
+        endpoint: Option<SocketAddr>,
+        options: SessionOptions,
+    ) -> ClamResult<ClamSession> {
+        let mut session = ClamSession {
+            reader: BufReader::new(connection),
+            endpoint,
+            next_id: 1,
+            broken: false,
+            options,
+            outstanding: BTreeSet::new(),
+            streams: BTreeSet::new(),
+            received: HashMap::new(),
+        };
+
+        if let Err(e) = session.reader.get_mut().write_all(b"zIDSESSION\0") {
+            session.broken = true;
+            return Err(session.failed(e, Phase::Sending).for_command("IDSESSION"));
+        }
+
+        Ok(session)
+    }
+
+    /// Returns the ClamD endpoint the session is connected to, or `None` if it is connected over
+    /// a Unix socket.
+    pub fn endpoint(&self) -> Option<SocketAddr> {
+        self.endpoint
+    }
+
+    /// Returns true if an error has occurred on the underlying connection, in which case the
+    /// session cannot be used to issue any further commands.
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Returns true if the session can still be used, that is it has not failed and ClamD has not
+    /// since closed the connection, as it does when restarted, e.g. after `freshclam` triggers a
+    /// reload.
+    pub fn is_connected(&self) -> bool {
+        !self.broken && !self.reader.get_ref().is_closed()
+    }
+
+    /// Returns the number of queued requests whose results have not yet been collected.
+    pub fn pending_requests(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Implements the ClamD `PING` command, returns true if ClamD responds with `PONG`.
+    pub fn ping(&mut self) -> ClamResult<bool> {
+        let resp = self.send_command(b"zPING\0")?;
+        Ok(resp == "PONG")
+    }
+
+    /// Implements the ClamD `VERSION` command, returns a struct of `ClamVersion` if successful.
+    pub fn version(&mut self) -> ClamResult<ClamVersion> {
+        let resp = self.send_command(b"zVERSION\0")?;
+        ClamVersion::parse(resp).map_err(|e| e.redact(self.options.redaction))
+    }
+
+    /// Implements the ClamD `VERSIONCOMMANDS` command, returning the daemon's version and the
+    /// commands it supports. Unlike `ClamClient::server_info` the reply is never cached.
+    pub fn server_info(&mut self) -> ClamResult<ServerInfo> {
+        let resp = self.send_command(b"zVERSIONCOMMANDS\0")?;
+        ServerInfo::parse(resp).map_err(|e| e.redact(self.options.redaction))
+    }
+
+    /// Implements the ClamD `STATS` command, and returns a struct of `ClamStats`, see
+    /// `ClamClient::stats` for how the response is parsed.
+    pub fn stats(&mut self) -> ClamResult<ClamStats> {
+        let resp = self.send_command(b"zSTATS\0")?;
+        let stats = if self.options.lenient_stats {
+            ClamStats::parse_lenient(&resp)
+        } else {
+            match ClamStats::parse(&resp) {
+                Ok(stats) => Ok(stats),
+                Err(_) => ClamStats::parse_for_version(&resp, &self.version()?),
+            }
+        };
+        stats.map_err(|e| e.redact(self.options.redaction))
+    }
+
+    /// Implements the ClamD `SCAN` command for a single path, returns the `ClamScanResult` from
+    /// ClamD. Since `SCAN` stops at the first virus found, only one result is ever produced.
+    ///
+    /// *Arguments:*
+    ///
+    /// - `path`: The path to scan, this is a path that is on the ClamD server, or that it has access to.
+    pub fn scan_path(&mut self, path: &str) -> ClamResult<ClamScanResult> {
+        let resp = self.send_command(&format!("zSCAN {}\0", path).into_bytes())?;
+        single_result(resp, self.options.strict_parsing).redact(self.options.redaction)
+    }
+
+    /// Queues a `SCAN` of `path` without waiting for its result, which is collected with
+    /// `ClamSession::wait` or `ClamSession::next_result`.
+    ///
+    /// *Note*: ClamD's replies are only read when results are collected, so only queue as many
+    /// requests as ClamD's `MaxQueue` allows before collecting their results.
+    ///
+    /// *Example*
+    ///
+    /// ```rust,no_run
+    /// extern crate clam_client;
+    ///
+    /// use clam_client::client::ClamClient;
+    ///
+    /// fn main() {
+    ///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+    ///     let mut session = client.session().unwrap();
+    ///
+    ///     let first = session.queue_scan_path("/tmp/first").unwrap();
+    ///     let second = session.queue_scan_path("/tmp/second").unwrap();
+    ///
+    ///     while let Some((id, result)) = session.next_result().unwrap() {
+    ///         let path = if id == first { "/tmp/first" } else { "/tmp/second" };
+    ///         println!("{}: {:?}", path, result);
+    ///     }
+    /// }
+    /// ```
+    pub fn queue_scan_path(&mut self, path: &str) -> ClamResult<RequestId> {
+        self.issue(&format!("zSCAN {}\0", path).into_bytes())
+            .map(RequestId)
+    }
+
+    /// Queues an `INSTREAM` of `stream` without waiting for its result, see
+    /// `ClamSession::queue_scan_path`. The stream is sent in full before this returns.
+    pub fn queue_stream<T: Read>(&mut self, stream: T) -> ClamResult<RequestId> {
+        let max_length = self.options.stream_max_length;
+        self.issue_stream(stream, max_length, &mut |_| {})
+            .map(RequestId)
+    }
+
+    /// Waits for the result of the queued request `id`, buffering the results of any other
+    /// requests which complete first.
+    pub fn wait(&mut self, id: RequestId) -> ClamResult<ClamScanResult> {
+        if !self.outstanding.contains(&id.0) {
+            return Err(ClamError::InvalidData(format!("unknown request {}", id.0)));
+        }
+
+        let reply = self.read_reply(id.0)?;
+        self.queued_result(id.0, reply)
+    }
+
+    /// Returns the result of whichever queued request completes next, or `None` once the results
+    /// of every queued request have been collected. The outer error is a failure of the session
+    /// itself, which fails every outstanding request.
+    pub fn next_result(&mut self) -> ClamResult<Option<(RequestId, ClamResult<ClamScanResult>)>> {
+        let id = match self.received.keys().min() {
+            Some(id) => *id,
+            None if self.outstanding.is_empty() => return Ok(None),
+            None => {
+                let (id, reply) = self.read_any_reply()?;
+                self.received.insert(id, reply);
+                id
+            }
+        };
+
+        let result = self
+            .read_reply(id)
+            .and_then(|reply| self.queued_result(id, reply));
+        Ok(Some((RequestId(id), result)))
+    }
+
+    /// Implements the ClamD `INSTREAM` command, see `ClamClient::scan_stream` for details.
+    pub fn scan_stream<T: Read>(&mut self, stream: T) -> ClamResult<ClamScanResult> {
+        let max_length = self.options.stream_max_length;
+        self.stream(stream, max_length, &mut |_| {})
+    }
+
+    /// Implements the ClamD `INSTREAM` command, see `ClamClient::scan_stream_with_progress` for
+    /// details.
+    pub fn scan_stream_with_progress<T, P>(
+        &mut self,
+        stream: T,
+        total_hint: Option<u64>,
+        mut progress: P,
+    ) -> ClamResult<ClamScanResult>
+    where
+        T: Read,
+        P: FnMut(u64, Option<u64>),
+    {
+        let max_length = self.options.stream_max_length;
+        self.stream(stream, max_length, &mut |sent| progress(sent, total_hint))
+    }
+
+    /// Streams to ClamD, abandoning the stream if it exceeds `max_length` and calling `progress`
+    /// with the total number of bytes sent after each chunk.
+    pub(crate) fn stream<T: Read>(
+        &mut self,
+        stream: T,
+        max_length: Option<u64>,
+        progress: &mut dyn FnMut(u64),
+    ) -> ClamResult<ClamScanResult> {
+        let id = self.issue_stream(stream, max_length, progress)?;
+        let resp = self.read_reply(id).map_err(|e| e.for_command("INSTREAM"))?;
+        self.streams.remove(&id);
+        stream_result(resp, self.options.strict_parsing).redact(self.options.redaction)
+    }
+
+    /// Writes `INSTREAM` and the stream to the session, returning the ID of the request.
+    fn issue_stream<T: Read>(
+        &mut self,
+        stream: T,
+        max_length: Option<u64>,
+        progress: &mut dyn FnMut(u64),
+    ) -> ClamResult<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let observers = &self.options.observers;
+        let written = connection_write(self.reader.get_mut(), b"zINSTREAM\0")
+            .inspect(|_| observers.command_sent(b"zINSTREAM\0"))
+            .and_then(|_| {
+                write_stream(
+                    self.reader.get_mut(),
+                    stream,
+                    max_length,
+                    observers,
+                    progress,
+                )
+            });
+
+        if let Err(e) = written {
+            self.broken = true;
+            let e = recover_stream_error(&mut self.reader, e).redact(self.options.redaction);
+            return Err(e
+                .in_phase(Phase::Sending, &self.peer())
+                .for_command("INSTREAM"));
+        }
+
+        self.outstanding.insert(id);
+        self.streams.insert(id);
+        Ok(id)
+    }
+
+    /// Issues `INSTREAM` without sending any data, for a `ClamScanWriter` which writes the chunks
+    /// itself with `write_stream_chunk`, returning the ID of the request.
+    pub(crate) fn begin_stream(&mut self) -> ClamResult<u64> {
+        let id = self
+            .issue(b"zINSTREAM\0")
+            .map_err(|e| e.for_command("INSTREAM"))?;
+        self.streams.insert(id);
+        Ok(id)
+    }
+
+    /// Writes a chunk of the stream begun with `begin_stream`.
+    pub(crate) fn write_stream_chunk(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        let written = protocol::write_chunk(self.reader.get_mut(), chunk);
+        self.broken |= written.is_err();
+        written
+    }
+
+    /// Terminates the stream begun as request `id` with `begin_stream`, and returns its result.
+    pub(crate) fn finish_stream(&mut self, id: u64) -> ClamResult<ClamScanResult> {
+        if let Err(e) = self.reader.get_mut().write_all(&protocol::END_OF_STREAM) {
+            self.broken = true;
+            return Err(self.failed(e, Phase::Sending).for_command("INSTREAM"));
+        }
+
+        let resp = self.read_reply(id).map_err(|e| e.for_command("INSTREAM"))?;
+        self.streams.remove(&id);
+        stream_result(resp, self.options.strict_parsing).redact(self.options.redaction)
+    }
+
+    /// Marks the session as unusable, for a stream which was abandoned part way through.
+    pub(crate) fn abandon(&mut self) {
+        self.broken = true;
+    }
+
+    /// Writes a command to the session and reads back the reply, stripping the request ID.
+    fn send_command(&mut self, command: &[u8]) -> ClamResult<String> {
+        self.issue(command)
+            .and_then(|id| self.read_reply(id))
+            .map_err(|e| e.for_command(&protocol::command_name(command)))
+    }
+
+    /// Writes a command to the session, returning the ID of the request.
+    fn issue(&mut self, command: &[u8]) -> ClamResult<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        if let Err(e) = self.reader.get_mut().write_all(command) {
+            self.broken = true;
+            return Err(self.failed(e, Phase::Sending));
+        }
+        self.options.observers.command_sent(command);
+
+        self.outstanding.insert(id);
+        Ok(id)
+    }
+
+    /// Parses the reply to the queued request `id` as the `INSTREAM` or `SCAN` it answers, the
+    /// latter exactly as `ClamClient::scan_path` parses it.
+    fn queued_result(&mut self, id: u64, reply: String) -> ClamResult<ClamScanResult> {
+        let strict = self.options.strict_parsing;
+        let result = if self.streams.remove(&id) {
+            stream_result(reply, strict)
+        } else {
+            single_result(reply, strict)
+        };
+        result.redact(self.options.redaction)
+    }
+
+    /// Describes the endpoint of the session for the context of an error.
+    fn peer(&self) -> String {
+        match self.endpoint {
+            Some(endpoint) => endpoint.to_string(),
+            None => self.options.peer.clone(),
+        }
+    }
+
+    /// Wraps an I/O error which occurred in `phase` of issuing a command to the session.
+    fn failed(&self, e: std::io::Error, phase: Phase) -> ClamError {
+        ClamError::CommandError(e).in_phase(phase, &self.peer())
+    }
+
+    /// Returns the reply to the request with the given `id`, reading replies from the session
+    /// until it arrives and holding on to those answering other outstanding requests.
+    fn read_reply(&mut self, id: u64) -> ClamResult<String> {
+        loop {
+            if let Some(reply) = self.received.remove(&id) {
+                self.outstanding.remove(&id);
+                return check_reply(reply);
+            }
+
+            let (reply_id, reply) = self.read_any_reply()?;
+            self.received.insert(reply_id, reply);
+        }
+    }
+
+    /// Reads a single NUL terminated reply from the session and validates that it answers an
+    /// outstanding request, returning the request's ID and the reply with the ID stripped.
+    fn read_any_reply(&mut self) -> ClamResult<(u64, String)> {
+        let mut buf = Vec::new();
+
+        match self.reader.read_until(b'\0', &mut buf) {
+            Ok(0) => {
+                self.broken = true;
+                let e = ClamError::ConnectionError(ErrorKind::UnexpectedEof.into());
+                return Err(e.in_phase(Phase::Receiving, &self.peer()));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                self.broken = true;
+                return Err(self.failed(e, Phase::Receiving));
+            }
+        }
+
+        if buf.last() == Some(&0) {
+            buf.pop();
+        }
+
+        let reply = String::from_utf8_lossy(&buf).into_owned();
+
+        let reply_id = reply
+            .split_once(": ")
+            .and_then(|(reply_id, body)| Some((reply_id.parse::<u64>().ok()?, body)));
+
+        match reply_id {
+            Some((reply_id, body)) if self.outstanding.contains(&reply_id) => {
+                Ok((reply_id, body.to_owned()))
+            }
+            // ClamD gives up on the session without answering the request
+            None if reply == COMMAND_READ_TIMED_OUT_REPLY => {
+                self.broken = true;
+                Err(ClamError::CommandReadTimedOut)
+            }
+            _ => {
+                // A reply we cannot match up leaves the session in an unknown state.
+                self.broken = true;
+                Err(ClamError::InvalidData(reply))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ClamSession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClamSession")
+            .field("endpoint", &self.endpoint)
+            .field("broken", &self.broken)
+            .field("pending_requests", &self.pending_requests())
+            .finish()
+    }
+}
+
+impl Drop for ClamSession {
+    fn drop(&mut self) {
+        if !self.broken {
+            let _ = self.reader.get_mut().write_all(b"zEND\0");
+        }
+    }
+}
+
+/// Converts a reply expected to contain exactly one scan result into a `ClamScanResult`.
+fn single_result(resp: String, strict: bool) -> ClamResult<ClamScanResult> {
+    match parse_results(&resp, strict)?.first() {
+        Some(singular) => Ok(singular.clone()),
+        None => Err(ClamError::InvalidData(resp)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::response::ClamScanResult;
+    use crate::testing::fake_clamd;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_session_ping() {
+        let port = fake_clamd(|_| b"PONG".to_vec());
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+        let mut session = client.session().unwrap();
+        assert!(session.ping().unwrap());
+        assert!(session.ping().unwrap());
+    }
+
+    #[test]
+    fn test_session_scan_stream() {
+        let port = fake_clamd(|cmd| {
+            if cmd.starts_with(b"zINSTREAM") {
+                b"stream: Eicar-Test-Signature FOUND".to_vec()
+            } else {
+                b"PONG".to_vec()
+            }
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+        let mut session = client.session().unwrap();
+        assert!(session.ping().unwrap());
+        assert_eq!(
+            session.scan_stream(&b"data"[..]).unwrap(),
+            ClamScanResult::Found("stream".to_string(), "Eicar-Test-Signature".to_string())
+        );
+    }
+
+    #[test]
+    fn test_session_pipelined_out_of_order() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(&stream);
+            for _ in 0..4 {
+                reader.read_until(b'\0', &mut Vec::new()).unwrap();
+            }
+            // Answer the three scans in reverse order
+            (&stream)
+                .write_all(b"3: /c: OK\x002: /b: Sig-B FOUND\x001: /a: OK\0")
+                .unwrap();
+        });
+
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+        let mut session = client.session().unwrap();
+        let a = session.queue_scan_path("/a").unwrap();
+        let b = session.queue_scan_path("/b").unwrap();
+        let c = session.queue_scan_path("/c").unwrap();
+        assert_eq!(session.pending_requests(), 3);
+
+        assert_eq!(
+            session.wait(b).unwrap(),
+            ClamScanResult::Found("/b".to_string(), "Sig-B".to_string())
+        );
+        let (id, result) = session.next_result().unwrap().unwrap();
+        assert_eq!((id, result.unwrap()), (c, ClamScanResult::Ok));
+        let (id, result) = session.next_result().unwrap().unwrap();
+        assert_eq!((id, result.unwrap()), (a, ClamScanResult::Ok));
+        assert!(session.next_result().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_session_queued_scan_parsed_as_scan() {
+        let port = fake_clamd(|cmd| {
+            if cmd.starts_with(b"zSCAN") {
+                b"/srv/INSTREAM size limit exceeded.txt: OK".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+        let mut session = client.session().unwrap();
+        let scan = session
+            .queue_scan_path("/srv/INSTREAM size limit exceeded.txt")
+            .unwrap();
+        let stream = session.queue_stream(&b"data"[..]).unwrap();
+        assert_eq!(session.wait(scan).unwrap(), ClamScanResult::Ok);
+        assert_eq!(session.wait(stream).unwrap(), ClamScanResult::Ok);
+    }
+
+    #[test]
+    fn test_session_multi_file_contscan() {
+        let port = fake_clamd(|cmd| {
+            if cmd.starts_with(b"zCONTSCAN") {
+                b"/dir/a: Sig-A FOUND\0/dir/b: Sig-B FOUND\0/dir/c: OK".to_vec()
+            } else {
+                b"/dir/a: Sig-A FOUND".to_vec()
+            }
+        });
+        let client = ClamClient::builder("127.0.0.1", port)
+            .persistent_connection(true)
+            .build()
+            .unwrap();
+
+        // CONTSCAN is answered in several parts so it is never issued on the session
+        assert_eq!(
+            client.scan_path("/dir", true).unwrap(),
+            vec![
+                ClamScanResult::Found("/dir/a".to_string(), "Sig-A".to_string()),
+                ClamScanResult::Found("/dir/b".to_string(), "Sig-B".to_string()),
+                ClamScanResult::Ok,
+            ]
+        );
+        assert_eq!(
+            client.scan_path("/dir", false).unwrap(),
+            vec![ClamScanResult::Found(
+                "/dir/a".to_string(),
+                "Sig-A".to_string()
+            )]
+        );
+    }
+}
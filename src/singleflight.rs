@@ -0,0 +1,129 @@
+//! Coalesces concurrent scans of identical payloads, so that however many callers submit the same
+//! payload at once only one `INSTREAM` is issued and its verdict is shared between them all, see
+//! `ClamClientBuilder::coalesce_scans`.
+
+use crate::cache::CacheKey;
+use crate::client::ClamResult;
+use crate::response::ClamScanResult;
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// The scans in flight on a client, keyed by the SHA-256 of their payload.
+#[derive(Default)]
+pub(crate) struct InFlight {
+    scans: Mutex<HashMap<CacheKey, Arc<Scan>>>,
+}
+
+/// A scan in flight, whose outcome is `None` until it completes, then `Some(None)` if it failed.
+#[derive(Default)]
+struct Scan {
+    outcome: Mutex<Option<Option<ClamScanResult>>>,
+    completed: Condvar,
+}
+
+impl InFlight {
+    /// Runs `scan` for the payload with `key`, unless a scan of an identical payload is already
+    /// in flight, in which case its verdict is waited for and shared. `ClamError` cannot be
+    /// shared, so if that scan fails each waiter runs `scan` itself to report its own error.
+    pub(crate) fn run<F>(&self, key: CacheKey, scan: F) -> ClamResult<ClamScanResult>
+    where
+        F: FnOnce() -> ClamResult<ClamScanResult>,
+    {
+        let (in_flight, leader) = {
+            let mut scans = self.scans.lock().unwrap();
+            match scans.get(&key) {
+                Some(in_flight) => (in_flight.clone(), false),
+                None => {
+                    let in_flight = Arc::new(Scan::default());
+                    scans.insert(key, in_flight.clone());
+                    (in_flight, true)
+                }
+            }
+        };
+
+        if leader {
+            let mut completion = Completion {
+                in_flight: self,
+                key,
+                scan: &in_flight,
+                outcome: None,
+            };
+            let result = scan();
+            completion.outcome = result.as_ref().ok().cloned();
+            return result;
+        }
+
+        let mut outcome = in_flight.outcome.lock().unwrap();
+        while outcome.is_none() {
+            outcome = in_flight.completed.wait(outcome).unwrap();
+        }
+
+        match outcome.clone().flatten() {
+            Some(result) => Ok(result),
+            None => {
+                drop(outcome);
+                scan()
+            }
+        }
+    }
+}
+
+/// Publishes the outcome of a scan to its waiters when dropped, even if the scan panicked.
+struct Completion<'a> {
+    in_flight: &'a InFlight,
+    key: CacheKey,
+    scan: &'a Scan,
+    outcome: Option<ClamScanResult>,
+}
+
+impl Drop for Completion<'_> {
+    fn drop(&mut self) {
+        self.in_flight.scans.lock().unwrap().remove(&self.key);
+        *self.scan.outcome.lock().unwrap() = Some(self.outcome.take());
+        self.scan.completed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::response::ClamScanResult;
+    use crate::testing::fake_clamd;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_coalesce_scans() {
+        static STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+        let port = fake_clamd(|_| {
+            STREAMS.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(200));
+            b"stream: Sig-One FOUND".to_vec()
+        });
+        let client = ClamClient::builder("127.0.0.1", port)
+            .coalesce_scans(true)
+            .build()
+            .unwrap();
+
+        let results: Vec<_> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..8)
+                .map(|_| scope.spawn(|| client.scan_bytes(b"the same attachment")))
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+
+        for result in results {
+            assert_eq!(
+                result.unwrap(),
+                ClamScanResult::Found("stream".to_owned(), "Sig-One".to_owned())
+            );
+        }
+        assert_eq!(STREAMS.load(Ordering::SeqCst), 1);
+
+        // Once complete, the next scan is issued afresh
+        client.scan_bytes(b"the same attachment").unwrap();
+        assert_eq!(STREAMS.load(Ordering::SeqCst), 2);
+    }
+}
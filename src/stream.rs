@@ -0,0 +1,542 @@
+//! Adapters for plugging `INSTREAM` scanning into `std::io` pipelines, so that data can be scanned
+//! as it is written or read elsewhere without being buffered a second time.
+//!
+//! `ReadScanExt` and, with the `tokio` feature, `AsyncReadScanExt` extend readers with
+//! `scan_with`, so that a source can be streamed to ClamD directly from pipeline style code.
+
+use crate::client::{parse_results, ClamClient, ClamResult, CommandDelimiter};
+use crate::error::ClamError;
+use crate::instrument;
+use crate::protocol::{self, ReplyLength};
+use crate::redact::{Redact, Redaction};
+use crate::response::ClamScanResult;
+use crate::session::ClamSession;
+use crate::transport::Connection;
+#[cfg(feature = "tokio")]
+use std::future::Future;
+use std::io::{self, BufReader, Read, Write};
+#[cfg(feature = "tokio")]
+use tokio::io::AsyncRead;
+
+/// `ClamScanWriter` is a `Write` sink which streams everything written to it to ClamD with
+/// `INSTREAM`, handling the chunk framing internally. It is obtained via
+/// `ClamClient::scan_writer`, and the scan result is collected with `finish`.
+///
+/// If `ClamClientBuilder::stream_max_length` is set, writes which would exceed it fail with an
+/// `io::Error` wrapping `ClamError::StreamTooLarge`.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use std::fs::File;
+/// use std::io;
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///
+///     if let Ok(mut writer) = client.scan_writer() {
+///         let mut file = File::open("/etc/hosts").unwrap();
+///         io::copy(&mut file, &mut writer).unwrap();
+///         println!("{:?}", writer.finish());
+///     };
+/// }
+/// ```
+pub struct ClamScanWriter<'a> {
+    client: &'a ClamClient,
+    target: Option<StreamTarget>,
+    command: Option<instrument::Command>,
+    delimiter: CommandDelimiter,
+    max_length: Option<u64>,
+    strict: bool,
+    redaction: Redaction,
+    written: u64,
+}
+
+/// Where a `ClamScanWriter` streams to, once `INSTREAM` has been issued.
+pub(crate) enum StreamTarget {
+    /// A connection of its own, which ClamD closes once it has replied
+    Connection(Connection),
+    /// The client's persistent session, on loan until the stream is finished, along with the ID
+    /// of the `INSTREAM` request
+    Session(Box<ClamSession>, u64),
+}
+
+impl<'a> ClamScanWriter<'a> {
+    /// Creates a writer streaming to `target`, on which `INSTREAM` has already been issued as
+    /// `command`.
+    pub(crate) fn new(
+        client: &'a ClamClient,
+        target: StreamTarget,
+        command: instrument::Command,
+        delimiter: CommandDelimiter,
+        max_length: Option<u64>,
+        strict: bool,
+        redaction: Redaction,
+    ) -> ClamScanWriter<'a> {
+        ClamScanWriter {
+            client,
+            target: Some(target),
+            command: Some(command),
+            delimiter,
+            max_length,
+            strict,
+            redaction,
+            written: 0,
+        }
+    }
+
+    /// Returns the number of bytes streamed to ClamD so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    /// Terminates the stream and returns the `ClamScanResult` from ClamD.
+    pub fn finish(mut self) -> ClamResult<ClamScanResult> {
+        let target = self.target.take().unwrap();
+        let command = self.command.take().unwrap();
+
+        let result = command.in_scope(|| {
+            instrument::record_bytes(self.written);
+            match target {
+                StreamTarget::Connection(connection) => self.finish_connection(connection),
+                StreamTarget::Session(mut session, id) => {
+                    let result = session.finish_stream(id);
+                    self.client.restore_session(*session);
+                    result
+                }
+            }
+        });
+        self.client
+            .finish_instrumented(command, result, std::slice::from_ref)
+    }
+
+    /// Terminates the stream on a connection of its own and reads ClamD's reply.
+    fn finish_connection(&self, mut connection: Connection) -> ClamResult<ClamScanResult> {
+        connection
+            .write_all(&protocol::END_OF_STREAM)
+            .map_err(ClamError::CommandError)?;
+
+        let terminator = self.delimiter.terminator();
+        let reply = protocol::read_reply(
+            &mut BufReader::new(connection),
+            terminator,
+            ReplyLength::Single,
+        )
+        .map_err(ClamError::ConnectionError)?;
+        let result = String::from_utf8_lossy(&reply).into_owned();
+        let result = self.delimiter.normalise(result, true);
+
+        let results = parse_results(&result, self.strict).redact(self.redaction)?;
+        match results.into_iter().next() {
+            Some(singular) => Ok(singular),
+            None => Err(ClamError::InvalidData(result).redact(self.redaction)),
+        }
+    }
+}
+
+impl Write for ClamScanWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A zero length chunk would terminate the stream
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let buf = &buf[..buf.len().min(protocol::MAX_CHUNK_LEN)];
+        if let Some(limit) = self.max_length {
+            if self.written + buf.len() as u64 > limit {
+                return Err(io::Error::other(ClamError::StreamTooLarge(limit)));
+            }
+        }
+
+        match self.target.as_mut().unwrap() {
+            StreamTarget::Connection(connection) => protocol::write_chunk(connection, buf)?,
+            StreamTarget::Session(session, _) => session.write_stream_chunk(buf)?,
+        }
+        self.written += buf.len() as u64;
+        self.client.observers().streamed(buf.len() as u64);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.target.as_mut().unwrap() {
+            StreamTarget::Connection(connection) => connection.flush(),
+            StreamTarget::Session(..) => Ok(()),
+        }
+    }
+}
+
+impl Drop for ClamScanWriter<'_> {
+    fn drop(&mut self) {
+        // A stream abandoned part way through leaves the session expecting more chunks
+        if let Some(StreamTarget::Session(session, _)) = &mut self.target {
+            session.abandon();
+        }
+    }
+}
+
+/// `ScanningReader` wraps a `Read` source, passing every byte through to the caller unchanged
+/// whilst simultaneously streaming it to ClamD with `INSTREAM`. Once the source reaches EOF the
+/// stream is terminated and the verdict is available from `verdict`. It is obtained via
+/// `ClamClient::scanning_reader`.
+///
+/// Errors talking to ClamD do not interrupt reading, the data continues to pass through and the
+/// error is reported as the verdict instead.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use std::fs::File;
+/// use std::io;
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///     let source = File::open("/etc/hosts").unwrap();
+///
+///     if let Ok(mut reader) = client.scanning_reader(source) {
+///         let mut copy = Vec::new();
+///         io::copy(&mut reader, &mut copy).unwrap();
+///         println!("{:?}", reader.verdict());
+///     };
+/// }
+/// ```
+pub struct ScanningReader<'a, R: Read> {
+    inner: R,
+    writer: Option<ClamScanWriter<'a>>,
+    verdict: Option<ClamResult<ClamScanResult>>,
+}
+
+impl<'a, R: Read> ScanningReader<'a, R> {
+    /// Wraps `inner`, streaming it to ClamD through `writer`.
+    pub(crate) fn new(inner: R, writer: ClamScanWriter<'a>) -> ScanningReader<'a, R> {
+        ScanningReader {
+            inner,
+            writer: Some(writer),
+            verdict: None,
+        }
+    }
+
+    /// Returns the result of the scan, or `None` if the source has not yet reached EOF and no
+    /// error has occurred.
+    pub fn verdict(&self) -> Option<&ClamResult<ClamScanResult>> {
+        self.verdict.as_ref()
+    }
+
+    /// Consumes the reader, returning the result of the scan as `verdict` does.
+    pub fn into_verdict(self) -> Option<ClamResult<ClamScanResult>> {
+        self.verdict
+    }
+}
+
+impl<R: Read> Read for ScanningReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+
+        if let Some(mut writer) = self.writer.take() {
+            if read == 0 {
+                self.verdict = Some(writer.finish());
+            } else {
+                match writer.write_all(&buf[..read]) {
+                    Ok(_) => self.writer = Some(writer),
+                    Err(e) => self.verdict = Some(Err(into_clam_error(e))),
+                }
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+/// `ReadScanExt` extends every `Read` with `scan_with`, streaming it to ClamD with `INSTREAM`.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::stream::ReadScanExt;
+/// use std::fs::File;
+///
+/// fn main() {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///
+///     if let Ok(verdict) = File::open("/etc/hosts").unwrap().scan_with(&client) {
+///         println!("{}", verdict);
+///     }
+/// }
+/// ```
+pub trait ReadScanExt: Read {
+    /// Streams the reader to ClamD through `client`, returning the verdict, see
+    /// `ClamClient::scan_stream`.
+    fn scan_with(self, client: &ClamClient) -> ClamResult<ClamScanResult>
+    where
+        Self: Sized,
+    {
+        client.scan_stream(self)
+    }
+}
+
+impl<R: Read> ReadScanExt for R {}
+
+/// `AsyncReadScanExt` extends every `tokio::io::AsyncRead` with `scan_with_async`, streaming it
+/// to ClamD with `INSTREAM` without blocking. Requires the `tokio` feature.
+///
+/// *Example*
+///
+/// ```rust
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::stream::AsyncReadScanExt;
+///
+/// async fn scan(body: &[u8]) {
+///     let client = ClamClient::new("127.0.0.1", 3310).unwrap();
+///
+///     if let Ok(verdict) = body.scan_with_async(&client).await {
+///         println!("{}", verdict);
+///     }
+/// }
+/// ```
+#[cfg(feature = "tokio")]
+pub trait AsyncReadScanExt: AsyncRead {
+    /// Streams the reader to ClamD through `client`, returning the verdict, see
+    /// `ClamClient::scan_stream_async`.
+    fn scan_with_async(
+        self,
+        client: &ClamClient,
+    ) -> impl Future<Output = ClamResult<ClamScanResult>>
+    where
+        Self: Sized + Unpin,
+    {
+        client.scan_stream_async(self)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R: AsyncRead> AsyncReadScanExt for R {}
+
+/// Recovers the `ClamError` from an `io::Error` returned by `ClamScanWriter`.
+fn into_clam_error(error: io::Error) -> ClamError {
+    if error.get_ref().is_some_and(|e| e.is::<ClamError>()) {
+        *error.into_inner().unwrap().downcast::<ClamError>().unwrap()
+    } else {
+        ClamError::CommandError(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::ClamClient;
+    use crate::observer::ClamObserver;
+    use crate::response::ClamScanResult;
+    use crate::testing::fake_clamd;
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+
+    /// Records every event it is notified of.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl ClamObserver for Recorder {
+        fn on_command_sent(&self, command: &str) {
+            self.0.lock().unwrap().push(format!("sent {}", command));
+        }
+
+        fn on_bytes_streamed(&self, bytes: u64) {
+            self.0.lock().unwrap().push(format!("streamed {}", bytes));
+        }
+
+        fn on_result(&self, command: &str, result: &ClamScanResult) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} {}", command, result));
+        }
+    }
+
+    #[test]
+    fn test_scan_writer() {
+        let port = fake_clamd(|cmd| {
+            if cmd == b"zINSTREAMhello world" {
+                b"stream: OK".to_vec()
+            } else {
+                b"stream: Sig-One FOUND".to_vec()
+            }
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        let mut writer = client.scan_writer().unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer.write_all(b"").unwrap();
+        writer.write_all(b" world").unwrap();
+        assert_eq!(writer.bytes_written(), 11);
+        assert_eq!(writer.finish().unwrap(), ClamScanResult::Ok);
+    }
+
+    #[test]
+    fn test_scan_writer_instrumented() {
+        let port = fake_clamd(|_| b"stream: OK".to_vec());
+        let client = ClamClient::builder("127.0.0.1", port)
+            .collect_latency(true)
+            .build()
+            .unwrap();
+
+        let mut writer = client.scan_writer().unwrap();
+        writer.write_all(b"data").unwrap();
+        assert_eq!(writer.finish().unwrap(), ClamScanResult::Ok);
+
+        let metrics = client.client_metrics().unwrap();
+        assert_eq!(metrics.commands["INSTREAM"].count, 1);
+    }
+
+    #[test]
+    fn test_scan_writer_reads_single_reply() {
+        let port = crate::testing::lingering_clamd(b"stream: OK");
+        let client = ClamClient::builder("127.0.0.1", port)
+            .read_timeout(std::time::Duration::from_secs(5))
+            .build()
+            .unwrap();
+
+        // The reply is complete at its terminator, even though the connection is left open
+        let started = std::time::Instant::now();
+        let mut writer = client.scan_writer().unwrap();
+        writer.write_all(b"data").unwrap();
+        assert_eq!(writer.finish().unwrap(), ClamScanResult::Ok);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_scan_writer_observed() {
+        let port = fake_clamd(|_| b"stream: OK".to_vec());
+        let recorder = Arc::new(Recorder::default());
+        let client = ClamClient::builder("127.0.0.1", port)
+            .observer(recorder.clone())
+            .build()
+            .unwrap();
+
+        let mut reader = client.scanning_reader(&b"data"[..]).unwrap();
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            ["sent INSTREAM", "streamed 4", "INSTREAM OK"]
+        );
+    }
+
+    #[test]
+    fn test_scan_writer_persistent_session() {
+        let port = fake_clamd(|cmd| match cmd {
+            b"zPING" => b"PONG".to_vec(),
+            b"zINSTREAMhello world" => b"stream: OK".to_vec(),
+            _ => b"stream: Sig-One FOUND".to_vec(),
+        });
+        // A client created from a connection can only stream on its persistent session
+        let stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let client = ClamClient::from_stream(stream).unwrap();
+
+        for _ in 0..2 {
+            let mut writer = client.scan_writer().unwrap();
+            writer.write_all(b"hello world").unwrap();
+            assert_eq!(writer.finish().unwrap(), ClamScanResult::Ok);
+        }
+        assert!(client.ping());
+
+        // Abandoning a stream part way through discards the session
+        let mut writer = client.scan_writer().unwrap();
+        writer.write_all(b"hello").unwrap();
+        drop(writer);
+        assert!(client.scan_writer().is_err());
+    }
+
+    #[test]
+    fn test_scanning_reader() {
+        let port = fake_clamd(|cmd| {
+            if cmd.ends_with(b"bad") {
+                b"stream: Sig-One FOUND".to_vec()
+            } else {
+                b"stream: OK".to_vec()
+            }
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        let mut reader = client.scanning_reader(&b"data that is bad"[..]).unwrap();
+        let mut copy = Vec::new();
+        reader.read_to_end(&mut copy).unwrap();
+
+        assert_eq!(copy, b"data that is bad");
+        assert_eq!(
+            reader.into_verdict().unwrap().unwrap(),
+            ClamScanResult::Found("stream".to_owned(), "Sig-One".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_scanning_reader_too_large() {
+        let port = fake_clamd(|_| b"stream: OK".to_vec());
+        let client = ClamClient::builder("127.0.0.1", port)
+            .stream_max_length(4)
+            .build()
+            .unwrap();
+
+        let mut reader = client.scanning_reader(&b"too much data"[..]).unwrap();
+        let mut copy = Vec::new();
+        reader.read_to_end(&mut copy).unwrap();
+
+        assert_eq!(copy, b"too much data");
+        assert!(matches!(
+            reader.verdict(),
+            Some(Err(crate::error::ClamError::StreamTooLarge(4)))
+        ));
+    }
+
+    #[test]
+    fn test_scan_writer_max_length() {
+        let port = fake_clamd(|_| b"stream: OK".to_vec());
+        let client = ClamClient::builder("127.0.0.1", port)
+            .stream_max_length(4)
+            .build()
+            .unwrap();
+
+        let mut writer = client.scan_writer().unwrap();
+        assert!(writer.write_all(b"four").is_ok());
+        assert!(writer.write_all(b"more").is_err());
+    }
+
+    #[test]
+    fn test_read_scan_with() {
+        use crate::stream::ReadScanExt;
+
+        let port = fake_clamd(|cmd| {
+            if cmd == b"zINSTREAMpiped data" {
+                b"stream: OK".to_vec()
+            } else {
+                b"stream: Sig-One FOUND".to_vec()
+            }
+        });
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        let verdict = (&b"piped "[..]).chain(&b"data"[..]).scan_with(&client);
+        assert_eq!(verdict.unwrap(), ClamScanResult::Ok);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_read_scan_with() {
+        use crate::stream::AsyncReadScanExt;
+
+        let port = fake_clamd(|_| b"stream: Sig-One FOUND".to_vec());
+        let client = ClamClient::new("127.0.0.1", port).unwrap();
+
+        assert_eq!(
+            (&b"data"[..]).scan_with_async(&client).await.unwrap(),
+            ClamScanResult::Found("stream".to_owned(), "Sig-One".to_owned())
+        );
+    }
+}
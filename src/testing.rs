@@ -0,0 +1,81 @@
+//! Helpers shared by the unit tests, most notably a minimal in-process stand in for ClamD.
+
+use crate::mock::serve;
+use crate::protocol;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Starts a fake ClamD listening on an ephemeral local port and returns the port. Every command
+/// received is passed to `respond`, along with the streamed bytes for `INSTREAM`, and the reply
+/// is written back NUL terminated. `IDSESSION` is honoured by prefixing replies with request IDs.
+pub(crate) fn fake_clamd<F>(respond: F) -> u16
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + Sync + Copy + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    serve(listener, Arc::new(respond));
+
+    port
+}
+
+/// Starts a fake ClamD which closes every session after replying to its first command, as ClamD
+/// does to its open connections when it restarts. Replies to simple commands such as `PING` are
+/// produced by `respond` as for `fake_clamd`. Returns the port, along with a count of the connections accepted.
+pub(crate) fn restarting_clamd<F>(respond: F) -> (u16, Arc<AtomicUsize>)
+where
+    F: Fn(&[u8]) -> Vec<u8> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = connections.clone();
+
+    thread::spawn(move || {
+        for connection in listener.incoming() {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let mut connection = BufReader::new(connection.unwrap());
+
+            let mut command = Vec::new();
+            while command.is_empty() || command == b"zIDSESSION\0" {
+                command.clear();
+                if connection.read_until(b'\0', &mut command).unwrap_or(0) == 0 {
+                    break;
+                }
+            }
+
+            let mut reply = b"1: ".to_vec();
+            reply.extend(respond(command.strip_suffix(b"\0").unwrap_or(&command)));
+            reply.push(0);
+            let _ = connection.get_mut().write_all(&reply);
+        }
+    });
+
+    (port, connections)
+}
+
+/// Starts a fake ClamD which answers each `INSTREAM` with `reply`, but then leaves the connection
+/// open until the client closes it, as a proxy in front of ClamD may. Returns the port.
+pub(crate) fn lingering_clamd(reply: &'static [u8]) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    thread::spawn(move || {
+        for connection in listener.incoming() {
+            let mut connection = BufReader::new(connection.unwrap());
+            thread::spawn(move || {
+                connection.read_until(b'\0', &mut Vec::new())?;
+                while protocol::read_chunk(&mut connection)?.is_some() {}
+                connection.get_mut().write_all(reply)?;
+                connection.get_mut().write_all(b"\0")?;
+                connection.read_to_end(&mut Vec::new())
+            });
+        }
+    });
+
+    port
+}
@@ -0,0 +1,165 @@
+//! TLS support for `ClamClient`, enabled by the `tls` feature. ClamD has no native TLS support,
+//! but is commonly exposed remotely behind stunnel or HAProxy performing TLS termination.
+
+use crate::client::ClamResult;
+use crate::error::ClamError;
+use crate::transport::Connection;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+/// `TlsConfig` describes how to establish TLS connections to ClamD, it is supplied to
+/// `ClamClientBuilder::tls`. At least one root certificate must be trusted, either from the
+/// bundled Mozilla roots or from the certificates of a private CA.
+///
+/// *Example*
+///
+/// ```rust,no_run
+/// extern crate clam_client;
+///
+/// use clam_client::client::ClamClient;
+/// use clam_client::tls::TlsConfig;
+///
+/// fn main() {
+///     let tls = TlsConfig::new("clamav.internal")
+///         .unwrap()
+///         .add_root_certificates_pem(&std::fs::read("/etc/ssl/private-ca.pem").unwrap())
+///         .unwrap();
+///
+///     let client = ClamClient::builder("10.0.0.10", 3311)
+///         .tls(tls)
+///         .build()
+///         .unwrap();
+///
+///     println!("{:?}", client.version());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct TlsConfig {
+    server_name: ServerName<'static>,
+    roots: RootCertStore,
+}
+
+impl TlsConfig {
+    /// Creates a new `TlsConfig` which verifies the server's certificate against `server_name`,
+    /// this may be either a DNS name or an IP address.
+    pub fn new(server_name: &str) -> ClamResult<TlsConfig> {
+        let server_name = match ServerName::try_from(server_name.to_owned()) {
+            Ok(name) => name,
+            Err(e) => return Err(ClamError::TlsError(e.to_string())),
+        };
+
+        Ok(TlsConfig {
+            server_name,
+            roots: RootCertStore::empty(),
+        })
+    }
+
+    /// Trusts the Mozilla root certificates bundled by `webpki-roots`.
+    pub fn with_webpki_roots(mut self) -> Self {
+        self.roots
+            .extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        self
+    }
+
+    /// Trusts every certificate in the given PEM encoded data, such as a private CA bundle.
+    pub fn add_root_certificates_pem(mut self, pem: &[u8]) -> ClamResult<Self> {
+        for cert in CertificateDer::pem_slice_iter(pem) {
+            let cert = cert.map_err(|e| ClamError::TlsError(e.to_string()))?;
+            self = self.add_root_certificate_der(cert.as_ref())?;
+        }
+
+        Ok(self)
+    }
+
+    /// Trusts a single DER encoded certificate.
+    pub fn add_root_certificate_der(mut self, der: &[u8]) -> ClamResult<Self> {
+        match self.roots.add(CertificateDer::from(der.to_vec())) {
+            Ok(_) => Ok(self),
+            Err(e) => Err(ClamError::TlsError(e.to_string())),
+        }
+    }
+
+    /// Builds the connector used by `ClamClient` to wrap its TCP connections.
+    pub(crate) fn connector(self) -> ClamResult<TlsConnector> {
+        if self.roots.is_empty() {
+            return Err(ClamError::TlsError(
+                "no root certificates have been configured".to_owned(),
+            ));
+        }
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| ClamError::TlsError(e.to_string()))?
+            .with_root_certificates(self.roots)
+            .with_no_client_auth();
+
+        Ok(TlsConnector {
+            config: Arc::new(config),
+            server_name: self.server_name,
+        })
+    }
+}
+
+/// `TlsConnector` holds the prepared TLS configuration for a `ClamClient`.
+pub(crate) struct TlsConnector {
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+}
+
+impl TlsConnector {
+    /// Performs the TLS handshake over an established TCP connection.
+    pub(crate) fn connect(&self, mut stream: TcpStream) -> ClamResult<Connection> {
+        let mut connection = ClientConnection::new(self.config.clone(), self.server_name.clone())
+            .map_err(|e| ClamError::TlsError(e.to_string()))?;
+
+        while connection.is_handshaking() {
+            connection
+                .complete_io(&mut stream)
+                .map_err(ClamError::ConnectionError)?;
+        }
+
+        Ok(Connection::Tls(Box::new(StreamOwned::new(
+            connection, stream,
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tls::TlsConfig;
+
+    #[test]
+    fn test_tls_invalid_server_name() {
+        assert!(TlsConfig::new("not a valid name!").is_err());
+    }
+
+    #[test]
+    fn test_tls_requires_roots() {
+        assert!(TlsConfig::new("clamav.internal")
+            .unwrap()
+            .connector()
+            .is_err());
+    }
+
+    #[test]
+    fn test_tls_webpki_roots() {
+        assert!(TlsConfig::new("clamav.internal")
+            .unwrap()
+            .with_webpki_roots()
+            .connector()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_tls_invalid_pem() {
+        let pem = b"-----BEGIN CERTIFICATE-----\nnot base64\n-----END CERTIFICATE-----\n";
+        assert!(TlsConfig::new("clamav.internal")
+            .unwrap()
+            .add_root_certificates_pem(pem)
+            .is_err());
+    }
+}
@@ -0,0 +1,418 @@
+//! The transports over which `ClamClient` talks to ClamD, all of which are exposed through the
+//! single `Connection` type so that the protocol handling is shared between them.
+
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
+use std::io::{self, ErrorKind, IoSlice, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::fd::AsFd as AsSocket;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(windows)]
+use std::os::windows::io::AsSocket;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait on one connection attempt before racing the next address against it, as
+/// recommended by RFC 8305.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// How long a single connection attempt of `connect_any` may take when no connect timeout is
+/// configured, so that an attempt abandoned after another address won the race cannot outlive it
+/// by more than this.
+const CONNECTION_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The most connection attempts `connect_any` has in flight at once.
+const MAX_CONCURRENT_ATTEMPTS: usize = 4;
+
+/// `Connection` is an established connection to ClamD.
+pub(crate) enum Connection {
+    /// A plain TCP connection.
+    Tcp(TcpStream),
+    /// A TCP connection wrapped in TLS, typically terminated by stunnel or HAProxy in front of
+    /// ClamD.
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+    /// A Unix domain socket connection, to ClamD's `LocalSocket`.
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write_vectored(bufs),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.write_vectored(bufs),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write_vectored(bufs),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Connection {
+    /// Returns true if the peer has closed the connection, e.g. because ClamD restarted whilst it
+    /// was idle, or the socket has otherwise failed. This does not block, and does not consume
+    /// any data that has been received.
+    pub(crate) fn is_closed(&self) -> bool {
+        match self {
+            Connection::Tcp(stream) => {
+                is_closed(stream, TcpStream::set_nonblocking, |s, b| s.peek(b))
+            }
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => {
+                is_closed(&stream.sock, TcpStream::set_nonblocking, |s, b| s.peek(b))
+            }
+            // `UnixStream::peek` is not yet stable, so a closed Unix socket is only noticed once
+            // a command fails on it
+            #[cfg(unix)]
+            Connection::Unix(_) => false,
+        }
+    }
+}
+
+/// Connects to the Unix domain socket at `path`. On Linux a path beginning with `@`, such as
+/// `@clamd`, names a socket in the abstract namespace, which has no presence on the filesystem.
+#[cfg(unix)]
+pub(crate) fn connect_unix(path: &Path) -> io::Result<UnixStream> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::net::SocketAddr;
+
+        if let Some(name) = path.as_os_str().as_bytes().strip_prefix(b"@") {
+            return UnixStream::connect_addr(&SocketAddr::from_abstract_name(name)?);
+        }
+    }
+
+    UnixStream::connect(path)
+}
+
+/// Peeks at `socket` without blocking, treating end of stream or any error other than there
+/// being nothing to read as the connection having closed.
+fn is_closed<S>(
+    socket: &S,
+    set_nonblocking: fn(&S, bool) -> io::Result<()>,
+    peek: fn(&S, &mut [u8]) -> io::Result<usize>,
+) -> bool {
+    if set_nonblocking(socket, true).is_err() {
+        return true;
+    }
+
+    let closed = match peek(socket, &mut [0]) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => e.kind() != ErrorKind::WouldBlock,
+    };
+
+    set_nonblocking(socket, false).is_err() || closed
+}
+
+/// Applies the socket options configured on the client to a TCP connection to ClamD, disabling
+/// Nagle's algorithm if `nodelay` is set, and enabling keepalive probes every `keepalive`.
+pub(crate) fn set_tcp_options<S: AsSocket>(
+    stream: &S,
+    nodelay: bool,
+    keepalive: Option<Duration>,
+) -> io::Result<()> {
+    let socket = SockRef::from(stream);
+    if nodelay {
+        socket.set_tcp_nodelay(true)?;
+    }
+
+    if let Some(interval) = keepalive {
+        let keepalive = TcpKeepalive::new().with_time(interval);
+        #[cfg(any(
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux",
+            target_os = "macos",
+            windows
+        ))]
+        let keepalive = keepalive.with_interval(interval);
+        socket.set_tcp_keepalive(&keepalive)?;
+    }
+
+    Ok(())
+}
+
+/// `SocketOptions` are the options configured on the client which must be applied to a TCP
+/// socket before it connects to ClamD.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct SocketOptions {
+    /// The local address to bind to, with an ephemeral port
+    pub(crate) local_address: Option<IpAddr>,
+    /// The size of the send buffer, `SO_SNDBUF`
+    pub(crate) send_buffer_size: Option<usize>,
+    /// The size of the receive buffer, `SO_RCVBUF`, which must be set before connecting for a
+    /// large enough TCP window scale to be negotiated
+    pub(crate) recv_buffer_size: Option<usize>,
+}
+
+impl SocketOptions {
+    /// Returns true if no options are set, in which case the socket needs no configuring.
+    pub(crate) fn is_default(&self) -> bool {
+        *self == SocketOptions::default()
+    }
+}
+
+/// Creates a TCP socket for connecting to `addr`, configured with `options`.
+pub(crate) fn new_socket(addr: SocketAddr, options: SocketOptions) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(local) = options.local_address {
+        socket.bind(&SocketAddr::new(local, 0).into())?;
+    }
+    Ok(socket)
+}
+
+/// Connects to a single address, applying `options` to the socket first, and honouring the
+/// connect timeout if one is set.
+pub(crate) fn connect_tcp(
+    addr: SocketAddr,
+    options: SocketOptions,
+    timeout: Option<Duration>,
+) -> io::Result<TcpStream> {
+    if options.is_default() {
+        return match timeout {
+            Some(t) => TcpStream::connect_timeout(&addr, t),
+            None => TcpStream::connect(addr),
+        };
+    }
+
+    let socket = new_socket(addr, options)?;
+    match timeout {
+        Some(t) => socket.connect_timeout(&addr.into(), t)?,
+        None => socket.connect(&addr.into())?,
+    }
+    Ok(socket.into())
+}
+
+/// Connects to the first of `addrs` to accept a connection, in the style of RFC 8305 "Happy
+/// Eyeballs". Each address is attempted in turn, the next attempt starting as soon as the previous
+/// one fails or after `CONNECTION_ATTEMPT_DELAY`, whichever is sooner, so that an unreachable
+/// address (typically a broken IPv6 route) delays the connection only briefly.
+///
+/// Each attempt runs on its own thread and is bounded by `timeout`, or by
+/// `CONNECTION_ATTEMPT_TIMEOUT` if none is set, so attempts still racing once one has succeeded
+/// finish shortly afterwards. At most `MAX_CONCURRENT_ATTEMPTS` attempts are in flight at once;
+/// the next address is only tried once one of them has failed.
+pub(crate) fn connect_any(
+    addrs: &[SocketAddr],
+    options: SocketOptions,
+    timeout: Option<Duration>,
+) -> io::Result<TcpStream> {
+    if let [addr] = addrs {
+        return connect_tcp(*addr, options, timeout);
+    }
+
+    let timeout = Some(timeout.unwrap_or(CONNECTION_ATTEMPT_TIMEOUT));
+    let (tx, rx) = mpsc::channel();
+    let mut error = None;
+    let mut in_flight = 0;
+
+    for (i, &addr) in addrs.iter().enumerate() {
+        if i > 0 {
+            let result = if in_flight < MAX_CONCURRENT_ATTEMPTS {
+                rx.recv_timeout(CONNECTION_ATTEMPT_DELAY).ok()
+            } else {
+                rx.recv().ok()
+            };
+            match result {
+                Some(Ok(stream)) => return Ok(stream),
+                Some(Err(e)) => {
+                    in_flight -= 1;
+                    error = Some(e);
+                }
+                None => {}
+            }
+        }
+
+        let tx = tx.clone();
+        thread::spawn(move || {
+            // The receiver is gone once another attempt has won the race
+            let _ = tx.send(connect_tcp(addr, options, timeout));
+        });
+        in_flight += 1;
+    }
+
+    drop(tx);
+    for result in rx {
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = Some(e),
+        }
+    }
+
+    Err(error.unwrap_or_else(|| ErrorKind::NotFound.into()))
+}
+
+/// Orders resolved addresses as RFC 8305 recommends, alternating between address families
+/// starting with the family of the first address, so that consecutive attempts do not all depend
+/// on the same route.
+pub(crate) fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_v6);
+    preferred.reverse();
+    other.reverse();
+
+    let mut ordered = Vec::new();
+    while let Some(addr) = preferred.pop() {
+        ordered.push(addr);
+        ordered.extend(other.pop());
+    }
+    ordered.extend(other.into_iter().rev());
+
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transport::{
+        connect_any, connect_tcp, interleave, SocketOptions, MAX_CONCURRENT_ATTEMPTS,
+    };
+    use std::net::{SocketAddr, TcpListener};
+    use std::time::Duration;
+
+    #[test]
+    fn test_interleave() {
+        let addrs: Vec<SocketAddr> = [
+            "[::1]:1",
+            "[::2]:1",
+            "[::3]:1",
+            "127.0.0.1:1",
+            "127.0.0.2:1",
+        ]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect();
+        let ordered: Vec<String> = interleave(addrs).iter().map(|a| a.to_string()).collect();
+
+        assert_eq!(
+            ordered,
+            [
+                "[::1]:1",
+                "127.0.0.1:1",
+                "[::2]:1",
+                "127.0.0.2:1",
+                "[::3]:1"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_connect_any() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let addrs = [closed, listener.local_addr().unwrap()];
+
+        let options = SocketOptions::default();
+        let stream = connect_any(&addrs, options, Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+        assert!(connect_any(&[closed], options, Some(Duration::from_secs(5))).is_err());
+    }
+
+    #[test]
+    fn test_connect_any_more_addresses_than_attempts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut addrs: Vec<_> = (0..MAX_CONCURRENT_ATTEMPTS + 2)
+            .map(|_| {
+                TcpListener::bind("127.0.0.1:0")
+                    .unwrap()
+                    .local_addr()
+                    .unwrap()
+            })
+            .collect();
+        addrs.push(listener.local_addr().unwrap());
+
+        let stream = connect_any(&addrs, SocketOptions::default(), None).unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+        assert!(connect_any(&addrs[..2], SocketOptions::default(), None).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_connect_unix_abstract() {
+        use crate::transport::connect_unix;
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::{SocketAddr, UnixListener};
+        use std::path::Path;
+
+        let name = format!("clam-client-test-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let listener = UnixListener::bind_addr(&addr).unwrap();
+
+        let stream = connect_unix(Path::new(&format!("@{}", name))).unwrap();
+        let (accepted, _) = listener.accept().unwrap();
+        assert_eq!(
+            accepted.local_addr().unwrap().as_abstract_name(),
+            Some(name.as_bytes())
+        );
+        drop(stream);
+
+        // Without the `@` the name is a path, which does not exist
+        assert!(connect_unix(Path::new(&name)).is_err());
+    }
+
+    #[test]
+    fn test_connect_tcp_buffer_sizes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = SocketOptions {
+            send_buffer_size: Some(256 * 1024),
+            recv_buffer_size: Some(256 * 1024),
+            ..SocketOptions::default()
+        };
+
+        let stream = connect_tcp(listener.local_addr().unwrap(), options, None).unwrap();
+        let socket = socket2::SockRef::from(&stream);
+        // The kernel may round the sizes, e.g. Linux doubles them to allow for bookkeeping
+        assert!(socket.send_buffer_size().unwrap() >= 256 * 1024);
+        assert!(socket.recv_buffer_size().unwrap() >= 256 * 1024);
+    }
+}